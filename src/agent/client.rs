@@ -0,0 +1,496 @@
+//! Talking to the unlock agent from CLI commands
+
+use crate::agent::{decode_key, encode_key, Request, Response, VaultContents, MAX_MESSAGE_SIZE};
+use crate::crypto::key_derivation::KdfParams;
+use crate::crypto::{EncryptedData, MasterKey, SecretString};
+use crate::storage::attachment::Attachment;
+use crate::storage::{KeyHolder, Vault};
+use crate::{Result, TwoPasswordError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use zeroize::Zeroizing;
+
+/// How long to wait for a freshly spawned agent to start listening
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State reported by a running agent
+#[derive(Debug, Clone)]
+pub struct AgentStatus {
+    /// Vault the agent has unlocked
+    pub vault_path: Option<PathBuf>,
+    /// Idle time after which the agent locks
+    pub idle_timeout: Duration,
+    /// Time left before the agent locks unless it is used
+    pub locks_in: Duration,
+}
+
+/// Send a request to the agent and wait for its response
+///
+/// Error responses from the agent are returned as `AgentError`.
+pub async fn request(socket_path: &Path, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(socket_path).await.map_err(|e| {
+        TwoPasswordError::agent(format!("No agent at {}: {}", socket_path.display(), e))
+    })?;
+    exchange(stream, request).await
+}
+
+async fn exchange(stream: UnixStream, request: &Request) -> Result<Response> {
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = Zeroizing::new(serde_json::to_string(request)?);
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let mut reply = Zeroizing::new(String::new());
+    BufReader::new(reader)
+        .take(MAX_MESSAGE_SIZE)
+        .read_line(&mut reply)
+        .await?;
+    parse_response(&reply)
+}
+
+/// Send a request to the agent, blocking until it responds
+fn request_blocking(socket_path: &Path, request: &Request) -> Result<Response> {
+    use std::io::{BufRead, Read, Write};
+
+    let stream = std::os::unix::net::UnixStream::connect(socket_path).map_err(|e| {
+        TwoPasswordError::agent(format!("No agent at {}: {}", socket_path.display(), e))
+    })?;
+
+    let mut line = Zeroizing::new(serde_json::to_string(request)?);
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+
+    let mut reply = Zeroizing::new(String::new());
+    std::io::BufReader::new(&stream)
+        .take(MAX_MESSAGE_SIZE)
+        .read_line(&mut reply)?;
+    parse_response(&reply)
+}
+
+fn parse_response(reply: &str) -> Result<Response> {
+    match serde_json::from_str(reply) {
+        Ok(Response::Error { message }) => Err(TwoPasswordError::agent(message)),
+        Ok(response) => Ok(response),
+        Err(e) => Err(TwoPasswordError::agent(format!(
+            "Malformed response from agent: {}",
+            e
+        ))),
+    }
+}
+
+/// Unlock a vault in the agent, starting an agent first if none is running
+pub async fn unlock(
+    socket_path: &Path,
+    vault_path: &Path,
+    password: SecretString,
+    idle_timeout: Duration,
+) -> Result<()> {
+    let request = Request::Unlock {
+        vault_path: canonical(vault_path),
+        password,
+    };
+
+    let stream = match UnixStream::connect(socket_path).await {
+        Ok(stream) => stream,
+        Err(_) => spawn(socket_path, idle_timeout).await?,
+    };
+
+    match exchange(stream, &request).await? {
+        Response::Ok => Ok(()),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// Open `vault_path` with the contents decrypted by the agent
+///
+/// The returned session saves the vault through the agent, to be given to
+/// [`VaultManager::open_with_key_holder`](crate::storage::VaultManager::open_with_key_holder).
+pub async fn open(socket_path: &Path, vault_path: &Path) -> Result<(Vault, Session)> {
+    let request = Request::Open {
+        vault_path: canonical(vault_path),
+    };
+
+    match self::request(socket_path, &request).await? {
+        Response::Contents { contents, kdf } => {
+            let mut vault = Vault::new(vault_path);
+            contents.apply_to(&mut vault);
+            vault.is_modified = false;
+            let session = Session {
+                socket_path: socket_path.to_path_buf(),
+                vault_path: canonical(vault_path),
+                kdf,
+            };
+            Ok((vault, session))
+        }
+        other => Err(unexpected(other)),
+    }
+}
+
+/// A vault opened through the agent, which holds its data key
+#[derive(Debug, Clone)]
+pub struct Session {
+    socket_path: PathBuf,
+    vault_path: PathBuf,
+    kdf: KdfParams,
+}
+
+impl KeyHolder for Session {
+    fn kdf_params(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    fn save(&self, vault: &Vault) -> Result<()> {
+        let request = Request::Save {
+            vault_path: self.vault_path.clone(),
+            contents: Box::new(VaultContents::of(vault)),
+        };
+        match request_blocking(&self.socket_path, &request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn wrap_file_key(&self, file_key: &MasterKey) -> Result<EncryptedData> {
+        let request = Request::WrapFileKey {
+            vault_path: self.vault_path.clone(),
+            file_key: encode_key(file_key),
+        };
+        match request_blocking(&self.socket_path, &request)? {
+            Response::WrappedFileKey { wrapped_key } => Ok(wrapped_key),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn unwrap_file_key(&self, attachment: &Attachment) -> Result<MasterKey> {
+        let request = Request::UnwrapFileKey {
+            vault_path: self.vault_path.clone(),
+            attachment_id: attachment.id,
+        };
+        match request_blocking(&self.socket_path, &request)? {
+            Response::FileKey { file_key } => decode_key(&file_key),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+/// Get the state of the running agent, or `None` if no agent is running
+pub async fn status(socket_path: &Path) -> Result<Option<AgentStatus>> {
+    let Ok(stream) = UnixStream::connect(socket_path).await else {
+        return Ok(None);
+    };
+
+    match exchange(stream, &Request::Status).await? {
+        Response::Status {
+            vault_path,
+            idle_timeout_secs,
+            locks_in_secs,
+        } => Ok(Some(AgentStatus {
+            vault_path,
+            idle_timeout: Duration::from_secs(idle_timeout_secs),
+            locks_in: Duration::from_secs(locks_in_secs),
+        })),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// Tell the agent to forget the key and exit
+///
+/// Returns `false` if no agent was running.
+pub async fn lock(socket_path: &Path) -> Result<bool> {
+    let Ok(stream) = UnixStream::connect(socket_path).await else {
+        return Ok(false);
+    };
+
+    match exchange(stream, &Request::Lock).await? {
+        Response::Ok => Ok(true),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// Start an agent in the background and connect to it
+///
+/// The agent is this executable running the hidden `agent` command in its own
+/// process group, so it outlives the current command and ignores the
+/// terminal's interrupts.
+async fn spawn(socket_path: &Path, idle_timeout: Duration) -> Result<UnixStream> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let exe = std::env::current_exe()?;
+    Command::new(exe)
+        .arg("agent")
+        .arg("--socket")
+        .arg(socket_path)
+        .arg("--idle-timeout")
+        .arg(idle_timeout.as_secs().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| TwoPasswordError::agent(format!("Failed to start agent: {}", e)))?;
+
+    let started = tokio::time::Instant::now();
+    loop {
+        if let Ok(stream) = UnixStream::connect(socket_path).await {
+            return Ok(stream);
+        }
+        if started.elapsed() > SPAWN_TIMEOUT {
+            return Err(TwoPasswordError::agent(format!(
+                "Agent did not start listening on {}",
+                socket_path.display()
+            )));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Agent requests name vaults by canonical path, so the same file is
+/// recognised however it was spelled on the command line
+fn canonical(vault_path: &Path) -> PathBuf {
+    std::fs::canonicalize(vault_path).unwrap_or_else(|_| vault_path.to_path_buf())
+}
+
+fn unexpected(response: Response) -> TwoPasswordError {
+    TwoPasswordError::agent(format!("Unexpected response from agent: {:?}", response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::server::Agent;
+    use crate::storage::{PasswordEntry, VaultManager};
+
+    const PASSWORD: &str = "Agent-Test-Password-123!";
+
+    fn create_vault(dir: &Path) -> PathBuf {
+        let vault_path = dir.join("vault.enc");
+        let mut vault_manager = VaultManager::new();
+        vault_manager.create_vault(&vault_path, PASSWORD).unwrap();
+        vault_manager
+            .get_vault_mut()
+            .unwrap()
+            .add_entry(PasswordEntry::new(
+                "GitHub".to_string(),
+                "octocat".to_string(),
+                "hunter2".to_string(),
+            ));
+        vault_manager.save_vault().unwrap();
+        vault_path
+    }
+
+    /// Run an agent on a thread of its own, since sessions block on the
+    /// socket while saving
+    fn start_agent(socket_path: &Path, idle_timeout: Duration) -> std::thread::JoinHandle<()> {
+        let socket = socket_path.to_path_buf();
+        let handle = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(Agent::new(idle_timeout).serve(&socket))
+                .unwrap();
+        });
+        while std::os::unix::net::UnixStream::connect(socket_path).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        handle
+    }
+
+    async fn unlock_with(socket_path: &Path, vault_path: &Path, password: &str) -> Result<()> {
+        let stream = UnixStream::connect(socket_path).await.unwrap();
+        let request = Request::Unlock {
+            vault_path: canonical(vault_path),
+            password: SecretString::from(password),
+        };
+        exchange(stream, &request).await.map(|_| ())
+    }
+
+    async fn open_through_agent(socket_path: &Path, vault_path: &Path) -> Result<VaultManager> {
+        let (vault, session) = open(socket_path, vault_path).await?;
+        let mut vault_manager = VaultManager::new();
+        vault_manager.open_with_key_holder(vault, Box::new(session));
+        Ok(vault_manager)
+    }
+
+    #[tokio::test]
+    async fn test_unlock_open_and_lock() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vault_path = create_vault(temp_dir.path());
+        let socket_path = temp_dir.path().join("agent").join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_secs(60));
+
+        // Only the owner may connect
+        let mode = std::os::unix::fs::PermissionsExt::mode(
+            &std::fs::metadata(&socket_path).unwrap().permissions(),
+        );
+        assert_eq!(mode & 0o777, 0o600);
+
+        unlock_with(&socket_path, &vault_path, PASSWORD)
+            .await
+            .unwrap();
+
+        // A fresh vault manager, as in a new CLI process, works on the vault
+        // through the agent without ever holding its data key
+        let mut vault_manager = open_through_agent(&socket_path, &vault_path).await.unwrap();
+        assert_eq!(vault_manager.get_vault().unwrap().entries.len(), 1);
+        assert!(vault_manager
+            .change_master_password(PASSWORD, "Other-123!")
+            .is_err());
+
+        let entry = PasswordEntry::new("Bank".to_string(), "me".to_string(), "pw".to_string());
+        let entry_id = entry.id;
+        vault_manager.get_vault_mut().unwrap().add_entry(entry);
+        vault_manager.save_vault().unwrap();
+        let attachment = vault_manager
+            .attach_file(&entry_id, "notes.txt", &b"attached through the agent"[..])
+            .unwrap();
+
+        let mut extracted = Vec::new();
+        vault_manager
+            .extract_file(&attachment, &mut extracted)
+            .unwrap();
+        assert_eq!(extracted, b"attached through the agent");
+
+        // Changes were saved by the agent under the vault's own key
+        let mut direct = VaultManager::new();
+        direct.load_vault(&vault_path, PASSWORD).unwrap();
+        assert_eq!(direct.get_vault().unwrap().entries.len(), 2);
+        let mut extracted = Vec::new();
+        direct.extract_file(&attachment, &mut extracted).unwrap();
+        assert_eq!(extracted, b"attached through the agent");
+
+        let status = status(&socket_path).await.unwrap().unwrap();
+        assert_eq!(status.vault_path, Some(canonical(&vault_path)));
+        assert_eq!(status.idle_timeout, Duration::from_secs(60));
+
+        // Only the unlocked vault is served
+        let other = temp_dir.path().join("other.enc");
+        assert!(open(&socket_path, &other).await.is_err());
+
+        assert!(lock(&socket_path).await.unwrap());
+        agent.join().unwrap();
+        assert!(!socket_path.exists());
+        assert!(!lock(&socket_path).await.unwrap());
+        assert!(open(&socket_path, &vault_path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_keeps_vault_kdf_params() {
+        use crate::storage::export::{self, EncryptedExport, ExportFormat};
+        use crate::storage::kdbx;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let kdf = KdfParams::for_tests();
+        assert_ne!(kdf, KdfParams::default());
+        VaultManager::new()
+            .create_vault_with_params(&vault_path, PASSWORD, kdf)
+            .unwrap();
+        let socket_path = temp_dir.path().join("agent").join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_secs(60));
+        unlock_with(&socket_path, &vault_path, PASSWORD)
+            .await
+            .unwrap();
+
+        let vault_manager = open_through_agent(&socket_path, &vault_path).await.unwrap();
+        let session_kdf = *vault_manager.kdf_params().unwrap();
+        assert_eq!(session_kdf, kdf);
+
+        let entries = [PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "hunter2".to_string(),
+        )];
+        let passphrase = SecretString::from("Export-Passphrase-123!");
+        let files = export::AttachmentFiles::new();
+        let encrypted = export::export_entries(
+            &entries,
+            &files,
+            ExportFormat::Encrypted,
+            Some(&passphrase),
+            &session_kdf,
+        )
+        .unwrap();
+        let envelope: EncryptedExport = serde_json::from_slice(&encrypted).unwrap();
+        assert_eq!(envelope.kdf, kdf);
+
+        let database = export::export_entries(
+            &entries,
+            &files,
+            ExportFormat::Kdbx,
+            Some(&passphrase),
+            &session_kdf,
+        )
+        .unwrap();
+        assert_eq!(kdbx::argon2_params(&database).unwrap(), kdf);
+
+        assert!(lock(&socket_path).await.unwrap());
+        agent.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password_keeps_session() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vault_path = create_vault(temp_dir.path());
+        let socket_path = temp_dir.path().join("agent").join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_secs(60));
+
+        unlock_with(&socket_path, &vault_path, PASSWORD)
+            .await
+            .unwrap();
+        assert!(unlock_with(&socket_path, &vault_path, "wrong password")
+            .await
+            .is_err());
+
+        let status = status(&socket_path).await.unwrap().unwrap();
+        assert_eq!(status.vault_path, Some(canonical(&vault_path)));
+        let vault_manager = open_through_agent(&socket_path, &vault_path).await.unwrap();
+        assert_eq!(vault_manager.get_vault().unwrap().entries.len(), 1);
+
+        assert!(lock(&socket_path).await.unwrap());
+        agent.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password_stops_empty_agent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vault_path = create_vault(temp_dir.path());
+        let socket_path = temp_dir.path().join("agent").join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_secs(60));
+
+        // An agent started for an unlock that fails has nothing to serve
+        assert!(unlock_with(&socket_path, &vault_path, "wrong password")
+            .await
+            .is_err());
+        agent.join().unwrap();
+        assert!(status(&socket_path).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_locks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let vault_path = create_vault(temp_dir.path());
+        let socket_path = temp_dir.path().join("agent").join("agent.sock");
+        let agent = start_agent(&socket_path, Duration::from_secs(1));
+
+        unlock_with(&socket_path, &vault_path, PASSWORD)
+            .await
+            .unwrap();
+        assert!(open(&socket_path, &vault_path).await.is_ok());
+
+        let started = std::time::Instant::now();
+        while !agent.is_finished() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "agent should lock after the idle timeout"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        agent.join().unwrap();
+        assert!(open(&socket_path, &vault_path).await.is_err());
+    }
+}
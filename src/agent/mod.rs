@@ -0,0 +1,246 @@
+//! Background unlock agent
+//!
+//! Every CLI invocation is a separate process, so a vault unlocked by
+//! `twopassword unlock` would be forgotten as soon as that process exits. The
+//! agent is a long-running process that holds the unlocked `VaultManager` and
+//! answers requests on a Unix domain socket that only the owning user can
+//! reach.
+//!
+//! Other commands work on the vault through the agent: it reopens the vault
+//! file, which another process may have rewritten, and sends the decrypted
+//! contents; the command sends them back to be encrypted and saved. Keys of
+//! attachment blobs are wrapped and unwrapped by the agent one at a time. The
+//! master password and the data key never leave the agent, so nothing a
+//! command receives opens the vault later. The agent forgets the key and
+//! exits on `lock`, or once it has not been used for the idle timeout.
+//!
+//! Each connection carries one request and one response, each a single line
+//! of JSON.
+
+use crate::crypto::key_derivation::KdfParams;
+use crate::crypto::{MasterKey, SecretString};
+use crate::storage::{PasswordEntry, TrashedEntry, Vault, VaultMetadata, VaultSettings};
+use crate::{Result, TwoPasswordError};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+pub mod client;
+pub mod server;
+
+/// Environment variable overriding the agent socket path
+pub const SOCKET_ENV: &str = "TWOPASSWORD_AGENT_SOCK";
+
+/// Socket file name inside the agent directory
+const SOCKET_FILE_NAME: &str = "agent.sock";
+
+/// Longest accepted request or response line, in bytes; vault contents
+/// travel in a single line
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Request sent by the CLI to the agent
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Unlock a vault with its master password, replacing any vault the
+    /// agent already holds
+    Unlock {
+        vault_path: PathBuf,
+        password: SecretString,
+    },
+    /// Get the decrypted contents of the unlocked vault, which must be
+    /// `vault_path`
+    Open { vault_path: PathBuf },
+    /// Encrypt and save new contents of the unlocked vault
+    Save {
+        vault_path: PathBuf,
+        contents: Box<VaultContents>,
+    },
+    /// Wrap the base64 encoded key of a new attachment blob
+    WrapFileKey {
+        vault_path: PathBuf,
+        file_key: SecretString,
+    },
+    /// Unwrap the key of an attachment of the saved vault
+    UnwrapFileKey {
+        vault_path: PathBuf,
+        attachment_id: Uuid,
+    },
+    /// Report which vault is unlocked and when it will lock
+    Status,
+    /// Forget the key and exit
+    Lock,
+}
+
+/// Response sent by the agent
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// The request succeeded
+    Ok,
+    /// Decrypted contents of the unlocked vault, with the key derivation
+    /// parameters its master key was derived with
+    Contents {
+        contents: Box<VaultContents>,
+        kdf: KdfParams,
+    },
+    /// Wrapped key of an attachment blob
+    WrappedFileKey {
+        wrapped_key: crate::crypto::EncryptedData,
+    },
+    /// Base64 encoded key of an attachment blob
+    FileKey { file_key: SecretString },
+    /// Current agent state
+    Status {
+        vault_path: Option<PathBuf>,
+        idle_timeout_secs: u64,
+        locks_in_secs: u64,
+    },
+    /// The request failed
+    Error { message: String },
+}
+
+/// Decrypted contents of a vault, as sent between the agent and commands
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultContents {
+    pub metadata: VaultMetadata,
+    pub entries: HashMap<Uuid, PasswordEntry>,
+    pub trash: HashMap<Uuid, TrashedEntry>,
+    pub settings: VaultSettings,
+}
+
+impl VaultContents {
+    /// Copy the contents of a vault
+    pub fn of(vault: &Vault) -> Self {
+        Self {
+            metadata: vault.metadata.clone(),
+            entries: vault.entries.clone(),
+            trash: vault.trash.clone(),
+            settings: vault.settings.clone(),
+        }
+    }
+
+    /// Replace the contents of a vault
+    pub fn apply_to(self, vault: &mut Vault) {
+        vault.metadata = self.metadata;
+        vault.entries = self.entries;
+        vault.trash = self.trash;
+        vault.settings = self.settings;
+    }
+}
+
+/// Encode a key for a JSON message
+fn encode_key(key: &MasterKey) -> SecretString {
+    SecretString::from(base64::engine::general_purpose::STANDARD.encode(key.as_bytes()))
+}
+
+/// Decode a key encoded by [`encode_key`]
+fn decode_key(encoded: &SecretString) -> Result<MasterKey> {
+    let mut bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.expose())
+        .map_err(|_| TwoPasswordError::agent("Malformed key in agent message"))?;
+    let key = <[u8; 32]>::try_from(bytes.as_slice())
+        .map(MasterKey::from_bytes)
+        .map_err(|_| TwoPasswordError::agent("Malformed key in agent message"));
+    bytes.zeroize();
+    key
+}
+
+/// Get the agent socket path
+///
+/// Uses `$TWOPASSWORD_AGENT_SOCK` if set, then a directory under
+/// `$XDG_RUNTIME_DIR`, then a per-user directory in the system temporary
+/// directory.
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(SOCKET_ENV) {
+        return PathBuf::from(path);
+    }
+
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("twopassword"),
+        None => std::env::temp_dir().join(format!("twopassword-{}", current_uid())),
+    };
+    dir.join(SOCKET_FILE_NAME)
+}
+
+/// Create the directory holding the socket, or check an existing one
+///
+/// The directory must belong to the current user and be inaccessible to
+/// anyone else, since whoever can reach the socket can read the vault.
+pub(crate) fn prepare_socket_dir(socket_path: &Path) -> Result<()> {
+    let dir = socket_path
+        .parent()
+        .ok_or_else(|| TwoPasswordError::agent("Socket path has no parent directory"))?;
+
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| {
+                TwoPasswordError::agent(format!("Failed to create {}: {}", dir.display(), e))
+            })?;
+        // The mode passed to mkdir is still subject to the umask
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+
+    let metadata = std::fs::metadata(dir)?;
+    if metadata.uid() != current_uid() {
+        return Err(TwoPasswordError::agent(format!(
+            "{} belongs to another user",
+            dir.display()
+        )));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(TwoPasswordError::agent(format!(
+            "{} is accessible to other users",
+            dir.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Get the effective user ID of this process
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_are_single_json_lines() {
+        let request = Request::Unlock {
+            vault_path: PathBuf::from("/tmp/vault.enc"),
+            password: SecretString::from("correct horse"),
+        };
+        let line = serde_json::to_string(&request).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(line.contains("\"type\":\"unlock\""));
+
+        let Request::Unlock { password, .. } = serde_json::from_str(&line).unwrap() else {
+            panic!("expected an unlock request");
+        };
+        assert_eq!(password.expose(), "correct horse");
+
+        // The password is redacted from debug output
+        assert!(!format!("{:?}", request).contains("correct horse"));
+    }
+
+    #[test]
+    fn test_socket_dir_is_private() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let socket = temp_dir.path().join("agent").join(SOCKET_FILE_NAME);
+
+        prepare_socket_dir(&socket).unwrap();
+        let mode = std::fs::metadata(socket.parent().unwrap()).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+}
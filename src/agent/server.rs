@@ -0,0 +1,263 @@
+//! Agent process holding the unlocked vault
+
+use crate::agent::{
+    current_uid, decode_key, encode_key, prepare_socket_dir, Request, Response, VaultContents,
+    MAX_MESSAGE_SIZE,
+};
+use crate::crypto::SecretString;
+use crate::storage::{Vault, VaultManager};
+use crate::{Result, TwoPasswordError};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::Instant;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+/// Time allowed for a client to send its request and read the response
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the agent keeps running after a request
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// The agent: an unlocked vault and the time it locks
+pub struct Agent {
+    vault_manager: VaultManager,
+    vault_path: Option<PathBuf>,
+    idle_timeout: Duration,
+    deadline: Instant,
+}
+
+impl Agent {
+    /// Create an agent with no vault unlocked
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            vault_manager: VaultManager::new(),
+            vault_path: None,
+            idle_timeout,
+            deadline: Instant::now() + idle_timeout,
+        }
+    }
+
+    /// Listen on `socket_path` until locked or idle for the timeout
+    ///
+    /// The socket is removed and the key cleared before returning.
+    pub async fn serve(mut self, socket_path: &Path) -> Result<()> {
+        let listener = bind(socket_path)?;
+        tracing::info!("Unlock agent listening on {}", socket_path.display());
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    match tokio::time::timeout(CONNECTION_TIMEOUT, self.handle(stream)).await {
+                        Ok(Ok(Flow::Continue)) => {}
+                        Ok(Ok(Flow::Exit)) => break,
+                        Ok(Err(e)) => tracing::warn!("Agent request failed: {}", e),
+                        Err(_) => tracing::warn!("Agent client timed out"),
+                    }
+                }
+                _ = tokio::time::sleep_until(self.deadline) => {
+                    tracing::info!("Unlock agent idle for {:?}, locking", self.idle_timeout);
+                    break;
+                }
+            }
+        }
+
+        self.vault_manager.close_vault();
+        std::fs::remove_file(socket_path).ok();
+        Ok(())
+    }
+
+    /// Answer one connection
+    async fn handle(&mut self, stream: UnixStream) -> Result<Flow> {
+        let peer = stream.peer_cred()?;
+        if peer.uid() != current_uid() {
+            return Err(TwoPasswordError::agent(format!(
+                "Rejected connection from user {}",
+                peer.uid()
+            )));
+        }
+
+        let (reader, mut writer) = stream.into_split();
+        let mut line = Zeroizing::new(String::new());
+        BufReader::new(reader)
+            .take(MAX_MESSAGE_SIZE)
+            .read_line(&mut line)
+            .await?;
+
+        let (response, flow) = match serde_json::from_str(&line) {
+            Ok(request) => self.respond(request),
+            Err(e) => (
+                Response::Error {
+                    message: format!("Malformed request: {}", e),
+                },
+                Flow::Continue,
+            ),
+        };
+
+        let mut reply = Zeroizing::new(serde_json::to_string(&response)?);
+        reply.push('\n');
+        writer.write_all(reply.as_bytes()).await?;
+        writer.shutdown().await?;
+        Ok(flow)
+    }
+
+    /// Carry out a request
+    fn respond(&mut self, request: Request) -> (Response, Flow) {
+        let response = match request {
+            Request::Unlock {
+                vault_path,
+                password,
+            } => self.unlock(vault_path, &password),
+            Request::Open { vault_path } => self.open(&vault_path),
+            Request::Save {
+                vault_path,
+                contents,
+            } => self.save(&vault_path, *contents),
+            Request::WrapFileKey {
+                vault_path,
+                file_key,
+            } => self.wrap_file_key(&vault_path, &file_key),
+            Request::UnwrapFileKey {
+                vault_path,
+                attachment_id,
+            } => self.unwrap_file_key(&vault_path, &attachment_id),
+            Request::Status => Ok(Response::Status {
+                vault_path: self.vault_path.clone(),
+                idle_timeout_secs: self.idle_timeout.as_secs(),
+                locks_in_secs: self
+                    .deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_secs(),
+            }),
+            Request::Lock => {
+                self.vault_manager.close_vault();
+                self.vault_path = None;
+                tracing::info!("Unlock agent locked");
+                Ok(Response::Ok)
+            }
+        };
+
+        let response = response.unwrap_or_else(|e| Response::Error {
+            message: e.to_string(),
+        });
+
+        // A locked agent has nothing left to serve
+        if self.vault_manager.is_vault_loaded() {
+            (response, Flow::Continue)
+        } else {
+            (response, Flow::Exit)
+        }
+    }
+
+    fn unlock(&mut self, vault_path: PathBuf, password: &SecretString) -> Result<Response> {
+        // The vault already held is only replaced once the new one opens, so
+        // a mistyped password leaves it unlocked
+        let mut vault_manager = VaultManager::new();
+        vault_manager.load_vault(&vault_path, password.expose())?;
+        self.vault_manager.close_vault();
+        self.vault_manager = vault_manager;
+
+        tracing::info!("Unlock agent holds {}", vault_path.display());
+        self.vault_path = Some(vault_path);
+        self.deadline = Instant::now() + self.idle_timeout;
+        Ok(Response::Ok)
+    }
+
+    fn open(&mut self, vault_path: &Path) -> Result<Response> {
+        let contents = Box::new(VaultContents::of(self.reload(vault_path)?));
+        let kdf = self
+            .vault_manager
+            .kdf_params()
+            .copied()
+            .ok_or_else(|| TwoPasswordError::agent("No vault is unlocked"))?;
+        Ok(Response::Contents { contents, kdf })
+    }
+
+    fn save(&mut self, vault_path: &Path, contents: VaultContents) -> Result<Response> {
+        // Reloading first keeps key slots changed by other processes, such as
+        // a new master password
+        let vault = self.reload(vault_path)?;
+        contents.apply_to(vault);
+        vault.is_modified = true;
+        self.vault_manager.save_vault()?;
+        Ok(Response::Ok)
+    }
+
+    fn wrap_file_key(&mut self, vault_path: &Path, file_key: &SecretString) -> Result<Response> {
+        self.check_path(vault_path)?;
+        self.deadline = Instant::now() + self.idle_timeout;
+        let wrapped_key = self.vault_manager.wrap_file_key(&decode_key(file_key)?)?;
+        Ok(Response::WrappedFileKey { wrapped_key })
+    }
+
+    /// Only keys of attachments in the saved vault are unwrapped
+    fn unwrap_file_key(&mut self, vault_path: &Path, attachment_id: &Uuid) -> Result<Response> {
+        let vault = self.reload(vault_path)?;
+        let found = vault
+            .attachments()
+            .map(|(_, attachment)| attachment)
+            .find(|attachment| attachment.id == *attachment_id)
+            .cloned()
+            .ok_or_else(|| TwoPasswordError::agent("No such attachment in the vault"))?;
+        let file_key = self.vault_manager.unwrap_file_key(&found)?;
+        Ok(Response::FileKey {
+            file_key: encode_key(&file_key),
+        })
+    }
+
+    /// Reopen the unlocked vault, which must be `vault_path`, from its file
+    ///
+    /// Another process may have rewritten the file since it was unlocked.
+    fn reload(&mut self, vault_path: &Path) -> Result<&mut Vault> {
+        self.check_path(vault_path)?;
+        self.vault_manager.reload_vault()?;
+        self.deadline = Instant::now() + self.idle_timeout;
+        self.vault_manager
+            .get_vault_mut()
+            .ok_or_else(|| TwoPasswordError::agent("No vault is unlocked"))
+    }
+
+    fn check_path(&self, vault_path: &Path) -> Result<()> {
+        let unlocked_path = self
+            .vault_path
+            .as_deref()
+            .ok_or_else(|| TwoPasswordError::agent("No vault is unlocked"))?;
+        if unlocked_path != vault_path {
+            return Err(TwoPasswordError::agent(format!(
+                "The agent has {} unlocked, not {}",
+                unlocked_path.display(),
+                vault_path.display()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Bind the agent socket, replacing a stale one left by an agent that died
+fn bind(socket_path: &Path) -> Result<UnixListener> {
+    prepare_socket_dir(socket_path)?;
+
+    if socket_path.exists() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Err(TwoPasswordError::agent(format!(
+                "An agent is already listening on {}",
+                socket_path.display()
+            )));
+        }
+        std::fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path).map_err(|e| {
+        TwoPasswordError::agent(format!("Failed to bind {}: {}", socket_path.display(), e))
+    })?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
@@ -2,7 +2,7 @@
 //!
 //! Provides Touch ID integration and fallback password authentication
 
-use crate::{Result, TwoPasswordError};
+use crate::Result;
 
 #[cfg(target_os = "macos")]
 pub mod touchid;
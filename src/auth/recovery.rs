@@ -2,12 +2,18 @@
 //!
 //! This module implements the recovery system where users need any 2 of:
 //! 1. Simple password
-//! 2. Touch ID/Passkey authentication  
+//! 2. Touch ID/Passkey authentication
 //! 3. iCloud backup share
+//!
+//! The master secret is split with Shamir's Secret Sharing. The password share
+//! is stored sealed under a key derived from the password, the Touch ID share
+//! is kept in device storage gated by biometric authentication, and the backup
+//! share is stored in iCloud. No single share reveals the master secret.
 
+use crate::auth::{AuthManager, AuthResult};
+use crate::crypto::secret_sharing::{SecretShare, SecretSharing, SharingScheme};
+use crate::crypto::{aes_gcm, key_derivation, EncryptedData, MasterKey, Salt};
 use crate::{Result, TwoPasswordError};
-use crate::crypto::secret_sharing::{SecretSharing, SecretShare};
-use crate::crypto::{Salt, MasterKey};
 
 /// Recovery method types
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -19,124 +25,138 @@ pub enum RecoveryMethod {
 
 /// Recovery manager for 2-of-3 system
 pub struct RecoveryManager {
+    auth_manager: AuthManager,
     available_methods: Vec<RecoveryMethod>,
 }
 
 impl RecoveryManager {
     /// Create a new recovery manager
     pub fn new() -> Self {
+        let auth_manager = AuthManager::new();
         let mut available_methods = vec![RecoveryMethod::Password];
-        
+
         // Check if Touch ID is available
-        if crate::auth::touchid::is_available() {
+        if auth_manager.is_touch_id_available() {
             available_methods.push(RecoveryMethod::TouchId);
         }
-        
+
         // For now, assume iCloud backup is always available
         // In a full implementation, this would check iCloud status
         available_methods.push(RecoveryMethod::ICloudBackup);
-        
-        Self { available_methods }
+
+        Self {
+            auth_manager,
+            available_methods,
+        }
     }
-    
+
     /// Get available recovery methods
     pub fn available_methods(&self) -> &[RecoveryMethod] {
         &self.available_methods
     }
-    
+
     /// Check if recovery is possible (need at least 2 methods)
     pub fn can_recover(&self) -> bool {
         self.available_methods.len() >= 2
     }
-    
+
     /// Initialize master key setup with 2-of-3 recovery
-    pub fn setup_master_key(&self, password: &str, user_id: &str, device_id: &str) -> Result<RecoverySetup> {
+    pub fn setup_master_key(&self, password: &str) -> Result<RecoverySetup> {
         // Generate the actual master secret
         let master_secret = crate::crypto::secure_random::generate_bytes(32)?;
-        let master_secret_array: [u8; 32] = master_secret.try_into()
+        let master_secret_array: [u8; 32] = master_secret
+            .try_into()
             .map_err(|_| TwoPasswordError::crypto("Invalid master secret length"))?;
-            
-        // Split into 3 shares
-        let shares = SecretSharing::split_secret(&master_secret_array)?;
-        
-        // Generate salt for password-based key derivation
+
+        // Split into 3 shares, any 2 of which reconstruct the secret
+        let shares = SecretSharing::split(&master_secret_array, SharingScheme::TWO_OF_THREE)?;
+        let [password_share, touchid_share, backup_share]: [SecretShare; 3] = shares
+            .try_into()
+            .map_err(|_| TwoPasswordError::crypto("Unexpected number of shares"))?;
+
+        // Seal share 1 with a key derived from the password
         let password_salt = Salt::generate()?;
-        
-        // Create the recovery setup
-        let setup = RecoverySetup {
+        let sealed_password_share = seal_password_share(&password_share, password, &password_salt)?;
+
+        Ok(RecoverySetup {
             master_secret: master_secret_array,
-            password_salt: password_salt.clone(),
-            password_share: shares[0].clone(), // Share 1: from password
-            touchid_share: shares[1].clone(),  // Share 2: from Touch ID
-            backup_share: shares[2].clone(),   // Share 3: for iCloud backup
-        };
-        
-        Ok(setup)
-    }
-    
+            password_salt,
+            sealed_password_share,
+            touchid_share,
+            backup_share,
+        })
+    }
+
     /// Recover master key using password and Touch ID
     pub async fn recover_with_password_and_touchid(
         &self,
         password: &str,
-        user_id: &str,
-        device_id: &str,
         password_salt: &Salt,
+        sealed_password_share: &EncryptedData,
+        touchid_share: &SecretShare,
     ) -> Result<MasterKey> {
         // Authenticate with Touch ID first
-        crate::auth::touchid::authenticate("Authenticate to recover your passwords")?;
-        
-        // Derive password share
-        let password_share = SecretSharing::derive_password_share(password, &password_salt.bytes)?;
-        
-        // Derive Touch ID share
-        let touchid_share = SecretSharing::derive_touchid_share(user_id, device_id)?;
-        
+        self.authenticate_touch_id()?;
+
+        // Unseal password share
+        let password_share = open_password_share(sealed_password_share, password, password_salt)?;
+
         // Reconstruct master secret
-        let master_secret = SecretSharing::reconstruct_secret(&password_share, &touchid_share)?;
-        
+        let master_secret = SecretSharing::reconstruct_secret(&password_share, touchid_share)?;
+
         Ok(MasterKey::from_bytes(master_secret))
     }
-    
+
     /// Recover master key using password and iCloud backup
     pub fn recover_with_password_and_backup(
         &self,
         password: &str,
         password_salt: &Salt,
+        sealed_password_share: &EncryptedData,
         backup_data: &[u8],
     ) -> Result<MasterKey> {
-        // Derive password share
-        let password_share = SecretSharing::derive_password_share(password, &password_salt.bytes)?;
-        
+        // Unseal password share
+        let password_share = open_password_share(sealed_password_share, password, password_salt)?;
+
         // Restore backup share
         let backup_share = SecretSharing::restore_backup_share(backup_data)?;
-        
+
         // Reconstruct master secret
         let master_secret = SecretSharing::reconstruct_secret(&password_share, &backup_share)?;
-        
+
         Ok(MasterKey::from_bytes(master_secret))
     }
-    
+
     /// Recover master key using Touch ID and iCloud backup
     pub async fn recover_with_touchid_and_backup(
         &self,
-        user_id: &str,
-        device_id: &str,
+        touchid_share: &SecretShare,
         backup_data: &[u8],
     ) -> Result<MasterKey> {
         // Authenticate with Touch ID first
-        crate::auth::touchid::authenticate("Authenticate to recover your passwords")?;
-        
-        // Derive Touch ID share
-        let touchid_share = SecretSharing::derive_touchid_share(user_id, device_id)?;
-        
+        self.authenticate_touch_id()?;
+
         // Restore backup share
         let backup_share = SecretSharing::restore_backup_share(backup_data)?;
-        
+
         // Reconstruct master secret
-        let master_secret = SecretSharing::reconstruct_secret(&touchid_share, &backup_share)?;
-        
+        let master_secret = SecretSharing::reconstruct_secret(touchid_share, &backup_share)?;
+
         Ok(MasterKey::from_bytes(master_secret))
     }
+
+    fn authenticate_touch_id(&self) -> Result<()> {
+        match self
+            .auth_manager
+            .authenticate_touch_id("Authenticate to recover your passwords")?
+        {
+            AuthResult::TouchIdSuccess => Ok(()),
+            AuthResult::Failed(reason) => Err(TwoPasswordError::touch_id(reason)),
+            AuthResult::PasswordSuccess => Err(TwoPasswordError::touch_id(
+                "Unexpected authentication result",
+            )),
+        }
+    }
 }
 
 impl Default for RecoveryManager {
@@ -146,77 +166,144 @@ impl Default for RecoveryManager {
 }
 
 /// Recovery setup containing all necessary components
-#[derive(Debug)]
 pub struct RecoverySetup {
     /// The master secret (32 bytes)
     pub master_secret: [u8; 32],
     /// Salt for password-based key derivation
     pub password_salt: Salt,
-    /// Share 1: derived from password
-    pub password_share: SecretShare,
-    /// Share 2: derived from Touch ID
+    /// Share 1: sealed with a key derived from the password
+    pub sealed_password_share: EncryptedData,
+    /// Share 2: kept in Touch ID protected device storage
     pub touchid_share: SecretShare,
     /// Share 3: for iCloud backup
     pub backup_share: SecretShare,
 }
 
+impl std::fmt::Debug for RecoverySetup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecoverySetup")
+            .field("master_secret", &"[REDACTED]")
+            .field("password_salt", &self.password_salt)
+            .field("sealed_password_share", &self.sealed_password_share)
+            .field("touchid_share", &self.touchid_share)
+            .field("backup_share", &self.backup_share)
+            .finish()
+    }
+}
+
 impl RecoverySetup {
     /// Get the master key
     pub fn master_key(&self) -> MasterKey {
         MasterKey::from_bytes(self.master_secret)
     }
-    
+
     /// Get backup data for iCloud storage
     pub fn backup_data(&self) -> Result<Vec<u8>> {
         SecretSharing::create_backup_share(self.backup_share.clone())
     }
 }
 
+/// Encrypt the password share under a key derived from the password
+fn seal_password_share(share: &SecretShare, password: &str, salt: &Salt) -> Result<EncryptedData> {
+    let key = key_derivation::derive_key(password, &salt.bytes)?;
+    let share_data = serde_json::to_vec(share)?;
+    aes_gcm::encrypt(&key, &share_data)
+}
+
+/// Decrypt the password share, failing with `InvalidMasterPassword` on a wrong
+/// password
+fn open_password_share(sealed: &EncryptedData, password: &str, salt: &Salt) -> Result<SecretShare> {
+    let key = key_derivation::derive_key(password, &salt.bytes)?;
+    let share_data =
+        aes_gcm::decrypt(&key, sealed).map_err(|_| TwoPasswordError::InvalidMasterPassword)?;
+    let share: SecretShare = serde_json::from_slice(&share_data)?;
+    share.verify()?;
+    Ok(share)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_recovery_manager_creation() {
         let manager = RecoveryManager::new();
         assert!(!manager.available_methods.is_empty());
-        assert!(manager.available_methods.contains(&RecoveryMethod::Password));
+        assert!(manager
+            .available_methods
+            .contains(&RecoveryMethod::Password));
     }
-    
+
     #[test]
     fn test_master_key_setup() {
         let manager = RecoveryManager::new();
-        let setup = manager.setup_master_key("test_password", "user123", "device456").unwrap();
-        
+        let setup = manager.setup_master_key("test_password").unwrap();
+
         assert_eq!(setup.master_secret.len(), 32);
-        assert_eq!(setup.password_share.id, 1);
         assert_eq!(setup.touchid_share.id, 2);
         assert_eq!(setup.backup_share.id, 3);
+
+        // Neither stored share is the master secret itself
+        assert_ne!(setup.touchid_share.value, setup.master_secret.to_vec());
+        assert_ne!(setup.backup_share.value, setup.master_secret.to_vec());
+
+        let password_share = open_password_share(
+            &setup.sealed_password_share,
+            "test_password",
+            &setup.password_salt,
+        )
+        .unwrap();
+        assert_eq!(password_share.id, 1);
     }
-    
+
     #[test]
     fn test_password_and_backup_recovery() {
         let manager = RecoveryManager::new();
-        let setup = manager.setup_master_key("test_password", "user123", "device456").unwrap();
-        
+        let setup = manager.setup_master_key("test_password").unwrap();
+
         // Create backup data
         let backup_data = setup.backup_data().unwrap();
-        
+
         // Recover using password and backup
-        let recovered_key = manager.recover_with_password_and_backup(
-            "test_password",
-            &setup.password_salt,
-            &backup_data,
-        ).unwrap();
-        
+        let recovered_key = manager
+            .recover_with_password_and_backup(
+                "test_password",
+                &setup.password_salt,
+                &setup.sealed_password_share,
+                &backup_data,
+            )
+            .unwrap();
+
         // Should match original
         assert_eq!(recovered_key.as_bytes(), &setup.master_secret);
+
+        // Wrong password must not recover anything
+        let result = manager.recover_with_password_and_backup(
+            "wrong_password",
+            &setup.password_salt,
+            &setup.sealed_password_share,
+            &backup_data,
+        );
+        assert!(matches!(
+            result,
+            Err(TwoPasswordError::InvalidMasterPassword)
+        ));
     }
-    
+
+    #[test]
+    fn test_backup_alone_is_not_enough() {
+        let manager = RecoveryManager::new();
+        let setup = manager.setup_master_key("test_password").unwrap();
+
+        let backup_data = setup.backup_data().unwrap();
+        let backup_share = SecretSharing::restore_backup_share(&backup_data).unwrap();
+        assert!(SecretSharing::combine(&[backup_share]).is_err());
+    }
+
     #[test]
     fn test_can_recover() {
         let manager = RecoveryManager::new();
         // Should be able to recover if we have at least 2 methods available
         assert!(manager.can_recover() || manager.available_methods.len() < 2);
     }
-}
\ No newline at end of file
+}
@@ -1,71 +1,167 @@
 //! CLI command implementations
 
+use crate::crypto::SecretString;
 use crate::Result;
+use std::io::{self, Write};
 
-// Placeholder implementations for all CLI commands
-// These will be implemented in detail in future tasks
+/// Prompt for a password without echoing it
+fn read_secret(prompt: &str) -> Result<SecretString> {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    rpassword::read_password()
+        .map(SecretString::from)
+        .map_err(|e| crate::TwoPasswordError::storage(format!("Failed to read password: {}", e)))
+}
+
+/// Ask a yes/no question, defaulting to no
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} (y/N): ", prompt);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+/// Turn `--field` arguments into custom fields, prompting for the values
+/// that were left out; concealed values are read without echo
+fn read_fields(args: Vec<crate::cli::FieldArg>) -> Result<Vec<crate::storage::field::CustomField>> {
+    args.into_iter()
+        .map(|arg| {
+            let prompt = format!("{} ({}): ", arg.name, arg.field_type.name());
+            let value = match arg.value {
+                Some(value) => SecretString::from(value),
+                None if arg.field_type.is_concealed() => read_secret(&prompt)?,
+                None => {
+                    print!("{}", prompt);
+                    io::stdout().flush().unwrap();
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+                    SecretString::from(input.trim_end_matches(['\r', '\n']))
+                }
+            };
+            Ok(crate::storage::field::CustomField::new(
+                &arg.name,
+                arg.field_type,
+                value,
+            ))
+        })
+        .collect()
+}
+
+/// Check that a vault is unlocked, telling the user otherwise
+fn is_unlocked(vault_manager: &crate::storage::VaultManager) -> bool {
+    if !vault_manager.is_vault_loaded() {
+        println!("❌ No vault is currently unlocked.");
+        println!("💡 Use 'twopassword unlock' first.");
+        return false;
+    }
+    true
+}
+
+/// Find the entry an identifier refers to, asking the user to choose when
+/// it only matches entries approximately
+///
+/// Returns `None`, after telling the user why, if nothing was chosen.
+fn select_entry(
+    entries: &[crate::storage::PasswordEntry],
+    identifier: &str,
+) -> Result<Option<uuid::Uuid>> {
+    use crate::storage::entry::{EntryManager, Resolution};
+
+    let candidates = match EntryManager::resolve(entries, identifier) {
+        Resolution::Found(entry) => return Ok(Some(entry.id)),
+        Resolution::Candidates(candidates) => candidates,
+        Resolution::NotFound => {
+            println!("❌ No entries found matching '{}'", identifier);
+            return Ok(None);
+        }
+    };
+
+    println!("🔍 No exact match for '{}'. Did you mean:", identifier);
+    for (i, entry) in candidates.iter().enumerate() {
+        println!(
+            "{}. {} ({}) [{}]",
+            i + 1,
+            entry.title,
+            entry.username,
+            entry.id
+        );
+    }
+    print!(
+        "Select an entry [1-{}], or press Enter to cancel: ",
+        candidates.len()
+    );
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    match input.trim().parse::<usize>() {
+        Ok(choice) if (1..=candidates.len()).contains(&choice) => {
+            Ok(Some(candidates[choice - 1].id))
+        }
+        _ => {
+            println!("❌ No entry selected");
+            Ok(None)
+        }
+    }
+}
 
 pub mod init {
     use super::*;
     use crate::auth::AuthManager;
     use crate::storage::VaultManager;
     use std::path::Path;
-    use std::io::{self, Write};
 
     pub async fn run(
         vault_manager: &mut VaultManager,
         _auth_manager: &AuthManager,
         vault_path: &Path,
         _use_touch_id: bool,
+        hide_metadata: bool,
     ) -> Result<()> {
         println!("🚀 Initializing new TwoPassword vault...");
-        
+
         // Check if vault already exists
         if vault_path.exists() {
             println!("❌ Vault already exists at {}", vault_path.display());
             return Ok(());
         }
-        
+
         // Create parent directories if needed
         if let Some(parent) = vault_path.parent() {
             std::fs::create_dir_all(parent).map_err(|e| {
                 crate::TwoPasswordError::storage(format!("Failed to create directory: {}", e))
             })?;
         }
-        
+
         // Prompt for master password
-        print!("Enter master password: ");
-        io::stdout().flush().unwrap();
-        let password = rpassword::read_password().map_err(|e| {
-            crate::TwoPasswordError::storage(format!("Failed to read password: {}", e))
-        })?;
-        
-        if password.trim().is_empty() {
+        let password = read_secret("Enter master password: ")?;
+
+        if password.expose().trim().is_empty() {
             println!("❌ Password cannot be empty");
             return Ok(());
         }
-        
+
         // Validate password strength
-        crate::auth::password::validate_password_strength(&password)?;
-        
+        crate::auth::password::validate_password_strength(password.expose())?;
+
         // Confirm password
-        print!("Confirm master password: ");
-        io::stdout().flush().unwrap();
-        let confirm_password = rpassword::read_password().map_err(|e| {
-            crate::TwoPasswordError::storage(format!("Failed to read password: {}", e))
-        })?;
-        
+        let confirm_password = read_secret("Confirm master password: ")?;
+
         if password != confirm_password {
             println!("❌ Passwords do not match");
             return Ok(());
         }
-        
+
         // Create the vault
-        vault_manager.create_vault(vault_path, &password)?;
-        
+        vault_manager.create_vault(vault_path, password.expose())?;
+        if hide_metadata {
+            vault_manager.set_hide_metadata(true)?;
+        }
+
         println!("✅ Vault created successfully at {}", vault_path.display());
         println!("🔐 Your vault is now ready to store passwords securely.");
-        
+
         Ok(())
     }
 }
@@ -73,25 +169,25 @@ pub mod init {
 pub mod unlock {
     use super::*;
     use crate::auth::AuthManager;
-    use crate::storage::VaultManager;
     use std::path::Path;
-    use std::io::{self, Write};
+    use std::time::Duration;
 
     pub async fn run(
-        vault_manager: &mut VaultManager,
         auth_manager: &AuthManager,
         vault_path: &Path,
+        socket_path: &Path,
         use_touch_id: bool,
+        idle_timeout: u64,
     ) -> Result<()> {
         println!("🔓 Unlocking vault...");
-        
+
         // Check if vault exists
         if !vault_path.exists() {
             println!("❌ Vault not found at {}", vault_path.display());
             println!("💡 Use 'twopassword init' to create a new vault.");
             return Ok(());
         }
-        
+
         // Try Touch ID first if enabled and available
         if use_touch_id && auth_manager.is_touch_id_available() {
             println!("👆 Touch ID authentication requested...");
@@ -100,164 +196,282 @@ pub mod unlock {
                     println!("✅ Touch ID authentication successful!");
                     // In a full implementation, we would derive key from Touch ID
                     // For now, still prompt for password as fallback
-                },
+                }
                 Ok(crate::auth::AuthResult::Failed(reason)) => {
                     println!("❌ Touch ID failed: {}", reason);
                     println!("🔑 Falling back to password authentication...");
-                },
+                }
                 Ok(_) => {
                     println!("❌ Unexpected authentication result");
                     return Ok(());
-                },
+                }
                 Err(e) => {
                     println!("❌ Touch ID error: {}", e);
                     println!("🔑 Falling back to password authentication...");
-                },
+                }
             }
         }
-        
+
         // Prompt for master password
-        print!("Enter master password: ");
-        io::stdout().flush().unwrap();
-        let password = rpassword::read_password().map_err(|e| {
-            crate::TwoPasswordError::storage(format!("Failed to read password: {}", e))
-        })?;
-        
-        if password.trim().is_empty() {
+        let password = read_secret("Enter master password: ")?;
+
+        if password.expose().trim().is_empty() {
             println!("❌ Password cannot be empty");
             return Ok(());
         }
-        
-        // Try to load the vault
-        match vault_manager.load_vault(vault_path, &password) {
+
+        // Hand the password to the agent, which keeps the vault unlocked
+        let idle_timeout = Duration::from_secs(idle_timeout);
+        match crate::agent::client::unlock(socket_path, vault_path, password, idle_timeout).await {
             Ok(()) => {
                 println!("✅ Vault unlocked successfully!");
                 println!("🔐 You can now add, view, and manage your passwords.");
-            },
+                println!(
+                    "⏱️  The vault locks after {} minutes without use, or with 'twopassword lock'.",
+                    idle_timeout.as_secs() / 60
+                );
+            }
             Err(e) => {
                 println!("❌ Failed to unlock vault: {}", e);
                 return Ok(());
             }
         }
-        
+
         Ok(())
     }
 }
 
 pub mod add {
     use super::*;
-    use crate::storage::{VaultManager, PasswordEntry};
+    use crate::cli::{FieldArg, KindDetails};
     use crate::storage::entry::generate_password;
+    use crate::storage::kind::{ApiToken, Card, EntryKind, Identity, SshKey};
+    use crate::storage::{PasswordEntry, VaultManager};
     use std::io::{self, Write};
 
     pub async fn run(
         vault_manager: &mut VaultManager,
-        title: String,
-        username: String,
+        mut entry: PasswordEntry,
         password: Option<String>,
-        url: Option<String>,
-        notes: Option<String>,
+        fields: Vec<FieldArg>,
+        details: KindDetails,
     ) -> Result<()> {
         println!("➕ Adding new password entry...");
-        
-        // Check if vault is loaded
-        if !vault_manager.is_vault_loaded() {
-            println!("❌ No vault is currently unlocked.");
-            println!("💡 Use 'twopassword unlock' first.");
+
+        if !is_unlocked(vault_manager) {
             return Ok(());
         }
-        
-        // Get or generate password
-        let entry_password = if let Some(ref pwd) = password {
-            pwd.clone()
-        } else {
-            // Ask if user wants to generate a password
-            print!("Generate a secure password? (Y/n): ");
-            io::stdout().flush().unwrap();
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
-            let generate = input.trim().is_empty() || input.trim().to_lowercase().starts_with('y');
-            
-            if generate {
-                // Generate a secure password
-                generate_password(16, true, true, true, true)?
-            } else {
-                // Prompt for password
-                print!("Enter password: ");
+
+        entry.kind = match read_kind(&details)? {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+
+        // Get or generate the password, for the kinds that have one
+        let mut generated = false;
+        let entry_password = match (&entry.kind, password) {
+            (_, Some(_)) if entry.kind.password_label().is_none() => {
+                println!("❌ {} entries have no password", entry.kind.label());
+                return Ok(());
+            }
+            (_, Some(pwd)) => SecretString::from(pwd),
+            (EntryKind::Login, None) => {
+                // Ask if user wants to generate a password
+                print!("Generate a secure password? (Y/n): ");
                 io::stdout().flush().unwrap();
-                let pwd = rpassword::read_password().map_err(|e| {
-                    crate::TwoPasswordError::storage(format!("Failed to read password: {}", e))
-                })?;
-                
-                if pwd.trim().is_empty() {
-                    println!("❌ Password cannot be empty");
-                    return Ok(());
+                let mut input = String::new();
+                io::stdin().read_line(&mut input).unwrap();
+                generated = input.trim().is_empty() || input.trim().to_lowercase().starts_with('y');
+
+                if generated {
+                    // Generate a secure password
+                    SecretString::from(generate_password(16, true, true, true, true)?)
+                } else {
+                    // Prompt for password
+                    let pwd = read_secret("Enter password: ")?;
+
+                    if pwd.expose().trim().is_empty() {
+                        println!("❌ Password cannot be empty");
+                        return Ok(());
+                    }
+                    pwd
                 }
-                pwd
             }
+            (EntryKind::ApiToken(_), None) => read_secret("Enter token: ")?,
+            (EntryKind::SshKey(key), None) if key.info()?.encrypted => {
+                read_secret("Key passphrase (empty to leave it out): ")?
+            }
+            _ => SecretString::default(),
         };
-        
-        // Create the new entry
-        let mut entry = PasswordEntry::new(title, username, entry_password.clone());
-        entry.url = url;
-        entry.notes = notes;
-        
+        entry.password = entry_password.clone();
+        entry.fields = read_fields(fields)?;
+
         // Validate the entry
         crate::storage::entry::EntryManager::validate_entry(&entry)?;
-        
+
         // Add to vault
         if let Some(vault) = vault_manager.get_vault_mut() {
+            let label = entry.kind.label();
             vault.add_entry(entry);
-            
+
             // Save the vault
             vault_manager.save_vault()?;
-            
-            println!("✅ Password entry added successfully!");
-            if password.is_none() {
-                println!("🔑 Generated password: {}", entry_password);
+
+            match details.kind.as_str() {
+                "login" => println!("✅ Password entry added successfully!"),
+                _ => println!("✅ {} added successfully!", label),
+            }
+            if generated {
+                println!("🔑 Generated password: {}", entry_password.expose());
                 println!("💾 Password has been saved securely to your vault.");
             }
         }
-        
+
         Ok(())
     }
+
+    /// Build the entry kind from the command line, prompting for card
+    /// secrets; `None` if the user has been told what is missing
+    fn read_kind(details: &KindDetails) -> Result<Option<EntryKind>> {
+        let kind = match details.kind.as_str() {
+            "note" => EntryKind::SecureNote,
+            "card" => {
+                let expiry = details.expires.as_deref().map(str::parse).transpose()?;
+                let number = read_secret("Card number: ")?;
+                let security_code = read_secret("Security code (empty if none): ")?;
+                let card = Card::new(
+                    details.cardholder.clone(),
+                    number.expose(),
+                    expiry,
+                    Some(security_code),
+                )?;
+                EntryKind::Card(card)
+            }
+            "identity" => EntryKind::Identity(Identity {
+                full_name: details.full_name.clone(),
+                email: details.email.clone(),
+                phone: details.phone.clone(),
+                address: details.address.clone(),
+                birth_date: details.birth_date,
+            }),
+            "ssh-key" => {
+                let Some(path) = &details.private_key else {
+                    println!("❌ SSH key entries need the key file: --private-key <FILE>");
+                    return Ok(None);
+                };
+                let private_key = std::fs::read_to_string(path).map(SecretString::from)?;
+                let mut public_path = path.clone().into_os_string();
+                public_path.push(".pub");
+                let public_path = details.public_key.clone().unwrap_or(public_path.into());
+                let public_key = match public_path.exists() {
+                    true => Some(std::fs::read_to_string(&public_path)?),
+                    false if details.public_key.is_some() => {
+                        println!("❌ Public key file {} not found", public_path.display());
+                        return Ok(None);
+                    }
+                    false => None,
+                };
+                EntryKind::SshKey(SshKey::new(private_key, public_key)?)
+            }
+            "api-token" => EntryKind::ApiToken(ApiToken {
+                expires_on: match &details.expires {
+                    Some(date) => Some(
+                        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+                            crate::TwoPasswordError::validation(format!(
+                                "Invalid expiry date '{}' (expected YYYY-MM-DD)",
+                                date
+                            ))
+                        })?,
+                    ),
+                    None => None,
+                },
+            }),
+            _ => EntryKind::Login,
+        };
+        Ok(Some(kind))
+    }
 }
 
 pub mod get {
     use super::*;
-    use crate::storage::{VaultManager, PasswordEntry, entry::EntryManager};
+    use crate::storage::kind::EntryKind;
+    use crate::storage::query::Query;
+    use crate::storage::{entry::EntryManager, PasswordEntry, VaultManager};
     use std::io::Write;
 
     pub async fn run(vault_manager: &VaultManager, query: String) -> Result<()> {
         println!("🔍 Searching for password entry...");
-        
-        // Check if vault is loaded
-        if !vault_manager.is_vault_loaded() {
-            println!("❌ No vault is currently unlocked.");
-            println!("💡 Use 'twopassword unlock' first.");
+
+        if !is_unlocked(vault_manager) {
             return Ok(());
         }
-        
+
+        let parsed: Query = match query.parse() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+        };
+
         if let Some(vault) = vault_manager.get_vault() {
-            let entries_vec: Vec<_> = vault.entries.values().collect();
             let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
-            
+
             // Search for entries matching the query
-            let matches = EntryManager::fuzzy_search(&entries, &query);
-            
+            let matches = EntryManager::search(&entries, &parsed);
+
             if matches.is_empty() {
                 println!("❌ No entries found matching '{}'", query);
                 return Ok(());
             }
-            
+
             // Display the results
             if matches.len() == 1 {
                 let entry = matches[0];
                 println!("✅ Found matching entry:");
                 println!();
                 println!("Title:    {}", entry.title);
-                println!("Username: {}", entry.username);
-                println!("Password: {}", "*".repeat(entry.password.len()));
+                if entry.kind != EntryKind::Login {
+                    println!("Kind:     {}", entry.kind.label());
+                }
+                if entry.kind == EntryKind::Login || !entry.username.is_empty() {
+                    println!("Username: {}", entry.username);
+                }
+
+                // Secrets are masked until the user asks to see them
+                let mut secrets: Vec<(&str, &SecretString)> = Vec::new();
+                if let Some(label) = entry.kind.password_label() {
+                    if entry.kind == EntryKind::Login || !entry.password.is_empty() {
+                        secrets.push((label, &entry.password));
+                    }
+                }
+                secrets.extend(entry.kind.secrets());
+                for (label, secret) in &secrets {
+                    let masked = match &entry.kind {
+                        EntryKind::Card(card) if *label == "Card number" => card.masked_number(),
+                        _ if secret.expose().contains('\n') => "(hidden)".to_string(),
+                        _ => "*".repeat(secret.len()),
+                    };
+                    println!("{:<9} {}", format!("{}:", label), masked);
+                }
+                for (label, value) in entry.kind.fields(chrono::Local::now().date_naive()) {
+                    println!("{:<9} {}", format!("{}:", label), value);
+                }
+                // Concealed custom fields are masked and revealed like the password
+                for custom in &entry.fields {
+                    println!(
+                        "{:<9} {}",
+                        format!("{}:", custom.name),
+                        custom.display_value()
+                    );
+                }
+                secrets.extend(
+                    entry
+                        .fields
+                        .iter()
+                        .filter(|custom| custom.field_type.is_concealed())
+                        .map(|custom| (custom.name.as_str(), &custom.value)),
+                );
                 if let Some(ref url) = entry.url {
                     println!("URL:      {}", url);
                 }
@@ -267,16 +481,49 @@ pub mod get {
                 if !entry.tags.is_empty() {
                     println!("Tags:     {}", entry.tags.join(", "));
                 }
-                println!("Created:  {}", entry.created_at.format("%Y-%m-%d %H:%M:%S UTC"));
-                println!("Updated:  {}", entry.updated_at.format("%Y-%m-%d %H:%M:%S UTC"));
-                
-                // Ask if user wants to copy password to clipboard
-                print!("\nReveal password? (y/N): ");
+                if let Some(ref otp) = entry.otp {
+                    println!("OTP:      {}", otp);
+                }
+                if !entry.history.is_empty() {
+                    println!("History:  {} previous passwords", entry.history.len());
+                }
+                for (i, attachment) in entry.attachments.iter().enumerate() {
+                    let label = if i == 0 { "Files:" } else { "" };
+                    println!(
+                        "{:<9} {} ({})",
+                        label,
+                        attachment.name,
+                        crate::storage::attachment::format_size(attachment.size)
+                    );
+                }
+                println!(
+                    "Created:  {}",
+                    entry.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+                println!(
+                    "Updated:  {}",
+                    entry.updated_at.format("%Y-%m-%d %H:%M:%S UTC")
+                );
+
+                if secrets.is_empty() {
+                    return Ok(());
+                }
+                let what = match secrets.as_slice() {
+                    [(label, _)] => label.to_lowercase(),
+                    _ => "secrets".to_string(),
+                };
+                print!("\nReveal {}? (y/N): ", what);
                 std::io::stdout().flush().unwrap();
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
                 if input.trim().to_lowercase().starts_with('y') {
-                    println!("Password: {}", entry.password);
+                    for (label, secret) in &secrets {
+                        if secret.expose().contains('\n') {
+                            println!("{}:\n{}", label, secret.expose().trim_end());
+                        } else {
+                            println!("{}: {}", label, secret.expose());
+                        }
+                    }
                 }
             } else {
                 println!("✅ Found {} matching entries:", matches.len());
@@ -289,55 +536,94 @@ pub mod get {
                 }
             }
         }
-        
+
         Ok(())
     }
 }
 
 pub mod list {
     use super::*;
-    use crate::storage::{VaultManager, PasswordEntry, entry::EntryManager};
+    use crate::storage::kind::EntryKind;
+    use crate::storage::query::Query;
+    use crate::storage::tag::TagNode;
+    use crate::storage::{entry::EntryManager, PasswordEntry, VaultManager};
 
-    pub async fn run(vault_manager: &VaultManager, tag: Option<String>) -> Result<()> {
+    pub async fn run(
+        vault_manager: &VaultManager,
+        query: String,
+        tag: Option<String>,
+        tree: bool,
+    ) -> Result<()> {
         println!("📋 Listing password entries...");
-        
-        // Check if vault is loaded
-        if !vault_manager.is_vault_loaded() {
-            println!("❌ No vault is currently unlocked.");
-            println!("💡 Use 'twopassword unlock' first.");
+
+        if !is_unlocked(vault_manager) {
             return Ok(());
         }
-        
+
+        let parsed: Query = match query.parse() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+        };
+
         if let Some(vault) = vault_manager.get_vault() {
             let all_entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
-            
-            // Filter by tag if specified
-            let entries = if let Some(ref tag_filter) = tag {
-                EntryManager::find_by_tag(&all_entries, tag_filter)
-            } else {
-                all_entries.iter().collect()
-            };
-            
+            if all_entries.is_empty() {
+                println!("📭 Your vault is empty. Add some passwords with 'twopassword add'.");
+                return Ok(());
+            }
+
+            // Filter by query, then by tag if specified
+            let mut entries = EntryManager::search(&all_entries, &parsed);
+            if let Some(ref tag_filter) = tag {
+                let tagged = EntryManager::find_by_tag(&all_entries, tag_filter);
+                entries.retain(|entry| tagged.iter().any(|t| t.id == entry.id));
+            }
+
             if entries.is_empty() {
-                if tag.is_some() {
-                    println!("❌ No entries found with tag '{}'", tag.unwrap());
-                } else {
-                    println!("📭 Your vault is empty. Add some passwords with 'twopassword add'.");
+                match (&tag, query.trim().is_empty()) {
+                    (Some(tag_filter), true) => {
+                        println!("❌ No entries found with tag '{}'", tag_filter)
+                    }
+                    _ => println!("❌ No entries match '{}'", query.trim()),
+                }
+                return Ok(());
+            }
+
+            if tree {
+                let root = TagNode::build(entries.iter().copied());
+                match tag.as_deref().and_then(|tag_filter| root.find(tag_filter)) {
+                    Some(node) => {
+                        println!("📁 {} ({})", node.path, node.count());
+                        print_tree(node, 1);
+                    }
+                    None => print_tree(&root, 0),
                 }
                 return Ok(());
             }
-            
+
             // Display the entries
             if let Some(ref tag_filter) = tag {
-                println!("✅ Found {} entries with tag '{}':", entries.len(), tag_filter);
+                println!(
+                    "✅ Found {} entries with tag '{}':",
+                    entries.len(),
+                    tag_filter
+                );
             } else {
                 println!("✅ Found {} entries:", entries.len());
             }
             println!();
-            
+
             for (i, entry) in entries.iter().enumerate() {
                 println!("{}. {}", i + 1, entry.title);
-                println!("   Username: {}", entry.username);
+                if entry.kind != EntryKind::Login {
+                    println!("   Kind:     {}", entry.kind.label());
+                }
+                if entry.kind == EntryKind::Login || !entry.username.is_empty() {
+                    println!("   Username: {}", entry.username);
+                }
                 if let Some(ref url) = entry.url {
                     println!("   URL:      {}", url);
                 }
@@ -348,115 +634,1325 @@ pub mod list {
                 println!();
             }
         }
-        
+
         Ok(())
     }
+
+    /// Print the folders below a tag, then the entries tagged with it;
+    /// at the root, those are the untagged entries
+    fn print_tree(node: &TagNode, depth: usize) {
+        let indent = "   ".repeat(depth);
+        for child in node.children.values() {
+            println!("{}📁 {} ({})", indent, child.name, child.count());
+            print_tree(child, depth + 1);
+        }
+        for entry in &node.entries {
+            if entry.username.is_empty() {
+                println!("{}• {}", indent, entry.title);
+            } else {
+                println!("{}• {} ({})", indent, entry.title, entry.username);
+            }
+        }
+    }
 }
 
-pub mod update {
+pub mod tag {
     use super::*;
-    use crate::storage::VaultManager;
+    use crate::storage::tag::TagNode;
+    use crate::storage::{PasswordEntry, VaultManager};
 
-    pub async fn run(
-        _vault_manager: &mut VaultManager,
-        _identifier: String,
-        _title: Option<String>,
-        _username: Option<String>,
-        _password: Option<String>,
-        _url: Option<String>,
-        _notes: Option<String>,
-    ) -> Result<()> {
-        println!("✏️  Updating password entry...");
-        println!("This command will be implemented in the next phase.");
+    pub async fn list(vault_manager: &VaultManager) -> Result<()> {
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+
+        let entries: Vec<&PasswordEntry> = vault.entries.values().collect();
+        let root = TagNode::build(entries);
+        if root.children.is_empty() {
+            println!("🏷️  No entries are tagged yet.");
+            println!("💡 Tag entries with 'twopassword update <entry> --add-tag work/servers'.");
+            return Ok(());
+        }
+
+        println!("🏷️  Tags:");
+        print_folders(&root, 1);
+        if !root.entries.is_empty() {
+            println!("   ({} untagged entries)", root.entries.len());
+        }
         Ok(())
     }
-}
 
-pub mod remove {
-    use super::*;
-    use crate::storage::VaultManager;
+    fn print_folders(node: &TagNode, depth: usize) {
+        for child in node.children.values() {
+            println!("{}{} ({})", "   ".repeat(depth), child.name, child.count());
+            print_folders(child, depth + 1);
+        }
+    }
 
-    pub async fn run(
-        _vault_manager: &mut VaultManager,
-        _identifier: String,
-        _force: bool,
+    pub async fn rename(vault_manager: &mut VaultManager, from: String, to: String) -> Result<()> {
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+
+        match vault.rename_tag(&from, &to) {
+            Ok(count) => {
+                vault_manager.save_vault()?;
+                println!("✅ Renamed '{}' to '{}' on {} entries", from, to, count);
+            }
+            Err(crate::TwoPasswordError::ValidationError(message)) => println!("❌ {}", message),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    pub async fn move_to(
+        vault_manager: &mut VaultManager,
+        tag: String,
+        parent: Option<String>,
     ) -> Result<()> {
-        println!("🗑️  Removing password entry...");
-        println!("This command will be implemented in the next phase.");
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+
+        match vault.move_tag(&tag, parent.as_deref()) {
+            Ok(count) => {
+                vault_manager.save_vault()?;
+                match parent {
+                    Some(parent) => {
+                        println!("✅ Moved '{}' under '{}' on {} entries", tag, parent, count)
+                    }
+                    None => println!("✅ Moved '{}' to the top level on {} entries", tag, count),
+                }
+            }
+            Err(crate::TwoPasswordError::ValidationError(message)) => println!("❌ {}", message),
+            Err(e) => return Err(e),
+        }
         Ok(())
     }
 }
 
-pub mod generate {
+pub mod update {
     use super::*;
+    use crate::storage::entry::{EntryManager, EntryUpdate};
+    use crate::storage::{PasswordEntry, VaultManager};
 
     pub async fn run(
-        length: usize,
-        uppercase: bool,
-        lowercase: bool,
-        numbers: bool,
-        symbols: bool,
+        vault_manager: &mut VaultManager,
+        identifier: String,
+        mut update: EntryUpdate,
+        fields: Vec<crate::cli::FieldArg>,
     ) -> Result<()> {
-        let password = crate::storage::entry::generate_password(
-            length, uppercase, lowercase, numbers, symbols,
-        )?;
+        println!("✏️  Updating password entry...");
 
-        println!("🎲 Generated password: {}", password);
-        println!("💡 Tip: Use this password with 'twopassword add' to save it securely.");
-        Ok(())
-    }
-}
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
 
-pub mod status {
-    use super::*;
-    use crate::storage::VaultManager;
+        if update.is_empty() && fields.is_empty() {
+            println!("❌ Nothing to update");
+            println!(
+                "💡 Pass the fields to change, e.g. --username, --url, --otp, --add-tag or --field."
+            );
+            return Ok(());
+        }
 
-    pub async fn run(_vault_manager: &VaultManager) -> Result<()> {
-        println!("📊 Vault Status:");
-        println!("This command will be implemented in the next phase.");
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+        let Some(before) = vault.get_entry(&id).cloned() else {
+            return Ok(());
+        };
+        update.set_fields = read_fields(fields)?;
+
+        // Apply the changes to a copy, so nothing is modified unless it validates
+        let mut after = before.clone();
+        update.apply(&mut after);
+
+        let changes = EntryManager::diff(&before, &after);
+        if changes.is_empty() {
+            println!(
+                "💡 '{}' already has these values; nothing changed.",
+                before.title
+            );
+            return Ok(());
+        }
+
+        if let Err(e) = EntryManager::validate_entry(&after) {
+            println!("❌ {}", e);
+            return Ok(());
+        }
+        if let Some(vault) = vault_manager.get_vault_mut() {
+            vault.update_entry(after)?;
+        }
+        vault_manager.save_vault()?;
+
+        println!("✅ Updated '{}':", before.title);
+        for change in changes {
+            println!("   {}", change);
+        }
         Ok(())
     }
 }
 
-pub mod lock {
+pub mod totp {
     use super::*;
-    use crate::storage::VaultManager;
+    use crate::storage::{PasswordEntry, VaultManager};
 
-    pub async fn run(_vault_manager: &mut VaultManager) -> Result<()> {
-        println!("🔒 Locking vault...");
-        println!("This command will be implemented in the next phase.");
+    pub async fn run(vault_manager: &mut VaultManager, query: String) -> Result<()> {
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &query)? else {
+            return Ok(());
+        };
+        let Some(entry) = vault.get_entry(&id) else {
+            return Ok(());
+        };
+        let Some(otp) = entry.otp.clone() else {
+            println!("❌ '{}' has no one-time password", entry.title);
+            println!(
+                "💡 Add one with 'twopassword update \"{}\" --otp <otpauth URI or secret>'.",
+                entry.title
+            );
+            return Ok(());
+        };
+        let title = entry.title.clone();
+
+        let code = match otp.code(chrono::Utc::now()) {
+            Ok(code) => code,
+            Err(e) => {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+        };
+
+        // Each HOTP code is used once; the counter is saved past it before
+        // the code is shown, so a failed save never reveals a code twice
+        if code.remaining.is_none() {
+            if let Some(entry) = vault_manager
+                .get_vault_mut()
+                .and_then(|vault| vault.get_entry_mut(&id))
+            {
+                if let Some(ref mut otp) = entry.otp {
+                    otp.advance();
+                }
+            }
+            vault_manager.save_vault()?;
+        }
+
+        println!("🔑 One-time password for '{}':", title);
+        println!();
+        println!("{}", code.code);
+        println!();
+        if let Some(remaining) = code.remaining {
+            println!("⏱️  Valid for {} more seconds", remaining);
+        } else {
+            println!("🔢 Counter advanced; the next request shows a new code");
+        }
         Ok(())
     }
 }
 
-pub mod export {
+pub mod remove {
     use super::*;
-    use crate::storage::VaultManager;
+    use crate::storage::{PasswordEntry, VaultManager};
+
+    pub async fn run(
+        vault_manager: &mut VaultManager,
+        identifier: String,
+        force: bool,
+    ) -> Result<()> {
+        println!("🗑️  Removing password entry...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+        let Some(entry) = vault.get_entry(&id) else {
+            return Ok(());
+        };
+        let title = entry.title.clone();
+
+        if !force
+            && !confirm(&format!(
+                "Move '{}' ({}) to the trash?",
+                title, entry.username
+            ))?
+        {
+            println!("❌ Cancelled");
+            return Ok(());
+        }
+
+        if let Some(vault) = vault_manager.get_vault_mut() {
+            vault.trash_entry(&id)?;
+        }
+        vault_manager.save_vault()?;
+
+        println!("✅ Moved '{}' to the trash", title);
+        println!(
+            "💡 Use 'twopassword trash restore \"{}\"' to bring it back.",
+            title
+        );
+        Ok(())
+    }
+}
+
+pub mod attach {
+    use super::*;
+    use crate::storage::attachment::format_size;
+    use crate::storage::{PasswordEntry, VaultManager};
+    use std::path::PathBuf;
+
+    pub async fn run(
+        vault_manager: &mut VaultManager,
+        identifier: String,
+        file: PathBuf,
+        name: Option<String>,
+    ) -> Result<()> {
+        println!("📎 Attaching file...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+
+        let name = match name {
+            Some(name) => name,
+            None => match file.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => {
+                    println!("❌ Cannot name the attachment after {}", file.display());
+                    println!("💡 Give it a name with --name.");
+                    return Ok(());
+                }
+            },
+        };
+        let reader = match std::fs::File::open(&file) {
+            Ok(file) => io::BufReader::new(file),
+            Err(e) => {
+                println!("❌ Failed to open {}: {}", file.display(), e);
+                return Ok(());
+            }
+        };
+
+        match vault_manager.attach_file(&id, &name, reader) {
+            Ok(attachment) => {
+                println!(
+                    "✅ Attached '{}' ({}) to '{}'",
+                    attachment.name,
+                    format_size(attachment.size),
+                    vault_manager
+                        .get_vault()
+                        .and_then(|vault| vault.get_entry(&id))
+                        .map(|entry| entry.title.as_str())
+                        .unwrap_or_default()
+                );
+                println!(
+                    "💡 The original file at {} was left in place.",
+                    file.display()
+                );
+            }
+            Err(crate::TwoPasswordError::ValidationError(message)) => println!("❌ {}", message),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+}
+
+pub mod detach {
+    use super::*;
+    use crate::storage::attachment;
+    use crate::storage::{PasswordEntry, VaultManager};
+
+    pub async fn run(
+        vault_manager: &mut VaultManager,
+        identifier: String,
+        attachment: String,
+        force: bool,
+    ) -> Result<()> {
+        println!("📎 Removing attachment...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+        let Some(entry) = vault.get_entry(&id) else {
+            return Ok(());
+        };
+        let Some(found) = attachment::find(&entry.attachments, &attachment) else {
+            println!("❌ '{}' has no attachment '{}'", entry.title, attachment);
+            return Ok(());
+        };
+        let (title, found) = (entry.title.clone(), found.clone());
+
+        if !force
+            && !confirm(&format!(
+                "Permanently delete '{}' from '{}'?",
+                found.name, title
+            ))?
+        {
+            println!("❌ Cancelled");
+            return Ok(());
+        }
+
+        vault_manager.detach_file(&id, &found.id)?;
+        println!("✅ Removed '{}' from '{}'", found.name, title);
+        Ok(())
+    }
+}
+
+pub mod extract {
+    use super::*;
+    use crate::storage::attachment::{self, format_size};
+    use crate::storage::{PasswordEntry, VaultManager};
     use std::path::PathBuf;
 
     pub async fn run(
-        _vault_manager: &VaultManager,
-        _output: PathBuf,
-        _format: String,
+        vault_manager: &VaultManager,
+        identifier: String,
+        attachment: String,
+        output: Option<PathBuf>,
+        force: bool,
+    ) -> Result<()> {
+        println!("📎 Extracting attachment...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+        let Some(entry) = vault.get_entry(&id) else {
+            return Ok(());
+        };
+        let Some(found) = attachment::find(&entry.attachments, &attachment) else {
+            println!("❌ '{}' has no attachment '{}'", entry.title, attachment);
+            return Ok(());
+        };
+
+        let output = output.unwrap_or_else(|| PathBuf::from(&found.name));
+        if output.exists() && !force {
+            println!("❌ {} already exists", output.display());
+            println!("💡 Use --force to overwrite it.");
+            return Ok(());
+        }
+
+        // The file only appears at its destination once fully verified
+        let dir = match output.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut temp_file = tempfile::NamedTempFile::new_in(&dir).map_err(|e| {
+            crate::TwoPasswordError::storage(format!("Failed to write to {}: {}", dir.display(), e))
+        })?;
+        if let Err(e) =
+            vault_manager.extract_file(found, io::BufWriter::new(temp_file.as_file_mut()))
+        {
+            println!("❌ {}", e);
+            return Ok(());
+        }
+        temp_file.as_file().sync_all()?;
+        temp_file.persist(&output).map_err(|e| {
+            crate::TwoPasswordError::storage(format!(
+                "Failed to write {}: {}",
+                output.display(),
+                e.error
+            ))
+        })?;
+
+        println!(
+            "✅ Extracted '{}' ({}) to {}",
+            found.name,
+            format_size(found.size),
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+pub mod attachments {
+    use super::*;
+    use crate::storage::VaultManager;
+
+    pub async fn prune(vault_manager: &VaultManager, force: bool) -> Result<()> {
+        println!("📎 Looking for stored files no entry refers to...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        let orphaned = vault_manager.orphaned_attachments()?;
+        if orphaned.is_empty() {
+            println!("✅ Every stored file belongs to an entry.");
+            return Ok(());
+        }
+        for id in &orphaned {
+            println!("  {}", id);
+        }
+        println!("⚠️  Backups or other copies of this vault may still refer to these files.");
+        let prompt = format!("Permanently delete {} files?", orphaned.len());
+        if !force && !confirm(&prompt)? {
+            println!("❌ Cancelled");
+            return Ok(());
+        }
+
+        let removed = vault_manager.remove_orphaned_attachments()?;
+        println!("✅ Deleted {} files", removed);
+        Ok(())
+    }
+}
+
+pub mod trash {
+    use super::*;
+    use crate::storage::{PasswordEntry, VaultManager};
+
+    pub async fn list(vault_manager: &VaultManager) -> Result<()> {
+        println!("🗑️  Trash:");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+
+        if vault.trash.is_empty() {
+            println!("The trash is empty.");
+            return Ok(());
+        }
+
+        let mut trashed: Vec<_> = vault.trash.values().collect();
+        trashed.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+
+        let retention_days = vault.settings.trash_retention_days;
+        println!();
+        for item in trashed {
+            print!(
+                "• {} ({}) deleted {}",
+                item.entry.title,
+                item.entry.username,
+                item.deleted_at.format("%Y-%m-%d %H:%M UTC")
+            );
+            if retention_days > 0 {
+                let purge_at = item.deleted_at + chrono::Duration::days(i64::from(retention_days));
+                print!(", purged after {}", purge_at.format("%Y-%m-%d"));
+            }
+            println!();
+        }
+        println!();
+        if retention_days > 0 {
+            println!(
+                "💡 Entries are purged {} days after deletion.",
+                retention_days
+            );
+        } else {
+            println!("💡 Entries are kept until the trash is emptied.");
+        }
+        Ok(())
+    }
+
+    pub async fn restore(vault_manager: &mut VaultManager, identifier: String) -> Result<()> {
+        println!("♻️  Restoring entry from the trash...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+
+        let trashed: Vec<PasswordEntry> = vault
+            .trash
+            .values()
+            .map(|item| item.entry.clone())
+            .collect();
+        let Some(id) = select_entry(&trashed, &identifier)? else {
+            return Ok(());
+        };
+
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+        vault.restore_entry(&id)?;
+        let title = vault
+            .get_entry(&id)
+            .map(|e| e.title.clone())
+            .unwrap_or_default();
+        vault_manager.save_vault()?;
+
+        println!("✅ Restored '{}'", title);
+        Ok(())
+    }
+
+    pub async fn empty(vault_manager: &mut VaultManager, force: bool) -> Result<()> {
+        println!("🗑️  Emptying the trash...");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+
+        let count = vault.trash.len();
+        if count == 0 {
+            println!("The trash is already empty.");
+            return Ok(());
+        }
+        let prompt = format!(
+            "Permanently delete {} entries? This cannot be undone.",
+            count
+        );
+        if !force && !confirm(&prompt)? {
+            println!("❌ Cancelled");
+            return Ok(());
+        }
+
+        if let Some(vault) = vault_manager.get_vault_mut() {
+            vault.empty_trash();
+        }
+        vault_manager.save_vault()?;
+
+        println!("✅ Permanently deleted {} entries", count);
+        Ok(())
+    }
+
+    pub async fn retention(vault_manager: &mut VaultManager, days: Option<u32>) -> Result<()> {
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+
+        let Some(days) = days else {
+            match vault.settings.trash_retention_days {
+                0 => println!("🗑️  Trashed entries are kept until the trash is emptied"),
+                days => println!("🗑️  Trashed entries are purged after {} days", days),
+            }
+            return Ok(());
+        };
+
+        vault.settings.trash_retention_days = days;
+        vault.is_modified = true;
+        let purged = vault.purge_trash(chrono::Utc::now());
+        vault_manager.save_vault()?;
+
+        match days {
+            0 => println!("✅ Trashed entries are now kept until the trash is emptied"),
+            days => println!("✅ Trashed entries are now purged after {} days", days),
+        }
+        if purged > 0 {
+            println!("🗑️  Purged {} entries older than that", purged);
+        }
+        Ok(())
+    }
+}
+
+pub mod history {
+    use super::*;
+    use crate::storage::{PasswordEntry, VaultManager};
+
+    /// Retention changes requested on the command line
+    #[derive(Debug, Default)]
+    pub struct Retention {
+        pub size: Option<usize>,
+        pub max_age_days: Option<u32>,
+        pub usernames: Option<bool>,
+    }
+
+    impl Retention {
+        fn is_empty(&self) -> bool {
+            self.size.is_none() && self.max_age_days.is_none() && self.usernames.is_none()
+        }
+    }
+
+    pub async fn run(
+        vault_manager: &mut VaultManager,
+        identifier: Option<String>,
+        restore: Option<usize>,
+        reveal: bool,
+        retention: Retention,
     ) -> Result<()> {
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        if !retention.is_empty() {
+            set_retention(vault_manager, retention)?;
+        }
+
+        let Some(identifier) = identifier else {
+            show_retention(vault_manager);
+            return Ok(());
+        };
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let Some(id) = select_entry(&entries, &identifier)? else {
+            return Ok(());
+        };
+
+        if let Some(number) = restore {
+            let Some(vault) = vault_manager.get_vault_mut() else {
+                return Ok(());
+            };
+            if let Err(e) = vault.restore_history(&id, number - 1) {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+            let title = vault
+                .get_entry(&id)
+                .map(|e| e.title.clone())
+                .unwrap_or_default();
+            vault_manager.save_vault()?;
+            println!("✅ Restored password {} of '{}'", number, title);
+            println!("💡 The replaced password is now first in its history.");
+            return Ok(());
+        }
+
+        let Some(entry) = vault_manager.get_vault().and_then(|v| v.get_entry(&id)) else {
+            return Ok(());
+        };
+        if entry.history.is_empty() {
+            println!("🕘 '{}' has no previous passwords", entry.title);
+            return Ok(());
+        }
+
+        println!("🕘 Previous passwords of '{}', newest first:", entry.title);
+        println!();
+        for (i, item) in entry.history.iter().enumerate() {
+            let password = if reveal {
+                item.password.expose().to_string()
+            } else {
+                "*".repeat(item.password.len())
+            };
+            print!("{}. {}", i + 1, password);
+            if let Some(ref username) = item.username {
+                print!(" ({})", username);
+            }
+            println!(
+                "  replaced {}",
+                item.replaced_at.format("%Y-%m-%d %H:%M UTC")
+            );
+        }
+        println!();
+        println!(
+            "💡 Use 'twopassword history \"{}\" --restore <N>' to make one current again.",
+            entry.title
+        );
+        Ok(())
+    }
+
+    fn set_retention(vault_manager: &mut VaultManager, retention: Retention) -> Result<()> {
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+        if let Some(size) = retention.size {
+            vault.settings.history_size = size;
+        }
+        if let Some(max_age_days) = retention.max_age_days {
+            vault.settings.history_max_age_days = max_age_days;
+        }
+        if let Some(usernames) = retention.usernames {
+            vault.settings.history_usernames = usernames;
+        }
+        vault.is_modified = true;
+        let pruned = vault.prune_history(chrono::Utc::now());
+        vault_manager.save_vault()?;
+
+        println!("✅ Password history settings updated");
+        if pruned > 0 {
+            println!(
+                "🗑️  Dropped {} previous passwords outside the new limits",
+                pruned
+            );
+        }
+        Ok(())
+    }
+
+    fn show_retention(vault_manager: &VaultManager) {
+        let Some(vault) = vault_manager.get_vault() else {
+            return;
+        };
+        let settings = &vault.settings;
+
+        println!("🕘 Password history:");
+        println!();
+        if settings.history_size == 0 {
+            println!("Kept:      disabled");
+        } else {
+            println!(
+                "Kept:      last {} passwords per entry",
+                settings.history_size
+            );
+        }
+        if settings.history_max_age_days == 0 {
+            println!("Max age:   none");
+        } else {
+            println!("Max age:   {} days", settings.history_max_age_days);
+        }
+        println!(
+            "Usernames: {}",
+            if settings.history_usernames {
+                "recorded"
+            } else {
+                "not recorded"
+            }
+        );
+    }
+}
+
+pub mod generate {
+    use super::*;
+
+    pub async fn run(
+        length: usize,
+        uppercase: bool,
+        lowercase: bool,
+        numbers: bool,
+        symbols: bool,
+    ) -> Result<()> {
+        let password = crate::storage::entry::generate_password(
+            length, uppercase, lowercase, numbers, symbols,
+        )?;
+
+        println!("🎲 Generated password: {}", password);
+        println!("💡 Tip: Use this password with 'twopassword add' to save it securely.");
+        Ok(())
+    }
+}
+
+pub mod status {
+    use super::*;
+    use std::path::Path;
+
+    pub async fn run(socket_path: &Path) -> Result<()> {
+        println!("📊 Vault Status:");
+        match crate::agent::client::status(socket_path).await? {
+            Some(crate::agent::client::AgentStatus {
+                vault_path: Some(vault_path),
+                idle_timeout,
+                locks_in,
+            }) => {
+                println!("🔓 Unlocked: {}", vault_path.display());
+                println!(
+                    "⏱️  Locks in {}s (after {}s without use)",
+                    locks_in.as_secs(),
+                    idle_timeout.as_secs()
+                );
+            }
+            _ => println!("🔒 Locked"),
+        }
+
+        let protection = crate::crypto::guarded::status();
+        let mark = |active: bool| if active { "✅" } else { "⚠️ " };
+        println!();
+        println!("🛡️  Memory protection:");
+        println!(
+            "  {} Core dumps disabled",
+            mark(protection.core_dumps_disabled)
+        );
+        println!(
+            "  {} Key memory locked into RAM (RLIMIT_MEMLOCK: {})",
+            mark(protection.memory_locked),
+            protection
+                .memlock_limit
+                .map_or("unlimited".to_string(), |limit| format!("{} bytes", limit))
+        );
+        println!(
+            "  {} Guard pages around key memory",
+            mark(protection.guard_pages)
+        );
+        println!(
+            "  {} Key memory excluded from core dumps",
+            mark(protection.excluded_from_dumps)
+        );
+        if !protection.memory_locked {
+            println!("💡 Raise the locked memory limit (ulimit -l) to keep keys out of swap.");
+        }
+        Ok(())
+    }
+}
+
+pub mod passwd {
+    use super::*;
+    use crate::storage::VaultManager;
+    use std::path::Path;
+
+    pub async fn run(vault_manager: &mut VaultManager, vault_path: &Path) -> Result<()> {
+        println!("🔑 Changing master password...");
+
+        // Check if vault exists
+        if !vault_path.exists() {
+            println!("❌ Vault not found at {}", vault_path.display());
+            println!("💡 Use 'twopassword init' to create a new vault.");
+            return Ok(());
+        }
+
+        let old_password = read_secret("Enter current master password: ")?;
+        if let Err(e) = vault_manager.load_vault(vault_path, old_password.expose()) {
+            println!("❌ Failed to unlock vault: {}", e);
+            return Ok(());
+        }
+
+        let new_password = read_secret("Enter new master password: ")?;
+        if new_password.expose().trim().is_empty() {
+            println!("❌ Password cannot be empty");
+            return Ok(());
+        }
+
+        // Validate password strength
+        crate::auth::password::validate_password_strength(new_password.expose())?;
+
+        let confirm_password = read_secret("Confirm new master password: ")?;
+        if new_password != confirm_password {
+            println!("❌ Passwords do not match");
+            return Ok(());
+        }
+
+        vault_manager.change_master_password(old_password.expose(), new_password.expose())?;
+
+        println!("✅ Master password changed successfully!");
+        println!("🔐 Use your new password the next time you unlock the vault.");
+        Ok(())
+    }
+}
+
+pub mod rekey {
+    use super::*;
+    use crate::crypto::CipherSuite;
+    use crate::storage::VaultManager;
+    use std::path::Path;
+
+    pub async fn run(
+        vault_manager: &mut VaultManager,
+        vault_path: &Path,
+        cipher: Option<String>,
+        hide_metadata: Option<bool>,
+    ) -> Result<()> {
+        let cipher: Option<CipherSuite> = cipher.map(|c| c.parse()).transpose()?;
+        println!("🔁 Re-encrypting vault...");
+
+        // Check if vault exists
+        if !vault_path.exists() {
+            println!("❌ Vault not found at {}", vault_path.display());
+            println!("💡 Use 'twopassword init' to create a new vault.");
+            return Ok(());
+        }
+
+        let password = read_secret("Enter master password: ")?;
+
+        if let Err(e) = vault_manager.load_vault(vault_path, password.expose()) {
+            println!("❌ Failed to unlock vault: {}", e);
+            return Ok(());
+        }
+
+        if let Some(cipher) = cipher {
+            if vault_manager.cipher() == Some(cipher) {
+                println!("✅ Vault already uses {}.", cipher);
+            } else {
+                vault_manager.set_cipher(cipher)?;
+                println!("✅ Vault re-encrypted with {}.", cipher);
+            }
+        }
+
+        if let Some(hide_metadata) = hide_metadata {
+            if vault_manager.hide_metadata() == Some(hide_metadata) {
+                println!("✅ Metadata layout unchanged.");
+            } else {
+                vault_manager.set_hide_metadata(hide_metadata)?;
+                if hide_metadata {
+                    println!("✅ Metadata moved into the encrypted payload.");
+                } else {
+                    println!("✅ Metadata stored in the vault header.");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub mod lock {
+    use super::*;
+    use crate::storage::VaultManager;
+    use std::path::Path;
+
+    pub async fn run(vault_manager: &mut VaultManager, socket_path: &Path) -> Result<()> {
+        println!("🔒 Locking vault...");
+
+        vault_manager.close_vault();
+        if crate::agent::client::lock(socket_path).await? {
+            println!("✅ Vault locked. The key has been cleared from memory.");
+        } else {
+            println!("💡 The vault was not unlocked.");
+        }
+        Ok(())
+    }
+}
+
+pub mod export {
+    use super::*;
+    use crate::storage::export::{self, ExportFormat};
+    use crate::storage::{pass, PasswordEntry, VaultManager};
+    use std::path::PathBuf;
+
+    pub async fn run(vault_manager: &VaultManager, output: PathBuf, format: String) -> Result<()> {
+        let format: ExportFormat = format.parse()?;
         println!("📤 Exporting vault...");
-        println!("This command will be implemented in the next phase.");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+
+        // Exports are as hard to brute-force as the vault they came from
+        let kdf = vault_manager.kdf_params().copied().ok_or_else(|| {
+            crate::TwoPasswordError::storage("Key derivation parameters of the vault are unknown")
+        })?;
+        let mut entries: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.title.to_lowercase());
+
+        let passphrase = if format.is_plaintext() {
+            println!(
+                "⚠️  A {} export stores all {} passwords unencrypted in {}.",
+                format,
+                entries.len(),
+                output.display()
+            );
+            println!("💡 Use --format encrypted for backups.");
+            if !confirm("Write a plaintext export?")? {
+                println!("❌ Export cancelled");
+                return Ok(());
+            }
+            None
+        } else {
+            let passphrase = read_secret("Export passphrase: ")?;
+            if let Err(e) = crate::auth::password::validate_password_strength(passphrase.expose()) {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+            if passphrase != read_secret("Confirm export passphrase: ")? {
+                println!("❌ Passphrases do not match");
+                return Ok(());
+            }
+            Some(passphrase)
+        };
+
+        if format.is_directory() {
+            let gpg = passphrase.map(|passphrase| pass::Gpg::new(Some(passphrase)));
+            if let Err(e) = pass::write_store(&output, &entries, gpg.as_ref()) {
+                println!("❌ {}", e);
+                return Ok(());
+            }
+        } else {
+            // KeePass databases carry attachments; other formats hold entries only
+            let mut files = export::AttachmentFiles::new();
+            if format == ExportFormat::Kdbx {
+                for attachment in entries.iter().flat_map(|entry| &entry.attachments) {
+                    let mut contents = zeroize::Zeroizing::new(Vec::with_capacity(
+                        usize::try_from(attachment.size).unwrap_or_default(),
+                    ));
+                    vault_manager.extract_file(attachment, &mut *contents)?;
+                    files.insert(attachment.id, contents);
+                }
+            }
+            let contents =
+                export::export_entries(&entries, &files, format, passphrase.as_ref(), &kdf)?;
+            export::write_private(&output, &contents)?;
+        }
+
+        println!(
+            "✅ Exported {} entries to {} ({})",
+            entries.len(),
+            output.display(),
+            format
+        );
+        let attachments = entries
+            .iter()
+            .map(|entry| entry.attachments.len())
+            .sum::<usize>();
+        if attachments > 0 && format != ExportFormat::Kdbx {
+            println!(
+                "⚠️  {} attachments were left out; only kdbx exports include them.",
+                attachments
+            );
+        }
+        if format.is_plaintext() {
+            println!("🗑️  Delete the file once you no longer need it.");
+        } else if format == ExportFormat::Kdbx {
+            println!("🔐 Open it in KeePass or KeePassXC with the export passphrase.");
+        } else if format == ExportFormat::Pass {
+            println!(
+                "🔐 Read it with 'PASSWORD_STORE_DIR={} pass' or import it with \
+                 'twopassword import --input {}'.",
+                output.display(),
+                output.display()
+            );
+        } else {
+            println!(
+                "🔐 Restore it with 'twopassword import --input {}'.",
+                output.display()
+            );
+        }
         Ok(())
     }
 }
 
 pub mod import {
     use super::*;
-    use crate::storage::VaultManager;
+    use crate::storage::import::{self, ImportPlan};
+    use crate::storage::{kdbx, pass, PasswordEntry, VaultManager};
     use std::path::PathBuf;
 
     pub async fn run(
-        _vault_manager: &mut VaultManager,
-        _input: PathBuf,
-        _format: String,
+        vault_manager: &mut VaultManager,
+        input: PathBuf,
+        format: String,
+        dry_run: bool,
     ) -> Result<()> {
         println!("📥 Importing entries...");
-        println!("This command will be implemented in the next phase.");
+
+        if !is_unlocked(vault_manager) {
+            return Ok(());
+        }
+
+        // Directories are pass password stores
+        let (parsed, description) = if input.is_dir() {
+            let passphrase = if pass::is_encrypted_store(&input)? {
+                let passphrase = read_secret("GPG passphrase (empty to use gpg-agent): ")?;
+                (!passphrase.expose().is_empty()).then_some(passphrase)
+            } else {
+                None
+            };
+            let parsed = pass::read_store(&input, &pass::Gpg::new(passphrase));
+            (parsed, pass::DESCRIPTION)
+        } else {
+            let mut contents = zeroize::Zeroizing::new(std::fs::read(&input)?);
+            let importer = if import::is_encrypted_export(&contents) {
+                let passphrase = read_secret("Export passphrase: ")?;
+                match import::decrypt_export(&contents, &passphrase) {
+                    Ok(decrypted) => contents = decrypted,
+                    Err(e) => {
+                        println!("❌ {}", e);
+                        return Ok(());
+                    }
+                }
+                &import::TwoPasswordJson
+            } else if kdbx::is_kdbx(&contents) {
+                let password = read_secret("KeePass database password: ")?;
+                match kdbx::decrypt(&contents, &password) {
+                    Ok(payload) => contents = payload,
+                    Err(e) => {
+                        println!("❌ {}", e);
+                        return Ok(());
+                    }
+                }
+                &import::keepass::KeePassKdbx
+            } else {
+                match import::importer(&format) {
+                    Ok(importer) => importer,
+                    Err(e) => {
+                        println!("❌ {}", e);
+                        return Ok(());
+                    }
+                }
+            };
+
+            let parsed = importer.parse(&contents);
+            (parsed, importer.description())
+        };
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                println!("❌ Failed to read {}: {}", input.display(), e);
+                return Ok(());
+            }
+        };
+
+        let Some(vault) = vault_manager.get_vault() else {
+            return Ok(());
+        };
+        let existing: Vec<PasswordEntry> = vault.entries.values().cloned().collect();
+        let plan = ImportPlan::new(parsed, &existing);
+
+        println!("📄 {} from {}", description, input.display());
+        println!();
+        println!("New:        {}", plan.new.len());
+        println!("Duplicates: {}", plan.duplicates.len());
+        println!("Errors:     {}", plan.errors.len());
+        let attachments: usize = plan.new.iter().map(|record| record.files.len()).sum();
+        if attachments > 0 {
+            println!("Files:      {}", attachments);
+        }
+
+        if !plan.duplicates.is_empty() {
+            println!();
+            println!("🔁 Duplicates, skipped:");
+            for record in &plan.duplicates {
+                println!(
+                    "   {}: {} ({})",
+                    record.location, record.entry.title, record.entry.username
+                );
+            }
+        }
+        if !plan.errors.is_empty() {
+            println!();
+            println!("⚠️  Not imported:");
+            for error in &plan.errors {
+                println!("   {}", error);
+            }
+        }
+        println!();
+
+        if dry_run {
+            if !plan.new.is_empty() {
+                println!("📋 Would import:");
+                for record in &plan.new {
+                    println!("   {} ({})", record.entry.title, record.entry.username);
+                }
+                println!();
+            }
+            println!("💡 Dry run: nothing was imported. Run again without --dry-run to import.");
+            return Ok(());
+        }
+
+        // Files are attached once their entries are in the saved vault
+        let files: Vec<(uuid::Uuid, String, import::ImportedFile)> = plan
+            .new
+            .iter()
+            .flat_map(|record| {
+                record
+                    .files
+                    .iter()
+                    .map(|file| (record.entry.id, record.entry.title.clone(), file.clone()))
+            })
+            .collect();
+
+        let Some(vault) = vault_manager.get_vault_mut() else {
+            return Ok(());
+        };
+        let imported = plan.apply(vault);
+        if imported > 0 {
+            vault_manager.save_vault()?;
+        }
+
+        let mut attached = 0;
+        for (id, title, file) in files {
+            match vault_manager.attach_file(&id, &file.name, file.contents.as_slice()) {
+                Ok(_) => attached += 1,
+                Err(e) => println!("⚠️  '{}' was not attached to '{}': {}", file.name, title, e),
+            }
+        }
+
+        println!("✅ Imported {} entries", imported);
+        if attached > 0 {
+            println!("📎 Attached {} files", attached);
+        }
+        Ok(())
+    }
+}
+
+pub mod kdf {
+    use super::*;
+    use crate::crypto::key_derivation;
+    use crate::storage::VaultManager;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub async fn tune(
+        vault_manager: &mut VaultManager,
+        vault_path: &Path,
+        target_ms: u64,
+        max_memory_mib: u32,
+        apply: bool,
+    ) -> Result<()> {
+        println!(
+            "⏱️  Benchmarking key derivation (target {} ms)...",
+            target_ms
+        );
+
+        let max_memory_kib = max_memory_mib.saturating_mul(1024);
+        let (params, elapsed) =
+            key_derivation::tune_params(Duration::from_millis(target_ms), max_memory_kib)?;
+
+        println!("✅ Recommended Argon2id parameters:");
+        println!();
+        println!("Memory:      {} MiB", params.memory_cost_kib / 1024);
+        println!("Iterations:  {}", params.time_cost);
+        println!("Parallelism: {}", params.parallelism);
+        println!("Unlock time: {} ms", elapsed.as_millis());
+
+        if !apply {
+            println!();
+            println!("💡 Run with --apply to re-key your vault with these parameters.");
+            return Ok(());
+        }
+
+        if !vault_path.exists() {
+            println!("❌ Vault not found at {}", vault_path.display());
+            return Ok(());
+        }
+
+        let password = read_secret("Enter master password: ")?;
+
+        if let Err(e) = vault_manager.load_vault(vault_path, password.expose()) {
+            println!("❌ Failed to unlock vault: {}", e);
+            return Ok(());
+        }
+
+        vault_manager.update_kdf_params(password.expose(), params)?;
+        println!("🔐 Vault re-keyed with the new key derivation parameters.");
+
         Ok(())
     }
 }
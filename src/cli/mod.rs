@@ -1,7 +1,11 @@
 //! Command Line Interface for TwoPassword
 
+use crate::crypto::otp::Otp;
+use crate::storage::field::FieldType;
+use crate::storage::kind::KIND_NAMES;
+use crate::storage::query;
 use crate::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 pub mod commands;
 
@@ -23,6 +27,90 @@ pub struct Cli {
     pub command: Commands,
 }
 
+/// Read an `--otp` value
+fn parse_otp(value: &str) -> std::result::Result<Otp, String> {
+    Otp::parse(value).map_err(|e| e.to_string())
+}
+
+/// Read a date given as YYYY-MM-DD
+fn parse_date(value: &str) -> std::result::Result<chrono::NaiveDate, String> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}' (expected YYYY-MM-DD)", value))
+}
+
+/// A `--field` value
+#[derive(Debug, Clone)]
+pub struct FieldArg {
+    pub name: String,
+    pub field_type: FieldType,
+    /// Value given on the command line; prompted for when missing
+    pub value: Option<String>,
+}
+
+/// Read a `--field` value: `NAME[:TYPE][=VALUE]`
+///
+/// A suffix after the last colon that is not a field type stays part of
+/// the name, so names such as `Q: first pet` need no type.
+fn parse_field(value: &str) -> std::result::Result<FieldArg, String> {
+    let (spec, value) = match value.split_once('=') {
+        Some((spec, value)) => (spec, Some(value.to_string())),
+        None => (value, None),
+    };
+    let (name, field_type) = match spec.rsplit_once(':') {
+        Some((name, suffix)) => match FieldType::from_name(suffix) {
+            Ok(field_type) => (name, field_type),
+            Err(_) => (spec, FieldType::Text),
+        },
+        None => (spec, FieldType::Text),
+    };
+    if name.trim().is_empty() {
+        return Err("field name cannot be empty (expected NAME[:TYPE][=VALUE])".to_string());
+    }
+    Ok(FieldArg {
+        name: name.trim().to_string(),
+        field_type,
+        value,
+    })
+}
+
+/// Kind of a new entry, with the fields of kinds other than logins
+///
+/// Card numbers and security codes are prompted for rather than passed as
+/// arguments, so they stay out of the shell history.
+#[derive(Args, Debug)]
+pub struct KindDetails {
+    /// Entry kind
+    #[arg(long, default_value = "login", value_parser = KIND_NAMES)]
+    pub kind: String,
+    /// Cardholder name (card)
+    #[arg(long)]
+    pub cardholder: Option<String>,
+    /// Expiry date: MM/YY for cards, YYYY-MM-DD for API tokens
+    #[arg(long)]
+    pub expires: Option<String>,
+    /// Full name (identity)
+    #[arg(long)]
+    pub full_name: Option<String>,
+    /// Email address (identity)
+    #[arg(long)]
+    pub email: Option<String>,
+    /// Phone number (identity)
+    #[arg(long)]
+    pub phone: Option<String>,
+    /// Postal address (identity)
+    #[arg(long)]
+    pub address: Option<String>,
+    /// Date of birth as YYYY-MM-DD (identity)
+    #[arg(long, value_parser = parse_date)]
+    pub birth_date: Option<chrono::NaiveDate>,
+    /// Private key file (ssh-key)
+    #[arg(long)]
+    pub private_key: Option<std::path::PathBuf>,
+    /// Public key file (ssh-key; default: the private key file with .pub)
+    #[arg(long)]
+    pub public_key: Option<std::path::PathBuf>,
+}
+
 /// Available commands
 #[derive(Subcommand)]
 pub enum Commands {
@@ -31,6 +119,9 @@ pub enum Commands {
         /// Use Touch ID for authentication (macOS only)
         #[arg(long)]
         touch_id: bool,
+        /// Keep metadata encrypted and pad the vault to hide its size
+        #[arg(long)]
+        hide_metadata: bool,
     },
 
     /// Unlock the vault
@@ -38,6 +129,9 @@ pub enum Commands {
         /// Try Touch ID first (macOS only)
         #[arg(long)]
         touch_id: bool,
+        /// Lock again after this many seconds without use
+        #[arg(long, default_value_t = crate::config::AGENT_IDLE_TIMEOUT_SECS)]
+        idle_timeout: u64,
     },
 
     /// Add a new password entry
@@ -46,29 +140,48 @@ pub enum Commands {
         title: String,
         /// Username
         #[arg(short, long)]
-        username: String,
-        /// Password (will prompt if not provided)
+        username: Option<String>,
+        /// Password, API token or SSH key passphrase (will prompt if not provided)
         #[arg(short, long)]
         password: Option<String>,
         /// Website URL
-        #[arg(short, long)]
+        #[arg(long)]
         url: Option<String>,
         /// Notes
         #[arg(short, long)]
         notes: Option<String>,
+        /// Two-factor secret: an otpauth:// URI or a base32 TOTP secret
+        #[arg(long, value_parser = parse_otp)]
+        otp: Option<Otp>,
+        /// Custom field as NAME[:TYPE][=VALUE], with TYPE one of text,
+        /// concealed, url, email, date or otp; the value is prompted for
+        /// if left out (repeatable)
+        #[arg(long = "field", value_name = "FIELD", value_parser = parse_field)]
+        fields: Vec<FieldArg>,
+        #[command(flatten)]
+        details: KindDetails,
     },
 
     /// Get a password entry
     Get {
-        /// Search query (title or URL)
-        query: String,
+        /// Search query: words, field:value terms such as tag:work, url:github.com
+        /// or created:>2025-01-01, -term to exclude, OR and parentheses; a quoted
+        /// argument with spaces is searched as one term
+        #[arg(required = true, allow_hyphen_values = true)]
+        query: Vec<String>,
     },
 
     /// List all entries
     List {
-        /// Filter by tag
+        /// Only list entries matching this search query, as for get
+        #[arg(allow_hyphen_values = true)]
+        query: Vec<String>,
+        /// Filter by tag, including the tags nested below it
         #[arg(short, long)]
         tag: Option<String>,
+        /// Show entries in a tree of nested tags
+        #[arg(long)]
+        tree: bool,
     },
 
     /// Update an entry
@@ -84,15 +197,81 @@ pub enum Commands {
         /// New password
         #[arg(short, long)]
         password: Option<String>,
-        /// New URL
+        /// New URL (empty to clear)
         #[arg(long)]
         url: Option<String>,
-        /// New notes
+        /// New notes (empty to clear)
         #[arg(short, long)]
         notes: Option<String>,
+        /// New two-factor secret: an otpauth:// URI or a base32 TOTP secret
+        #[arg(long, value_parser = parse_otp)]
+        otp: Option<Otp>,
+        /// Remove the two-factor secret
+        #[arg(long, conflicts_with = "otp")]
+        remove_otp: bool,
+        /// Add a tag (repeatable)
+        #[arg(long = "add-tag", value_name = "TAG")]
+        add_tags: Vec<String>,
+        /// Remove a tag (repeatable)
+        #[arg(long = "remove-tag", value_name = "TAG")]
+        remove_tags: Vec<String>,
+        /// Add or replace a custom field, as NAME[:TYPE][=VALUE] (repeatable)
+        #[arg(long = "field", value_name = "FIELD", value_parser = parse_field)]
+        fields: Vec<FieldArg>,
+        /// Remove a custom field (repeatable)
+        #[arg(long = "remove-field", value_name = "NAME")]
+        remove_fields: Vec<String>,
+    },
+
+    /// Show the current one-time password of an entry
+    Totp {
+        /// Entry ID or title
+        query: String,
+    },
+
+    /// Encrypt a file and attach it to an entry
+    Attach {
+        /// Entry ID or title
+        identifier: String,
+        /// File to attach
+        file: std::path::PathBuf,
+        /// Name to store the file under (defaults to its file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Permanently delete an attachment from an entry
+    Detach {
+        /// Entry ID or title
+        identifier: String,
+        /// Attachment name or ID
+        attachment: String,
+        /// Skip confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Decrypt an attachment of an entry to a file
+    Extract {
+        /// Entry ID or title
+        identifier: String,
+        /// Attachment name or ID
+        attachment: String,
+        /// Output file (defaults to the attachment name)
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        /// Overwrite the output file if it exists
+        #[arg(short, long)]
+        force: bool,
     },
 
-    /// Remove an entry
+    /// Maintain the encrypted files stored next to the vault
+    Attachments {
+        #[command(subcommand)]
+        command: AttachmentCommands,
+    },
+
+    /// Move an entry to the trash
     Remove {
         /// Entry ID or title
         identifier: String,
@@ -101,6 +280,44 @@ pub enum Commands {
         force: bool,
     },
 
+    /// List or restore previous passwords of an entry, or set how many are kept
+    History {
+        /// Entry ID or title; omit to show the history settings
+        identifier: Option<String>,
+        /// Make previous password N current again
+        #[arg(
+            long,
+            value_name = "N",
+            requires = "identifier",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        restore: Option<usize>,
+        /// Show previous passwords instead of masking them
+        #[arg(long)]
+        reveal: bool,
+        /// Keep this many previous passwords per entry (0 disables the history)
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+        /// Forget previous passwords after this many days (0 keeps them)
+        #[arg(long, value_name = "DAYS")]
+        max_age_days: Option<u32>,
+        /// Record username changes along with passwords
+        #[arg(long, value_name = "BOOL")]
+        record_usernames: Option<bool>,
+    },
+
+    /// List, restore or permanently delete removed entries
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommands,
+    },
+
+    /// List, rename or move nested tags
+    Tag {
+        #[command(subcommand)]
+        command: TagCommands,
+    },
+
     /// Generate a secure password
     Generate {
         /// Password length
@@ -123,27 +340,137 @@ pub enum Commands {
     /// Show vault status
     Status,
 
+    /// Change the master password
+    Passwd,
+
+    /// Re-encrypt the vault with another cipher suite or metadata layout
+    Rekey {
+        /// Cipher suite (aes-256-gcm, xchacha20-poly1305)
+        #[arg(long, required_unless_present = "hide_metadata")]
+        cipher: Option<String>,
+        /// Keep metadata encrypted and pad the vault to hide its size
+        #[arg(long)]
+        hide_metadata: Option<bool>,
+    },
+
     /// Lock the vault
     Lock,
 
     /// Export vault (for backup)
     Export {
-        /// Export file path
+        /// Export file path, or directory for pass stores
         #[arg(short, long)]
         output: std::path::PathBuf,
-        /// Export format (json, csv)
+        /// Export format (json, csv, encrypted, kdbx, pass, pass-plaintext)
         #[arg(short, long, default_value = "json")]
         format: String,
     },
 
     /// Import entries from file
     Import {
-        /// Import file path
+        /// Import file path, or pass password store directory
         #[arg(short, long)]
         input: std::path::PathBuf,
-        /// Import format (json, csv)
+        /// Import format: json, csv, bitwarden-json, bitwarden-csv,
+        /// 1password-1pux, 1password-csv, chrome-csv, firefox-csv,
+        /// keepassxc-csv or kdbx; encrypted TwoPassword exports, KeePass
+        /// databases and pass stores are detected
         #[arg(short, long, default_value = "json")]
         format: String,
+        /// Show what would be imported without changing the vault
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Run the unlock agent in the foreground (started by `unlock`)
+    #[command(hide = true)]
+    Agent {
+        /// Socket to listen on
+        #[arg(long)]
+        socket: Option<std::path::PathBuf>,
+        /// Lock after this many seconds without use
+        #[arg(long, default_value_t = crate::config::AGENT_IDLE_TIMEOUT_SECS)]
+        idle_timeout: u64,
+    },
+
+    /// Key derivation settings
+    Kdf {
+        #[command(subcommand)]
+        command: KdfCommands,
+    },
+}
+
+/// Key derivation subcommands
+#[derive(Subcommand)]
+pub enum KdfCommands {
+    /// Benchmark this machine and pick Argon2id parameters for a target unlock time
+    Tune {
+        /// Target unlock time in milliseconds
+        #[arg(long, default_value = "1000")]
+        target_ms: u64,
+        /// Maximum memory cost in MiB
+        #[arg(long, default_value = "1024")]
+        max_memory_mib: u32,
+        /// Apply the parameters to the vault (prompts for the master password)
+        #[arg(long)]
+        apply: bool,
+    },
+}
+
+/// Attachment subcommands
+#[derive(Subcommand)]
+pub enum AttachmentCommands {
+    /// Delete stored files that no entry of the vault refers to, such as
+    /// those left behind by other copies of the vault
+    Prune {
+        /// Skip confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+/// Trash subcommands
+#[derive(Subcommand)]
+pub enum TrashCommands {
+    /// List entries in the trash
+    List,
+    /// Move an entry from the trash back into the vault
+    Restore {
+        /// Entry ID or title
+        identifier: String,
+    },
+    /// Permanently delete every entry in the trash
+    Empty {
+        /// Skip confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Show or set how many days entries stay in the trash (0 keeps them)
+    Retention {
+        /// Days before trashed entries are purged
+        days: Option<u32>,
+    },
+}
+
+/// Tag subcommands
+#[derive(Subcommand)]
+pub enum TagCommands {
+    /// Show the tag tree with the number of entries under each tag
+    List,
+    /// Rename a tag on every entry, along with the tags nested below it
+    Rename {
+        /// Tag to rename, e.g. work/servers
+        from: String,
+        /// New name, e.g. work/infrastructure
+        to: String,
+    },
+    /// Move a tag, with the tags nested below it, under another tag
+    Move {
+        /// Tag to move
+        tag: String,
+        /// New parent tag; omit to move the tag to the top level
+        #[arg(long)]
+        to: Option<String>,
     },
 }
 
@@ -171,24 +498,55 @@ impl CliRunner {
 
         // Get vault path
         let vault_path = cli.vault.unwrap_or_else(|| self.get_default_vault_path());
+        let socket_path = crate::agent::socket_path();
+
+        // Commands that read or change entries use the vault unlocked by the agent
+        if matches!(
+            cli.command,
+            Commands::Add { .. }
+                | Commands::Get { .. }
+                | Commands::List { .. }
+                | Commands::Update { .. }
+                | Commands::Totp { .. }
+                | Commands::Attach { .. }
+                | Commands::Detach { .. }
+                | Commands::Extract { .. }
+                | Commands::Attachments { .. }
+                | Commands::Remove { .. }
+                | Commands::Trash { .. }
+                | Commands::Tag { .. }
+                | Commands::History { .. }
+                | Commands::Export { .. }
+                | Commands::Import { .. }
+        ) {
+            self.open_agent_session(&socket_path, &vault_path).await;
+        }
 
         // Execute command
         match cli.command {
-            Commands::Init { touch_id } => {
+            Commands::Init {
+                touch_id,
+                hide_metadata,
+            } => {
                 commands::init::run(
                     &mut self.vault_manager,
                     &self.auth_manager,
                     &vault_path,
                     touch_id,
+                    hide_metadata,
                 )
                 .await
             }
-            Commands::Unlock { touch_id } => {
+            Commands::Unlock {
+                touch_id,
+                idle_timeout,
+            } => {
                 commands::unlock::run(
-                    &mut self.vault_manager,
                     &self.auth_manager,
                     &vault_path,
+                    &socket_path,
                     touch_id,
+                    idle_timeout,
                 )
                 .await
             }
@@ -198,19 +556,26 @@ impl CliRunner {
                 password,
                 url,
                 notes,
+                otp,
+                fields,
+                details,
             } => {
-                commands::add::run(
-                    &mut self.vault_manager,
+                let mut entry = crate::storage::PasswordEntry::new(
                     title,
-                    username,
-                    password,
-                    url,
-                    notes,
-                )
-                .await
+                    username.unwrap_or_default(),
+                    String::new(),
+                );
+                entry.url = url;
+                entry.notes = notes;
+                entry.otp = otp;
+                commands::add::run(&mut self.vault_manager, entry, password, fields, details).await
+            }
+            Commands::Get { query } => {
+                commands::get::run(&self.vault_manager, query::from_args(&query)).await
+            }
+            Commands::List { query, tag, tree } => {
+                commands::list::run(&self.vault_manager, query::from_args(&query), tag, tree).await
             }
-            Commands::Get { query } => commands::get::run(&self.vault_manager, query).await,
-            Commands::List { tag } => commands::list::run(&self.vault_manager, tag).await,
             Commands::Update {
                 identifier,
                 title,
@@ -218,21 +583,104 @@ impl CliRunner {
                 password,
                 url,
                 notes,
+                otp,
+                remove_otp,
+                add_tags,
+                remove_tags,
+                fields,
+                remove_fields,
             } => {
-                commands::update::run(
-                    &mut self.vault_manager,
-                    identifier,
+                let update = crate::storage::entry::EntryUpdate {
                     title,
                     username,
-                    password,
+                    password: password.map(crate::crypto::SecretString::from),
                     url,
                     notes,
-                )
-                .await
+                    otp: if remove_otp {
+                        Some(None)
+                    } else {
+                        otp.map(Some)
+                    },
+                    add_tags,
+                    remove_tags,
+                    set_fields: Vec::new(),
+                    remove_fields,
+                };
+                commands::update::run(&mut self.vault_manager, identifier, update, fields).await
             }
+            Commands::Totp { query } => commands::totp::run(&mut self.vault_manager, query).await,
+            Commands::Attach {
+                identifier,
+                file,
+                name,
+            } => commands::attach::run(&mut self.vault_manager, identifier, file, name).await,
+            Commands::Detach {
+                identifier,
+                attachment,
+                force,
+            } => {
+                commands::detach::run(&mut self.vault_manager, identifier, attachment, force).await
+            }
+            Commands::Extract {
+                identifier,
+                attachment,
+                output,
+                force,
+            } => {
+                commands::extract::run(&self.vault_manager, identifier, attachment, output, force)
+                    .await
+            }
+            Commands::Attachments { command } => match command {
+                AttachmentCommands::Prune { force } => {
+                    commands::attachments::prune(&self.vault_manager, force).await
+                }
+            },
             Commands::Remove { identifier, force } => {
                 commands::remove::run(&mut self.vault_manager, identifier, force).await
             }
+            Commands::History {
+                identifier,
+                restore,
+                reveal,
+                keep,
+                max_age_days,
+                record_usernames,
+            } => {
+                let retention = commands::history::Retention {
+                    size: keep,
+                    max_age_days,
+                    usernames: record_usernames,
+                };
+                commands::history::run(
+                    &mut self.vault_manager,
+                    identifier,
+                    restore,
+                    reveal,
+                    retention,
+                )
+                .await
+            }
+            Commands::Tag { command } => match command {
+                TagCommands::List => commands::tag::list(&self.vault_manager).await,
+                TagCommands::Rename { from, to } => {
+                    commands::tag::rename(&mut self.vault_manager, from, to).await
+                }
+                TagCommands::Move { tag, to } => {
+                    commands::tag::move_to(&mut self.vault_manager, tag, to).await
+                }
+            },
+            Commands::Trash { command } => match command {
+                TrashCommands::List => commands::trash::list(&self.vault_manager).await,
+                TrashCommands::Restore { identifier } => {
+                    commands::trash::restore(&mut self.vault_manager, identifier).await
+                }
+                TrashCommands::Empty { force } => {
+                    commands::trash::empty(&mut self.vault_manager, force).await
+                }
+                TrashCommands::Retention { days } => {
+                    commands::trash::retention(&mut self.vault_manager, days).await
+                }
+            },
             Commands::Generate {
                 length,
                 uppercase,
@@ -240,14 +688,71 @@ impl CliRunner {
                 numbers,
                 symbols,
             } => commands::generate::run(length, uppercase, lowercase, numbers, symbols).await,
-            Commands::Status => commands::status::run(&self.vault_manager).await,
-            Commands::Lock => commands::lock::run(&mut self.vault_manager).await,
+            Commands::Status => commands::status::run(&socket_path).await,
+            Commands::Passwd => commands::passwd::run(&mut self.vault_manager, &vault_path).await,
+            Commands::Rekey {
+                cipher,
+                hide_metadata,
+            } => {
+                commands::rekey::run(&mut self.vault_manager, &vault_path, cipher, hide_metadata)
+                    .await
+            }
+            Commands::Lock => commands::lock::run(&mut self.vault_manager, &socket_path).await,
             Commands::Export { output, format } => {
                 commands::export::run(&self.vault_manager, output, format).await
             }
-            Commands::Import { input, format } => {
-                commands::import::run(&mut self.vault_manager, input, format).await
+            Commands::Import {
+                input,
+                format,
+                dry_run,
+            } => commands::import::run(&mut self.vault_manager, input, format, dry_run).await,
+            Commands::Agent {
+                socket,
+                idle_timeout,
+            } => {
+                let socket = socket.unwrap_or(socket_path);
+                crate::agent::server::Agent::new(std::time::Duration::from_secs(idle_timeout))
+                    .serve(&socket)
+                    .await
             }
+            Commands::Kdf { command } => match command {
+                KdfCommands::Tune {
+                    target_ms,
+                    max_memory_mib,
+                    apply,
+                } => {
+                    commands::kdf::tune(
+                        &mut self.vault_manager,
+                        &vault_path,
+                        target_ms,
+                        max_memory_mib,
+                        apply,
+                    )
+                    .await
+                }
+            },
+        }
+    }
+
+    /// Open the vault through the unlock agent, if it has this vault
+    /// unlocked; the agent keeps the data key and saves changes
+    ///
+    /// Failures are only logged: the command then reports that no vault is
+    /// unlocked.
+    async fn open_agent_session(
+        &mut self,
+        socket_path: &std::path::Path,
+        vault_path: &std::path::Path,
+    ) {
+        if self.vault_manager.is_vault_loaded() {
+            return;
+        }
+
+        match crate::agent::client::open(socket_path, vault_path).await {
+            Ok((vault, session)) => self
+                .vault_manager
+                .open_with_key_holder(vault, Box::new(session)),
+            Err(e) => tracing::debug!("No agent session for {}: {}", vault_path.display(), e),
         }
     }
 
@@ -273,3 +778,58 @@ impl Default for CliRunner {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_field() {
+        let field = parse_field("Recovery codes:concealed").unwrap();
+        assert_eq!(field.name, "Recovery codes");
+        assert_eq!(field.field_type, FieldType::Concealed);
+        assert!(field.value.is_none());
+
+        let field = parse_field("Q: first pet=Rex").unwrap();
+        assert_eq!(field.name, "Q: first pet");
+        assert_eq!(field.field_type, FieldType::Text);
+        assert_eq!(field.value.as_deref(), Some("Rex"));
+
+        let field = parse_field("Console:url=https://example.com/?a=b").unwrap();
+        assert_eq!(field.field_type, FieldType::Url);
+        assert_eq!(field.value.as_deref(), Some("https://example.com/?a=b"));
+
+        assert!(parse_field(":concealed=x").is_err());
+    }
+
+    #[test]
+    fn test_query_arguments() {
+        let cli = Cli::try_parse_from(["twopassword", "get", "tag:work", "-tag:old", "has:totp"])
+            .unwrap();
+        let Commands::Get { query } = cli.command else {
+            panic!("expected get");
+        };
+        assert_eq!(query::from_args(&query), "tag:work -tag:old has:totp");
+
+        let cli = Cli::try_parse_from(["twopassword", "get", "title:my bank"]).unwrap();
+        let Commands::Get { query } = cli.command else {
+            panic!("expected get");
+        };
+        assert_eq!(query::from_args(&query), r#"title:"my bank""#);
+
+        let cli =
+            Cli::try_parse_from(["twopassword", "list", "--tree", "-t", "work", "github"]).unwrap();
+        let Commands::List { query, tag, tree } = cli.command else {
+            panic!("expected list");
+        };
+        assert_eq!(query, ["github"]);
+        assert_eq!(tag.as_deref(), Some("work"));
+        assert!(tree);
+    }
+}
@@ -1,18 +1,30 @@
 //! AES-GCM encryption and decryption
 
-use crate::crypto::{secure_random, EncryptedData};
+use crate::crypto::key_schedule::{self, KeySchedule};
+use crate::crypto::{secure_random, CipherSuite, EncryptedData};
 use crate::{Result, TwoPasswordError};
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Key, Nonce,
 };
 use ring::hmac;
 
 /// Encrypt data using AES-256-GCM
+///
+/// Separate cipher and HMAC keys are expanded from `key` with HKDF.
 pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<EncryptedData> {
+    encrypt_with_aad(key, plaintext, &[])
+}
+
+/// Encrypt data using AES-256-GCM, binding `aad` as associated data
+///
+/// The associated data is not stored; decryption fails unless the same bytes
+/// are supplied again.
+pub fn encrypt_with_aad(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<EncryptedData> {
+    let (cipher_key, mac_key) = key_schedule::aead_keys(key, KeySchedule::Hkdf)?;
+
     // Create cipher
-    let cipher_key = Key::<Aes256Gcm>::from_slice(key);
-    let cipher = Aes256Gcm::new(cipher_key);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(cipher_key.as_bytes()));
 
     // Generate random nonce
     let nonce_bytes = secure_random::generate_nonce()?;
@@ -20,19 +32,28 @@ pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<EncryptedData> {
 
     // Encrypt
     let ciphertext = cipher
-        .encrypt(nonce, plaintext)
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
         .map_err(|e| TwoPasswordError::crypto(format!("Encryption failed: {}", e)))?;
 
     // Calculate HMAC for integrity
-    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, mac_key.as_bytes());
     let mut hmac_input = Vec::new();
     hmac_input.extend_from_slice(&nonce_bytes);
     hmac_input.extend_from_slice(&ciphertext);
+    hmac_input.extend_from_slice(aad);
 
     let hmac_tag = hmac::sign(&hmac_key, &hmac_input);
     let hmac_bytes = hmac_tag.as_ref().to_vec();
 
     Ok(EncryptedData {
+        cipher: CipherSuite::Aes256Gcm,
+        key_schedule: KeySchedule::Hkdf,
         ciphertext,
         nonce: nonce_bytes,
         hmac: hmac_bytes,
@@ -41,19 +62,32 @@ pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<EncryptedData> {
 
 /// Decrypt data using AES-256-GCM
 pub fn decrypt(key: &[u8; 32], encrypted: &EncryptedData) -> Result<Vec<u8>> {
+    decrypt_with_aad(key, encrypted, &[])
+}
+
+/// Decrypt data using AES-256-GCM that was encrypted with associated data
+pub fn decrypt_with_aad(key: &[u8; 32], encrypted: &EncryptedData, aad: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.cipher != CipherSuite::Aes256Gcm {
+        return Err(TwoPasswordError::crypto(
+            "Data was not encrypted with AES-256-GCM",
+        ));
+    }
+
+    let (cipher_key, mac_key) = key_schedule::aead_keys(key, encrypted.key_schedule)?;
+
     // Verify HMAC first
-    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, mac_key.as_bytes());
     let mut hmac_input = Vec::new();
     hmac_input.extend_from_slice(&encrypted.nonce);
     hmac_input.extend_from_slice(&encrypted.ciphertext);
+    hmac_input.extend_from_slice(aad);
 
     hmac::verify(&hmac_key, &hmac_input, &encrypted.hmac).map_err(|_| {
         TwoPasswordError::crypto("HMAC verification failed - data may be corrupted")
     })?;
 
     // Create cipher
-    let cipher_key = Key::<Aes256Gcm>::from_slice(key);
-    let cipher = Aes256Gcm::new(cipher_key);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(cipher_key.as_bytes()));
 
     // Decrypt
     if encrypted.nonce.len() != crate::config::NONCE_SIZE {
@@ -62,7 +96,13 @@ pub fn decrypt(key: &[u8; 32], encrypted: &EncryptedData) -> Result<Vec<u8>> {
 
     let nonce = Nonce::from_slice(&encrypted.nonce);
     let plaintext = cipher
-        .decrypt(nonce, encrypted.ciphertext.as_ref())
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &encrypted.ciphertext,
+                aad,
+            },
+        )
         .map_err(|e| TwoPasswordError::crypto(format!("Decryption failed: {}", e)))?;
 
     Ok(plaintext)
@@ -104,6 +144,58 @@ mod tests {
         assert!(decrypt(&key1, &encrypted2).is_err());
     }
 
+    #[test]
+    fn test_legacy_single_key_data_still_decrypts() {
+        // Data written before subkeys used the key for both AES-GCM and HMAC
+        let key = [42u8; 32];
+        let nonce_bytes = secure_random::generate_nonce().unwrap();
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce_bytes), b"legacy data".as_ref())
+            .unwrap();
+        let mut hmac_input = nonce_bytes.clone();
+        hmac_input.extend_from_slice(&ciphertext);
+        let hmac_tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), &hmac_input);
+
+        let legacy = EncryptedData {
+            cipher: CipherSuite::Aes256Gcm,
+            key_schedule: KeySchedule::Legacy,
+            ciphertext,
+            nonce: nonce_bytes,
+            hmac: hmac_tag.as_ref().to_vec(),
+        };
+        assert_eq!(decrypt(&key, &legacy).unwrap(), b"legacy data");
+
+        // Relabelling new data as legacy fails rather than decrypting
+        let mut downgraded = encrypt(&key, b"new data").unwrap();
+        assert_eq!(downgraded.key_schedule, KeySchedule::Hkdf);
+        downgraded.key_schedule = KeySchedule::Legacy;
+        assert!(decrypt(&key, &downgraded).is_err());
+    }
+
+    #[test]
+    fn test_hmac_key_differs_from_cipher_key() {
+        let key = [42u8; 32];
+        let encrypted = encrypt(&key, b"test data").unwrap();
+
+        let mut hmac_input = encrypted.nonce.clone();
+        hmac_input.extend_from_slice(&encrypted.ciphertext);
+        let raw_key = hmac::Key::new(hmac::HMAC_SHA256, &key);
+        assert!(hmac::verify(&raw_key, &hmac_input, &encrypted.hmac).is_err());
+    }
+
+    #[test]
+    fn test_associated_data_must_match() {
+        let key = [42u8; 32];
+        let encrypted = encrypt_with_aad(&key, b"test data", b"header v1").unwrap();
+
+        assert_eq!(
+            decrypt_with_aad(&key, &encrypted, b"header v1").unwrap(),
+            b"test data"
+        );
+        assert!(decrypt_with_aad(&key, &encrypted, b"header v2").is_err());
+        assert!(decrypt(&key, &encrypted).is_err());
+    }
+
     #[test]
     fn test_tampered_data_fails_verification() {
         let key = [42u8; 32];
@@ -0,0 +1,496 @@
+//! Guarded memory for key material
+//!
+//! Keys and decrypted vault contents live in dedicated page-aligned mappings
+//! rather than on the ordinary heap. Each mapping is locked into RAM so it is
+//! never written to swap, excluded from core dumps, and placed between two
+//! inaccessible guard pages so that running off either end faults instead of
+//! reading a neighbouring allocation.
+//!
+//! Every protection is best effort. When one is unavailable, most often
+//! because `RLIMIT_MEMLOCK` is too low to lock another page, the allocation
+//! still succeeds without it and [`status`] reports what is actually in
+//! effect.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Set once any allocation could not be locked into memory
+static LOCK_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Set once any allocation could not be given guard pages
+static GUARD_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Set once any allocation could not be excluded from core dumps
+static DONTDUMP_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Memory protections currently in effect for this process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectionStatus {
+    /// The core file size limit is zero, so a crash leaves no memory image
+    pub core_dumps_disabled: bool,
+    /// Guarded allocations are locked into RAM and never swapped out
+    pub memory_locked: bool,
+    /// Guarded allocations are surrounded by inaccessible guard pages
+    pub guard_pages: bool,
+    /// Guarded allocations are excluded from core dumps
+    pub excluded_from_dumps: bool,
+    /// Soft `RLIMIT_MEMLOCK` limit in bytes; `None` when unlimited or unknown
+    pub memlock_limit: Option<u64>,
+}
+
+impl ProtectionStatus {
+    /// Check whether every protection is active
+    pub fn is_fully_protected(&self) -> bool {
+        self.core_dumps_disabled
+            && self.memory_locked
+            && self.guard_pages
+            && self.excluded_from_dumps
+    }
+}
+
+/// Report which memory protections are active
+///
+/// A one-byte probe allocation is made first, so the report reflects what
+/// the next key allocation would get even if none has been made yet.
+pub fn status() -> ProtectionStatus {
+    let probe = GuardedBuffer::new(1);
+    drop(probe);
+
+    ProtectionStatus {
+        core_dumps_disabled: sys::core_dumps_disabled(),
+        memory_locked: !LOCK_FAILED.load(Ordering::Relaxed),
+        guard_pages: !GUARD_FAILED.load(Ordering::Relaxed),
+        excluded_from_dumps: !DONTDUMP_FAILED.load(Ordering::Relaxed),
+        memlock_limit: sys::memlock_limit(),
+    }
+}
+
+/// Disable core dumps for this process
+///
+/// Sets the core file size limit to zero and, on Linux, marks the process as
+/// non-dumpable, which also stops other processes of the same user from
+/// attaching a debugger. Called once at startup.
+pub fn disable_core_dumps() -> bool {
+    let disabled = sys::disable_core_dumps();
+    if !disabled {
+        tracing::warn!("Could not disable core dumps");
+    }
+    disabled
+}
+
+/// Where the bytes of a guarded buffer live
+enum Backing {
+    /// Dedicated mapping: guard page, data pages, guard page
+    Mapped {
+        base: *mut u8,
+        map_len: usize,
+        data_pages: usize,
+        locked: bool,
+    },
+    /// Ordinary heap memory, used when a mapping cannot be created
+    Heap(Box<[u8]>),
+}
+
+/// Fixed-size byte buffer in guarded memory
+///
+/// The contents are zeroized before the memory is released, and the buffer
+/// never reallocates, so no stale copy is left behind as it is used.
+pub struct GuardedBuffer {
+    backing: Backing,
+    /// Offset of the data within the data pages; the data ends flush against
+    /// the trailing guard page so an overrun faults immediately
+    offset: usize,
+    len: usize,
+}
+
+// The mapping is owned exclusively by the buffer and only reached through
+// `&self`/`&mut self`, like a `Box<[u8]>`.
+unsafe impl Send for GuardedBuffer {}
+unsafe impl Sync for GuardedBuffer {}
+
+impl GuardedBuffer {
+    /// Allocate a zero-filled buffer of `len` bytes
+    pub fn new(len: usize) -> Self {
+        if len == 0 {
+            return Self::on_heap(0);
+        }
+
+        let page = sys::page_size();
+        let data_pages = (len + page - 1) / page * page;
+        let map_len = data_pages + 2 * page;
+
+        let Some(base) = sys::map(map_len) else {
+            if !GUARD_FAILED.swap(true, Ordering::Relaxed) {
+                tracing::warn!("Guarded memory unavailable; keeping secrets on the heap");
+            }
+            LOCK_FAILED.store(true, Ordering::Relaxed);
+            DONTDUMP_FAILED.store(true, Ordering::Relaxed);
+            return Self::on_heap(len);
+        };
+
+        // SAFETY: `base` is the start of a fresh mapping of `map_len` bytes,
+        // so the data pages after the leading guard page are inside it.
+        let data = unsafe { base.add(page) };
+        if !sys::make_accessible(data, data_pages) {
+            sys::unmap(base, map_len);
+            GUARD_FAILED.store(true, Ordering::Relaxed);
+            return Self::on_heap(len);
+        }
+
+        let locked = sys::lock(data, data_pages);
+        if !locked && !LOCK_FAILED.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "Could not lock key memory (RLIMIT_MEMLOCK {}); it may be swapped to disk",
+                sys::memlock_limit().map_or("unlimited".to_string(), |limit| limit.to_string())
+            );
+        }
+        if !sys::exclude_from_dumps(data, data_pages) {
+            DONTDUMP_FAILED.store(true, Ordering::Relaxed);
+        }
+
+        Self {
+            backing: Backing::Mapped {
+                base,
+                map_len,
+                data_pages,
+                locked,
+            },
+            offset: data_pages - len,
+            len,
+        }
+    }
+
+    /// Allocate a buffer holding a copy of `bytes`
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let mut buffer = Self::new(bytes.len());
+        buffer.as_mut_slice().copy_from_slice(bytes);
+        buffer
+    }
+
+    fn on_heap(len: usize) -> Self {
+        Self {
+            backing: Backing::Heap(vec![0u8; len].into_boxed_slice()),
+            offset: 0,
+            len,
+        }
+    }
+
+    /// Access the contents
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.backing {
+            // SAFETY: the data pages are readable and writable for the
+            // lifetime of the buffer, and `offset + len <= data_pages`.
+            Backing::Mapped { base, .. } => unsafe {
+                std::slice::from_raw_parts(base.add(sys::page_size() + self.offset), self.len)
+            },
+            Backing::Heap(bytes) => &bytes[..self.len],
+        }
+    }
+
+    /// Access the contents mutably
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        match &mut self.backing {
+            // SAFETY: as in `as_slice`, and `&mut self` guarantees exclusive
+            // access.
+            Backing::Mapped { base, .. } => unsafe {
+                std::slice::from_raw_parts_mut(base.add(sys::page_size() + self.offset), self.len)
+            },
+            Backing::Heap(bytes) => &mut bytes[..self.len],
+        }
+    }
+
+    /// Length of the contents in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check whether the buffer is locked into RAM
+    pub fn is_locked(&self) -> bool {
+        matches!(self.backing, Backing::Mapped { locked: true, .. })
+    }
+
+    /// Check whether the buffer is surrounded by guard pages
+    pub fn has_guard_pages(&self) -> bool {
+        matches!(self.backing, Backing::Mapped { .. })
+    }
+
+    /// Zero every byte the buffer owns, including any past the current length
+    fn wipe(&mut self) {
+        match &mut self.backing {
+            Backing::Mapped {
+                base, data_pages, ..
+            } => {
+                // SAFETY: the data pages are mapped read-write until drop.
+                let data = unsafe {
+                    std::slice::from_raw_parts_mut(base.add(sys::page_size()), *data_pages)
+                };
+                data.zeroize();
+            }
+            Backing::Heap(bytes) => bytes.zeroize(),
+        }
+    }
+}
+
+/// Only the length and protections are shown, never the contents
+impl std::fmt::Debug for GuardedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardedBuffer")
+            .field("len", &self.len)
+            .field("locked", &self.is_locked())
+            .field("guard_pages", &self.has_guard_pages())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for GuardedBuffer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Clone for GuardedBuffer {
+    fn clone(&self) -> Self {
+        Self::from_slice(self.as_slice())
+    }
+}
+
+impl PartialEq for GuardedBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for GuardedBuffer {}
+
+/// Zeroizing empties the buffer; the memory stays allocated until drop
+impl Zeroize for GuardedBuffer {
+    fn zeroize(&mut self) {
+        self.wipe();
+        self.len = 0;
+    }
+}
+
+impl ZeroizeOnDrop for GuardedBuffer {}
+
+impl Drop for GuardedBuffer {
+    fn drop(&mut self) {
+        self.wipe();
+        if let Backing::Mapped {
+            base,
+            map_len,
+            data_pages,
+            locked,
+        } = self.backing
+        {
+            // SAFETY: the data pages start one page into the mapping.
+            let data = unsafe { base.add(sys::page_size()) };
+            if locked {
+                sys::unlock(data, data_pages);
+            }
+            sys::unmap(base, map_len);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ptr;
+
+    pub fn page_size() -> usize {
+        // SAFETY: sysconf has no memory safety requirements.
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        usize::try_from(size).unwrap_or(4096)
+    }
+
+    /// Map `len` bytes of inaccessible anonymous memory
+    pub fn map(len: usize) -> Option<*mut u8> {
+        // SAFETY: a fresh anonymous mapping does not alias any Rust object.
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+        (base != libc::MAP_FAILED).then_some(base.cast())
+    }
+
+    pub fn unmap(base: *mut u8, len: usize) {
+        // SAFETY: callers pass a mapping created by `map` that is unmapped once.
+        unsafe {
+            libc::munmap(base.cast(), len);
+        }
+    }
+
+    pub fn make_accessible(data: *mut u8, len: usize) -> bool {
+        // SAFETY: `data..data + len` lies within a mapping owned by the caller.
+        unsafe { libc::mprotect(data.cast(), len, libc::PROT_READ | libc::PROT_WRITE) == 0 }
+    }
+
+    pub fn lock(data: *mut u8, len: usize) -> bool {
+        // SAFETY: as in `make_accessible`.
+        unsafe { libc::mlock(data.cast(), len) == 0 }
+    }
+
+    pub fn unlock(data: *mut u8, len: usize) {
+        // SAFETY: as in `make_accessible`.
+        unsafe {
+            libc::munlock(data.cast(), len);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn exclude_from_dumps(data: *mut u8, len: usize) -> bool {
+        // SAFETY: as in `make_accessible`.
+        unsafe { libc::madvise(data.cast(), len, libc::MADV_DONTDUMP) == 0 }
+    }
+
+    /// Only Linux can exclude individual mappings; elsewhere core dumps are
+    /// disabled for the whole process instead
+    #[cfg(not(target_os = "linux"))]
+    pub fn exclude_from_dumps(_data: *mut u8, _len: usize) -> bool {
+        false
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `limit` is a valid rlimit for the duration of the call.
+        let disabled = unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) == 0 };
+
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE takes a plain integer argument.
+        let disabled = disabled && unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) == 0 };
+
+        disabled
+    }
+
+    fn soft_limit(get: impl FnOnce(&mut libc::rlimit) -> libc::c_int) -> Option<libc::rlim_t> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        (get(&mut limit) == 0).then_some(limit.rlim_cur)
+    }
+
+    pub fn core_dumps_disabled() -> bool {
+        // SAFETY: `limit` is a valid, writable rlimit for the duration of the call.
+        soft_limit(|limit| unsafe { libc::getrlimit(libc::RLIMIT_CORE, limit) }) == Some(0)
+    }
+
+    #[allow(clippy::unnecessary_cast)] // rlim_t is not u64 on every platform
+    pub fn memlock_limit() -> Option<u64> {
+        // SAFETY: as in `core_dumps_disabled`.
+        soft_limit(|limit| unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, limit) })
+            .filter(|&limit| limit != libc::RLIM_INFINITY)
+            .map(|limit| limit as u64)
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub fn page_size() -> usize {
+        4096
+    }
+
+    pub fn map(_len: usize) -> Option<*mut u8> {
+        None
+    }
+
+    pub fn unmap(_base: *mut u8, _len: usize) {}
+
+    pub fn make_accessible(_data: *mut u8, _len: usize) -> bool {
+        false
+    }
+
+    pub fn lock(_data: *mut u8, _len: usize) -> bool {
+        false
+    }
+
+    pub fn unlock(_data: *mut u8, _len: usize) {}
+
+    pub fn exclude_from_dumps(_data: *mut u8, _len: usize) -> bool {
+        false
+    }
+
+    pub fn disable_core_dumps() -> bool {
+        false
+    }
+
+    pub fn core_dumps_disabled() -> bool {
+        false
+    }
+
+    pub fn memlock_limit() -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_roundtrip() {
+        let mut buffer = GuardedBuffer::from_slice(b"secret key material");
+        assert_eq!(buffer.as_slice(), b"secret key material");
+        assert_eq!(buffer.len(), 19);
+
+        buffer.as_mut_slice()[0] = b'S';
+        assert_eq!(&buffer.as_slice()[..6], b"Secret");
+
+        let copy = buffer.clone();
+        assert_eq!(copy, buffer);
+
+        buffer.zeroize();
+        assert!(buffer.is_empty());
+        assert_eq!(copy.as_slice(), b"Secret key material");
+    }
+
+    #[test]
+    fn test_larger_than_a_page() {
+        let len = sys::page_size() * 2 + 17;
+        let bytes: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let buffer = GuardedBuffer::from_slice(&bytes);
+        assert_eq!(buffer.as_slice(), &bytes[..]);
+    }
+
+    #[test]
+    fn test_empty_buffer() {
+        let buffer = GuardedBuffer::default();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_slice(), b"");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mapped_with_guard_pages() {
+        let buffer = GuardedBuffer::new(32);
+        assert!(buffer.has_guard_pages());
+        // Locking depends on RLIMIT_MEMLOCK; the status must agree with it
+        if !buffer.is_locked() {
+            assert!(!status().memory_locked);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_status_reports_core_dump_limit() {
+        assert!(disable_core_dumps());
+        let status = status();
+        assert!(status.core_dumps_disabled);
+        assert!(status.guard_pages);
+        #[cfg(target_os = "linux")]
+        assert!(status.excluded_from_dumps);
+    }
+}
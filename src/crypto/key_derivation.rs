@@ -1,38 +1,161 @@
 //! Key derivation functions using Argon2id
 
+use std::time::{Duration, Instant};
+
 use crate::{Result, TwoPasswordError};
 use argon2::password_hash::{rand_core::OsRng, SaltString};
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 
-/// Derive a key from password and salt using Argon2id
-pub fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
-    let argon2 = Argon2::default();
+/// Upper bound on the memory cost accepted from a vault header (4 GiB)
+const MAX_MEMORY_COST_KIB: u32 = 4 * 1024 * 1024;
 
-    // Convert salt bytes to SaltString using base64 encoding without padding
-    use base64::engine::{general_purpose, Engine as _};
-    let salt_b64 = general_purpose::STANDARD_NO_PAD.encode(salt);
-    let salt_string = SaltString::from_b64(&salt_b64)
-        .map_err(|e| TwoPasswordError::crypto(format!("Salt conversion failed: {}", e)))?;
+/// Upper bound on the time cost accepted from a vault header
+const MAX_TIME_COST: u32 = 64;
 
-    // Hash the password
-    let password_hash = argon2
-        .hash_password(password.as_bytes(), &salt_string)
-        .map_err(|e| TwoPasswordError::crypto(format!("Key derivation failed: {}", e)))?;
+/// Upper bound on the parallelism accepted from a vault header
+const MAX_PARALLELISM: u32 = 16;
+
+/// Key derivation algorithm recorded in the vault header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfAlgorithm {
+    /// Argon2id, version 0x13
+    Argon2id,
+}
+
+/// Key derivation parameters stored alongside each vault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct KdfParams {
+    pub algorithm: KdfAlgorithm,
+    /// Memory cost in KiB
+    pub memory_cost_kib: u32,
+    /// Number of passes over memory
+    pub time_cost: u32,
+    /// Degree of parallelism (lanes)
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// Parameters used by vaults created before they were recorded
+    fn default() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_cost_kib: crate::config::ARGON2_MEMORY_COST_KIB,
+            time_cost: crate::config::ARGON2_TIME_COST,
+            parallelism: crate::config::ARGON2_PARALLELISM,
+        }
+    }
+}
 
-    // Extract the hash bytes
-    let hash_bytes = password_hash
-        .hash
-        .ok_or_else(|| TwoPasswordError::crypto("No hash in password hash"))?;
+impl KdfParams {
+    /// Cheap parameters that keep tests fast; far too weak for real use
+    #[doc(hidden)]
+    pub fn for_tests() -> Self {
+        Self {
+            memory_cost_kib: 8 * 1024,
+            time_cost: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Check the parameters are within the range this build accepts
+    pub fn validate(&self) -> Result<()> {
+        if self.memory_cost_kib > MAX_MEMORY_COST_KIB
+            || self.time_cost == 0
+            || self.time_cost > MAX_TIME_COST
+            || self.parallelism == 0
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(TwoPasswordError::crypto(format!(
+                "Unsupported key derivation parameters: m={} KiB, t={}, p={}",
+                self.memory_cost_kib, self.time_cost, self.parallelism
+            )));
+        }
+
+        self.argon2().map(|_| ())
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(
+            self.memory_cost_kib,
+            self.time_cost,
+            self.parallelism,
+            Some(crate::config::KEY_SIZE),
+        )
+        .map_err(|e| {
+            TwoPasswordError::crypto(format!("Invalid key derivation parameters: {}", e))
+        })?;
+
+        let algorithm = match self.algorithm {
+            KdfAlgorithm::Argon2id => Algorithm::Argon2id,
+        };
+
+        Ok(Argon2::new(algorithm, Version::V0x13, params))
+    }
+}
+
+/// Derive a key from password and salt using Argon2id with default parameters
+pub fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    derive_key_with_params(password, salt, &KdfParams::default())
+}
+
+/// Derive a key from password and salt using the given parameters
+pub fn derive_key_with_params(password: &str, salt: &[u8], params: &KdfParams) -> Result<[u8; 32]> {
+    params.validate()?;
 
-    // Convert to fixed-size array
     let mut key = [0u8; 32];
-    let hash_slice = hash_bytes.as_bytes();
-    let copy_len = std::cmp::min(32, hash_slice.len());
-    key[..copy_len].copy_from_slice(&hash_slice[..copy_len]);
+    params
+        .argon2()?
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| TwoPasswordError::crypto(format!("Key derivation failed: {}", e)))?;
 
     Ok(key)
 }
 
+/// Benchmark this machine and pick parameters whose derivation takes about
+/// `target` without exceeding `max_memory_kib`
+///
+/// Memory cost is raised first, as it is the more expensive resource for an
+/// attacker, then time cost. Parameters never drop below the defaults.
+/// Returns the chosen parameters and the measured derivation time.
+pub fn tune_params(target: Duration, max_memory_kib: u32) -> Result<(KdfParams, Duration)> {
+    let salt = [0u8; crate::config::SALT_SIZE];
+    let measure = |params: &KdfParams| -> Result<Duration> {
+        let start = Instant::now();
+        derive_key_with_params("TwoPassword-KDF-benchmark", &salt, params)?;
+        Ok(start.elapsed())
+    };
+
+    let mut params = KdfParams::default();
+    let mut elapsed = measure(&params)?;
+
+    // Double memory while the doubled cost still fits the target
+    while elapsed * 2 <= target && params.memory_cost_kib <= max_memory_kib / 2 {
+        let candidate = KdfParams {
+            memory_cost_kib: params.memory_cost_kib * 2,
+            ..params
+        };
+        let candidate_elapsed = measure(&candidate)?;
+        if candidate_elapsed > target {
+            break;
+        }
+        params = candidate;
+        elapsed = candidate_elapsed;
+    }
+
+    // Then add passes, estimating each from the cost of a single pass
+    let per_pass = elapsed / params.time_cost;
+    while per_pass * (params.time_cost + 1) <= target && params.time_cost < MAX_TIME_COST {
+        params.time_cost += 1;
+    }
+
+    if params.time_cost != KdfParams::default().time_cost || elapsed > target {
+        elapsed = measure(&params)?;
+    }
+
+    Ok((params, elapsed))
+}
+
 /// Hash a password for storage (with random salt)
 pub fn hash_password_for_storage(password: &str) -> Result<String> {
     let salt = SaltString::generate(&mut OsRng);
@@ -83,6 +206,77 @@ mod tests {
         assert_ne!(key, key3);
     }
 
+    #[test]
+    fn test_default_params_match_legacy_derivation() {
+        // Vaults created before parameters were recorded hashed through a
+        // base64 SaltString with Argon2::default(); they must keep opening.
+        use base64::engine::{general_purpose, Engine as _};
+
+        let password = "test_password";
+        let salt = [7u8; 32];
+
+        let salt_b64 = general_purpose::STANDARD_NO_PAD.encode(salt);
+        let salt_string = SaltString::from_b64(&salt_b64).unwrap();
+        let legacy_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt_string)
+            .unwrap();
+        let legacy_key = legacy_hash.hash.unwrap();
+
+        let key = derive_key(password, &salt).unwrap();
+        assert_eq!(&key[..], legacy_key.as_bytes());
+    }
+
+    #[test]
+    fn test_derive_key_with_params() {
+        let salt = [1u8; 32];
+        let params = KdfParams::for_tests();
+
+        let key = derive_key_with_params("password", &salt, &params).unwrap();
+        assert_eq!(
+            key,
+            derive_key_with_params("password", &salt, &params).unwrap()
+        );
+        assert_ne!(key, derive_key("password", &salt).unwrap());
+    }
+
+    #[test]
+    fn test_params_validation() {
+        assert!(KdfParams::default().validate().is_ok());
+
+        let too_much_memory = KdfParams {
+            memory_cost_kib: u32::MAX,
+            ..KdfParams::default()
+        };
+        assert!(too_much_memory.validate().is_err());
+
+        let no_passes = KdfParams {
+            time_cost: 0,
+            ..KdfParams::default()
+        };
+        assert!(no_passes.validate().is_err());
+    }
+
+    #[test]
+    fn test_params_default_when_missing() {
+        #[derive(serde::Deserialize)]
+        struct Header {
+            #[serde(default)]
+            kdf: KdfParams,
+        }
+
+        let header: Header = serde_json::from_str("{}").unwrap();
+        assert_eq!(header.kdf, KdfParams::default());
+
+        let json = serde_json::to_string(&KdfParams::default()).unwrap();
+        assert!(json.contains("\"argon2id\""));
+    }
+
+    #[test]
+    fn test_tune_params_never_below_defaults() {
+        let (params, _) = tune_params(Duration::ZERO, 64 * 1024).unwrap();
+        assert_eq!(params, KdfParams::default());
+    }
+
     #[test]
     fn test_hash_and_verify_password() {
         let password = "test_password";
@@ -0,0 +1,143 @@
+//! HKDF-SHA256 subkey schedule
+//!
+//! A key is never used for more than one purpose. Each purpose expands its
+//! own 32-byte subkey from the parent key with a distinct label, so the vault
+//! data key yields independent keys for payload encryption, header
+//! authentication, search indexing and exports, and every AEAD key yields
+//! separate cipher and HMAC keys.
+
+use crate::crypto::MasterKey;
+use crate::{Result, TwoPasswordError};
+use ring::hkdf;
+
+/// Fixed HKDF salt; the input keys are already uniformly random
+const HKDF_SALT: &[u8] = b"TwoPassword-HKDF-Salt-v1";
+
+/// Label for the cipher key expanded from an AEAD key
+const AEAD_CIPHER_LABEL: &[u8] = b"TwoPassword v1 aead cipher";
+
+/// Label for the HMAC key expanded from an AEAD key
+const AEAD_MAC_LABEL: &[u8] = b"TwoPassword v1 aead hmac";
+
+/// How the keys protecting a piece of encrypted data were obtained
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySchedule {
+    /// The caller's key was used directly for both cipher and HMAC, as in
+    /// data written before subkeys were introduced
+    #[default]
+    Legacy,
+    /// Cipher and HMAC keys are expanded from the caller's key with HKDF
+    Hkdf,
+}
+
+/// Purposes of subkeys derived from the vault data key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPurpose {
+    /// Encrypting the vault payload
+    Encryption,
+    /// Authenticating the vault header
+    HeaderAuthentication,
+    /// Keyed hashing for search indexes
+    SearchIndex,
+    /// Protecting exports derived from the vault
+    Export,
+    /// Wrapping the keys of file attachments
+    AttachmentKeys,
+}
+
+impl KeyPurpose {
+    /// Domain separation label passed to HKDF-Expand
+    pub fn label(&self) -> &'static [u8] {
+        match self {
+            KeyPurpose::Encryption => b"TwoPassword v1 vault encryption",
+            KeyPurpose::HeaderAuthentication => b"TwoPassword v1 header authentication",
+            KeyPurpose::SearchIndex => b"TwoPassword v1 search index",
+            KeyPurpose::Export => b"TwoPassword v1 export",
+            KeyPurpose::AttachmentKeys => b"TwoPassword v1 attachment keys",
+        }
+    }
+}
+
+/// Output length marker for 32-byte HKDF output
+struct KeyLength;
+
+impl hkdf::KeyType for KeyLength {
+    fn len(&self) -> usize {
+        crate::config::KEY_SIZE
+    }
+}
+
+/// Expand a 32-byte subkey from `key` for the given label
+pub fn expand(key: &[u8; 32], label: &[u8]) -> Result<MasterKey> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, HKDF_SALT).extract(key);
+    let info = [label];
+    let okm = prk
+        .expand(&info, KeyLength)
+        .map_err(|_| TwoPasswordError::crypto("HKDF expansion failed"))?;
+
+    MasterKey::fill_with(|subkey| {
+        okm.fill(subkey)
+            .map_err(|_| TwoPasswordError::crypto("HKDF expansion failed"))
+    })
+}
+
+/// Derive the subkey for a purpose from the vault data key
+pub fn derive_subkey(key: &MasterKey, purpose: KeyPurpose) -> Result<MasterKey> {
+    expand(key.as_bytes(), purpose.label())
+}
+
+/// Get the cipher and HMAC keys for an AEAD key under the given schedule
+pub(crate) fn aead_keys(key: &[u8; 32], schedule: KeySchedule) -> Result<(MasterKey, MasterKey)> {
+    match schedule {
+        KeySchedule::Legacy => Ok((MasterKey::from_bytes(*key), MasterKey::from_bytes(*key))),
+        KeySchedule::Hkdf => Ok((
+            expand(key, AEAD_CIPHER_LABEL)?,
+            expand(key, AEAD_MAC_LABEL)?,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subkeys_are_deterministic_and_separated() {
+        let key = MasterKey::from_bytes([7u8; 32]);
+        let purposes = [
+            KeyPurpose::Encryption,
+            KeyPurpose::HeaderAuthentication,
+            KeyPurpose::SearchIndex,
+            KeyPurpose::Export,
+            KeyPurpose::AttachmentKeys,
+        ];
+
+        let subkeys: Vec<[u8; 32]> = purposes
+            .iter()
+            .map(|&purpose| *derive_subkey(&key, purpose).unwrap().as_bytes())
+            .collect();
+
+        for (i, subkey) in subkeys.iter().enumerate() {
+            assert_ne!(subkey, key.as_bytes());
+            assert_eq!(subkey, derive_subkey(&key, purposes[i]).unwrap().as_bytes());
+            for other in &subkeys[i + 1..] {
+                assert_ne!(subkey, other);
+            }
+        }
+    }
+
+    #[test]
+    fn test_aead_keys() {
+        let key = [9u8; 32];
+
+        let (cipher_key, mac_key) = aead_keys(&key, KeySchedule::Legacy).unwrap();
+        assert_eq!(cipher_key.as_bytes(), &key);
+        assert_eq!(mac_key.as_bytes(), &key);
+
+        let (cipher_key, mac_key) = aead_keys(&key, KeySchedule::Hkdf).unwrap();
+        assert_ne!(cipher_key.as_bytes(), &key);
+        assert_ne!(mac_key.as_bytes(), &key);
+        assert_ne!(cipher_key.as_bytes(), mac_key.as_bytes());
+    }
+}
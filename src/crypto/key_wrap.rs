@@ -0,0 +1,84 @@
+//! Data key wrapping
+//!
+//! Vault contents are encrypted with a random data-encryption key (DEK). The
+//! DEK is stored in the vault file once per unlock method, each time wrapped
+//! with a different key-encryption key (KEK): the password-derived key, a
+//! standalone recovery key, or the secret reconstructed from recovery shares.
+
+use crate::crypto::{aes_gcm, EncryptedData, MasterKey};
+use crate::{Result, TwoPasswordError};
+use zeroize::Zeroize;
+
+/// Unlock method a key slot belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySlotKind {
+    /// KEK derived from the master password with the vault's KDF parameters
+    Password,
+    /// KEK is a random recovery key held by the user
+    RecoveryKey,
+    /// KEK is the master secret reconstructed from Shamir shares
+    SecretSharing,
+}
+
+impl std::fmt::Display for KeySlotKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            KeySlotKind::Password => "password",
+            KeySlotKind::RecoveryKey => "recovery key",
+            KeySlotKind::SecretSharing => "secret sharing",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A copy of the data key wrapped by one key-encryption key
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct KeySlot {
+    pub kind: KeySlotKind,
+    pub wrapped_key: EncryptedData,
+}
+
+/// Wrap a data key with a key-encryption key
+pub fn wrap_key(kek: &MasterKey, data_key: &MasterKey) -> Result<EncryptedData> {
+    aes_gcm::encrypt(kek.as_bytes(), data_key.as_bytes())
+}
+
+/// Unwrap a data key, failing if the key-encryption key is wrong
+pub fn unwrap_key(kek: &MasterKey, wrapped: &EncryptedData) -> Result<MasterKey> {
+    let mut key_bytes = aes_gcm::decrypt(kek.as_bytes(), wrapped)?;
+
+    let result = <[u8; 32]>::try_from(key_bytes.as_slice())
+        .map(MasterKey::from_bytes)
+        .map_err(|_| TwoPasswordError::crypto("Invalid wrapped key length"));
+
+    key_bytes.zeroize();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap_roundtrip() {
+        let kek = MasterKey::generate().unwrap();
+        let data_key = MasterKey::generate().unwrap();
+
+        let wrapped = wrap_key(&kek, &data_key).unwrap();
+        assert_ne!(wrapped.ciphertext[..32], data_key.as_bytes()[..]);
+
+        let unwrapped = unwrap_key(&kek, &wrapped).unwrap();
+        assert_eq!(unwrapped.as_bytes(), data_key.as_bytes());
+    }
+
+    #[test]
+    fn test_unwrap_with_wrong_kek_fails() {
+        let kek = MasterKey::generate().unwrap();
+        let other = MasterKey::generate().unwrap();
+        let data_key = MasterKey::generate().unwrap();
+
+        let wrapped = wrap_key(&kek, &data_key).unwrap();
+        assert!(unwrap_key(&other, &wrapped).is_err());
+    }
+}
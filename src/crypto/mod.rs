@@ -4,34 +4,163 @@
 //! operations using industry-standard algorithms.
 
 use crate::{Result, TwoPasswordError};
-use zeroize::ZeroizeOnDrop;
+use guarded::GuardedBuffer;
+use key_schedule::{KeyPurpose, KeySchedule};
+use ring::hmac;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+pub use secret::{SecretBytes, SecretString};
 
 pub mod aes_gcm;
+pub mod guarded;
 pub mod key_derivation;
-pub mod secure_random;
+pub mod key_schedule;
+pub mod key_wrap;
+pub mod otp;
+pub mod secret;
 pub mod secret_sharing;
+pub mod secure_random;
+pub mod stream;
+pub mod xchacha20;
 
 /// Master key for encryption operations
+///
+/// The key bytes live in guarded memory, so moving a `MasterKey` never copies
+/// them and cloning places the copy in a new guarded allocation.
 #[derive(Clone, ZeroizeOnDrop)]
 pub struct MasterKey {
-    key: [u8; 32],
+    key: GuardedBuffer,
 }
 
 impl MasterKey {
-    /// Create a new master key from raw bytes
-    pub fn from_bytes(bytes: [u8; 32]) -> Self {
-        Self { key: bytes }
+    /// Create a new master key from raw bytes, wiping the caller's copy
+    pub fn from_bytes(mut bytes: [u8; 32]) -> Self {
+        let key = GuardedBuffer::from_slice(&bytes);
+        bytes.zeroize();
+        Self { key }
+    }
+
+    /// Create a key by filling guarded memory in place
+    pub(crate) fn fill_with(fill: impl FnOnce(&mut [u8]) -> Result<()>) -> Result<Self> {
+        let mut key = GuardedBuffer::new(crate::config::KEY_SIZE);
+        fill(key.as_mut_slice())?;
+        Ok(Self { key })
+    }
+
+    /// Generate a new random key
+    pub fn generate() -> Result<Self> {
+        Self::fill_with(secure_random::fill_random)
     }
 
     /// Get the key bytes (should be used carefully)
     pub fn as_bytes(&self) -> &[u8; 32] {
-        &self.key
+        self.key
+            .as_slice()
+            .try_into()
+            .expect("master key is always 32 bytes")
+    }
+
+    /// Check whether the key is locked into RAM
+    pub fn is_locked(&self) -> bool {
+        self.key.is_locked()
+    }
+}
+
+/// Authenticated encryption algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CipherSuite {
+    /// AES-256-GCM with 12-byte random nonces
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// XChaCha20-Poly1305 with 24-byte random nonces
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// Identifier used in vault files and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            CipherSuite::Aes256Gcm => "aes-256-gcm",
+            CipherSuite::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+}
+
+impl std::fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for CipherSuite {
+    type Err = TwoPasswordError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "aes-256-gcm" | "aes256gcm" | "aes" => Ok(CipherSuite::Aes256Gcm),
+            "xchacha20-poly1305" | "xchacha20poly1305" | "xchacha" => {
+                Ok(CipherSuite::XChaCha20Poly1305)
+            }
+            _ => Err(TwoPasswordError::validation(format!(
+                "Unknown cipher '{}' (expected aes-256-gcm or xchacha20-poly1305)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Encrypt data with the given cipher suite
+pub fn encrypt_with(
+    cipher: CipherSuite,
+    key: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<EncryptedData> {
+    encrypt_with_aad(cipher, key, plaintext, &[])
+}
+
+/// Encrypt data with the given cipher suite, binding `aad` as associated data
+pub fn encrypt_with_aad(
+    cipher: CipherSuite,
+    key: &[u8; 32],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<EncryptedData> {
+    match cipher {
+        CipherSuite::Aes256Gcm => aes_gcm::encrypt_with_aad(key, plaintext, aad),
+        CipherSuite::XChaCha20Poly1305 => xchacha20::encrypt_with_aad(key, plaintext, aad),
+    }
+}
+
+/// Decrypt data with the cipher suite recorded in it
+pub fn decrypt_any(key: &[u8; 32], encrypted: &EncryptedData) -> Result<Vec<u8>> {
+    decrypt_any_with_aad(key, encrypted, &[])
+}
+
+/// Decrypt data with the cipher suite recorded in it and the associated data
+/// it was encrypted with
+pub fn decrypt_any_with_aad(
+    key: &[u8; 32],
+    encrypted: &EncryptedData,
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    match encrypted.cipher {
+        CipherSuite::Aes256Gcm => aes_gcm::decrypt_with_aad(key, encrypted, aad),
+        CipherSuite::XChaCha20Poly1305 => xchacha20::decrypt_with_aad(key, encrypted, aad),
     }
 }
 
 /// Encrypted data container
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EncryptedData {
+    /// Cipher suite used; data written before this field existed is AES-256-GCM
+    #[serde(default)]
+    pub cipher: CipherSuite,
+    /// How the cipher and HMAC keys were obtained; data written before this
+    /// field existed used one key for both
+    #[serde(default)]
+    pub key_schedule: KeySchedule,
     /// The encrypted ciphertext
     pub ciphertext: Vec<u8>,
     /// The nonce used for encryption
@@ -86,29 +215,106 @@ impl CryptoManager {
         self.master_key = None;
     }
 
-    /// Encrypt data using the current master key
+    /// Encrypt data using the current master key with AES-256-GCM
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedData> {
-        let key = self
-            .master_key
-            .as_ref()
-            .ok_or_else(|| TwoPasswordError::crypto("Master key not set"))?;
+        self.encrypt_with(CipherSuite::Aes256Gcm, plaintext)
+    }
+
+    /// Encrypt data using the current master key with the given cipher suite
+    ///
+    /// The data is encrypted under the encryption subkey, never the master
+    /// key itself.
+    pub fn encrypt_with(&self, cipher: CipherSuite, plaintext: &[u8]) -> Result<EncryptedData> {
+        self.encrypt_with_aad(cipher, plaintext, &[])
+    }
+
+    /// Encrypt data with the given cipher suite, binding `aad` as associated
+    /// data
+    pub fn encrypt_with_aad(
+        &self,
+        cipher: CipherSuite,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<EncryptedData> {
+        let key = self.derive_subkey(KeyPurpose::Encryption)?;
+        encrypt_with_aad(cipher, key.as_bytes(), plaintext, aad)
+    }
+
+    /// Decrypt data using the current master key and the cipher suite
+    /// recorded in the data
+    pub fn decrypt(&self, encrypted: &EncryptedData) -> Result<SecretBytes> {
+        self.decrypt_with_aad(encrypted, &[])
+    }
+
+    /// Decrypt data that was encrypted with associated data
+    pub fn decrypt_with_aad(&self, encrypted: &EncryptedData, aad: &[u8]) -> Result<SecretBytes> {
+        let plaintext = match encrypted.key_schedule {
+            // Written before subkeys existed, directly under the master key
+            KeySchedule::Legacy => decrypt_any_with_aad(self.key()?.as_bytes(), encrypted, aad)?,
+            KeySchedule::Hkdf => {
+                let key = self.derive_subkey(KeyPurpose::Encryption)?;
+                decrypt_any_with_aad(key.as_bytes(), encrypted, aad)?
+            }
+        };
+        Ok(SecretBytes::from(plaintext))
+    }
 
-        aes_gcm::encrypt(key.as_bytes(), plaintext)
+    /// Derive the subkey for a purpose from the current master key
+    pub fn derive_subkey(&self, purpose: KeyPurpose) -> Result<MasterKey> {
+        key_schedule::derive_subkey(self.key()?, purpose)
     }
 
-    /// Decrypt data using the current master key
-    pub fn decrypt(&self, encrypted: &EncryptedData) -> Result<Vec<u8>> {
-        let key = self
-            .master_key
+    /// Compute an authentication tag over vault header bytes
+    pub fn authenticate_header(&self, header: &[u8]) -> Result<Vec<u8>> {
+        let key = self.derive_subkey(KeyPurpose::HeaderAuthentication)?;
+        let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        Ok(hmac::sign(&hmac_key, header).as_ref().to_vec())
+    }
+
+    /// Verify an authentication tag over vault header bytes
+    pub fn verify_header(&self, header: &[u8], tag: &[u8]) -> Result<()> {
+        let key = self.derive_subkey(KeyPurpose::HeaderAuthentication)?;
+        let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        hmac::verify(&hmac_key, header, tag).map_err(|_| TwoPasswordError::InvalidVaultFormat)
+    }
+
+    /// Get the current master key
+    pub(crate) fn key(&self) -> Result<&MasterKey> {
+        self.master_key
             .as_ref()
-            .ok_or_else(|| TwoPasswordError::crypto("Master key not set"))?;
+            .ok_or_else(|| TwoPasswordError::crypto("Master key not set"))
+    }
 
-        aes_gcm::decrypt(key.as_bytes(), encrypted)
+    /// Replace the master key with a freshly generated random data key
+    pub fn generate_master_key(&mut self) -> Result<()> {
+        self.master_key = Some(MasterKey::generate()?);
+        Ok(())
+    }
+
+    /// Wrap the current master key with a key-encryption key
+    pub fn wrap_master_key(&self, kek: &MasterKey) -> Result<EncryptedData> {
+        key_wrap::wrap_key(kek, self.key()?)
+    }
+
+    /// Unwrap a data key with a key-encryption key and make it the master key
+    pub fn unwrap_master_key(&mut self, kek: &MasterKey, wrapped: &EncryptedData) -> Result<()> {
+        self.master_key = Some(key_wrap::unwrap_key(kek, wrapped)?);
+        Ok(())
     }
 
     /// Derive master key from password and salt
     pub fn derive_key(&mut self, password: &str, salt: &Salt) -> Result<()> {
-        let key_bytes = key_derivation::derive_key(password, &salt.bytes)?;
+        self.derive_key_with_params(password, salt, &key_derivation::KdfParams::default())
+    }
+
+    /// Derive master key from password and salt using explicit KDF parameters
+    pub fn derive_key_with_params(
+        &mut self,
+        password: &str,
+        salt: &Salt,
+        params: &key_derivation::KdfParams,
+    ) -> Result<()> {
+        let key_bytes = key_derivation::derive_key_with_params(password, &salt.bytes, params)?;
         self.master_key = Some(MasterKey::from_bytes(key_bytes));
         Ok(())
     }
@@ -0,0 +1,512 @@
+//! One-time passwords
+//!
+//! HOTP (RFC 4226) and TOTP (RFC 6238) codes for two-factor authentication.
+//! Entries keep the shared secret base32-encoded, as authenticator apps and
+//! `otpauth://` URIs exchange it:
+//!
+//! ```text
+//! otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub&period=30
+//! ```
+
+use crate::crypto::SecretString;
+use crate::{Result, TwoPasswordError};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+/// Characters of the RFC 4648 base32 alphabet, by value
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Scheme of key URIs, as defined by Google Authenticator
+pub const URI_SCHEME: &str = "otpauth";
+
+/// Digits in a code unless configured otherwise
+pub const DEFAULT_DIGITS: u32 = 6;
+
+/// Seconds a TOTP code is valid unless configured otherwise
+pub const DEFAULT_PERIOD: u64 = 30;
+
+/// Hash function used for the HMAC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OtpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    /// Identifier used in key URIs
+    pub fn name(&self) -> &'static str {
+        match self {
+            OtpAlgorithm::Sha1 => "SHA1",
+            OtpAlgorithm::Sha256 => "SHA256",
+            OtpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+
+    fn hmac(&self) -> hmac::Algorithm {
+        match self {
+            // SHA-1 is still sound as an HMAC, and most services use it
+            OtpAlgorithm::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            OtpAlgorithm::Sha256 => hmac::HMAC_SHA256,
+            OtpAlgorithm::Sha512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+impl std::fmt::Display for OtpAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for OtpAlgorithm {
+    type Err = TwoPasswordError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(OtpAlgorithm::Sha1),
+            "SHA256" => Ok(OtpAlgorithm::Sha256),
+            "SHA512" => Ok(OtpAlgorithm::Sha512),
+            _ => Err(TwoPasswordError::validation(format!(
+                "Unknown OTP algorithm '{}' (expected SHA1, SHA256 or SHA512)",
+                s
+            ))),
+        }
+    }
+}
+
+/// How the moving factor of a code is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    /// Time-based: a new code every `period` seconds
+    Totp { period: u64 },
+    /// Counter-based: a new code each time one is used
+    Hotp { counter: u64 },
+}
+
+/// One-time password settings of an entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Otp {
+    /// Shared secret, base32-encoded without padding
+    pub secret: SecretString,
+    pub kind: OtpKind,
+    #[serde(default)]
+    pub algorithm: OtpAlgorithm,
+    pub digits: u32,
+    /// Service the secret belongs to, as given in the key URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Account name, as given in the key URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+}
+
+/// A generated code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpCode {
+    pub code: String,
+    /// Seconds until a TOTP code changes; `None` for HOTP
+    pub remaining: Option<u64>,
+}
+
+impl Otp {
+    /// TOTP settings with the usual defaults for a base32 secret
+    pub fn totp(secret: &str) -> Result<Self> {
+        let otp = Self {
+            secret: normalize_secret(secret)?,
+            kind: OtpKind::Totp {
+                period: DEFAULT_PERIOD,
+            },
+            algorithm: OtpAlgorithm::default(),
+            digits: DEFAULT_DIGITS,
+            issuer: None,
+            account: None,
+        };
+        Ok(otp)
+    }
+
+    /// Read an `otpauth://` URI or a bare base32 TOTP secret
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let is_uri = input
+            .get(..URI_SCHEME.len() + 1)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(&format!("{}:", URI_SCHEME)));
+        if is_uri {
+            Self::from_uri(input)
+        } else {
+            Self::totp(input)
+        }
+    }
+
+    /// Read an `otpauth://totp/...` or `otpauth://hotp/...` key URI
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| TwoPasswordError::validation(format!("Invalid otpauth URI: {}", reason));
+        let url = url::Url::parse(uri.trim()).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != URI_SCHEME {
+            return Err(invalid("the scheme must be otpauth"));
+        }
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut algorithm = OtpAlgorithm::default();
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter = None;
+        for (key, value) in url.query_pairs() {
+            let number = || {
+                value
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| invalid(&format!("{} must be a number", key)))
+            };
+            match key.to_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "issuer" => issuer = non_empty(&value),
+                "algorithm" => algorithm = value.parse()?,
+                "digits" => {
+                    digits = u32::try_from(number()?).map_err(|_| invalid("too many digits"))?
+                }
+                "period" => period = number()?,
+                "counter" => counter = Some(number()?),
+                _ => {}
+            }
+        }
+
+        let kind = match url.host_str().map(str::to_lowercase).as_deref() {
+            Some("totp") => OtpKind::Totp { period },
+            Some("hotp") => OtpKind::Hotp {
+                counter: counter.ok_or_else(|| invalid("HOTP URIs need a counter"))?,
+            },
+            _ => return Err(invalid("the type must be totp or hotp")),
+        };
+
+        // The label is "issuer:account" or just the account
+        let label = percent_decode(url.path().trim_start_matches('/'));
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (non_empty(issuer), non_empty(account)),
+            None => (None, non_empty(&label)),
+        };
+
+        let otp = Self {
+            secret: secret.ok_or_else(|| invalid("the secret is missing"))?,
+            kind,
+            algorithm,
+            digits,
+            issuer: issuer.or(label_issuer),
+            account,
+        };
+        otp.validate()?;
+        Ok(otp)
+    }
+
+    /// Key URI for these settings, for moving them to another app
+    pub fn to_uri(&self) -> SecretString {
+        let (kind, parameter, value) = match self.kind {
+            OtpKind::Totp { period } => ("totp", "period", period),
+            OtpKind::Hotp { counter } => ("hotp", "counter", counter),
+        };
+        let mut url = url::Url::parse(&format!("{}://{}/", URI_SCHEME, kind))
+            .expect("otpauth URIs are valid URLs");
+
+        let label = [self.issuer.as_deref(), self.account.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(":");
+        url.path_segments_mut()
+            .expect("otpauth URIs have a path")
+            .clear()
+            .push(&label);
+
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("secret", self.secret.expose());
+            if let Some(issuer) = &self.issuer {
+                query.append_pair("issuer", issuer);
+            }
+            query
+                .append_pair("algorithm", self.algorithm.name())
+                .append_pair("digits", &self.digits.to_string())
+                .append_pair(parameter, &value.to_string());
+        }
+        SecretString::from(String::from(url))
+    }
+
+    /// Check the settings can produce codes
+    pub fn validate(&self) -> Result<()> {
+        if !(6..=8).contains(&self.digits) {
+            return Err(TwoPasswordError::validation(format!(
+                "One-time passwords have 6 to 8 digits, not {}",
+                self.digits
+            )));
+        }
+        if self.kind == (OtpKind::Totp { period: 0 }) {
+            return Err(TwoPasswordError::validation(
+                "The TOTP period must be at least one second",
+            ));
+        }
+        decode_base32(self.secret.expose()).map(|_| ())
+    }
+
+    /// Generate the current code
+    ///
+    /// HOTP codes use the stored counter; call [`Otp::advance`] once the
+    /// code has been handed out.
+    pub fn code(&self, now: chrono::DateTime<chrono::Utc>) -> Result<OtpCode> {
+        self.validate()?;
+        let key = decode_base32(self.secret.expose())?;
+        let code = match self.kind {
+            OtpKind::Totp { period } => {
+                let time = u64::try_from(now.timestamp()).unwrap_or(0);
+                OtpCode {
+                    code: hotp(&key, time / period, self.algorithm, self.digits),
+                    remaining: Some(period - time % period),
+                }
+            }
+            OtpKind::Hotp { counter } => OtpCode {
+                code: hotp(&key, counter, self.algorithm, self.digits),
+                remaining: None,
+            },
+        };
+        Ok(code)
+    }
+
+    /// Move an HOTP counter past the code last generated
+    pub fn advance(&mut self) {
+        if let OtpKind::Hotp { counter } = &mut self.kind {
+            *counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+impl std::fmt::Display for Otp {
+    /// Summary of the settings, without the secret
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            OtpKind::Totp { period } => write!(
+                f,
+                "TOTP ({}, {} digits, every {}s)",
+                self.algorithm, self.digits, period
+            ),
+            OtpKind::Hotp { counter } => write!(
+                f,
+                "HOTP ({}, {} digits, counter {})",
+                self.algorithm, self.digits, counter
+            ),
+        }
+    }
+}
+
+/// Compute an HOTP value (RFC 4226)
+pub fn hotp(key: &[u8], counter: u64, algorithm: OtpAlgorithm, digits: u32) -> String {
+    let key = hmac::Key::new(algorithm.hmac(), key);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+
+    // Dynamic truncation: 31 bits from the offset in the last nibble
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let bytes: [u8; 4] = digest[offset..offset + 4]
+        .try_into()
+        .expect("offset leaves four bytes");
+    let value = u64::from(u32::from_be_bytes(bytes) & 0x7fff_ffff);
+    format!(
+        "{:0width$}",
+        value % 10u64.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Compute a TOTP value (RFC 6238) for a Unix time
+pub fn totp(key: &[u8], time: u64, period: u64, algorithm: OtpAlgorithm, digits: u32) -> String {
+    hotp(key, time / period, algorithm, digits)
+}
+
+/// Decode base32, ignoring case, spaces, dashes and padding
+pub fn decode_base32(input: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let mut out = Zeroizing::new(Vec::with_capacity(input.len() * 5 / 8));
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').chars() {
+        if c.is_whitespace() || c == '-' {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&letter| char::from(letter) == c.to_ascii_uppercase())
+            .ok_or_else(|| {
+                TwoPasswordError::validation(format!("Invalid character '{}' in OTP secret", c))
+            })?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if out.is_empty() {
+        return Err(TwoPasswordError::validation("The OTP secret is empty"));
+    }
+    Ok(out)
+}
+
+/// Check a base32 secret and bring it into its stored form
+fn normalize_secret(secret: &str) -> Result<SecretString> {
+    decode_base32(secret)?;
+    let normalized: String = secret
+        .trim_end_matches('=')
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    Ok(SecretString::from(normalized))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Decode `%XX` escapes in a URI path
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_KEY_SHA1: &[u8] = b"12345678901234567890";
+    const RFC_KEY_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const RFC_KEY_SHA512: &[u8] =
+        b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn test_rfc4226_hotp_vectors() {
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(RFC_KEY_SHA1, counter as u64, OtpAlgorithm::Sha1, 6),
+                *code
+            );
+        }
+    }
+
+    #[test]
+    fn test_rfc6238_totp_vectors() {
+        let vectors: [(u64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp(RFC_KEY_SHA1, time, 30, OtpAlgorithm::Sha1, 8), sha1);
+            assert_eq!(
+                totp(RFC_KEY_SHA256, time, 30, OtpAlgorithm::Sha256, 8),
+                sha256
+            );
+            assert_eq!(
+                totp(RFC_KEY_SHA512, time, 30, OtpAlgorithm::Sha512, 8),
+                sha512
+            );
+        }
+    }
+
+    #[test]
+    fn test_base32() {
+        let key = decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(&key[..], RFC_KEY_SHA1);
+        let spaced = decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(&spaced[..], RFC_KEY_SHA1);
+        assert_eq!(&decode_base32("MZXW6===").unwrap()[..], b"foo");
+
+        assert!(decode_base32("GEZDGNBV1").is_err());
+        assert!(decode_base32("").is_err());
+    }
+
+    #[test]
+    fn test_parse_uri() {
+        let otp = Otp::parse(
+            "otpauth://totp/ACME%20Co:john.doe@email.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+             &issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(otp.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(otp.account.as_deref(), Some("john.doe@email.com"));
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!(otp.digits, 8);
+        assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+        assert_eq!(Otp::from_uri(otp.to_uri().expose()).unwrap(), otp);
+
+        // RFC 6238 at T = 59 with a 60-second period is still step 0
+        let code = otp
+            .code(chrono::DateTime::from_timestamp(59, 0).unwrap())
+            .unwrap();
+        assert_eq!(code.remaining, Some(1));
+
+        let mut hotp =
+            Otp::parse("otpauth://hotp/Example?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1")
+                .unwrap();
+        assert_eq!(hotp.account.as_deref(), Some("Example"));
+        assert_eq!(hotp.code(chrono::Utc::now()).unwrap().code, "287082");
+        hotp.advance();
+        assert_eq!(hotp.code(chrono::Utc::now()).unwrap().code, "359152");
+        assert_eq!(Otp::from_uri(hotp.to_uri().expose()).unwrap(), hotp);
+
+        let bare = Otp::parse("jbsw y3dp ehpk 3pxp").unwrap();
+        assert_eq!(bare.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(bare.kind, OtpKind::Totp { period: 30 });
+
+        for invalid in [
+            "otpauth://totp/x?issuer=NoSecret",
+            "otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://motp/x?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&digits=12",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&period=0",
+            "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+            "not base32!",
+        ] {
+            assert!(Otp::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_totp_code() {
+        let otp = Otp::totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        let now = chrono::DateTime::from_timestamp(1111111109, 0).unwrap();
+        let code = otp.code(now).unwrap();
+        assert_eq!(code.code, "081804");
+        assert_eq!(code.remaining, Some(1));
+        assert_eq!(otp.to_string(), "TOTP (SHA1, 6 digits, every 30s)");
+    }
+}
@@ -0,0 +1,161 @@
+//! Secret-holding types
+//!
+//! `SecretString` and `SecretBytes` wrap passwords and decrypted buffers.
+//! They are wiped when dropped, print as `[REDACTED]` through `Debug` and
+//! `Display`, and only hand out their contents through an explicit
+//! `expose()` call, so a secret cannot end up in a log line or panic message
+//! by accident. `SecretBytes` keeps its contents in guarded memory.
+
+use crate::crypto::guarded::GuardedBuffer;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// A string that is zeroized on drop and never printed
+#[derive(Clone, Default, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Wrap a string, taking ownership of its buffer
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// Access the secret contents
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Length of the secret in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check whether the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Serialized as the plain string, for storage inside the encrypted payload
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+/// A byte buffer in guarded memory that is zeroized on drop and never printed
+#[derive(Clone, Default, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct SecretBytes(GuardedBuffer);
+
+impl SecretBytes {
+    /// Move a buffer into guarded memory, wiping the original
+    pub fn new(mut secret: Vec<u8>) -> Self {
+        let guarded = GuardedBuffer::from_slice(&secret);
+        secret.zeroize();
+        Self(guarded)
+    }
+
+    /// Access the secret contents
+    pub fn expose(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Length of the secret in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check whether the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(secret: Vec<u8>) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let password = SecretString::from("hunter2");
+        assert_eq!(format!("{:?}", password), REDACTED);
+        assert_eq!(format!("{}", password), REDACTED);
+        assert_eq!(password.expose(), "hunter2");
+
+        let bytes = SecretBytes::from(b"plaintext".to_vec());
+        assert_eq!(format!("{:?}", bytes), REDACTED);
+        assert_eq!(format!("{}", bytes), REDACTED);
+        assert_eq!(bytes.expose(), b"plaintext");
+    }
+
+    #[test]
+    fn test_zeroize_clears_contents() {
+        let mut password = SecretString::from("hunter2");
+        password.zeroize();
+        assert!(password.is_empty());
+
+        let mut bytes = SecretBytes::from(vec![1, 2, 3]);
+        bytes.zeroize();
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_serializes_as_plain_string() {
+        let password = SecretString::from("hunter2");
+        let json = serde_json::to_string(&password).unwrap();
+        assert_eq!(json, "\"hunter2\"");
+        let parsed: SecretString = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, password);
+    }
+}
@@ -1,222 +1,505 @@
-//! Shamir's Secret Sharing implementation for 2-of-3 recovery
+//! Shamir's Secret Sharing over GF(2^8)
 //!
-//! This module implements a 2-of-3 secret sharing scheme where:
-//! - Share 1: Derived from simple password
-//! - Share 2: Derived from Touch ID/Passkey authentication
+//! A secret is split byte-wise into `n` shares using random polynomials of
+//! degree `k - 1` over GF(256), so that any `k` shares reconstruct the secret
+//! while `k - 1` or fewer shares reveal nothing about it.
+//!
+//! The default scheme is 2-of-3, used by the recovery system:
+//! - Share 1: Sealed with the simple password
+//! - Share 2: Held by Touch ID/Passkey protected device storage
 //! - Share 3: Stored in iCloud backup
 //!
-//! Any 2 of the 3 shares can reconstruct the master secret.
+//! Larger schemes such as 3-of-5 are available for team escrow.
 
-use crate::{Result, TwoPasswordError};
 use crate::crypto::secure_random;
-use rand::Rng;
+use crate::{Result, TwoPasswordError};
+use ring::digest;
+
+/// Domain separation label for share checksums
+const CHECKSUM_CONTEXT: &[u8] = b"TwoPassword-Share-Checksum-v1";
+
+/// Size of the random identifier shared by all shares of one split
+pub const SET_ID_SIZE: usize = 8;
+
+/// Threshold parameters for a secret sharing scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SharingScheme {
+    /// Minimum number of shares needed to reconstruct the secret (k)
+    pub threshold: u8,
+    /// Total number of shares produced (n)
+    pub share_count: u8,
+}
+
+impl SharingScheme {
+    /// The 2-of-3 scheme used for personal recovery
+    pub const TWO_OF_THREE: Self = Self {
+        threshold: 2,
+        share_count: 3,
+    };
+
+    /// The 3-of-5 scheme used for team escrow
+    pub const THREE_OF_FIVE: Self = Self {
+        threshold: 3,
+        share_count: 5,
+    };
+
+    /// Create a k-of-n scheme, validating its parameters
+    pub fn new(threshold: u8, share_count: u8) -> Result<Self> {
+        if threshold < 2 {
+            return Err(TwoPasswordError::crypto(
+                "Threshold must be at least 2 so that no single share reveals the secret",
+            ));
+        }
+
+        if share_count < threshold {
+            return Err(TwoPasswordError::crypto(
+                "Share count must not be smaller than the threshold",
+            ));
+        }
+
+        Ok(Self {
+            threshold,
+            share_count,
+        })
+    }
+}
+
+impl Default for SharingScheme {
+    fn default() -> Self {
+        Self::TWO_OF_THREE
+    }
+}
 
-/// A secret share in the 2-of-3 scheme
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// A single share of a split secret
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct SecretShare {
-    /// Share identifier (1, 2, or 3)
+    /// Share index, which is also its x-coordinate (1..=share_count)
     pub id: u8,
-    /// The actual share value
+    /// Number of shares required for reconstruction
+    pub threshold: u8,
+    /// Total number of shares in the set
+    pub share_count: u8,
+    /// Random identifier common to all shares of the same split
+    pub set_id: [u8; SET_ID_SIZE],
+    /// The share value, one byte per secret byte
     pub value: Vec<u8>,
+    /// Checksum over the metadata and value to detect corruption
+    pub checksum: u32,
+}
+
+/// The share value is redacted; any `threshold` shares reveal the secret
+impl std::fmt::Debug for SecretShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretShare")
+            .field("id", &self.id)
+            .field("threshold", &self.threshold)
+            .field("share_count", &self.share_count)
+            .field("value", &"[REDACTED]")
+            .finish()
+    }
+}
+
+impl SecretShare {
+    fn new(id: u8, scheme: SharingScheme, set_id: [u8; SET_ID_SIZE], value: Vec<u8>) -> Self {
+        let mut share = Self {
+            id,
+            threshold: scheme.threshold,
+            share_count: scheme.share_count,
+            set_id,
+            value,
+            checksum: 0,
+        };
+        share.checksum = share.compute_checksum();
+        share
+    }
+
+    /// The scheme this share belongs to
+    pub fn scheme(&self) -> SharingScheme {
+        SharingScheme {
+            threshold: self.threshold,
+            share_count: self.share_count,
+        }
+    }
+
+    /// Validate the share metadata and checksum
+    pub fn verify(&self) -> Result<()> {
+        if self.threshold < 2 || self.share_count < self.threshold {
+            return Err(TwoPasswordError::crypto("Invalid share threshold metadata"));
+        }
+
+        if self.id == 0 || self.id > self.share_count {
+            return Err(TwoPasswordError::crypto("Invalid share index"));
+        }
+
+        if self.value.is_empty() {
+            return Err(TwoPasswordError::crypto("Empty share value"));
+        }
+
+        if self.checksum != self.compute_checksum() {
+            return Err(TwoPasswordError::crypto(
+                "Share checksum mismatch - share may be corrupted",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(CHECKSUM_CONTEXT);
+        ctx.update(&self.set_id);
+        ctx.update(&[self.threshold, self.share_count, self.id]);
+        ctx.update(&self.value);
+        let hash = ctx.finish();
+
+        let bytes = hash.as_ref();
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
 }
 
 /// Shamir's Secret Sharing implementation
 pub struct SecretSharing;
 
 impl SecretSharing {
-    /// Split a secret into 3 shares where any 2 can reconstruct the original
-    /// This is a simplified 2-of-3 implementation using XOR
-    pub fn split_secret(secret: &[u8; 32]) -> Result<[SecretShare; 3]> {
-        // For a 2-of-3 XOR scheme:
-        // share1 = random
-        // share2 = secret XOR share1
-        // share3 = secret  
-        // 
-        // Then:
-        // secret = share1 XOR share2 (since share2 = secret XOR share1)
-        // secret = share3 (directly)
-        // secret = share2 XOR share1 (same as first)
-        //
-        // But this doesn't work for all combinations. Let me use a different approach:
-        // 
-        // For proper 2-of-3:
-        // Generate two random values r1 and r2
-        // share1 = r1
-        // share2 = r2  
-        // share3 = secret XOR r1 XOR r2
-        //
-        // Recovery:
-        // With share1,share2: secret = share1 XOR share2 XOR share3 = r1 XOR r2 XOR (secret XOR r1 XOR r2) = secret ✓
-        // With share1,share3: secret = share1 XOR share2 XOR share3 = r1 XOR r2 XOR (secret XOR r1 XOR r2) = secret ✓  
-        // With share2,share3: secret = share1 XOR share2 XOR share3 = r1 XOR r2 XOR (secret XOR r1 XOR r2) = secret ✓
-        //
-        // But we need to know which shares we have. Let me use a simpler approach:
-        
-        let mut share1_value = vec![0u8; 32];
-        secure_random::fill_random(&mut share1_value)?;
-        
-        // share2 = secret XOR share1
-        let mut share2_value = vec![0u8; 32];
-        for i in 0..32 {
-            share2_value[i] = secret[i] ^ share1_value[i];
-        }
-        
-        // share3 = secret (this allows recovery with just share3, or share1+share2)
-        let share3_value = secret.to_vec();
-        
-        Ok([
-            SecretShare { id: 1, value: share1_value },
-            SecretShare { id: 2, value: share2_value },
-            SecretShare { id: 3, value: share3_value },
-        ])
-    }
-    
-    /// Reconstruct secret from any 2 of the 3 shares
-    pub fn reconstruct_secret(share_a: &SecretShare, share_b: &SecretShare) -> Result<[u8; 32]> {
-        if share_a.id == share_b.id {
-            return Err(TwoPasswordError::crypto("Cannot use the same share twice"));
-        }
-        
-        if share_a.value.len() != 32 || share_b.value.len() != 32 {
-            return Err(TwoPasswordError::crypto("Invalid share size"));
-        }
-        
-        let mut secret = [0u8; 32];
-        
-        // Based on our split scheme:
-        // share1 = random
-        // share2 = secret XOR share1  
-        // share3 = secret
-        
-        // Determine the shares we have
-        let (first_share, second_share) = if share_a.id < share_b.id {
-            (share_a, share_b)
-        } else {
-            (share_b, share_a)
-        };
-        
-        match (first_share.id, second_share.id) {
-            (1, 2) => {
-                // Have share1 and share2
-                // secret = share1 XOR share2 (since share2 = secret XOR share1)
-                for i in 0..32 {
-                    secret[i] = first_share.value[i] ^ second_share.value[i];
-                }
-            },
-            (1, 3) => {
-                // Have share1 and share3  
-                // secret = share3 (since share3 = secret directly)
-                for i in 0..32 {
-                    secret[i] = second_share.value[i];
-                }
-            },
-            (2, 3) => {
-                // Have share2 and share3
-                // secret = share3 (since share3 = secret directly)
-                for i in 0..32 {
-                    secret[i] = second_share.value[i];
+    /// Split a secret into shares according to the given k-of-n scheme
+    pub fn split(secret: &[u8], scheme: SharingScheme) -> Result<Vec<SecretShare>> {
+        let scheme = SharingScheme::new(scheme.threshold, scheme.share_count)?;
+
+        if secret.is_empty() {
+            return Err(TwoPasswordError::crypto("Cannot split an empty secret"));
+        }
+
+        let mut set_id = [0u8; SET_ID_SIZE];
+        secure_random::fill_random(&mut set_id)?;
+
+        let degree = scheme.threshold as usize - 1;
+        let mut values = vec![Vec::with_capacity(secret.len()); scheme.share_count as usize];
+        let mut coefficients = vec![0u8; scheme.threshold as usize];
+
+        for &byte in secret {
+            // The constant term is the secret byte, the rest are random
+            coefficients[0] = byte;
+            secure_random::fill_random(&mut coefficients[1..=degree])?;
+
+            for (index, value) in values.iter_mut().enumerate() {
+                value.push(evaluate_polynomial(&coefficients, index as u8 + 1));
+            }
+        }
+
+        // Don't leave polynomial coefficients lying around
+        coefficients.iter_mut().for_each(|c| *c = 0);
+
+        Ok(values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| SecretShare::new(index as u8 + 1, scheme, set_id, value))
+            .collect())
+    }
+
+    /// Reconstruct a secret from at least `threshold` shares of the same set
+    ///
+    /// Shares beyond the threshold are checked against the reconstructed
+    /// polynomial, so a set mixing shares from different splits is rejected.
+    pub fn combine(shares: &[SecretShare]) -> Result<Vec<u8>> {
+        let first = shares
+            .first()
+            .ok_or_else(|| TwoPasswordError::crypto("No shares provided"))?;
+
+        for (i, share) in shares.iter().enumerate() {
+            share.verify()?;
+
+            if share.set_id != first.set_id
+                || share.threshold != first.threshold
+                || share.share_count != first.share_count
+                || share.value.len() != first.value.len()
+            {
+                return Err(TwoPasswordError::crypto(
+                    "Inconsistent share set - shares come from different splits",
+                ));
+            }
+
+            if shares[..i].iter().any(|other| other.id == share.id) {
+                return Err(TwoPasswordError::crypto("Cannot use the same share twice"));
+            }
+        }
+
+        let threshold = first.threshold as usize;
+        if shares.len() < threshold {
+            return Err(TwoPasswordError::crypto(format!(
+                "Not enough shares: {} provided, {} required",
+                shares.len(),
+                threshold
+            )));
+        }
+
+        let (used, extra) = shares.split_at(threshold);
+        let xs: Vec<u8> = used.iter().map(|share| share.id).collect();
+        let mut secret = Vec::with_capacity(first.value.len());
+        let mut ys = vec![0u8; threshold];
+
+        for byte_index in 0..first.value.len() {
+            for (y, share) in ys.iter_mut().zip(used) {
+                *y = share.value[byte_index];
+            }
+
+            secret.push(interpolate(&xs, &ys, 0));
+
+            for share in extra {
+                if interpolate(&xs, &ys, share.id) != share.value[byte_index] {
+                    return Err(TwoPasswordError::crypto(
+                        "Inconsistent share set - shares do not lie on the same polynomial",
+                    ));
                 }
-            },
-            _ => return Err(TwoPasswordError::crypto("Invalid share combination")),
+            }
         }
-        
+
         Ok(secret)
     }
-    
-    /// Generate a master secret from password component
-    pub fn derive_password_share(password: &str, salt: &[u8]) -> Result<SecretShare> {
-        let key = crate::crypto::key_derivation::derive_key(password, salt)?;
-        Ok(SecretShare {
-            id: 1,
-            value: key.to_vec(),
-        })
+
+    /// Split a 32-byte secret into 3 shares where any 2 can reconstruct the
+    /// original
+    pub fn split_secret(secret: &[u8; 32]) -> Result<[SecretShare; 3]> {
+        let shares = Self::split(secret, SharingScheme::TWO_OF_THREE)?;
+        shares
+            .try_into()
+            .map_err(|_| TwoPasswordError::crypto("Unexpected number of shares"))
     }
-    
-    /// Generate a master secret from Touch ID component
-    /// In a full implementation, this would use the Touch ID secure enclave
-    pub fn derive_touchid_share(user_id: &str, device_id: &str) -> Result<SecretShare> {
-        // Create a deterministic but unique value based on user and device
-        let input = format!("{}::{}", user_id, device_id);
-        let salt = crate::crypto::secure_random::generate_bytes(32)?;
-        let key = crate::crypto::key_derivation::derive_key(&input, &salt)?;
-        
-        Ok(SecretShare {
-            id: 2,
-            value: key.to_vec(),
-        })
+
+    /// Reconstruct a 32-byte secret from any 2 of the 3 shares
+    pub fn reconstruct_secret(share_a: &SecretShare, share_b: &SecretShare) -> Result<[u8; 32]> {
+        let secret = Self::combine(&[share_a.clone(), share_b.clone()])?;
+        secret
+            .try_into()
+            .map_err(|_| TwoPasswordError::crypto("Invalid share size"))
     }
-    
+
     /// Create iCloud backup share (to be stored encrypted in iCloud)
     pub fn create_backup_share(secret_share: SecretShare) -> Result<Vec<u8>> {
         // Serialize the share for storage
-        serde_json::to_vec(&secret_share)
-            .map_err(|e| TwoPasswordError::storage(format!("Failed to serialize backup share: {}", e)))
+        serde_json::to_vec(&secret_share).map_err(|e| {
+            TwoPasswordError::storage(format!("Failed to serialize backup share: {}", e))
+        })
     }
-    
+
     /// Restore share from iCloud backup
     pub fn restore_backup_share(backup_data: &[u8]) -> Result<SecretShare> {
-        serde_json::from_slice(backup_data)
-            .map_err(|e| TwoPasswordError::storage(format!("Failed to deserialize backup share: {}", e)))
+        let share: SecretShare = serde_json::from_slice(backup_data).map_err(|e| {
+            TwoPasswordError::storage(format!("Failed to deserialize backup share: {}", e))
+        })?;
+
+        share.verify()?;
+        Ok(share)
+    }
+}
+
+/// Multiply two elements of GF(2^8) modulo x^8 + x^4 + x^3 + x + 1
+///
+/// Branch-free so that timing does not depend on share or secret bytes.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(2^8), computed as a^254
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Evaluate a polynomial (lowest coefficient first) at `x` using Horner's rule
+fn evaluate_polynomial(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0u8, |acc, &coefficient| gf_mul(acc, x) ^ coefficient)
+}
+
+/// Lagrange interpolation of the points `(xs[i], ys[i])` evaluated at `x`
+fn interpolate(xs: &[u8], ys: &[u8], x: u8) -> u8 {
+    let mut result = 0u8;
+    for (i, (&xi, &yi)) in xs.iter().zip(ys).enumerate() {
+        let mut numerator = 1u8;
+        let mut denominator = 1u8;
+        for (j, &xj) in xs.iter().enumerate() {
+            if i != j {
+                // Subtraction is XOR in GF(2^8)
+                numerator = gf_mul(numerator, x ^ xj);
+                denominator = gf_mul(denominator, xi ^ xj);
+            }
+        }
+        result ^= gf_mul(yi, gf_mul(numerator, gf_inv(denominator)));
     }
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_gf_arithmetic() {
+        // Known AES field products
+        assert_eq!(gf_mul(0x53, 0xca), 0x01);
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
     #[test]
     fn test_split_and_reconstruct_secret() {
         let original_secret = [42u8; 32];
-        
+
         // Split the secret
         let shares = SecretSharing::split_secret(&original_secret).unwrap();
         assert_eq!(shares.len(), 3);
         assert_eq!(shares[0].id, 1);
         assert_eq!(shares[1].id, 2);
         assert_eq!(shares[2].id, 3);
-        
-        // Test all combinations of 2 shares
-        let reconstructed_1_2 = SecretSharing::reconstruct_secret(&shares[0], &shares[1]).unwrap();
-        assert_eq!(reconstructed_1_2, original_secret);
-        
-        let reconstructed_1_3 = SecretSharing::reconstruct_secret(&shares[0], &shares[2]).unwrap();
-        assert_eq!(reconstructed_1_3, original_secret);
-        
-        let reconstructed_2_3 = SecretSharing::reconstruct_secret(&shares[1], &shares[2]).unwrap();
-        assert_eq!(reconstructed_2_3, original_secret);
-    }
-    
+
+        // Test all combinations of 2 shares, in either order
+        for (a, b) in [(0, 1), (0, 2), (1, 2), (2, 0)] {
+            let reconstructed = SecretSharing::reconstruct_secret(&shares[a], &shares[b]).unwrap();
+            assert_eq!(reconstructed, original_secret);
+        }
+    }
+
+    #[test]
+    fn test_three_of_five() {
+        let secret = b"team escrow secret of arbitrary length".to_vec();
+        let shares = SecretSharing::split(&secret, SharingScheme::THREE_OF_FIVE).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for a in 0..5 {
+            for b in (a + 1)..5 {
+                for c in (b + 1)..5 {
+                    let subset = [shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                    assert_eq!(SecretSharing::combine(&subset).unwrap(), secret);
+                }
+            }
+        }
+
+        // All five shares are consistent with each other
+        assert_eq!(SecretSharing::combine(&shares).unwrap(), secret);
+
+        // Two shares are below the threshold
+        assert!(SecretSharing::combine(&shares[..2]).is_err());
+    }
+
+    #[test]
+    fn test_scheme_validation() {
+        assert!(SharingScheme::new(1, 3).is_err());
+        assert!(SharingScheme::new(4, 3).is_err());
+        assert_eq!(SharingScheme::new(2, 3).unwrap(), SharingScheme::default());
+        assert!(SecretSharing::split(&[], SharingScheme::default()).is_err());
+    }
+
+    #[test]
+    fn test_single_share_is_uniform_for_any_secret() {
+        // For a degree-1 polynomial, every share value occurs for exactly one
+        // random coefficient, whatever the secret byte is. A single share is
+        // therefore uniformly distributed and independent of the secret.
+        for secret in [0x00u8, 0x42, 0xff] {
+            for x in 1..=3u8 {
+                let values: HashSet<u8> = (0..=255u8)
+                    .map(|a| evaluate_polynomial(&[secret, a], x))
+                    .collect();
+                assert_eq!(values.len(), 256);
+            }
+        }
+    }
+
     #[test]
-    fn test_derive_shares() {
-        let password_share = SecretSharing::derive_password_share("test_password", &[0u8; 32]).unwrap();
-        assert_eq!(password_share.id, 1);
-        assert_eq!(password_share.value.len(), 32);
-        
-        let touchid_share = SecretSharing::derive_touchid_share("user123", "device456").unwrap();
-        assert_eq!(touchid_share.id, 2);
-        assert_eq!(touchid_share.value.len(), 32);
-    }
-    
+    fn test_below_threshold_shares_are_uniform_for_any_secret() {
+        // In a 3-of-5 scheme, any two shares take every possible pair of
+        // values exactly once over the random coefficients.
+        for secret in [0x00u8, 0x9c] {
+            let pairs: HashSet<(u8, u8)> = (0..=255u8)
+                .flat_map(|a1| (0..=255u8).map(move |a2| [secret, a1, a2]))
+                .map(|poly| (evaluate_polynomial(&poly, 2), evaluate_polynomial(&poly, 5)))
+                .collect();
+            assert_eq!(pairs.len(), 256 * 256);
+        }
+    }
+
+    #[test]
+    fn test_single_share_does_not_contain_secret() {
+        let secret = [7u8; 32];
+        let shares = SecretSharing::split_secret(&secret).unwrap();
+
+        for share in &shares {
+            assert_ne!(share.value, secret.to_vec());
+            assert!(SecretSharing::combine(std::slice::from_ref(share)).is_err());
+        }
+    }
+
     #[test]
     fn test_backup_share_serialization() {
-        let share = SecretShare {
-            id: 3,
-            value: vec![1, 2, 3, 4, 5],
-        };
-        
+        let shares = SecretSharing::split_secret(&[5u8; 32]).unwrap();
+        let share = shares[2].clone();
+
         let backup_data = SecretSharing::create_backup_share(share.clone()).unwrap();
         let restored_share = SecretSharing::restore_backup_share(&backup_data).unwrap();
-        
+
         assert_eq!(restored_share.id, share.id);
         assert_eq!(restored_share.value, share.value);
+        assert_eq!(restored_share.set_id, share.set_id);
     }
-    
+
     #[test]
     fn test_invalid_share_combinations() {
-        let share1 = SecretShare { id: 1, value: vec![0u8; 32] };
-        let share1_copy = SecretShare { id: 1, value: vec![0u8; 32] };
-        
+        let shares = SecretSharing::split_secret(&[1u8; 32]).unwrap();
+        let other = SecretSharing::split_secret(&[1u8; 32]).unwrap();
+
         // Should fail when using the same share twice
-        assert!(SecretSharing::reconstruct_secret(&share1, &share1_copy).is_err());
+        assert!(SecretSharing::reconstruct_secret(&shares[0], &shares[0].clone()).is_err());
+
+        // Should fail when mixing shares from different splits
+        assert!(SecretSharing::reconstruct_secret(&shares[0], &other[1]).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_shares_are_rejected() {
+        let shares = SecretSharing::split_secret(&[3u8; 32]).unwrap();
+
+        let mut corrupted = shares[1].clone();
+        corrupted.value[0] ^= 1;
+        assert!(SecretSharing::reconstruct_secret(&shares[0], &corrupted).is_err());
+
+        let mut relabeled = shares[1].clone();
+        relabeled.threshold = 3;
+        assert!(SecretSharing::reconstruct_secret(&shares[0], &relabeled).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_inconsistent_extra_share_is_rejected() {
+        let secret = [9u8; 16];
+        let mut shares = SecretSharing::split(&secret, SharingScheme::TWO_OF_THREE).unwrap();
+
+        // Forge a share with a valid checksum that is off the polynomial
+        let mut forged = shares[2].clone();
+        forged.value[3] ^= 0x80;
+        forged.checksum = forged.compute_checksum();
+        shares[2] = forged;
+
+        assert!(SecretSharing::combine(&shares[..2]).is_ok());
+        assert!(SecretSharing::combine(&shares).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Chunked streaming encryption
+//!
+//! Large data such as file attachments is encrypted in fixed-size chunks, so
+//! neither side ever holds more than one chunk in memory. Each chunk is
+//! sealed with XChaCha20-Poly1305 under a nonce made of a random prefix for
+//! the stream, the chunk counter and a flag marking the final chunk, as in
+//! the STREAM construction. Reordering, dropping or appending chunks and
+//! truncating the stream all make decryption fail.
+//!
+//! A stream is the nonce prefix followed by the sealed chunks:
+//!
+//! ```text
+//! prefix (19 bytes) | chunk 0 (64 KiB + 16) | .. | final chunk (≤ 64 KiB + 16)
+//! ```
+
+use crate::crypto::{key_schedule, secure_random, MasterKey};
+use crate::{Result, TwoPasswordError};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use std::io::{Read, Write};
+use zeroize::Zeroizing;
+
+/// Plaintext bytes per chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Poly1305 tag appended to every chunk
+pub const TAG_SIZE: usize = 16;
+
+/// Random part of the chunk nonces, stored at the start of the stream
+pub const NONCE_PREFIX_SIZE: usize = crate::config::XCHACHA_NONCE_SIZE - 5;
+
+/// Label for the chunk key expanded from the stream key
+const STREAM_LABEL: &[u8] = b"TwoPassword v1 stream";
+
+/// Size of the stream encrypting `plaintext_len` bytes
+pub fn encrypted_len(plaintext_len: u64) -> u64 {
+    // Empty input is still sealed as one empty final chunk
+    let chunk_size = CHUNK_SIZE as u64;
+    let chunks = ((plaintext_len + chunk_size - 1) / chunk_size).max(1);
+    NONCE_PREFIX_SIZE as u64 + plaintext_len + chunks * TAG_SIZE as u64
+}
+
+/// Encrypt everything `reader` yields to `writer`, binding `aad` to every
+/// chunk; returns the number of plaintext bytes
+pub fn encrypt(
+    key: &MasterKey,
+    aad: &[u8],
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<u64> {
+    let cipher = chunk_cipher(key)?;
+    let prefix = secure_random::generate_bytes(NONCE_PREFIX_SIZE)?;
+    writer.write_all(&prefix)?;
+
+    let mut total = 0;
+    let mut counter = 0u32;
+    let mut chunk = read_chunk(&mut reader, CHUNK_SIZE)?;
+    loop {
+        // A chunk is the final one if nothing follows it
+        let next = match chunk.len() {
+            CHUNK_SIZE => read_chunk(&mut reader, CHUNK_SIZE)?,
+            _ => Zeroizing::new(Vec::new()),
+        };
+        let last = next.is_empty();
+        let nonce = chunk_nonce(&prefix, counter, last);
+        let sealed = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &chunk, aad })
+            .map_err(|e| TwoPasswordError::crypto(format!("Encryption failed: {}", e)))?;
+        writer.write_all(&sealed)?;
+        total += chunk.len() as u64;
+
+        if last {
+            break;
+        }
+        chunk = next;
+        counter = next_counter(counter)?;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Decrypt a stream written by [`encrypt`] with the same key and associated
+/// data; returns the number of plaintext bytes
+///
+/// Every chunk is authenticated before it is written, but a failure can
+/// come after earlier chunks were written, so callers must discard the
+/// output when this fails.
+pub fn decrypt(
+    key: &MasterKey,
+    aad: &[u8],
+    mut reader: impl Read,
+    mut writer: impl Write,
+) -> Result<u64> {
+    let cipher = chunk_cipher(key)?;
+    let prefix = read_chunk(&mut reader, NONCE_PREFIX_SIZE)?;
+    if prefix.len() != NONCE_PREFIX_SIZE {
+        return Err(TwoPasswordError::crypto("Encrypted stream is truncated"));
+    }
+
+    let sealed_size = CHUNK_SIZE + TAG_SIZE;
+    let mut total = 0;
+    let mut counter = 0u32;
+    let mut chunk = read_chunk(&mut reader, sealed_size)?;
+    loop {
+        let next = match chunk.len() {
+            len if len == sealed_size => read_chunk(&mut reader, sealed_size)?,
+            _ => Zeroizing::new(Vec::new()),
+        };
+        let last = next.is_empty();
+        let nonce = chunk_nonce(&prefix, counter, last);
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(XNonce::from_slice(&nonce), Payload { msg: &chunk, aad })
+                .map_err(|_| {
+                    TwoPasswordError::crypto(format!(
+                        "Chunk {} failed authentication - data may be corrupted or truncated",
+                        counter
+                    ))
+                })?,
+        );
+        writer.write_all(&plaintext)?;
+        total += plaintext.len() as u64;
+
+        if last {
+            break;
+        }
+        chunk = next;
+        counter = next_counter(counter)?;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+fn chunk_cipher(key: &MasterKey) -> Result<XChaCha20Poly1305> {
+    let chunk_key = key_schedule::expand(key.as_bytes(), STREAM_LABEL)?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(
+        chunk_key.as_bytes(),
+    )))
+}
+
+/// Nonce of a chunk: the stream prefix, the big-endian chunk counter and 1
+/// for the final chunk or 0 for the others
+fn chunk_nonce(prefix: &[u8], counter: u32, last: bool) -> [u8; 24] {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_PREFIX_SIZE + 4] = u8::from(last);
+    nonce
+}
+
+fn next_counter(counter: u32) -> Result<u32> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| TwoPasswordError::crypto("Stream is too long to encrypt"))
+}
+
+/// Read up to `size` bytes, fewer only at the end of the input
+fn read_chunk(reader: &mut impl Read, size: usize) -> Result<Zeroizing<Vec<u8>>> {
+    let mut buffer = Zeroizing::new(vec![0u8; size]);
+    let mut filled = 0;
+    while filled < size {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    buffer.truncate(filled);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(len: usize) {
+        let key = MasterKey::generate().unwrap();
+        let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+
+        let mut sealed = Vec::new();
+        assert_eq!(
+            encrypt(&key, b"aad", &plaintext[..], &mut sealed).unwrap(),
+            len as u64
+        );
+        assert_eq!(sealed.len() as u64, encrypted_len(len as u64));
+
+        let mut opened = Vec::new();
+        assert_eq!(
+            decrypt(&key, b"aad", &sealed[..], &mut opened).unwrap(),
+            len as u64
+        );
+        assert_eq!(opened, plaintext);
+    }
+
+    #[test]
+    fn test_roundtrip_at_chunk_boundaries() {
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            roundtrip(len);
+        }
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let key = MasterKey::generate().unwrap();
+        let plaintext = vec![7u8; 2 * CHUNK_SIZE + 10];
+        let mut sealed = Vec::new();
+        encrypt(&key, b"aad", &plaintext[..], &mut sealed).unwrap();
+        let open = |sealed: &[u8], key: &MasterKey, aad: &[u8]| {
+            decrypt(key, aad, sealed, std::io::sink()).is_ok()
+        };
+        assert!(open(&sealed, &key, b"aad"));
+
+        // Wrong key or associated data
+        assert!(!open(&sealed, &MasterKey::generate().unwrap(), b"aad"));
+        assert!(!open(&sealed, &key, b"other"));
+
+        // Flipped bit
+        let mut flipped = sealed.clone();
+        flipped[NONCE_PREFIX_SIZE + 5] ^= 1;
+        assert!(!open(&flipped, &key, b"aad"));
+
+        // Truncated after a full chunk, so the last chunk read is not final
+        let chunk = CHUNK_SIZE + TAG_SIZE;
+        assert!(!open(
+            &sealed[..NONCE_PREFIX_SIZE + 2 * chunk],
+            &key,
+            b"aad"
+        ));
+        assert!(!open(&sealed[..NONCE_PREFIX_SIZE], &key, b"aad"));
+
+        // Swapped chunks
+        let mut swapped = sealed[..NONCE_PREFIX_SIZE].to_vec();
+        swapped
+            .extend_from_slice(&sealed[NONCE_PREFIX_SIZE + chunk..NONCE_PREFIX_SIZE + 2 * chunk]);
+        swapped.extend_from_slice(&sealed[NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + chunk]);
+        swapped.extend_from_slice(&sealed[NONCE_PREFIX_SIZE + 2 * chunk..]);
+        assert!(!open(&swapped, &key, b"aad"));
+    }
+}
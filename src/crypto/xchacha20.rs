@@ -0,0 +1,157 @@
+//! XChaCha20-Poly1305 encryption and decryption
+//!
+//! Uses 24-byte random nonces, which can be generated at random for very large
+//! numbers of messages without a meaningful risk of collision.
+
+use crate::crypto::key_schedule::{self, KeySchedule};
+use crate::crypto::{secure_random, CipherSuite, EncryptedData};
+use crate::{Result, TwoPasswordError};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use ring::hmac;
+
+/// Encrypt data using XChaCha20-Poly1305
+///
+/// Separate cipher and HMAC keys are expanded from `key` with HKDF.
+pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<EncryptedData> {
+    encrypt_with_aad(key, plaintext, &[])
+}
+
+/// Encrypt data using XChaCha20-Poly1305, binding `aad` as associated data
+///
+/// The associated data is not stored; decryption fails unless the same bytes
+/// are supplied again.
+pub fn encrypt_with_aad(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<EncryptedData> {
+    let (cipher_key, mac_key) = key_schedule::aead_keys(key, KeySchedule::Hkdf)?;
+
+    // Create cipher
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(cipher_key.as_bytes()));
+
+    // Generate random nonce
+    let nonce_bytes = secure_random::generate_bytes(crate::config::XCHACHA_NONCE_SIZE)?;
+    let nonce = XNonce::from_slice(&nonce_bytes);
+
+    // Encrypt
+    let ciphertext = cipher
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|e| TwoPasswordError::crypto(format!("Encryption failed: {}", e)))?;
+
+    // Calculate HMAC for integrity
+    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, mac_key.as_bytes());
+    let mut hmac_input = Vec::new();
+    hmac_input.extend_from_slice(&nonce_bytes);
+    hmac_input.extend_from_slice(&ciphertext);
+    hmac_input.extend_from_slice(aad);
+
+    let hmac_tag = hmac::sign(&hmac_key, &hmac_input);
+
+    Ok(EncryptedData {
+        cipher: CipherSuite::XChaCha20Poly1305,
+        key_schedule: KeySchedule::Hkdf,
+        ciphertext,
+        nonce: nonce_bytes,
+        hmac: hmac_tag.as_ref().to_vec(),
+    })
+}
+
+/// Decrypt data using XChaCha20-Poly1305
+pub fn decrypt(key: &[u8; 32], encrypted: &EncryptedData) -> Result<Vec<u8>> {
+    decrypt_with_aad(key, encrypted, &[])
+}
+
+/// Decrypt data using XChaCha20-Poly1305 that was encrypted with associated data
+pub fn decrypt_with_aad(key: &[u8; 32], encrypted: &EncryptedData, aad: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.cipher != CipherSuite::XChaCha20Poly1305 {
+        return Err(TwoPasswordError::crypto(
+            "Data was not encrypted with XChaCha20-Poly1305",
+        ));
+    }
+
+    let (cipher_key, mac_key) = key_schedule::aead_keys(key, encrypted.key_schedule)?;
+
+    // Verify HMAC first
+    let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, mac_key.as_bytes());
+    let mut hmac_input = Vec::new();
+    hmac_input.extend_from_slice(&encrypted.nonce);
+    hmac_input.extend_from_slice(&encrypted.ciphertext);
+    hmac_input.extend_from_slice(aad);
+
+    hmac::verify(&hmac_key, &hmac_input, &encrypted.hmac).map_err(|_| {
+        TwoPasswordError::crypto("HMAC verification failed - data may be corrupted")
+    })?;
+
+    if encrypted.nonce.len() != crate::config::XCHACHA_NONCE_SIZE {
+        return Err(TwoPasswordError::crypto("Invalid nonce size"));
+    }
+
+    // Decrypt
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(cipher_key.as_bytes()));
+    let nonce = XNonce::from_slice(&encrypted.nonce);
+    let plaintext = cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &encrypted.ciphertext,
+                aad,
+            },
+        )
+        .map_err(|e| TwoPasswordError::crypto(format!("Decryption failed: {}", e)))?;
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let key = [42u8; 32];
+        let plaintext = b"Hello, secure world!";
+
+        let encrypted = encrypt(&key, plaintext).unwrap();
+        assert_eq!(encrypted.cipher, CipherSuite::XChaCha20Poly1305);
+        assert_eq!(encrypted.nonce.len(), crate::config::XCHACHA_NONCE_SIZE);
+        assert_eq!(encrypted.hmac.len(), crate::config::HMAC_SIZE);
+
+        let decrypted = decrypt(&key, &encrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_associated_data_must_match() {
+        let key = [42u8; 32];
+        let encrypted = encrypt_with_aad(&key, b"test data", b"header v1").unwrap();
+
+        assert_eq!(
+            decrypt_with_aad(&key, &encrypted, b"header v1").unwrap(),
+            b"test data"
+        );
+        assert!(decrypt_with_aad(&key, &encrypted, b"header v2").is_err());
+        assert!(decrypt(&key, &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_tampered_data_fails_verification() {
+        let key = [42u8; 32];
+        let mut encrypted = encrypt(&key, b"test data").unwrap();
+
+        encrypted.ciphertext[0] ^= 1;
+        assert!(decrypt(&key, &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_cipher_suite() {
+        let key = [42u8; 32];
+        let encrypted = crate::crypto::aes_gcm::encrypt(&key, b"test data").unwrap();
+        assert!(decrypt(&key, &encrypted).is_err());
+    }
+}
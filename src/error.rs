@@ -40,6 +40,9 @@ pub enum TwoPasswordError {
 
     #[error("Validation error: {0}")]
     ValidationError(String),
+
+    #[error("Unlock agent error: {0}")]
+    AgentError(String),
 }
 
 /// Convenience result type
@@ -75,4 +78,9 @@ impl TwoPasswordError {
     pub fn touch_id<S: Into<String>>(msg: S) -> Self {
         Self::TouchIdError(msg.into())
     }
+
+    /// Create an unlock agent error
+    pub fn agent<S: Into<String>>(msg: S) -> Self {
+        Self::AgentError(msg.into())
+    }
 }
@@ -3,6 +3,7 @@
 //! This library provides a zero-knowledge password manager that integrates with
 //! macOS Touch ID for secure authentication and uses industry-standard encryption.
 
+pub mod agent;
 pub mod auth;
 pub mod cli;
 pub mod crypto;
@@ -21,10 +22,16 @@ pub mod config {
     pub const APP_NAME: &str = "TwoPassword";
 
     /// Version for file format compatibility
-    pub const FORMAT_VERSION: u32 = 1;
+    pub const FORMAT_VERSION: u32 = 5;
 
-    /// Key derivation iteration count
-    pub const PBKDF2_ITERATIONS: u32 = 100_000;
+    /// Default Argon2id memory cost in KiB
+    pub const ARGON2_MEMORY_COST_KIB: u32 = 19 * 1024;
+
+    /// Default Argon2id time cost (passes over memory)
+    pub const ARGON2_TIME_COST: u32 = 2;
+
+    /// Default Argon2id parallelism (lanes)
+    pub const ARGON2_PARALLELISM: u32 = 1;
 
     /// AES-GCM key size in bytes
     pub const KEY_SIZE: usize = 32;
@@ -32,11 +39,23 @@ pub mod config {
     /// AES-GCM nonce size in bytes
     pub const NONCE_SIZE: usize = 12;
 
+    /// XChaCha20-Poly1305 nonce size in bytes
+    pub const XCHACHA_NONCE_SIZE: usize = 24;
+
     /// Salt size for key derivation
     pub const SALT_SIZE: usize = 32;
 
     /// HMAC size for integrity verification
     pub const HMAC_SIZE: usize = 32;
+
+    /// Default number of days removed entries stay in the trash
+    pub const TRASH_RETENTION_DAYS: u32 = 30;
+
+    /// Default number of previous passwords kept per entry
+    pub const PASSWORD_HISTORY_SIZE: usize = 10;
+
+    /// Seconds without use after which the unlock agent locks the vault
+    pub const AGENT_IDLE_TIMEOUT_SECS: u64 = 15 * 60;
 }
 
 /// Initialize the application with proper logging
@@ -44,6 +63,9 @@ pub fn init() -> Result<()> {
     // Initialize tracing subscriber for logging
     tracing_subscriber::fmt::init();
 
+    // Keep key material out of crash dumps
+    crypto::guarded::disable_core_dumps();
+
     tracing::info!("TwoPassword initialized");
     Ok(())
 }
@@ -59,8 +81,14 @@ mod tests {
 
     #[test]
     fn test_constants() {
-        assert_eq!(config::FORMAT_VERSION, 1);
+        assert_eq!(config::FORMAT_VERSION, 5);
         assert_eq!(config::KEY_SIZE, 32);
         assert_eq!(config::NONCE_SIZE, 12);
+        assert_eq!(
+            config::ARGON2_MEMORY_COST_KIB,
+            argon2::Params::DEFAULT_M_COST
+        );
+        assert_eq!(config::ARGON2_TIME_COST, argon2::Params::DEFAULT_T_COST);
+        assert_eq!(config::ARGON2_PARALLELISM, argon2::Params::DEFAULT_P_COST);
     }
 }
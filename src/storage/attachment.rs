@@ -0,0 +1,565 @@
+//! Encrypted file attachments
+//!
+//! Files attached to entries are not kept in the vault payload, which is
+//! decrypted as a whole on every unlock. Each file is encrypted on its own
+//! into a blob in a directory next to the vault, named after the vault with
+//! an `.attachments` extension. A blob is encrypted with a random key of its
+//! own, which the entry keeps wrapped under a subkey of the vault data key,
+//! so blobs never need to be re-encrypted when unlock methods change.
+//!
+//! Files are encrypted and decrypted in chunks with [`stream`], so a file is
+//! never held in memory as a whole. The entry records the size and SHA-256
+//! digest of every file, which are checked when it is extracted; on unlock,
+//! blobs are only checked to exist and have the expected length.
+//!
+//! A blob is a small header followed by the encrypted stream:
+//!
+//! ```text
+//! magic "2PWA" | version (1 byte) | encrypted stream
+//! ```
+
+use crate::crypto::key_schedule::{self, KeyPurpose};
+use crate::crypto::{key_wrap, stream, EncryptedData, MasterKey};
+use crate::{Result, TwoPasswordError};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Magic bytes at the start of every blob
+const BLOB_MAGIC: &[u8; 4] = b"2PWA";
+
+/// Version of the blob layout
+const BLOB_VERSION: u8 = 1;
+
+/// Extension of blob files
+const BLOB_EXTENSION: &str = "bin";
+
+/// A file attached to an entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Identifier of the blob holding the encrypted file
+    pub id: Uuid,
+    /// File name, unique within the entry ignoring case
+    pub name: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// SHA-256 digest of the file, in lowercase hex
+    pub sha256: String,
+    /// Key the blob is encrypted with, wrapped by the attachment key subkey
+    pub wrapped_key: EncryptedData,
+    pub added_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Something wrong with the blob of an attachment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentProblem {
+    /// Entry the attachment belongs to
+    pub entry_title: String,
+    pub attachment_name: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for AttachmentProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "attachment '{}' of '{}' {}",
+            self.attachment_name, self.entry_title, self.reason
+        )
+    }
+}
+
+/// Directory holding the blobs of the vault at `vault_path`
+pub fn blob_dir(vault_path: &Path) -> PathBuf {
+    vault_path.with_extension("attachments")
+}
+
+/// Path of the blob with the given identifier
+pub fn blob_path(vault_path: &Path, id: &Uuid) -> PathBuf {
+    blob_dir(vault_path).join(format!("{}.{}", id, BLOB_EXTENSION))
+}
+
+/// Find an attachment by name, ignoring case, or by identifier
+pub fn find<'a>(attachments: &'a [Attachment], name_or_id: &str) -> Option<&'a Attachment> {
+    let name_or_id = name_or_id.trim();
+    attachments
+        .iter()
+        .find(|attachment| attachment.name.eq_ignore_ascii_case(name_or_id))
+        .or_else(|| {
+            let id = Uuid::parse_str(name_or_id).ok()?;
+            attachments.iter().find(|attachment| attachment.id == id)
+        })
+}
+
+/// Check that an attachment name is usable and not taken by another
+/// attachment of the entry
+pub fn validate_name(attachments: &[Attachment], name: &str) -> Result<()> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(TwoPasswordError::validation(
+            "Attachment names cannot be empty",
+        ));
+    }
+    if name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(TwoPasswordError::validation(format!(
+            "Attachment name '{}' cannot contain path separators",
+            name
+        )));
+    }
+    if attachments
+        .iter()
+        .any(|attachment| attachment.name.eq_ignore_ascii_case(name))
+    {
+        return Err(TwoPasswordError::validation(format!(
+            "An attachment named '{}' already exists",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Wrap the key of a blob under the attachment key subkey of the data key
+pub fn wrap_file_key(data_key: &MasterKey, file_key: &MasterKey) -> Result<EncryptedData> {
+    key_wrap::wrap_key(&attachment_kek(data_key)?, file_key)
+}
+
+/// Unwrap the key of a blob with the data key
+pub fn unwrap_file_key(data_key: &MasterKey, wrapped_key: &EncryptedData) -> Result<MasterKey> {
+    key_wrap::unwrap_key(&attachment_kek(data_key)?, wrapped_key)
+}
+
+/// Encrypt everything `reader` yields into a new blob next to the vault,
+/// under `file_key`, which the attachment keeps as `wrapped_key`
+///
+/// The blob is written to a temporary file and moved into place once
+/// complete, so a failed attach leaves no blob behind.
+pub fn store(
+    vault_path: &Path,
+    file_key: &MasterKey,
+    wrapped_key: EncryptedData,
+    name: &str,
+    reader: impl Read,
+) -> Result<Attachment> {
+    let id = Uuid::new_v4();
+    create_blob_dir(vault_path)?;
+    let path = blob_path(vault_path, &id);
+    let temp_path = path.with_extension("tmp");
+    let written = write_blob(&temp_path, &id, file_key, reader).and_then(|hashed| {
+        fs::rename(&temp_path, &path).map_err(|e| {
+            TwoPasswordError::storage(format!("Failed to finalize attachment: {}", e))
+        })?;
+        Ok(hashed)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    let (size, sha256) = written?;
+
+    Ok(Attachment {
+        id,
+        name: name.trim().to_string(),
+        size,
+        sha256,
+        wrapped_key,
+        added_at: chrono::Utc::now(),
+    })
+}
+
+/// Decrypt the blob of an attachment with its unwrapped key to `writer`
+///
+/// Fails if the blob was changed, truncated or swapped with another, or does
+/// not match the size and digest recorded in the entry. Data may already
+/// have been written when this fails, so callers must discard the output.
+pub fn extract(
+    vault_path: &Path,
+    file_key: &MasterKey,
+    attachment: &Attachment,
+    writer: impl Write,
+) -> Result<()> {
+    let path = blob_path(vault_path, &attachment.id);
+    let file = File::open(&path).map_err(|e| {
+        TwoPasswordError::storage(format!(
+            "Failed to open attachment '{}': {}",
+            attachment.name, e
+        ))
+    })?;
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; BLOB_MAGIC.len() + 1];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| corrupted(attachment))?;
+    if header != blob_header() {
+        return Err(corrupted(attachment));
+    }
+
+    let mut writer = HashingWriter::new(writer);
+    let size =
+        stream::decrypt(file_key, &blob_aad(&attachment.id), reader, &mut writer).map_err(|e| {
+            match e {
+                TwoPasswordError::CryptoError(_) => corrupted(attachment),
+                e => e,
+            }
+        })?;
+    if size != attachment.size || writer.finish() != attachment.sha256 {
+        return Err(corrupted(attachment));
+    }
+    Ok(())
+}
+
+/// Check that every blob exists and has the length its attachment implies,
+/// without decrypting anything
+pub fn check<'a>(
+    vault_path: &Path,
+    attachments: impl IntoIterator<Item = (&'a str, &'a Attachment)>,
+) -> Vec<AttachmentProblem> {
+    attachments
+        .into_iter()
+        .filter_map(|(entry_title, attachment)| {
+            let expected = blob_header().len() as u64 + stream::encrypted_len(attachment.size);
+            let reason = match fs::metadata(blob_path(vault_path, &attachment.id)) {
+                Err(_) => "is missing".to_string(),
+                Ok(metadata) if metadata.len() != expected => format!(
+                    "is damaged ({} bytes, expected {})",
+                    metadata.len(),
+                    expected
+                ),
+                Ok(_) => return None,
+            };
+            Some(AttachmentProblem {
+                entry_title: entry_title.to_string(),
+                attachment_name: attachment.name.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Find blobs in the blob directory that are not in `referenced`
+///
+/// Only files named like blobs are considered, so nothing else placed in the
+/// directory is ever taken for one.
+pub fn unreferenced(vault_path: &Path, referenced: &HashSet<Uuid>) -> Result<Vec<Uuid>> {
+    let dir = blob_dir(vault_path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut found = Vec::new();
+    for dir_entry in fs::read_dir(&dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(BLOB_EXTENSION) {
+            continue;
+        }
+        let id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| Uuid::parse_str(stem).ok());
+        if let Some(id) = id {
+            if !referenced.contains(&id) {
+                found.push(id);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Delete the blob with the given identifier; a blob that is already gone
+/// is not an error
+pub fn remove_blob(vault_path: &Path, id: &Uuid) -> Result<()> {
+    match fs::remove_file(blob_path(vault_path, id)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(TwoPasswordError::storage(
+            format!("Failed to delete attachment {}: {}", id, e),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Format a size in bytes for display
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn attachment_kek(data_key: &MasterKey) -> Result<MasterKey> {
+    key_schedule::derive_subkey(data_key, KeyPurpose::AttachmentKeys)
+}
+
+fn blob_header() -> [u8; 5] {
+    let mut header = [0u8; 5];
+    header[..4].copy_from_slice(BLOB_MAGIC);
+    header[4] = BLOB_VERSION;
+    header
+}
+
+/// Associated data of a blob's chunks: the header and the blob identifier,
+/// so a blob cannot be passed off as another
+fn blob_aad(id: &Uuid) -> Vec<u8> {
+    let mut aad = blob_header().to_vec();
+    aad.extend_from_slice(id.as_bytes());
+    aad
+}
+
+fn corrupted(attachment: &Attachment) -> TwoPasswordError {
+    TwoPasswordError::crypto(format!(
+        "Attachment '{}' failed its integrity check - the file may be corrupted or tampered with",
+        attachment.name
+    ))
+}
+
+fn create_blob_dir(vault_path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    use std::os::unix::fs::DirBuilderExt;
+
+    let dir = blob_dir(vault_path);
+    if !dir.is_dir() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&dir).map_err(|e| {
+            TwoPasswordError::storage(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Write an encrypted blob, returning the size and digest of the plaintext
+fn write_blob(
+    path: &Path,
+    id: &Uuid,
+    file_key: &MasterKey,
+    reader: impl Read,
+) -> Result<(u64, String)> {
+    #[cfg(unix)]
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let file = options.open(path).map_err(|e| {
+        TwoPasswordError::storage(format!("Failed to create {}: {}", path.display(), e))
+    })?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&blob_header())?;
+
+    let mut reader = HashingReader::new(reader);
+    let size = stream::encrypt(file_key, &blob_aad(id), &mut reader, &mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| TwoPasswordError::storage(format!("Failed to write attachment: {}", e)))?
+        .sync_all()?;
+    Ok((size, reader.finish()))
+}
+
+/// Reader computing the SHA-256 digest of what passes through it
+struct HashingReader<R> {
+    inner: R,
+    context: digest::Context,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            context: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    fn finish(self) -> String {
+        hex(self.context.finish().as_ref())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.context.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Writer computing the SHA-256 digest of what passes through it
+struct HashingWriter<W> {
+    inner: W,
+    context: digest::Context,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            context: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    fn finish(self) -> String {
+        hex(self.context.finish().as_ref())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.context.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (tempfile::TempDir, PathBuf, MasterKey) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.2pw");
+        (temp_dir, vault_path, MasterKey::generate().unwrap())
+    }
+
+    fn put(vault_path: &Path, data_key: &MasterKey, name: &str, contents: &[u8]) -> Attachment {
+        let file_key = MasterKey::generate().unwrap();
+        let wrapped_key = wrap_file_key(data_key, &file_key).unwrap();
+        store(vault_path, &file_key, wrapped_key, name, contents).unwrap()
+    }
+
+    fn get(
+        vault_path: &Path,
+        data_key: &MasterKey,
+        attachment: &Attachment,
+        writer: impl Write,
+    ) -> Result<()> {
+        let file_key = unwrap_file_key(data_key, &attachment.wrapped_key)?;
+        extract(vault_path, &file_key, attachment, writer)
+    }
+
+    #[test]
+    fn test_store_and_extract() {
+        let (_temp_dir, vault_path, key) = setup();
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+
+        let attachment = put(&vault_path, &key, " backup.tar ", &contents);
+        assert_eq!(attachment.name, "backup.tar");
+        assert_eq!(attachment.size, contents.len() as u64);
+        assert_eq!(
+            attachment.sha256,
+            hex(digest::digest(&digest::SHA256, &contents).as_ref())
+        );
+
+        let path = blob_path(&vault_path, &attachment.id);
+        let blob = fs::read(&path).unwrap();
+        assert!(!blob.windows(64).any(|window| contents.starts_with(window)));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut extracted = Vec::new();
+        get(&vault_path, &key, &attachment, &mut extracted).unwrap();
+        assert_eq!(extracted, contents);
+        assert!(check(&vault_path, [("Entry", &attachment)]).is_empty());
+
+        // Only the vault's data key opens the blob
+        let other = MasterKey::generate().unwrap();
+        assert!(get(&vault_path, &other, &attachment, std::io::sink()).is_err());
+    }
+
+    #[test]
+    fn test_damage_is_detected() {
+        let (_temp_dir, vault_path, key) = setup();
+        let first = put(&vault_path, &key, "a.txt", b"first file");
+        let second = put(&vault_path, &key, "b.txt", b"second file");
+        let first_path = blob_path(&vault_path, &first.id);
+        let original = fs::read(&first_path).unwrap();
+
+        // Flipped bit
+        let mut flipped = original.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        fs::write(&first_path, &flipped).unwrap();
+        assert!(get(&vault_path, &key, &first, std::io::sink()).is_err());
+
+        // Truncated
+        fs::write(&first_path, &original[..original.len() - 1]).unwrap();
+        let problems = check(&vault_path, [("Entry", &first)]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].to_string().contains("is damaged"));
+
+        // Replaced by another blob, even one opened with its own key
+        fs::copy(blob_path(&vault_path, &second.id), &first_path).unwrap();
+        let mut swapped = first.clone();
+        swapped.wrapped_key = second.wrapped_key.clone();
+        assert!(get(&vault_path, &key, &swapped, std::io::sink()).is_err());
+
+        fs::remove_file(&first_path).unwrap();
+        assert_eq!(
+            check(&vault_path, [("Entry", &first)])[0].reason,
+            "is missing"
+        );
+    }
+
+    #[test]
+    fn test_unreferenced_and_remove() {
+        let (_temp_dir, vault_path, key) = setup();
+        let kept = put(&vault_path, &key, "kept", b"kept");
+        let dropped = put(&vault_path, &key, "dropped", b"dropped");
+        fs::write(blob_dir(&vault_path).join("notes.txt"), "not a blob").unwrap();
+
+        let referenced = HashSet::from([kept.id]);
+        assert_eq!(
+            unreferenced(&vault_path, &referenced).unwrap(),
+            [dropped.id]
+        );
+
+        remove_blob(&vault_path, &dropped.id).unwrap();
+        remove_blob(&vault_path, &dropped.id).unwrap();
+        assert!(blob_path(&vault_path, &kept.id).exists());
+        assert!(!blob_path(&vault_path, &dropped.id).exists());
+        assert!(blob_dir(&vault_path).join("notes.txt").exists());
+        assert!(unreferenced(&vault_path, &referenced).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_names() {
+        let (_temp_dir, vault_path, key) = setup();
+        let attachment = put(&vault_path, &key, "Report.pdf", b"%PDF");
+        let attachments = [attachment.clone()];
+
+        let found = |name_or_id: &str| find(&attachments, name_or_id).map(|found| found.id);
+        assert_eq!(found("report.PDF"), Some(attachment.id));
+        assert_eq!(found(&attachment.id.to_string()), Some(attachment.id));
+        assert_eq!(found("other.pdf"), None);
+
+        assert!(validate_name(&attachments, "other.pdf").is_ok());
+        assert!(validate_name(&attachments, "REPORT.pdf").is_err());
+        assert!(validate_name(&attachments, " ").is_err());
+        assert!(validate_name(&attachments, "../vault.2pw").is_err());
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+}
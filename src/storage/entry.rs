@@ -1,56 +1,182 @@
 //! Password entry operations and utilities
 
-use crate::storage::PasswordEntry;
+use crate::crypto::otp::Otp;
+use crate::crypto::SecretString;
+use crate::storage::field::{self, CustomField};
+use crate::storage::kind::EntryKind;
+use crate::storage::query::Query;
+use crate::storage::{tag, PasswordEntry};
 use crate::{Result, TwoPasswordError};
 use uuid::Uuid;
 
+/// Outcome of looking up an entry by identifier
+#[derive(Debug)]
+pub enum Resolution<'a> {
+    /// The identifier is the entry's ID or exact title
+    Found(&'a PasswordEntry),
+    /// No ID or exact title matched; the user should pick from these
+    /// fuzzy matches, best first
+    Candidates(Vec<&'a PasswordEntry>),
+    /// Nothing matched
+    NotFound,
+}
+
+/// Field changes to apply to an entry
+///
+/// `None` leaves a field unchanged. An empty URL or notes value clears the
+/// field, and so does `Some(None)` for the one-time password. Custom fields
+/// are removed before they are set, so a field can be replaced with one of
+/// another type.
+#[derive(Debug, Default)]
+pub struct EntryUpdate {
+    pub title: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub url: Option<String>,
+    pub notes: Option<String>,
+    pub otp: Option<Option<Otp>>,
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    /// Custom fields to add, or to replace if the entry has one of that name
+    pub set_fields: Vec<CustomField>,
+    /// Names of custom fields to remove
+    pub remove_fields: Vec<String>,
+}
+
+impl EntryUpdate {
+    /// Check whether the update changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.username.is_none()
+            && self.password.is_none()
+            && self.url.is_none()
+            && self.notes.is_none()
+            && self.otp.is_none()
+            && self.add_tags.is_empty()
+            && self.remove_tags.is_empty()
+            && self.set_fields.is_empty()
+            && self.remove_fields.is_empty()
+    }
+
+    /// Apply the changes to an entry, without touching its timestamps
+    pub fn apply(&self, entry: &mut PasswordEntry) {
+        if let Some(ref title) = self.title {
+            entry.title = title.clone();
+        }
+        if let Some(ref username) = self.username {
+            entry.username = username.clone();
+        }
+        if let Some(ref password) = self.password {
+            entry.password = password.clone();
+        }
+        if let Some(ref url) = self.url {
+            entry.url = Some(url.clone()).filter(|url| !url.trim().is_empty());
+        }
+        if let Some(ref notes) = self.notes {
+            entry.notes = Some(notes.clone()).filter(|notes| !notes.trim().is_empty());
+        }
+        if let Some(ref otp) = self.otp {
+            entry.otp = otp.clone();
+        }
+
+        entry.tags.retain(|kept| {
+            !self
+                .remove_tags
+                .iter()
+                .any(|removed| tag::same(removed, kept))
+        });
+        for added in &self.add_tags {
+            let added = tag::normalize(added);
+            if !added.is_empty() && !entry.tags.iter().any(|kept| tag::same(kept, &added)) {
+                entry.tags.push(added);
+            }
+        }
+
+        for name in &self.remove_fields {
+            field::remove_field(&mut entry.fields, name);
+        }
+        for custom in &self.set_fields {
+            field::set_field(&mut entry.fields, custom.clone());
+        }
+    }
+}
+
+/// A field that differs between two versions of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Field name as shown to the user
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Secret values are never displayed, only the fact that they changed
+    pub secret: bool,
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.secret {
+            return write!(f, "{}: ******** → ******** (changed)", self.field);
+        }
+
+        let show = |value: &Option<String>| match value {
+            Some(value) => format!("\"{}\"", value),
+            None => "(none)".to_string(),
+        };
+        write!(
+            f,
+            "{}: {} → {}",
+            self.field,
+            show(&self.before),
+            show(&self.after)
+        )
+    }
+}
+
 /// Search and filter operations for password entries
 pub struct EntryManager;
 
 impl EntryManager {
     /// Find entries by fuzzy title matching
+    ///
+    /// Titles rank above URLs, usernames and custom fields. Concealed custom
+    /// field values are never matched.
     pub fn fuzzy_search<'a>(entries: &'a [PasswordEntry], query: &str) -> Vec<&'a PasswordEntry> {
         let query_lower = query.to_lowercase();
         let mut matches: Vec<(&PasswordEntry, i32)> = entries
             .iter()
-            .filter_map(|entry| {
-                let title_lower = entry.title.to_lowercase();
-
-                // Exact match gets highest score
-                if title_lower == query_lower {
-                    return Some((entry, 100));
-                }
-
-                // Starts with query gets high score
-                if title_lower.starts_with(&query_lower) {
-                    return Some((entry, 80));
-                }
-
-                // Contains query gets medium score
-                if title_lower.contains(&query_lower) {
-                    return Some((entry, 60));
-                }
+            .filter_map(|entry| Some((entry, fuzzy_score(entry, &query_lower)?)))
+            .collect();
 
-                // URL matching
-                if let Some(ref url) = entry.url {
-                    let url_lower = url.to_lowercase();
-                    if url_lower.contains(&query_lower) {
-                        return Some((entry, 40));
-                    }
-                }
+        // Sort by score (highest first)
+        matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
 
-                // Username matching
-                if entry.username.to_lowercase().contains(&query_lower) {
-                    return Some((entry, 30));
-                }
+        matches.into_iter().map(|(entry, _)| entry).collect()
+    }
 
-                None
+    /// Find entries matching a search query, best matches first
+    ///
+    /// Entries are ranked by how well they match the free text of the query,
+    /// as in [`EntryManager::fuzzy_search`], and then by title; entries
+    /// matched only through their notes or tags come after the others.
+    pub fn search<'a>(entries: &'a [PasswordEntry], query: &Query) -> Vec<&'a PasswordEntry> {
+        let text = query.text().map(|text| text.to_lowercase());
+        let mut matches: Vec<(&PasswordEntry, i32)> = entries
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .map(|entry| {
+                let score = text
+                    .as_deref()
+                    .and_then(|text| fuzzy_score(entry, text))
+                    .unwrap_or(0);
+                (entry, score)
             })
             .collect();
 
-        // Sort by score (highest first)
-        matches.sort_by(|a, b| b.1.cmp(&a.1));
-
+        matches.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        });
         matches.into_iter().map(|(entry, _)| entry).collect()
     }
 
@@ -73,33 +199,164 @@ impl EntryManager {
             .collect()
     }
 
-    /// Find entries by tag
+    /// Find entries by tag, including entries with tags nested below it
     pub fn find_by_tag<'a>(entries: &'a [PasswordEntry], tag: &str) -> Vec<&'a PasswordEntry> {
-        let tag_lower = tag.to_lowercase();
         entries
             .iter()
-            .filter(|entry| entry.tags.iter().any(|t| t.to_lowercase() == tag_lower))
+            .filter(|entry| entry.tags.iter().any(|t| tag::is_within(t, tag)))
             .collect()
     }
 
+    /// Resolve an identifier given on the command line to an entry
+    ///
+    /// The identifier is tried as an entry ID, then as an exact title, first
+    /// case-sensitively and then ignoring case. Otherwise the fuzzy search
+    /// results are returned for the user to choose from.
+    pub fn resolve<'a>(entries: &'a [PasswordEntry], identifier: &str) -> Resolution<'a> {
+        if let Ok(id) = Uuid::parse_str(identifier.trim()) {
+            if let Some(entry) = Self::find_by_id(entries, &id) {
+                return Resolution::Found(entry);
+            }
+        }
+
+        let exact: Vec<&PasswordEntry> = entries
+            .iter()
+            .filter(|entry| entry.title == identifier)
+            .collect();
+        if let [entry] = exact[..] {
+            return Resolution::Found(entry);
+        }
+
+        let exact_ignoring_case: Vec<&PasswordEntry> = entries
+            .iter()
+            .filter(|entry| entry.title.to_lowercase() == identifier.to_lowercase())
+            .collect();
+        if let [entry] = exact_ignoring_case[..] {
+            return Resolution::Found(entry);
+        }
+
+        let candidates = Self::fuzzy_search(entries, identifier);
+        if candidates.is_empty() {
+            Resolution::NotFound
+        } else {
+            Resolution::Candidates(candidates)
+        }
+    }
+
+    /// List the fields that differ between two versions of an entry
+    pub fn diff(before: &PasswordEntry, after: &PasswordEntry) -> Vec<FieldChange> {
+        fn change(field: &str, before: Option<&str>, after: Option<&str>) -> Option<FieldChange> {
+            (before != after).then(|| FieldChange {
+                field: field.to_string(),
+                before: before.map(str::to_string),
+                after: after.map(str::to_string),
+                secret: false,
+            })
+        }
+
+        let tags = |entry: &PasswordEntry| Some(entry.tags.join(", ")).filter(|t| !t.is_empty());
+        let secret = |field: &str, changed: bool| {
+            changed.then(|| FieldChange {
+                field: field.to_string(),
+                before: None,
+                after: None,
+                secret: true,
+            })
+        };
+
+        let mut changes: Vec<FieldChange> = [
+            change("Title", Some(&before.title), Some(&after.title)),
+            change("Username", Some(&before.username), Some(&after.username)),
+            secret("Password", before.password != after.password),
+            change("URL", before.url.as_deref(), after.url.as_deref()),
+            change("Notes", before.notes.as_deref(), after.notes.as_deref()),
+            change("Tags", tags(before).as_deref(), tags(after).as_deref()),
+            secret("One-time password", before.otp != after.otp),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        // Custom fields by name, in the order of the new version and then
+        // the removed ones; a field that is or was concealed stays redacted
+        let find = |entry: &'_ PasswordEntry, name: &str| -> Option<CustomField> {
+            entry
+                .fields
+                .iter()
+                .find(|custom| custom.name.eq_ignore_ascii_case(name))
+                .cloned()
+        };
+        let removed = before
+            .fields
+            .iter()
+            .filter(|custom| find(after, &custom.name).is_none());
+        for name in after
+            .fields
+            .iter()
+            .chain(removed)
+            .map(|custom| &custom.name)
+        {
+            let (old, new) = (find(before, name), find(after, name));
+            if old == new {
+                continue;
+            }
+            let concealed = [&old, &new]
+                .into_iter()
+                .flatten()
+                .any(|custom| custom.field_type.is_concealed());
+            let value = |custom: &Option<CustomField>| {
+                custom
+                    .as_ref()
+                    .map(|custom| custom.value.expose().to_string())
+            };
+            changes.push(match concealed {
+                true => FieldChange {
+                    field: name.clone(),
+                    before: None,
+                    after: None,
+                    secret: true,
+                },
+                false => FieldChange {
+                    field: name.clone(),
+                    before: value(&old),
+                    after: value(&new),
+                    secret: false,
+                },
+            });
+        }
+        changes
+    }
+
     /// Find entry by ID
     pub fn find_by_id<'a>(entries: &'a [PasswordEntry], id: &Uuid) -> Option<&'a PasswordEntry> {
         entries.iter().find(|entry| entry.id == *id)
     }
 
     /// Validate entry data
+    ///
+    /// Logins and API tokens need a password, secure notes need notes, and
+    /// the fields of the entry's kind and its custom fields must be valid.
+    /// Usernames are optional.
     pub fn validate_entry(entry: &PasswordEntry) -> Result<()> {
         if entry.title.trim().is_empty() {
             return Err(TwoPasswordError::validation("Title cannot be empty"));
         }
 
-        if entry.username.trim().is_empty() {
-            return Err(TwoPasswordError::validation("Username cannot be empty"));
-        }
-
-        if entry.password.trim().is_empty() {
-            return Err(TwoPasswordError::validation("Password cannot be empty"));
+        match &entry.kind {
+            EntryKind::Login | EntryKind::ApiToken(_)
+                if entry.password.expose().trim().is_empty() =>
+            {
+                return Err(TwoPasswordError::validation(format!(
+                    "{} cannot be empty",
+                    entry.kind.password_label().unwrap_or("Password")
+                )));
+            }
+            EntryKind::SecureNote if entry.notes.as_deref().unwrap_or("").trim().is_empty() => {
+                return Err(TwoPasswordError::validation("Secure notes need some text"));
+            }
+            kind => kind.validate()?,
         }
+        field::validate_fields(&entry.fields)?;
 
         // Validate URL if provided
         if let Some(ref url) = entry.url {
@@ -159,6 +416,46 @@ impl EntryManager {
     }
 }
 
+/// Score how well an entry matches lowercase text, if it matches at all
+fn fuzzy_score(entry: &PasswordEntry, query_lower: &str) -> Option<i32> {
+    let title_lower = entry.title.to_lowercase();
+
+    // Exact match gets highest score
+    if title_lower == query_lower {
+        return Some(100);
+    }
+
+    // Starts with query gets high score
+    if title_lower.starts_with(query_lower) {
+        return Some(80);
+    }
+
+    // Contains query gets medium score
+    if title_lower.contains(query_lower) {
+        return Some(60);
+    }
+
+    // URL matching
+    if let Some(ref url) = entry.url {
+        if url.to_lowercase().contains(query_lower) {
+            return Some(40);
+        }
+    }
+
+    // Username matching
+    if entry.username.to_lowercase().contains(query_lower) {
+        return Some(30);
+    }
+
+    // Custom field matching, on names and visible values only
+    let field_matches = entry.fields.iter().any(|custom| {
+        custom.name.to_lowercase().contains(query_lower)
+            || (!custom.field_type.is_concealed()
+                && custom.value.expose().to_lowercase().contains(query_lower))
+    });
+    field_matches.then_some(20)
+}
+
 /// Check if a string is a valid URL
 fn is_valid_url(url_str: &str) -> bool {
     url::Url::parse(url_str).is_ok()
@@ -217,6 +514,7 @@ pub fn generate_password(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::kind::{ApiToken, Card};
 
     fn create_test_entry(title: &str, username: &str, url: Option<String>) -> PasswordEntry {
         let mut entry = PasswordEntry::new(
@@ -256,11 +554,54 @@ mod tests {
 
         let results = EntryManager::find_by_domain(&entries, "google");
         assert_eq!(results.len(), 1); // Only "drive.google.com" contains "google"
-        
+
         let github_results = EntryManager::find_by_domain(&entries, "github");
         assert_eq!(github_results.len(), 1);
     }
 
+    #[test]
+    fn test_search_ranks_by_free_text() {
+        let mut entries = vec![
+            create_test_entry("Work email", "git", None),
+            create_test_entry("GitHub", "user1", Some("https://github.com".to_string())),
+            create_test_entry("Git", "user2", None),
+            create_test_entry("Bank", "user3", None),
+        ];
+        entries[3].notes = Some("Account opened via git-based paperwork".to_string());
+
+        let titles = |query: &str| -> Vec<String> {
+            EntryManager::search(&entries, &query.parse().unwrap())
+                .iter()
+                .map(|entry| entry.title.clone())
+                .collect()
+        };
+        assert_eq!(titles("git"), ["Git", "GitHub", "Work email", "Bank"]);
+        assert_eq!(titles("git -has:notes user:user"), ["Git", "GitHub"]);
+        assert_eq!(titles(""), ["Bank", "Git", "GitHub", "Work email"]);
+    }
+
+    #[test]
+    fn test_find_by_tag_includes_nested_tags() {
+        let mut entries = vec![
+            create_test_entry("db01", "root", None),
+            create_test_entry("GitHub", "user1", None),
+            create_test_entry("Workshop", "user2", None),
+        ];
+        entries[0].tags = vec!["Work/Servers".to_string()];
+        entries[1].tags = vec!["work".to_string()];
+        entries[2].tags = vec!["workshop".to_string()];
+
+        let titles = |tag: &str| -> Vec<String> {
+            EntryManager::find_by_tag(&entries, tag)
+                .iter()
+                .map(|entry| entry.title.clone())
+                .collect()
+        };
+        assert_eq!(titles("WORK"), ["db01", "GitHub"]);
+        assert_eq!(titles("work/servers/"), ["db01"]);
+        assert!(titles("work/desktops").is_empty());
+    }
+
     #[test]
     fn test_validate_entry() {
         let valid_entry =
@@ -269,6 +610,37 @@ mod tests {
 
         let invalid_entry = create_test_entry("", "user", None);
         assert!(EntryManager::validate_entry(&invalid_entry).is_err());
+
+        // Usernames are optional, but logins need a password
+        let no_username = create_test_entry("Wi-Fi", "", None);
+        assert!(EntryManager::validate_entry(&no_username).is_ok());
+        let no_password = PasswordEntry::new("Wi-Fi".to_string(), String::new(), "");
+        assert!(EntryManager::validate_entry(&no_password).is_err());
+
+        let token = ApiToken::default();
+        let mut entry = PasswordEntry::of_kind("CI".to_string(), EntryKind::ApiToken(token), "");
+        assert_eq!(
+            EntryManager::validate_entry(&entry)
+                .unwrap_err()
+                .to_string(),
+            "Validation error: Token cannot be empty"
+        );
+        entry.password = "ghp_token".into();
+        assert!(EntryManager::validate_entry(&entry).is_ok());
+
+        let mut note = PasswordEntry::of_kind("Safe".to_string(), EntryKind::SecureNote, "");
+        assert!(EntryManager::validate_entry(&note).is_err());
+        note.notes = Some("12-34-56".to_string());
+        assert!(EntryManager::validate_entry(&note).is_ok());
+
+        let card = Card {
+            cardholder: None,
+            number: "4242424242424241".into(),
+            expiry: None,
+            security_code: None,
+        };
+        let card = PasswordEntry::of_kind("Visa".to_string(), EntryKind::Card(card), "");
+        assert!(EntryManager::validate_entry(&card).is_err());
     }
 
     #[test]
@@ -282,6 +654,119 @@ mod tests {
         assert!(short_password.chars().all(|c| c.is_ascii_uppercase()));
     }
 
+    #[test]
+    fn test_resolve() {
+        let entries = vec![
+            create_test_entry("GitHub", "user1", None),
+            create_test_entry("GitHub Enterprise", "user2", None),
+            create_test_entry("GitLab", "user3", None),
+        ];
+
+        let by_id = entries[2].id.to_string();
+        assert!(matches!(
+            EntryManager::resolve(&entries, &by_id),
+            Resolution::Found(entry) if entry.title == "GitLab"
+        ));
+        assert!(matches!(
+            EntryManager::resolve(&entries, "github"),
+            Resolution::Found(entry) if entry.title == "GitHub"
+        ));
+        match EntryManager::resolve(&entries, "git") {
+            Resolution::Candidates(candidates) => assert_eq!(candidates.len(), 3),
+            other => panic!("expected candidates, got {:?}", other),
+        }
+        assert!(matches!(
+            EntryManager::resolve(&entries, "bitbucket"),
+            Resolution::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_update_and_diff() {
+        let mut entry = create_test_entry("GitHub", "user1", Some("https://github.com".into()));
+        entry.tags = vec!["dev".to_string(), "old".to_string()];
+        let before = entry.clone();
+
+        let update = EntryUpdate {
+            username: Some("octocat".to_string()),
+            password: Some(SecretString::from("n3w-s3cret")),
+            url: Some(String::new()),
+            otp: Some(Some(Otp::totp("JBSWY3DPEHPK3PXP").unwrap())),
+            add_tags: vec!["work".to_string(), "DEV".to_string()],
+            remove_tags: vec!["OLD".to_string()],
+            ..Default::default()
+        };
+        assert!(!update.is_empty());
+        update.apply(&mut entry);
+
+        assert_eq!(entry.username, "octocat");
+        assert_eq!(entry.url, None);
+        assert_eq!(entry.tags, vec!["dev", "work"]);
+        assert!(entry.otp.is_some());
+
+        let changes = EntryManager::diff(&before, &entry);
+        let fields: Vec<&str> = changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec!["Username", "Password", "URL", "Tags", "One-time password"]
+        );
+
+        // Passwords are redacted from the printed diff
+        let printed: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+        assert!(printed.iter().all(|line| !line.contains("n3w-s3cret")));
+        assert!(!format!("{:?}", changes).contains("n3w-s3cret"));
+        assert!(!format!("{:?}", changes).contains("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn test_custom_fields() {
+        use crate::storage::field::FieldType;
+
+        let mut entry = create_test_entry("Database", "admin", None);
+        entry.fields = vec![
+            CustomField::new("Host", FieldType::Text, "db.internal"),
+            CustomField::new("Recovery code", FieldType::Concealed, "r3c0very"),
+        ];
+        assert!(EntryManager::validate_entry(&entry).is_ok());
+        let before = entry.clone();
+
+        let update = EntryUpdate {
+            set_fields: vec![
+                CustomField::new("host", FieldType::Text, "db2.internal"),
+                CustomField::new("Recovery code", FieldType::Concealed, "n3w-c0de"),
+                CustomField::new("Schema", FieldType::Text, "orders"),
+            ],
+            remove_fields: vec!["Missing".to_string()],
+            ..Default::default()
+        };
+        assert!(!update.is_empty());
+        update.apply(&mut entry);
+        let names: Vec<&str> = entry.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["host", "Recovery code", "Schema"]);
+
+        let changes = EntryManager::diff(&before, &entry);
+        let printed: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(
+            printed,
+            [
+                "host: \"db.internal\" → \"db2.internal\"",
+                "Recovery code: ******** → ******** (changed)",
+                "Schema: (none) → \"orders\"",
+            ]
+        );
+
+        // Fields are searchable by name and visible value, never by secret
+        let entries = vec![entry.clone(), create_test_entry("GitHub", "octocat", None)];
+        assert_eq!(EntryManager::fuzzy_search(&entries, "schema").len(), 1);
+        assert_eq!(EntryManager::fuzzy_search(&entries, "db2").len(), 1);
+        assert!(EntryManager::fuzzy_search(&entries, "n3w-c0de").is_empty());
+
+        entry
+            .fields
+            .push(CustomField::new("SCHEMA", FieldType::Text, "users"));
+        assert!(EntryManager::validate_entry(&entry).is_err());
+    }
+
     #[test]
     fn test_find_duplicates() {
         let entries = vec![
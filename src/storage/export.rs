@@ -0,0 +1,507 @@
+//! Vault export
+//!
+//! JSON and CSV exports hold every password in plaintext and are meant for
+//! moving entries to another tool. KDBX exports are KeePass databases locked
+//! with an export passphrase, for moving entries to KeePass-compatible
+//! managers without a plaintext copy, and carry the entries' attachments
+//! too. Pass exports are `pass` password-store
+//! directories rather than files, written by [`crate::storage::pass`] with
+//! each entry encrypted by gpg under the export passphrase, or in plaintext.
+//! Encrypted exports are meant for backups:
+//! the entries are sealed with AES-256-GCM under a key derived from an export
+//! passphrase of their own, so a copy kept off-site neither depends on the
+//! vault's key slots nor opens with the master password.
+//!
+//! An encrypted export is a JSON envelope:
+//!
+//! ```text
+//! { "format": "twopassword-export", "version": 1, "kdf": {..}, "salt": {..},
+//!   "encrypted_data": {..} }
+//! ```
+//!
+//! The envelope fields other than `encrypted_data` are bound to the
+//! ciphertext as associated data, so weakening the KDF parameters or
+//! swapping the salt makes decryption fail.
+
+use crate::crypto::key_derivation::{self, KdfParams};
+use crate::crypto::{aes_gcm, EncryptedData, Salt, SecretString};
+use crate::storage::kdbx::payload::{Binary, Element, Payload};
+use crate::storage::kdbx::{self, OTP_FIELD};
+use crate::storage::PasswordEntry;
+use crate::{Result, TwoPasswordError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Identifier at the start of every encrypted export
+pub const ENCRYPTED_EXPORT_FORMAT: &str = "twopassword-export";
+
+/// Version of the export documents written by this build
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Columns of a CSV export, in order
+pub const CSV_HEADERS: [&str; 8] = [
+    "title",
+    "username",
+    "password",
+    "url",
+    "notes",
+    "tags",
+    "created_at",
+    "updated_at",
+];
+
+/// Decrypted contents of attachments by attachment ID, for exports that
+/// carry them
+pub type AttachmentFiles = HashMap<uuid::Uuid, Zeroizing<Vec<u8>>>;
+
+/// Export file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Plaintext JSON document with every entry field
+    Json,
+    /// Plaintext CSV with the common login fields
+    Csv,
+    /// JSON document encrypted with an export passphrase
+    Encrypted,
+    /// KeePass KDBX 4 database locked with an export passphrase
+    Kdbx,
+    /// `pass` store with entries encrypted by gpg with an export passphrase
+    Pass,
+    /// `pass` store with plaintext entries
+    PassPlaintext,
+}
+
+impl ExportFormat {
+    /// Identifier used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Encrypted => "encrypted",
+            ExportFormat::Kdbx => "kdbx",
+            ExportFormat::Pass => "pass",
+            ExportFormat::PassPlaintext => "pass-plaintext",
+        }
+    }
+
+    /// Check whether exports in this format are directories
+    pub fn is_directory(&self) -> bool {
+        matches!(self, ExportFormat::Pass | ExportFormat::PassPlaintext)
+    }
+
+    /// Check whether files in this format expose passwords to anyone who
+    /// can read them
+    pub fn is_plaintext(&self) -> bool {
+        !matches!(
+            self,
+            ExportFormat::Encrypted | ExportFormat::Kdbx | ExportFormat::Pass
+        )
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = TwoPasswordError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "encrypted" | "enc" => Ok(ExportFormat::Encrypted),
+            "kdbx" | "keepass" => Ok(ExportFormat::Kdbx),
+            "pass" => Ok(ExportFormat::Pass),
+            "pass-plaintext" => Ok(ExportFormat::PassPlaintext),
+            _ => Err(TwoPasswordError::validation(format!(
+                "Unknown export format '{}' \
+                 (expected json, csv, encrypted, kdbx, pass or pass-plaintext)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Entries as written to a JSON export and inside an encrypted export
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportDocument {
+    pub version: u32,
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub entries: Vec<PasswordEntry>,
+}
+
+/// Envelope of an encrypted export
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EncryptedExport {
+    pub format: String,
+    pub version: u32,
+    pub kdf: KdfParams,
+    pub salt: Salt,
+    pub encrypted_data: EncryptedData,
+}
+
+impl EncryptedExport {
+    /// Associated data binding the envelope header to the ciphertext
+    pub(crate) fn header_aad(&self) -> Result<Vec<u8>> {
+        header_aad(&self.format, self.version, &self.kdf, &self.salt)
+    }
+}
+
+fn header_aad(format: &str, version: u32, kdf: &KdfParams, salt: &Salt) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&(format, version, kdf, salt))?)
+}
+
+/// Serialize entries in a plaintext or encrypted format
+///
+/// `passphrase` is required for encrypted and KDBX exports and ignored
+/// otherwise, as is `files` outside KDBX exports.
+pub fn export_entries(
+    entries: &[PasswordEntry],
+    files: &AttachmentFiles,
+    format: ExportFormat,
+    passphrase: Option<&SecretString>,
+    kdf: &KdfParams,
+) -> Result<Zeroizing<Vec<u8>>> {
+    match format {
+        ExportFormat::Json => to_json(entries),
+        ExportFormat::Csv => to_csv(entries),
+        ExportFormat::Encrypted | ExportFormat::Kdbx => {
+            let passphrase = passphrase.ok_or_else(|| {
+                TwoPasswordError::validation(format!(
+                    "{} exports need an export passphrase",
+                    format
+                ))
+            })?;
+            if format == ExportFormat::Kdbx {
+                to_kdbx(entries, files, passphrase, kdf)
+            } else {
+                to_encrypted(entries, passphrase, kdf)
+            }
+        }
+        ExportFormat::Pass | ExportFormat::PassPlaintext => Err(TwoPasswordError::validation(
+            "pass exports are directories; write them with pass::write_store",
+        )),
+    }
+}
+
+/// Serialize entries as a pretty-printed JSON export document
+///
+/// Attachments are left out: their blobs stay next to the vault and can only
+/// be opened with its data key.
+pub fn to_json(entries: &[PasswordEntry]) -> Result<Zeroizing<Vec<u8>>> {
+    let document = ExportDocument {
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now(),
+        entries: entries
+            .iter()
+            .map(|entry| PasswordEntry {
+                attachments: Vec::new(),
+                ..entry.clone()
+            })
+            .collect(),
+    };
+    Ok(Zeroizing::new(serde_json::to_vec_pretty(&document)?))
+}
+
+/// Serialize entries as CSV with the columns in [`CSV_HEADERS`]
+///
+/// Tags are joined with commas inside their column. Password history, custom
+/// fields and the fields of entry kinds other than logins are not exported
+/// to CSV.
+pub fn to_csv(entries: &[PasswordEntry]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_HEADERS).map_err(csv_error)?;
+
+    for entry in entries {
+        let created_at = entry.created_at.to_rfc3339();
+        let updated_at = entry.updated_at.to_rfc3339();
+        let tags = entry.tags.join(",");
+        writer
+            .write_record([
+                entry.title.as_str(),
+                entry.username.as_str(),
+                entry.password.expose(),
+                entry.url.as_deref().unwrap_or_default(),
+                entry.notes.as_deref().unwrap_or_default(),
+                tags.as_str(),
+                created_at.as_str(),
+                updated_at.as_str(),
+            ])
+            .map_err(csv_error)?;
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| TwoPasswordError::storage(format!("Failed to write CSV: {}", e)))?;
+    Ok(Zeroizing::new(bytes))
+}
+
+/// Seal entries in an encrypted export protected by `passphrase`
+pub fn to_encrypted(
+    entries: &[PasswordEntry],
+    passphrase: &SecretString,
+    kdf: &KdfParams,
+) -> Result<Zeroizing<Vec<u8>>> {
+    if passphrase.is_empty() {
+        return Err(TwoPasswordError::validation(
+            "Export passphrase cannot be empty",
+        ));
+    }
+
+    let salt = Salt::generate()?;
+    let mut key = key_derivation::derive_key_with_params(passphrase.expose(), &salt.bytes, kdf)?;
+
+    let plaintext = to_json(entries)?;
+    let sealed = header_aad(ENCRYPTED_EXPORT_FORMAT, EXPORT_VERSION, kdf, &salt)
+        .and_then(|aad| aes_gcm::encrypt_with_aad(&key, &plaintext, &aad));
+    key.zeroize();
+
+    let envelope = EncryptedExport {
+        format: ENCRYPTED_EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        kdf: *kdf,
+        salt,
+        encrypted_data: sealed?,
+    };
+    Ok(Zeroizing::new(serde_json::to_vec_pretty(&envelope)?))
+}
+
+/// Write entries to a KeePass KDBX 4 database locked with `passphrase`
+///
+/// Entries go in the root group with their tags as KeePass tags, and each
+/// password history item becomes an earlier version of its entry.
+/// Attachments are stored in the database with their contents from `files`,
+/// which must hold every one of them. The database key is derived with
+/// Argon2id using `kdf`.
+pub fn to_kdbx(
+    entries: &[PasswordEntry],
+    files: &AttachmentFiles,
+    passphrase: &SecretString,
+    kdf: &KdfParams,
+) -> Result<Zeroizing<Vec<u8>>> {
+    if passphrase.is_empty() {
+        return Err(TwoPasswordError::validation(
+            "Export passphrase cannot be empty",
+        ));
+    }
+
+    let mut binaries = Vec::new();
+    let mut group = Element::new("Group")
+        .child(Element::with_text(
+            "UUID",
+            kdbx::payload::encode_uuid(uuid::Uuid::new_v4()),
+        ))
+        .child(Element::with_text("Name", crate::config::APP_NAME));
+    for entry in entries {
+        group = group.child(kdbx_entry(entry, files, &mut binaries)?);
+    }
+    let meta = Element::new("Meta")
+        .child(Element::with_text("Generator", crate::config::APP_NAME))
+        .child(Element::with_text("DatabaseName", crate::config::APP_NAME))
+        .child(Element::with_text("RecycleBinEnabled", "False"));
+    let root = Element::new("KeePassFile")
+        .child(meta)
+        .child(Element::new("Root").child(group));
+
+    let payload = Payload { binaries, root }.to_bytes()?;
+    kdbx::encrypt(&payload, passphrase, kdf, kdbx::Cipher::ChaCha20).map(Zeroizing::new)
+}
+
+fn kdbx_entry(
+    entry: &PasswordEntry,
+    files: &AttachmentFiles,
+    binaries: &mut Vec<Binary>,
+) -> Result<Element> {
+    let uuid = kdbx::payload::encode_uuid(entry.id);
+    let mut element = Element::new("Entry")
+        .child(Element::with_text("UUID", uuid.clone()))
+        .child(Element::with_text("Tags", entry.tags.join(";")))
+        .child(kdbx_times(entry.created_at, entry.updated_at))
+        .child(kdbx_string("Title", &entry.title, false))
+        .child(kdbx_string("UserName", &entry.username, false))
+        .child(kdbx_string("Password", entry.password.expose(), true))
+        .child(kdbx_string(
+            "URL",
+            entry.url.as_deref().unwrap_or_default(),
+            false,
+        ))
+        .child(kdbx_string(
+            "Notes",
+            entry.notes.as_deref().unwrap_or_default(),
+            false,
+        ));
+    if let Some(otp) = &entry.otp {
+        element = element.child(kdbx_string(OTP_FIELD, otp.to_uri().expose(), true));
+    }
+    for (key, value, secret) in entry.kind.text_fields() {
+        element = element.child(kdbx_string(key, &value, secret));
+    }
+    for custom in &entry.fields {
+        let protected = custom.field_type.is_concealed();
+        element = element.child(kdbx_string(&custom.name, custom.value.expose(), protected));
+    }
+    for attachment in &entry.attachments {
+        let data = files.get(&attachment.id).ok_or_else(|| {
+            TwoPasswordError::storage(format!(
+                "Contents of attachment '{}' of '{}' are missing",
+                attachment.name, entry.title
+            ))
+        })?;
+        let mut reference = Element::new("Value");
+        reference
+            .attributes
+            .push(("Ref".to_string(), binaries.len().to_string()));
+        binaries.push(Binary {
+            protected: false,
+            data: data.clone(),
+        });
+        element = element.child(
+            Element::new("Binary")
+                .child(Element::with_text("Key", &attachment.name))
+                .child(reference),
+        );
+    }
+    if entry.history.is_empty() {
+        return Ok(element);
+    }
+
+    // KeePass lists earlier versions oldest first
+    let history = entry.history.iter().rev().map(|item| {
+        let username = item.username.as_deref().unwrap_or(&entry.username);
+        Element::new("Entry")
+            .child(Element::with_text("UUID", uuid.clone()))
+            .child(kdbx_times(entry.created_at, item.replaced_at))
+            .child(kdbx_string("Title", &entry.title, false))
+            .child(kdbx_string("UserName", username, false))
+            .child(kdbx_string("Password", item.password.expose(), true))
+    });
+    Ok(element.child(history.fold(Element::new("History"), Element::child)))
+}
+
+fn kdbx_times(
+    created_at: chrono::DateTime<chrono::Utc>,
+    modified_at: chrono::DateTime<chrono::Utc>,
+) -> Element {
+    let time = |name: &str, time| Element::with_text(name, kdbx::payload::encode_time(time));
+    Element::new("Times")
+        .child(time("CreationTime", created_at))
+        .child(time("LastModificationTime", modified_at))
+        .child(time("LastAccessTime", modified_at))
+        .child(time("ExpiryTime", modified_at))
+        .child(Element::with_text("Expires", "False"))
+        .child(Element::with_text("UsageCount", "0"))
+        .child(time("LocationChanged", created_at))
+}
+
+fn kdbx_string(key: &str, value: &str, protected: bool) -> Element {
+    let value = Element::with_text("Value", value);
+    Element::new("String")
+        .child(Element::with_text("Key", key))
+        .child(if protected { value.protected() } else { value })
+}
+
+/// Write an export file readable only by its owner
+///
+/// On Unix the file is created with mode 0600, and an existing file is
+/// switched to 0600 before anything is written to it.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    #[cfg(unix)]
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).map_err(|e| {
+        TwoPasswordError::storage(format!("Failed to create {}: {}", path.display(), e))
+    })?;
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn csv_error(e: csv::Error) -> TwoPasswordError {
+    TwoPasswordError::storage(format!("Failed to write CSV: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_entries() -> Vec<PasswordEntry> {
+        let mut github = PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "hunter2".to_string(),
+        );
+        github.url = Some("https://github.com".to_string());
+        github.tags = vec!["dev".to_string(), "work".to_string()];
+
+        let mut bank = PasswordEntry::new(
+            "Bank, \"main\"".to_string(),
+            "alice".to_string(),
+            "p,a\"ss\nword".to_string(),
+        );
+        bank.notes = Some("line one\nline two".to_string());
+        vec![github, bank]
+    }
+
+    #[test]
+    fn test_csv_quotes_special_characters() {
+        let csv = to_csv(&sample_entries()).unwrap();
+        let text = std::str::from_utf8(&csv).unwrap();
+
+        assert!(text.starts_with("title,username,password,url,notes,tags,"));
+        assert!(text.contains("\"Bank, \"\"main\"\"\""));
+        assert!(text.contains("\"p,a\"\"ss\nword\""));
+        assert!(text.contains("\"dev,work\""));
+    }
+
+    #[test]
+    fn test_encrypted_export_hides_entries() {
+        let passphrase = SecretString::from("export passphrase");
+        let sealed = to_encrypted(&sample_entries(), &passphrase, &KdfParams::for_tests()).unwrap();
+        let text = std::str::from_utf8(&sealed).unwrap();
+
+        assert!(text.contains(ENCRYPTED_EXPORT_FORMAT));
+        assert!(!text.contains("hunter2"));
+        assert!(!text.contains("octocat"));
+
+        let empty = SecretString::from("");
+        assert!(to_encrypted(&sample_entries(), &empty, &KdfParams::for_tests()).is_err());
+        assert!(export_entries(
+            &[],
+            &AttachmentFiles::new(),
+            ExportFormat::Encrypted,
+            None,
+            &KdfParams::for_tests()
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_sets_owner_only_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("export.json");
+
+        // An existing world-readable file is tightened before it is written
+        std::fs::write(&path, b"old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"secret").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read(&path).unwrap(), b"secret");
+    }
+}
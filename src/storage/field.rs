@@ -0,0 +1,241 @@
+//! Custom fields
+//!
+//! Entries can carry named fields beyond those of their kind, such as
+//! recovery codes, security answers or a database name. Fields keep the
+//! order they were added in, and their names are unique within an entry,
+//! ignoring case. The type of a field decides how its value is checked and
+//! whether it is masked when the entry is shown; every value is stored as a
+//! secret, so none of them is printed by accident.
+
+use crate::crypto::otp::Otp;
+use crate::crypto::SecretString;
+use crate::storage::kind::is_valid_email;
+use crate::{Result, TwoPasswordError};
+use serde::{Deserialize, Serialize};
+
+/// Names of the field types, as used on the command line
+pub const FIELD_TYPES: [&str; 6] = ["text", "concealed", "url", "email", "date", "otp"];
+
+/// How a field value is checked and shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    /// Plain text, shown as is
+    #[default]
+    Text,
+    /// Secret text, masked until revealed
+    Concealed,
+    /// Web address
+    Url,
+    /// Email address
+    Email,
+    /// Calendar date, as YYYY-MM-DD
+    Date,
+    /// Two-factor secret: an otpauth:// URI or a base32 TOTP secret,
+    /// masked like concealed fields
+    Otp,
+}
+
+impl FieldType {
+    /// Identifier used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Concealed => "concealed",
+            FieldType::Url => "url",
+            FieldType::Email => "email",
+            FieldType::Date => "date",
+            FieldType::Otp => "otp",
+        }
+    }
+
+    /// Look up a type by its command line identifier
+    pub fn from_name(name: &str) -> Result<Self> {
+        [
+            FieldType::Text,
+            FieldType::Concealed,
+            FieldType::Url,
+            FieldType::Email,
+            FieldType::Date,
+            FieldType::Otp,
+        ]
+        .into_iter()
+        .find(|field_type| field_type.name().eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| {
+            TwoPasswordError::validation(format!(
+                "Unknown field type '{}' (expected one of: {})",
+                name,
+                FIELD_TYPES.join(", ")
+            ))
+        })
+    }
+
+    /// Check whether values of this type are masked when shown
+    pub fn is_concealed(&self) -> bool {
+        matches!(self, FieldType::Concealed | FieldType::Otp)
+    }
+}
+
+/// A named value added to an entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub field_type: FieldType,
+    pub value: SecretString,
+}
+
+impl CustomField {
+    /// Create a field, trimming its name
+    pub fn new(name: &str, field_type: FieldType, value: impl Into<SecretString>) -> Self {
+        Self {
+            name: name.trim().to_string(),
+            field_type,
+            value: value.into(),
+        }
+    }
+
+    /// Check that the field is named and its value fits its type
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(TwoPasswordError::validation(
+                "Custom field names cannot be empty",
+            ));
+        }
+
+        let value = self.value.expose().trim();
+        let invalid = |expected: &str| {
+            TwoPasswordError::validation(format!("Field '{}' is not {}", self.name, expected))
+        };
+        match self.field_type {
+            FieldType::Text | FieldType::Concealed => Ok(()),
+            FieldType::Url if url::Url::parse(value).is_err() => Err(invalid("a valid URL")),
+            FieldType::Email if !is_valid_email(value) => Err(invalid("an email address")),
+            FieldType::Date if chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_err() => {
+                Err(invalid("a date (expected YYYY-MM-DD)"))
+            }
+            FieldType::Otp => Otp::parse(value)
+                .map(|_| ())
+                .map_err(|_| invalid("an otpauth:// URI or base32 secret")),
+            FieldType::Url | FieldType::Email | FieldType::Date => Ok(()),
+        }
+    }
+
+    /// Value as shown before the user asks to reveal it
+    pub fn display_value(&self) -> String {
+        match self.field_type.is_concealed() {
+            true => "*".repeat(self.value.len()),
+            false => self.value.expose().to_string(),
+        }
+    }
+}
+
+/// Check every field, and that no two fields have the same name
+pub fn validate_fields(fields: &[CustomField]) -> Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        field.validate()?;
+        if fields[..i]
+            .iter()
+            .any(|other| other.name.eq_ignore_ascii_case(&field.name))
+        {
+            return Err(TwoPasswordError::validation(format!(
+                "Field '{}' is defined twice",
+                field.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Replace the field with the same name, keeping its position, or add the
+/// field at the end
+pub fn set_field(fields: &mut Vec<CustomField>, field: CustomField) {
+    match fields
+        .iter_mut()
+        .find(|existing| existing.name.eq_ignore_ascii_case(&field.name))
+    {
+        Some(existing) => *existing = field,
+        None => fields.push(field),
+    }
+}
+
+/// Remove the field named `name`, ignoring case; returns whether there was one
+pub fn remove_field(fields: &mut Vec<CustomField>, name: &str) -> bool {
+    let before = fields.len();
+    fields.retain(|field| !field.name.eq_ignore_ascii_case(name.trim()));
+    fields.len() != before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let valid = [
+            CustomField::new("Recovery codes", FieldType::Concealed, "1234-5678"),
+            CustomField::new("Console", FieldType::Url, "https://console.example.com"),
+            CustomField::new("Owner", FieldType::Email, "ops@example.com"),
+            CustomField::new("Rotate by", FieldType::Date, "2026-01-31"),
+            CustomField::new("Backup 2FA", FieldType::Otp, "JBSWY3DPEHPK3PXP"),
+        ];
+        assert!(validate_fields(&valid).is_ok());
+
+        for invalid in [
+            CustomField::new(" ", FieldType::Text, "value"),
+            CustomField::new("Console", FieldType::Url, "console"),
+            CustomField::new("Owner", FieldType::Email, "ops"),
+            CustomField::new("Rotate by", FieldType::Date, "31/01/2026"),
+            CustomField::new("Backup 2FA", FieldType::Otp, "not base32!"),
+        ] {
+            assert!(invalid.validate().is_err(), "{}", invalid.name);
+        }
+
+        let twice = [
+            CustomField::new("PIN", FieldType::Concealed, "1234"),
+            CustomField::new("pin", FieldType::Text, "5678"),
+        ];
+        assert_eq!(
+            validate_fields(&twice).unwrap_err().to_string(),
+            "Validation error: Field 'pin' is defined twice"
+        );
+    }
+
+    #[test]
+    fn test_set_and_remove() {
+        let mut fields = vec![
+            CustomField::new("Database", FieldType::Text, "orders"),
+            CustomField::new("PIN", FieldType::Concealed, "1234"),
+        ];
+        set_field(
+            &mut fields,
+            CustomField::new("database", FieldType::Text, "users"),
+        );
+        set_field(
+            &mut fields,
+            CustomField::new("Region", FieldType::Text, "eu-west-1"),
+        );
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(names, ["database", "PIN", "Region"]);
+        assert_eq!(fields[0].value.expose(), "users");
+
+        assert!(remove_field(&mut fields, "pin"));
+        assert!(!remove_field(&mut fields, "pin"));
+        assert_eq!(fields.len(), 2);
+    }
+
+    #[test]
+    fn test_concealed_values_are_masked() {
+        let pin = CustomField::new("PIN", FieldType::Concealed, "1234");
+        assert_eq!(pin.display_value(), "****");
+        assert!(!format!("{:?}", pin).contains("1234"));
+        let region = CustomField::new("Region", FieldType::Text, "eu-west-1");
+        assert_eq!(region.display_value(), "eu-west-1");
+
+        let json = serde_json::to_string(&pin).unwrap();
+        assert_eq!(json, r#"{"name":"PIN","type":"concealed","value":"1234"}"#);
+        assert_eq!(serde_json::from_str::<CustomField>(&json).unwrap(), pin);
+        assert_eq!(FieldType::from_name("OTP").unwrap(), FieldType::Otp);
+        assert!(FieldType::from_name("secret").is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! Binary vault container
+//!
+//! Vault files from format version 2 on are stored as a compact binary
+//! container instead of pretty-printed JSON:
+//!
+//! ```text
+//! magic "2PWVAULT" | header version (u16) | section*
+//! section = tag (u8) | length (u32) | body
+//! ```
+//!
+//! Integers are little-endian and byte strings inside a section body are
+//! prefixed with their u32 length. Every section except key slots appears
+//! exactly once; a reader rejects unknown tags rather than skipping them,
+//! because it could not authenticate their contents.
+
+use crate::crypto::key_derivation::{KdfAlgorithm, KdfParams};
+use crate::crypto::key_schedule::KeySchedule;
+use crate::crypto::key_wrap::{KeySlot, KeySlotKind};
+use crate::crypto::{CipherSuite, EncryptedData, Salt};
+use crate::storage::{VaultFile, VaultMetadata};
+use crate::{Result, TwoPasswordError};
+
+/// Magic bytes at the start of every binary vault file
+pub const MAGIC: &[u8; 8] = b"2PWVAULT";
+
+/// Container layout written by this version
+pub const CONTAINER_VERSION: u16 = 2;
+
+const SECTION_METADATA: u8 = 1;
+const SECTION_SALT: u8 = 2;
+const SECTION_KDF: u8 = 3;
+const SECTION_KEY_SLOT: u8 = 4;
+const SECTION_CIPHER: u8 = 5;
+const SECTION_FLAGS: u8 = 6;
+const SECTION_PAYLOAD: u8 = 7;
+const SECTION_INTEGRITY: u8 = 8;
+
+const FLAG_HIDE_METADATA: u8 = 0x01;
+
+/// Check whether file contents are a binary container
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Read the header version of a binary container
+pub fn container_version(bytes: &[u8]) -> Result<u16> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(TwoPasswordError::InvalidVaultFormat);
+    }
+    reader.u16()
+}
+
+/// Encode a vault file as a binary container
+pub fn encode(vault_file: &VaultFile) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&CONTAINER_VERSION.to_le_bytes());
+
+    let mut body = Writer::default();
+    let metadata = &vault_file.metadata;
+    body.u32(metadata.format_version);
+    body.timestamp(&metadata.created_at);
+    body.timestamp(&metadata.updated_at);
+    body.u64(metadata.entry_count as u64);
+    section(&mut out, SECTION_METADATA, body);
+
+    section(
+        &mut out,
+        SECTION_SALT,
+        Writer(vault_file.salt.bytes.clone()),
+    );
+
+    let mut body = Writer::default();
+    body.u8(match vault_file.kdf.algorithm {
+        KdfAlgorithm::Argon2id => 1,
+    });
+    body.u32(vault_file.kdf.memory_cost_kib);
+    body.u32(vault_file.kdf.time_cost);
+    body.u32(vault_file.kdf.parallelism);
+    section(&mut out, SECTION_KDF, body);
+
+    for slot in &vault_file.key_slots {
+        let mut body = Writer::default();
+        body.u8(match slot.kind {
+            KeySlotKind::Password => 1,
+            KeySlotKind::RecoveryKey => 2,
+            KeySlotKind::SecretSharing => 3,
+        });
+        body.encrypted(&slot.wrapped_key);
+        section(&mut out, SECTION_KEY_SLOT, body);
+    }
+
+    section(
+        &mut out,
+        SECTION_CIPHER,
+        Writer(vec![cipher_id(vault_file.cipher)]),
+    );
+
+    let flags = if vault_file.hide_metadata {
+        FLAG_HIDE_METADATA
+    } else {
+        0
+    };
+    section(&mut out, SECTION_FLAGS, Writer(vec![flags]));
+
+    let mut body = Writer::default();
+    body.encrypted(&vault_file.encrypted_data);
+    section(&mut out, SECTION_PAYLOAD, body);
+
+    section(
+        &mut out,
+        SECTION_INTEGRITY,
+        Writer(vault_file.integrity_hash.clone()),
+    );
+
+    out
+}
+
+/// Decode a binary container
+pub fn decode(bytes: &[u8]) -> Result<VaultFile> {
+    let version = container_version(bytes)?;
+    if version != CONTAINER_VERSION {
+        return Err(TwoPasswordError::InvalidVaultFormat);
+    }
+
+    let mut reader = Reader::new(&bytes[MAGIC.len() + 2..]);
+    let mut metadata = None;
+    let mut salt = None;
+    let mut kdf = None;
+    let mut key_slots = Vec::new();
+    let mut cipher = None;
+    let mut flags = None;
+    let mut encrypted_data = None;
+    let mut integrity_hash = None;
+
+    while !reader.is_empty() {
+        let tag = reader.u8()?;
+        let len = reader.u32()? as usize;
+        let mut body = Reader::new(reader.take(len)?);
+
+        let duplicate = match tag {
+            SECTION_METADATA => metadata.replace(read_metadata(&mut body)?).is_some(),
+            SECTION_SALT => salt
+                .replace(Salt::from_bytes(body.rest().to_vec()))
+                .is_some(),
+            SECTION_KDF => kdf.replace(read_kdf(&mut body)?).is_some(),
+            SECTION_KEY_SLOT => {
+                let kind = match body.u8()? {
+                    1 => KeySlotKind::Password,
+                    2 => KeySlotKind::RecoveryKey,
+                    3 => KeySlotKind::SecretSharing,
+                    _ => return Err(TwoPasswordError::InvalidVaultFormat),
+                };
+                let wrapped_key = body.encrypted()?;
+                key_slots.push(KeySlot { kind, wrapped_key });
+                false
+            }
+            SECTION_CIPHER => cipher.replace(cipher_from_id(body.u8()?)?).is_some(),
+            SECTION_FLAGS => {
+                let value = body.u8()?;
+                if value & !FLAG_HIDE_METADATA != 0 {
+                    return Err(TwoPasswordError::InvalidVaultFormat);
+                }
+                flags.replace(value).is_some()
+            }
+            SECTION_PAYLOAD => encrypted_data.replace(body.encrypted()?).is_some(),
+            SECTION_INTEGRITY => integrity_hash.replace(body.rest().to_vec()).is_some(),
+            _ => return Err(TwoPasswordError::InvalidVaultFormat),
+        };
+
+        if duplicate || !body.is_empty() {
+            return Err(TwoPasswordError::InvalidVaultFormat);
+        }
+    }
+
+    let missing = || TwoPasswordError::InvalidVaultFormat;
+    Ok(VaultFile {
+        metadata: metadata.ok_or_else(missing)?,
+        salt: salt.ok_or_else(missing)?,
+        kdf: kdf.ok_or_else(missing)?,
+        key_slots,
+        cipher: cipher.ok_or_else(missing)?,
+        hide_metadata: flags.unwrap_or(0) & FLAG_HIDE_METADATA != 0,
+        encrypted_data: encrypted_data.ok_or_else(missing)?,
+        integrity_hash: integrity_hash.ok_or_else(missing)?,
+    })
+}
+
+fn section(out: &mut Vec<u8>, tag: u8, body: Writer) {
+    out.push(tag);
+    out.extend_from_slice(&(body.0.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body.0);
+}
+
+fn read_metadata(body: &mut Reader) -> Result<VaultMetadata> {
+    Ok(VaultMetadata {
+        format_version: body.u32()?,
+        created_at: body.timestamp()?,
+        updated_at: body.timestamp()?,
+        entry_count: usize::try_from(body.u64()?)
+            .map_err(|_| TwoPasswordError::InvalidVaultFormat)?,
+    })
+}
+
+fn read_kdf(body: &mut Reader) -> Result<KdfParams> {
+    let algorithm = match body.u8()? {
+        1 => KdfAlgorithm::Argon2id,
+        _ => return Err(TwoPasswordError::InvalidVaultFormat),
+    };
+    Ok(KdfParams {
+        algorithm,
+        memory_cost_kib: body.u32()?,
+        time_cost: body.u32()?,
+        parallelism: body.u32()?,
+    })
+}
+
+fn cipher_id(cipher: CipherSuite) -> u8 {
+    match cipher {
+        CipherSuite::Aes256Gcm => 1,
+        CipherSuite::XChaCha20Poly1305 => 2,
+    }
+}
+
+fn cipher_from_id(id: u8) -> Result<CipherSuite> {
+    match id {
+        1 => Ok(CipherSuite::Aes256Gcm),
+        2 => Ok(CipherSuite::XChaCha20Poly1305),
+        _ => Err(TwoPasswordError::InvalidVaultFormat),
+    }
+}
+
+/// Append-only section body
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+    }
+
+    fn timestamp(&mut self, value: &chrono::DateTime<chrono::Utc>) {
+        self.0.extend_from_slice(&value.timestamp().to_le_bytes());
+        self.u32(value.timestamp_subsec_nanos());
+    }
+
+    fn encrypted(&mut self, value: &EncryptedData) {
+        self.u8(cipher_id(value.cipher));
+        self.u8(match value.key_schedule {
+            KeySchedule::Legacy => 0,
+            KeySchedule::Hkdf => 1,
+        });
+        self.bytes(&value.nonce);
+        self.bytes(&value.hmac);
+        self.bytes(&value.ciphertext);
+    }
+}
+
+/// Bounds-checked cursor over a byte slice
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(TwoPasswordError::InvalidVaultFormat);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self.take(N)?;
+        bytes
+            .try_into()
+            .map_err(|_| TwoPasswordError::InvalidVaultFormat)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn timestamp(&mut self) -> Result<chrono::DateTime<chrono::Utc>> {
+        let secs = i64::from_le_bytes(self.array()?);
+        let nanos = self.u32()?;
+        chrono::DateTime::from_timestamp(secs, nanos).ok_or(TwoPasswordError::InvalidVaultFormat)
+    }
+
+    fn encrypted(&mut self) -> Result<EncryptedData> {
+        let cipher = cipher_from_id(self.u8()?)?;
+        let key_schedule = match self.u8()? {
+            0 => KeySchedule::Legacy,
+            1 => KeySchedule::Hkdf,
+            _ => return Err(TwoPasswordError::InvalidVaultFormat),
+        };
+        Ok(EncryptedData {
+            cipher,
+            key_schedule,
+            nonce: self.bytes()?,
+            hmac: self.bytes()?,
+            ciphertext: self.bytes()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_file() -> VaultFile {
+        let encrypted = |len: usize| EncryptedData {
+            cipher: CipherSuite::XChaCha20Poly1305,
+            key_schedule: KeySchedule::Hkdf,
+            ciphertext: vec![0xAB; len],
+            nonce: vec![1; crate::config::XCHACHA_NONCE_SIZE],
+            hmac: vec![2; crate::config::HMAC_SIZE],
+        };
+
+        VaultFile {
+            metadata: VaultMetadata {
+                format_version: crate::config::FORMAT_VERSION,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+                entry_count: 3,
+            },
+            salt: Salt::from_bytes(vec![7; crate::config::SALT_SIZE]),
+            kdf: KdfParams::default(),
+            key_slots: vec![
+                KeySlot {
+                    kind: KeySlotKind::Password,
+                    wrapped_key: encrypted(48),
+                },
+                KeySlot {
+                    kind: KeySlotKind::RecoveryKey,
+                    wrapped_key: encrypted(48),
+                },
+            ],
+            cipher: CipherSuite::XChaCha20Poly1305,
+            hide_metadata: true,
+            encrypted_data: encrypted(1000),
+            integrity_hash: vec![3; crate::config::HMAC_SIZE],
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let file = sample_file();
+        let bytes = encode(&file);
+        assert!(is_binary(&bytes));
+        assert_eq!(container_version(&bytes).unwrap(), CONTAINER_VERSION);
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&file).unwrap()
+        );
+
+        // Much smaller than the JSON representation
+        let json = serde_json::to_vec_pretty(&file).unwrap();
+        assert!(bytes.len() * 3 < json.len());
+    }
+
+    #[test]
+    fn test_malformed_containers_rejected() {
+        let bytes = encode(&sample_file());
+
+        // Truncated at every length
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err());
+        }
+
+        // Unknown header version
+        let mut other = bytes.clone();
+        other[MAGIC.len()] = 9;
+        assert!(matches!(
+            decode(&other),
+            Err(TwoPasswordError::InvalidVaultFormat)
+        ));
+
+        // Unknown section
+        let mut other = bytes.clone();
+        other.extend_from_slice(&[99, 0, 0, 0, 0]);
+        assert!(decode(&other).is_err());
+
+        // Duplicate section
+        let mut other = bytes.clone();
+        other.extend_from_slice(&[SECTION_FLAGS, 1, 0, 0, 0, 0]);
+        assert!(decode(&other).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Bitwarden JSON and CSV exports
+//!
+//! Only login items are imported. Folders become tags, TOTP secrets become
+//! the entry's one-time password and custom fields become custom fields:
+//! hidden ones concealed, linked ones skipped since they only point at
+//! another field.
+
+use super::{
+    item_location, non_empty, parse_timestamp, push_field, read_csv, set_otp, Importer,
+    ParsedImport,
+};
+use crate::storage::field::FieldType;
+use crate::storage::{HistoryItem, PasswordEntry};
+use crate::{Result, TwoPasswordError};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Bitwarden item type of logins
+const LOGIN_TYPE: u8 = 1;
+
+/// Bitwarden custom field types
+const HIDDEN_FIELD: u8 = 1;
+const LINKED_FIELD: u8 = 3;
+
+/// Unencrypted JSON export from Bitwarden
+pub struct BitwardenJson;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(rename = "type")]
+    kind: u8,
+    name: String,
+    notes: Option<String>,
+    folder_id: Option<String>,
+    login: Option<Login>,
+    #[serde(default)]
+    fields: Vec<Field>,
+    password_history: Option<Vec<PasswordHistory>>,
+    creation_date: Option<String>,
+    revision_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<Uri>>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+    #[serde(rename = "type", default)]
+    kind: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PasswordHistory {
+    password: String,
+    last_used_date: String,
+}
+
+impl Importer for BitwardenJson {
+    fn name(&self) -> &'static str {
+        "bitwarden-json"
+    }
+
+    fn description(&self) -> &'static str {
+        "Bitwarden JSON export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        let export: Export = serde_json::from_slice(contents).map_err(|e| {
+            TwoPasswordError::validation(format!("Not a Bitwarden JSON export: {}", e))
+        })?;
+        if export.encrypted || export.password_protected {
+            return Err(TwoPasswordError::validation(
+                "Bitwarden export is encrypted; export it again as unencrypted JSON",
+            ));
+        }
+
+        let folders: HashMap<String, String> = export
+            .folders
+            .into_iter()
+            .map(|folder| (folder.id, folder.name))
+            .collect();
+
+        let mut parsed = ParsedImport::default();
+        for (i, value) in export.items.into_iter().enumerate() {
+            let location = item_location(i, value.get("name"));
+            let entry = serde_json::from_value::<Item>(value)
+                .map_err(TwoPasswordError::from)
+                .and_then(|item| json_entry(item, &folders));
+            parsed.push(location, entry);
+        }
+        Ok(parsed)
+    }
+}
+
+fn json_entry(item: Item, folders: &HashMap<String, String>) -> Result<PasswordEntry> {
+    let Some(login) = item.login.filter(|_| item.kind == LOGIN_TYPE) else {
+        return Err(unsupported_type(&item_type_name(item.kind)));
+    };
+
+    let mut entry = PasswordEntry::new(
+        item.name,
+        login.username.unwrap_or_default(),
+        login.password.unwrap_or_default(),
+    );
+    entry.url = login
+        .uris
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|uri| uri.uri.filter(|uri| !uri.trim().is_empty()));
+    entry.notes = item.notes.filter(|notes| !notes.trim().is_empty());
+    entry.tags = item
+        .folder_id
+        .and_then(|id| non_empty(folders.get(&id)?))
+        .into_iter()
+        .collect();
+    if let Some(totp) = login.totp {
+        set_otp(&mut entry, &totp);
+    }
+    for field in item
+        .fields
+        .into_iter()
+        .filter(|field| field.kind != LINKED_FIELD)
+    {
+        if let (Some(name), Some(value)) = (field.name, field.value) {
+            let field_type = match field.kind {
+                HIDDEN_FIELD => FieldType::Concealed,
+                _ => FieldType::Text,
+            };
+            push_field(&mut entry, &name, field_type, &value);
+        }
+    }
+    for previous in item.password_history.unwrap_or_default() {
+        entry.history.push(HistoryItem {
+            password: previous.password.into(),
+            username: None,
+            replaced_at: parse_timestamp(&previous.last_used_date)?,
+        });
+    }
+    if let Some(created_at) = item.creation_date {
+        entry.created_at = parse_timestamp(&created_at)?;
+    }
+    if let Some(updated_at) = item.revision_date {
+        entry.updated_at = parse_timestamp(&updated_at)?;
+    }
+    Ok(entry)
+}
+
+fn item_type_name(kind: u8) -> String {
+    match kind {
+        1 => "login without login details".to_string(),
+        2 => "secure note".to_string(),
+        3 => "card".to_string(),
+        4 => "identity".to_string(),
+        5 => "SSH key".to_string(),
+        other => format!("type {}", other),
+    }
+}
+
+fn unsupported_type(kind: &str) -> TwoPasswordError {
+    TwoPasswordError::validation(format!("Bitwarden {} items are not supported", kind))
+}
+
+/// CSV export from a personal Bitwarden vault
+pub struct BitwardenCsv;
+
+impl Importer for BitwardenCsv {
+    fn name(&self) -> &'static str {
+        "bitwarden-csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "Bitwarden CSV export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        read_csv(
+            contents,
+            &[&["type"], &["name"], &["login_password"]],
+            |record| {
+                let kind = record.get(&["type"]).unwrap_or_default();
+                if kind != "login" {
+                    return Err(unsupported_type(&kind));
+                }
+
+                let mut entry = PasswordEntry::new(
+                    record.get(&["name"]).unwrap_or_default(),
+                    record.get(&["login_username"]).unwrap_or_default(),
+                    record.get(&["login_password"]).unwrap_or_default(),
+                );
+                // Several URIs are separated by commas
+                entry.url = record
+                    .get(&["login_uri"])
+                    .and_then(|uris| non_empty(uris.split(',').next()?));
+                entry.notes = record.get(&["notes"]);
+                entry.tags = record.get(&["folder"]).into_iter().collect();
+                if let Some(totp) = record.get(&["login_totp"]) {
+                    set_otp(&mut entry, &totp);
+                }
+                // Custom fields are "name: value" lines
+                if let Some(fields) = record.get(&["fields"]) {
+                    for line in fields.lines().filter(|line| !line.trim().is_empty()) {
+                        let (name, value) = line.split_once(": ").unwrap_or(("Field", line));
+                        push_field(&mut entry, name, FieldType::Text, value);
+                    }
+                }
+                Ok(entry)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::import::ImportRecord;
+
+    fn titles(records: &[ImportRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.entry.title.as_str()).collect()
+    }
+
+    const JSON: &str = r#"{
+      "encrypted": false,
+      "folders": [{ "id": "f1", "name": "Work" }],
+      "items": [
+        {
+          "id": "a", "type": 1, "name": "GitHub", "notes": "2FA on phone",
+          "folderId": "f1", "favorite": false,
+          "login": {
+            "username": "octocat", "password": "hunter2", "totp": "JBSWY3DPEHPK3PXP",
+            "uris": [{ "match": null, "uri": "https://github.com/login" }]
+          },
+          "fields": [{ "name": "PIN", "value": "1234", "type": 1 }],
+          "passwordHistory": [
+            { "lastUsedDate": "2024-01-01T00:00:00.000Z", "password": "hunter1" }
+          ],
+          "creationDate": "2023-05-01T10:00:00.000Z",
+          "revisionDate": "2024-02-01T10:00:00.000Z"
+        },
+        { "id": "b", "type": 2, "name": "Wifi", "notes": "pw", "secureNote": { "type": 0 } },
+        { "id": "c", "type": 1, "name": 42 }
+      ]
+    }"#;
+
+    #[test]
+    fn test_json_export() {
+        let parsed = BitwardenJson.parse(JSON.as_bytes()).unwrap();
+        assert_eq!(titles(&parsed.records), ["GitHub"]);
+
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.username, "octocat");
+        assert_eq!(entry.password.expose(), "hunter2");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com/login"));
+        assert_eq!(entry.tags, ["Work"]);
+        assert_eq!(entry.notes.as_deref(), Some("2FA on phone"));
+        assert_eq!(entry.fields[0].name, "PIN");
+        assert_eq!(entry.fields[0].field_type, FieldType::Concealed);
+        assert_eq!(entry.fields[0].value.expose(), "1234");
+        assert_eq!(
+            entry.otp.as_ref().unwrap().secret.expose(),
+            "JBSWY3DPEHPK3PXP"
+        );
+        assert_eq!(entry.history[0].password.expose(), "hunter1");
+        assert_eq!(entry.created_at.to_rfc3339(), "2023-05-01T10:00:00+00:00");
+
+        let errors: Vec<String> = parsed.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors[0],
+            "item 2 (Wifi): Bitwarden secure note items are not supported"
+        );
+        assert!(errors[1].starts_with("item 3:"));
+
+        let encrypted = r#"{ "encrypted": true, "encKeyValidation_DO_NOT_EDIT": "x" }"#;
+        assert!(BitwardenJson.parse(encrypted.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let csv = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,\
+                   login_password,login_totp\n\
+                   Work,1,login,GitHub,,\"PIN: 1234\nColor: blue\",0,\
+                   \"https://github.com,https://gist.github.com\",octocat,hunter2,\n\
+                   ,,note,Wifi,pw,,0,,,,\n";
+        let parsed = BitwardenCsv.parse(csv.as_bytes()).unwrap();
+        assert_eq!(titles(&parsed.records), ["GitHub"]);
+
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+        assert_eq!(entry.tags, ["Work"]);
+        assert!(entry.notes.is_none());
+        let fields: Vec<(&str, &str)> = entry
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.value.expose()))
+            .collect();
+        assert_eq!(fields, [("PIN", "1234"), ("Color", "blue")]);
+        assert_eq!(
+            parsed.errors[0].message,
+            "Bitwarden note items are not supported"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Password CSV exports from Chrome and Firefox
+//!
+//! Browsers store logins by site, so entries are titled with the site's host
+//! when the export has no name for them.
+
+use super::{non_empty, read_csv, title_from_url, CsvRecord, Importer, ParsedImport};
+use crate::storage::PasswordEntry;
+use crate::{Result, TwoPasswordError};
+
+/// Chrome, Edge and other Chromium browsers
+pub struct ChromeCsv;
+
+impl Importer for ChromeCsv {
+    fn name(&self) -> &'static str {
+        "chrome-csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "Chrome password CSV export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        read_csv(contents, &[&["url"], &["password"]], |record| {
+            let url = record.get(&["url"]);
+            let mut entry = PasswordEntry::new(
+                site_title(record, &["name"], url.as_deref())?,
+                record.get(&["username"]).unwrap_or_default(),
+                record.get(&["password"]).unwrap_or_default(),
+            );
+            entry.url = url;
+            entry.notes = record.get(&["note", "notes"]);
+            Ok(entry)
+        })
+    }
+}
+
+/// Firefox
+pub struct FirefoxCsv;
+
+impl Importer for FirefoxCsv {
+    fn name(&self) -> &'static str {
+        "firefox-csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "Firefox password CSV export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        read_csv(contents, &[&["url"], &["password"]], |record| {
+            let url = record.get(&["url"]);
+            let mut entry = PasswordEntry::new(
+                site_title(record, &[], url.as_deref())?,
+                record.get(&["username"]).unwrap_or_default(),
+                record.get(&["password"]).unwrap_or_default(),
+            );
+            entry.url = url;
+            // Firefox records times in milliseconds since the Unix epoch
+            let time = |names: &[&str]| {
+                record
+                    .get(names)
+                    .and_then(|millis| millis.parse::<i64>().ok())
+                    .and_then(chrono::DateTime::from_timestamp_millis)
+            };
+            if let Some(created_at) = time(&["timeCreated"]) {
+                entry.created_at = created_at;
+            }
+            if let Some(updated_at) = time(&["timePasswordChanged"]) {
+                entry.updated_at = updated_at;
+            }
+            Ok(entry)
+        })
+    }
+}
+
+/// Title from a name column, or else the site's host
+fn site_title(record: &CsvRecord, names: &[&str], url: Option<&str>) -> Result<String> {
+    record
+        .get(names)
+        .or_else(|| title_from_url(url?))
+        .or_else(|| non_empty(url?))
+        .ok_or_else(|| TwoPasswordError::validation("Login has neither a name nor a URL"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chrome_export() {
+        let csv = "name,url,username,password,note\n\
+                   ,https://www.github.com/login,octocat,hunter2,\n\
+                   My bank,https://bank.example,alice,s3cret,Branch 12\n\
+                   ,,bob,secret,\n";
+        let parsed = ChromeCsv.parse(csv.as_bytes()).unwrap();
+
+        let titles: Vec<&str> = parsed
+            .records
+            .iter()
+            .map(|r| r.entry.title.as_str())
+            .collect();
+        assert_eq!(titles, ["github.com", "My bank"]);
+        assert_eq!(parsed.records[1].entry.notes.as_deref(), Some("Branch 12"));
+        assert_eq!(parsed.errors[0].location, "line 4");
+    }
+
+    #[test]
+    fn test_firefox_export() {
+        let csv = "\u{feff}\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\
+                   \"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+                   \"https://github.com\",\"octocat\",\"hunter2\",,\"https://github.com\",\
+                   \"{a1}\",\"1700000000000\",\"1710000000000\",\"1705000000000\"\n";
+        let parsed = FirefoxCsv.parse(csv.as_bytes()).unwrap();
+
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.title, "github.com");
+        assert_eq!(entry.username, "octocat");
+        assert_eq!(entry.created_at.timestamp(), 1700000000);
+        assert_eq!(entry.updated_at.timestamp(), 1705000000);
+    }
+}
@@ -0,0 +1,350 @@
+//! KeePass KDBX 4 databases
+//!
+//! A database is opened with [`kdbx::decrypt`] first; this importer reads
+//! the decrypted payload. Groups become tags holding the group path below
+//! the root group, as for KeePassXC CSV exports, next to the entry's own
+//! tags. KeePassXC's `otp` field becomes the entry's one-time password,
+//! other custom string fields become custom fields, concealed if their
+//! value is protected, and earlier
+//! versions of an entry that used another password or username become its
+//! password history. Attachments are read from the database's binaries, to
+//! be attached to the imported entries. Entries in the recycle bin are
+//! skipped.
+
+use super::{
+    non_empty, push_field, set_otp, split_tags, ImportRecord, ImportedFile, Importer, ParsedImport,
+};
+use crate::storage::field::FieldType;
+use crate::storage::kdbx::payload::{Binary, Element, Payload};
+use crate::storage::kdbx::{self, OTP_FIELD};
+use crate::storage::{HistoryItem, PasswordEntry};
+use crate::{Result, TwoPasswordError};
+
+/// String fields with a place in `PasswordEntry`
+const STANDARD_FIELDS: [&str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+
+/// Decrypted KeePass KDBX 4 database
+pub struct KeePassKdbx;
+
+impl Importer for KeePassKdbx {
+    fn name(&self) -> &'static str {
+        "kdbx"
+    }
+
+    fn description(&self) -> &'static str {
+        "KeePass KDBX 4 database"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        if kdbx::is_kdbx(contents) {
+            return Err(TwoPasswordError::validation(
+                "KeePass database must be opened with its password first",
+            ));
+        }
+        let payload = Payload::parse(contents)?;
+
+        let meta = payload.root.get("Meta");
+        let recycle_bin = meta
+            .filter(|meta| meta.text_of("RecycleBinEnabled") != Some("False"))
+            .and_then(|meta| meta.text_of("RecycleBinUUID"))
+            .and_then(kdbx::payload::decode_uuid)
+            .filter(|uuid| !uuid.is_nil());
+        let root = payload
+            .root
+            .get("Root")
+            .and_then(|root| root.get("Group"))
+            .ok_or_else(|| TwoPasswordError::validation("KeePass database has no root group"))?;
+
+        let mut parsed = ParsedImport::default();
+        let mut reader = GroupReader {
+            recycle_bin,
+            binaries: &payload.binaries,
+            path: Vec::new(),
+            parsed: &mut parsed,
+        };
+        reader.read(root, false);
+        Ok(parsed)
+    }
+}
+
+struct GroupReader<'a> {
+    recycle_bin: Option<uuid::Uuid>,
+    binaries: &'a [Binary],
+    /// Names of the groups below the root group leading to the current one
+    path: Vec<String>,
+    parsed: &'a mut ParsedImport,
+}
+
+impl GroupReader<'_> {
+    fn read(&mut self, group: &Element, in_recycle_bin: bool) {
+        for element in group.all("Entry") {
+            let index = self.parsed.records.len() + self.parsed.errors.len();
+            let location = match field(element, "Title").and_then(non_empty) {
+                Some(title) => format!("entry {} ({})", index + 1, title),
+                None => format!("entry {}", index + 1),
+            };
+            let entry = if in_recycle_bin {
+                Err(TwoPasswordError::validation(
+                    "Skipped entry in the recycle bin",
+                ))
+            } else {
+                kdbx_entry(element, &self.path, self.binaries)
+            };
+            match entry {
+                Ok((entry, files)) => self.parsed.records.push(ImportRecord {
+                    location,
+                    entry,
+                    files,
+                }),
+                Err(e) => self.parsed.push(location, Err(e)),
+            }
+        }
+
+        for child in group.all("Group") {
+            let is_recycle_bin = self.recycle_bin.is_some()
+                && child.text_of("UUID").and_then(kdbx::payload::decode_uuid) == self.recycle_bin;
+            self.path
+                .push(child.text_of("Name").unwrap_or_default().trim().to_string());
+            self.read(child, in_recycle_bin || is_recycle_bin);
+            self.path.pop();
+        }
+    }
+}
+
+/// Value of a string field of an entry
+fn field<'a>(entry: &'a Element, key: &str) -> Option<&'a str> {
+    entry
+        .all("String")
+        .find(|string| string.text_of("Key") == Some(key))
+        .and_then(|string| string.text_of("Value"))
+}
+
+fn time(entry: &Element, name: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    entry
+        .get("Times")?
+        .text_of(name)
+        .and_then(kdbx::payload::decode_time)
+}
+
+fn kdbx_entry(
+    element: &Element,
+    path: &[String],
+    binaries: &[Binary],
+) -> Result<(PasswordEntry, Vec<ImportedFile>)> {
+    let mut entry = PasswordEntry::new(
+        field(element, "Title")
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+        field(element, "UserName").unwrap_or_default().to_string(),
+        field(element, "Password").unwrap_or_default(),
+    );
+    if let Some(id) = element.text_of("UUID").and_then(kdbx::payload::decode_uuid) {
+        entry.id = id;
+    }
+    entry.url = field(element, "URL").and_then(non_empty);
+    entry.notes = field(element, "Notes").and_then(non_empty);
+
+    let group = path.iter().filter(|name| !name.is_empty()).cloned();
+    let group = group.collect::<Vec<_>>().join("/");
+    let tags = element.text_of("Tags").unwrap_or_default();
+    entry.tags = split_tags(&format!("{};{}", group, tags), &[';', ',']);
+
+    for string in element.all("String") {
+        let key = string.text_of("Key").unwrap_or_default();
+        let Some(value) = string.get("Value") else {
+            continue;
+        };
+        let Some(text) = non_empty(&value.text) else {
+            continue;
+        };
+        if key == OTP_FIELD {
+            set_otp(&mut entry, &text);
+        } else if !STANDARD_FIELDS.contains(&key) {
+            let field_type = match value.is_protected() {
+                true => FieldType::Concealed,
+                false => FieldType::Text,
+            };
+            push_field(&mut entry, key, field_type, &text);
+        }
+    }
+
+    let mut files = Vec::new();
+    for binary in element.all("Binary") {
+        let name = binary.text_of("Key").unwrap_or_default().trim();
+        let data = binary
+            .get("Value")
+            .and_then(|value| value.attribute("Ref"))
+            .and_then(|index| index.parse::<usize>().ok())
+            .and_then(|index| binaries.get(index))
+            .ok_or_else(|| {
+                TwoPasswordError::validation(format!(
+                    "Attachment '{}' is missing from the database",
+                    name
+                ))
+            })?;
+        files.push(ImportedFile {
+            name: name.to_string(),
+            contents: data.data.clone(),
+        });
+    }
+
+    // KeePass keeps whole earlier versions, oldest first; only those whose
+    // credentials were later replaced belong in the password history
+    if let Some(history) = element.get("History") {
+        let versions: Vec<&Element> = history.all("Entry").collect();
+        let credentials = |version: &Element| {
+            (
+                field(version, "Password").unwrap_or_default().to_string(),
+                field(version, "UserName").unwrap_or_default().to_string(),
+            )
+        };
+        let mut next = credentials(element);
+        for version in versions.into_iter().rev() {
+            let current = credentials(version);
+            if current != next {
+                if let Some(replaced_at) = time(version, "LastModificationTime") {
+                    let (password, username) = &current;
+                    entry.history.push(HistoryItem {
+                        password: password.as_str().into(),
+                        username: (*username != entry.username).then(|| username.clone()),
+                        replaced_at,
+                    });
+                }
+            }
+            next = current;
+        }
+    }
+
+    if let Some(created_at) = time(element, "CreationTime") {
+        entry.created_at = created_at;
+    }
+    if let Some(updated_at) = time(element, "LastModificationTime") {
+        entry.updated_at = updated_at;
+    }
+    Ok((entry, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretString;
+    use crate::storage::export;
+    use crate::storage::field::CustomField;
+
+    #[test]
+    fn test_kdbx_fixture() {
+        let fixture =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keepass_v4.kdbx");
+        let contents = std::fs::read(fixture).unwrap();
+        assert!(KeePassKdbx.parse(&contents).is_err());
+
+        let password = SecretString::from("correct horse battery staple");
+        let payload = kdbx::decrypt(&contents, &password).unwrap();
+        let parsed = KeePassKdbx.parse(&payload).unwrap();
+
+        let titles: Vec<&str> = parsed
+            .records
+            .iter()
+            .map(|r| r.entry.title.as_str())
+            .collect();
+        assert_eq!(titles, ["GitHub", "db01"]);
+
+        let github = &parsed.records[0].entry;
+        assert_eq!(github.username, "octocat");
+        assert_eq!(github.password.expose(), "hunter2");
+        assert_eq!(github.url.as_deref(), Some("https://github.com"));
+        assert_eq!(github.tags, ["dev", "work"]);
+        assert_eq!(github.notes.as_deref(), Some("Recovery codes in the safe"));
+        assert_eq!(github.fields[0].name, "PIN");
+        assert_eq!(github.fields[0].value.expose(), "1234");
+        let otp = github.otp.as_ref().unwrap();
+        assert_eq!(otp.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(github.created_at.to_rfc3339(), "2023-05-01T10:00:00+00:00");
+        assert_eq!(github.updated_at.to_rfc3339(), "2024-02-01T10:00:00+00:00");
+        let history: Vec<&str> = github.history.iter().map(|h| h.password.expose()).collect();
+        assert_eq!(history, ["hunter1", "hunter0"]);
+        assert_eq!(
+            github.history[0].replaced_at.to_rfc3339(),
+            "2023-09-01T10:00:00+00:00"
+        );
+
+        let server = &parsed.records[1].entry;
+        assert_eq!(server.tags, ["Work/Servers"]);
+        assert_eq!(server.password.expose(), "s3rv3r & <pass>");
+
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(
+            parsed.errors[0].to_string(),
+            "entry 3 (Old): Skipped entry in the recycle bin"
+        );
+    }
+
+    #[test]
+    fn test_kdbx_export_roundtrip() {
+        let mut entry =
+            PasswordEntry::new("GitHub".to_string(), "octocat".to_string(), "hunter2 <&>");
+        entry.url = Some("https://github.com".to_string());
+        entry.notes = Some("Line one\nLine two".to_string());
+        entry.tags = vec!["dev".to_string(), "Work/Servers".to_string()];
+        entry.otp = Some(crate::crypto::otp::Otp::totp("JBSWY3DPEHPK3PXP").unwrap());
+        entry.fields = vec![
+            CustomField::new("Recovery code", FieldType::Concealed, "r3c0very"),
+            CustomField::new("Region", FieldType::Text, "eu-west-1"),
+        ];
+        entry.history.push(HistoryItem {
+            password: "hunter1".into(),
+            username: Some("octo".to_string()),
+            replaced_at: chrono::DateTime::from_timestamp(1_650_000_000, 0).unwrap(),
+        });
+        entry.history.push(HistoryItem {
+            password: "hunter0".into(),
+            username: None,
+            replaced_at: chrono::DateTime::from_timestamp(1_600_000_000, 0).unwrap(),
+        });
+        let other = PasswordEntry::new("Bank".to_string(), String::new(), "s3cret");
+        let entries = vec![entry, other];
+
+        let password = SecretString::from("correct horse battery staple");
+        let kdf = crate::crypto::key_derivation::KdfParams::for_tests();
+        let database =
+            export::to_kdbx(&entries, &export::AttachmentFiles::new(), &password, &kdf).unwrap();
+        let payload = kdbx::decrypt(&database, &password).unwrap();
+        let parsed = KeePassKdbx.parse(&payload).unwrap();
+        assert!(parsed.errors.is_empty());
+
+        for (original, record) in entries.iter().zip(&parsed.records) {
+            let imported = &record.entry;
+            assert_eq!(imported.id, original.id);
+            assert_eq!(imported.title, original.title);
+            assert_eq!(imported.username, original.username);
+            assert_eq!(imported.password, original.password);
+            assert_eq!(imported.url, original.url);
+            assert_eq!(imported.notes, original.notes);
+            assert_eq!(imported.tags, original.tags);
+            assert_eq!(imported.otp, original.otp);
+            assert_eq!(imported.fields, original.fields);
+            assert_eq!(
+                imported.created_at.timestamp(),
+                original.created_at.timestamp()
+            );
+            assert_eq!(
+                imported.updated_at.timestamp(),
+                original.updated_at.timestamp()
+            );
+
+            let history = |entry: &PasswordEntry| -> Vec<(String, i64)> {
+                entry
+                    .history
+                    .iter()
+                    .map(|h| (h.password.expose().to_string(), h.replaced_at.timestamp()))
+                    .collect()
+            };
+            assert_eq!(history(imported), history(original));
+        }
+        assert_eq!(
+            parsed.records[0].entry.history[0].username.as_deref(),
+            Some("octo")
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! KeePassXC CSV export
+//!
+//! Groups become tags holding the group path below the root group, e.g.
+//! `Work/Servers`. Entries in the recycle bin are skipped.
+
+use super::{parse_timestamp, read_csv, set_otp, Importer, ParsedImport};
+use crate::storage::PasswordEntry;
+use crate::{Result, TwoPasswordError};
+
+/// Name KeePassXC gives the group of deleted entries
+const RECYCLE_BIN: &str = "Recycle Bin";
+
+/// KeePassXC
+pub struct KeePassXcCsv;
+
+impl Importer for KeePassXcCsv {
+    fn name(&self) -> &'static str {
+        "keepassxc-csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "KeePassXC CSV export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        read_csv(contents, &[&["title"], &["password"]], |record| {
+            let group = record.get(&["group"]).unwrap_or_default();
+            let mut path: Vec<&str> = group.split('/').map(str::trim).collect();
+            // The first group is the database's root group
+            path.remove(0);
+            path.retain(|segment| !segment.is_empty());
+            if path.first() == Some(&RECYCLE_BIN) {
+                return Err(TwoPasswordError::validation(
+                    "Skipped entry in the recycle bin",
+                ));
+            }
+
+            let mut entry = PasswordEntry::new(
+                record.get(&["title"]).unwrap_or_default(),
+                record.get(&["username"]).unwrap_or_default(),
+                record.get(&["password"]).unwrap_or_default(),
+            );
+            entry.url = record.get(&["url"]);
+            entry.notes = record.get(&["notes"]);
+            if !path.is_empty() {
+                entry.tags.push(path.join("/"));
+            }
+            if let Some(totp) = record.get(&["totp"]) {
+                set_otp(&mut entry, &totp);
+            }
+            if let Some(created_at) = record.get(&["created"]) {
+                entry.created_at = parse_timestamp(&created_at)?;
+            }
+            if let Some(updated_at) = record.get(&["last modified"]) {
+                entry.updated_at = parse_timestamp(&updated_at)?;
+            }
+            Ok(entry)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keepassxc_export() {
+        let csv = "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\",\
+                   \"Icon\",\"Last Modified\",\"Created\"\n\
+                   \"Passwords/Work/Servers\",\"db01\",\"admin\",\"hunter2\",\"\",\"\",\"\",\
+                   \"0\",\"2024-02-01T10:00:00Z\",\"2023-05-01T10:00:00Z\"\n\
+                   \"Passwords\",\"GitHub\",\"octocat\",\"hunter3\",\"https://github.com\",\
+                   \"\",\"otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP\",\"0\",\"\",\"\"\n\
+                   \"Passwords/Recycle Bin\",\"Old\",\"me\",\"x\",\"\",\"\",\"\",\"0\",\"\",\"\"\n";
+        let parsed = KeePassXcCsv.parse(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.records.len(), 2);
+
+        let server = &parsed.records[0].entry;
+        assert_eq!(server.tags, ["Work/Servers"]);
+        assert_eq!(server.created_at.to_rfc3339(), "2023-05-01T10:00:00+00:00");
+
+        let github = &parsed.records[1].entry;
+        assert!(github.tags.is_empty());
+        assert!(github.notes.is_none());
+        assert_eq!(
+            github.otp.as_ref().unwrap().account.as_deref(),
+            Some("GitHub")
+        );
+
+        assert_eq!(parsed.errors[0].message, "Skipped entry in the recycle bin");
+    }
+}
@@ -0,0 +1,616 @@
+//! Vault import
+//!
+//! Each source format has an [`Importer`] that maps its records onto
+//! `PasswordEntry`. Importers do not abort on a bad record: they return every
+//! entry they could read along with an error for each record they could not.
+//! [`ImportPlan`] then checks the entries against the vault, setting aside
+//! invalid entries and duplicates, so a dry run can show exactly what an
+//! import would do before anything is written.
+
+use crate::crypto::otp::Otp;
+use crate::crypto::{aes_gcm, key_derivation, SecretString};
+use crate::storage::entry::EntryManager;
+use crate::storage::export::{
+    EncryptedExport, CSV_HEADERS, ENCRYPTED_EXPORT_FORMAT, EXPORT_VERSION,
+};
+use crate::storage::field::{CustomField, FieldType};
+use crate::storage::{PasswordEntry, Vault};
+use crate::{Result, TwoPasswordError};
+use serde::Deserialize;
+use std::collections::HashSet;
+use zeroize::{Zeroize, Zeroizing};
+
+pub mod bitwarden;
+pub mod browser;
+pub mod keepass;
+pub mod keepassxc;
+pub mod onepassword;
+
+/// A source format that can be imported
+pub trait Importer: Sync {
+    /// Format name used on the command line
+    fn name(&self) -> &'static str;
+
+    /// Application and file type, as shown to the user
+    fn description(&self) -> &'static str;
+
+    /// Read entries from the file contents
+    ///
+    /// Fails only if the file as a whole cannot be read, e.g. when it is not
+    /// valid JSON or required columns are missing; problems with single
+    /// records are reported in the result.
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport>;
+}
+
+/// All importers, in the order they are listed to the user
+pub const IMPORTERS: &[&dyn Importer] = &[
+    &TwoPasswordJson,
+    &TwoPasswordCsv,
+    &bitwarden::BitwardenJson,
+    &bitwarden::BitwardenCsv,
+    &onepassword::OnePassword1Pux,
+    &onepassword::OnePasswordCsv,
+    &browser::ChromeCsv,
+    &browser::FirefoxCsv,
+    &keepassxc::KeePassXcCsv,
+    &keepass::KeePassKdbx,
+];
+
+/// Get the importer for a format name
+pub fn importer(name: &str) -> Result<&'static dyn Importer> {
+    IMPORTERS
+        .iter()
+        .copied()
+        .find(|importer| importer.name().eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| {
+            let names: Vec<&str> = IMPORTERS.iter().map(|importer| importer.name()).collect();
+            TwoPasswordError::validation(format!(
+                "Unknown import format '{}' (expected one of: {})",
+                name,
+                names.join(", ")
+            ))
+        })
+}
+
+/// An entry read from one record of an import file
+#[derive(Debug, Clone)]
+pub struct ImportRecord {
+    /// Where the record is in the file, e.g. "line 3"
+    pub location: String,
+    pub entry: PasswordEntry,
+    /// Files attached to the entry in the source, to be attached once the
+    /// entry is in the vault
+    pub files: Vec<ImportedFile>,
+}
+
+/// A file attached to an imported entry
+#[derive(Debug, Clone)]
+pub struct ImportedFile {
+    pub name: String,
+    pub contents: Zeroizing<Vec<u8>>,
+}
+
+/// A record that could not be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// Where the record is in the file, e.g. "line 3"
+    pub location: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Entries read from an import file, and the records that failed
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub records: Vec<ImportRecord>,
+    pub errors: Vec<RowError>,
+}
+
+impl ParsedImport {
+    /// Record the outcome of reading one record
+    pub fn push(&mut self, location: String, entry: Result<PasswordEntry>) {
+        match entry {
+            Ok(entry) => self.records.push(ImportRecord {
+                location,
+                entry,
+                files: Vec::new(),
+            }),
+            Err(e) => self.errors.push(RowError {
+                location,
+                message: message(e),
+            }),
+        }
+    }
+}
+
+/// What an import would change in a vault
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    /// Entries to add
+    pub new: Vec<ImportRecord>,
+    /// Entries already in the vault, or repeated earlier in the file
+    pub duplicates: Vec<ImportRecord>,
+    /// Records that could not be read or are not valid entries
+    pub errors: Vec<RowError>,
+}
+
+impl ImportPlan {
+    /// Sort parsed entries into new entries, duplicates and errors
+    ///
+    /// An entry is a duplicate if it has the ID of an existing entry, or if
+    /// [`EntryManager::find_duplicates`] groups it with an existing entry or
+    /// with an entry earlier in the file.
+    pub fn new(parsed: ParsedImport, existing: &[PasswordEntry]) -> Self {
+        let mut plan = ImportPlan {
+            errors: parsed.errors,
+            ..Default::default()
+        };
+
+        let mut valid = Vec::new();
+        for record in parsed.records {
+            match EntryManager::validate_entry(&record.entry) {
+                Ok(()) => valid.push(record),
+                Err(e) => plan.errors.push(RowError {
+                    location: record.location,
+                    message: message(e),
+                }),
+            }
+        }
+
+        let existing_ids: HashSet<_> = existing.iter().map(|entry| entry.id).collect();
+        let mut combined = existing.to_vec();
+        combined.extend(valid.iter().map(|record| record.entry.clone()));
+
+        // Groups keep the order of `combined`, so existing entries and then
+        // earlier records come first
+        let mut duplicate_ids = HashSet::new();
+        for group in EntryManager::find_duplicates(&combined) {
+            let keep_first = !group.iter().any(|entry| existing_ids.contains(&entry.id));
+            duplicate_ids.extend(
+                group
+                    .iter()
+                    .filter(|entry| !existing_ids.contains(&entry.id))
+                    .skip(usize::from(keep_first))
+                    .map(|entry| entry.id),
+            );
+        }
+
+        let mut seen_ids = existing_ids;
+        for record in valid {
+            if duplicate_ids.contains(&record.entry.id) || !seen_ids.insert(record.entry.id) {
+                plan.duplicates.push(record);
+            } else {
+                plan.new.push(record);
+            }
+        }
+        plan
+    }
+
+    /// Add the new entries to the vault, returning how many were added
+    pub fn apply(self, vault: &mut Vault) -> usize {
+        let count = self.new.len();
+        for record in self.new {
+            vault.add_entry(record.entry);
+        }
+        count
+    }
+}
+
+/// Decrypt an encrypted export with its export passphrase
+///
+/// Returns the JSON export inside, to be read with the `json` importer.
+pub fn decrypt_export(contents: &[u8], passphrase: &SecretString) -> Result<Zeroizing<Vec<u8>>> {
+    let envelope: EncryptedExport = serde_json::from_slice(contents)
+        .map_err(|e| TwoPasswordError::validation(format!("Not an encrypted export: {}", e)))?;
+    if envelope.format != ENCRYPTED_EXPORT_FORMAT {
+        return Err(TwoPasswordError::validation("Not an encrypted export"));
+    }
+    check_version(envelope.version)?;
+    // Reject parameters that would make derivation hang or exhaust memory
+    envelope.kdf.validate()?;
+
+    let mut key = key_derivation::derive_key_with_params(
+        passphrase.expose(),
+        &envelope.salt.bytes,
+        &envelope.kdf,
+    )?;
+    let opened = envelope
+        .header_aad()
+        .and_then(|aad| aes_gcm::decrypt_with_aad(&key, &envelope.encrypted_data, &aad));
+    key.zeroize();
+
+    opened
+        .map(Zeroizing::new)
+        .map_err(|_| TwoPasswordError::auth("Wrong export passphrase, or the export was modified"))
+}
+
+/// Check whether file contents look like an encrypted export
+pub fn is_encrypted_export(contents: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(contents)
+        .ok()
+        .and_then(|value| value.get("format")?.as_str().map(str::to_string))
+        .is_some_and(|format| format == ENCRYPTED_EXPORT_FORMAT)
+}
+
+/// JSON export written by TwoPassword
+pub struct TwoPasswordJson;
+
+impl Importer for TwoPasswordJson {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn description(&self) -> &'static str {
+        "TwoPassword JSON export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        // Entries are decoded one at a time so a bad entry does not hide
+        // the others
+        #[derive(Deserialize)]
+        struct Document {
+            version: u32,
+            entries: Vec<serde_json::Value>,
+        }
+
+        let document: Document = serde_json::from_slice(contents)
+            .map_err(|e| TwoPasswordError::validation(format!("Not a JSON export: {}", e)))?;
+        check_version(document.version)?;
+
+        let mut parsed = ParsedImport::default();
+        for (i, value) in document.entries.into_iter().enumerate() {
+            let location = item_location(i, value.get("title"));
+            // Attachments refer to blobs of the vault the entry came from
+            let entry = serde_json::from_value::<PasswordEntry>(value)
+                .map(|entry| PasswordEntry {
+                    attachments: Vec::new(),
+                    ..entry
+                })
+                .map_err(TwoPasswordError::from);
+            parsed.push(location, entry);
+        }
+        Ok(parsed)
+    }
+}
+
+/// CSV export written by TwoPassword
+pub struct TwoPasswordCsv;
+
+impl Importer for TwoPasswordCsv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "TwoPassword CSV export"
+    }
+
+    /// Columns are matched by header name, so their order does not matter.
+    /// Entries get fresh IDs, and missing timestamps are set to now.
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        let [title, username, password, url, notes, tags, created_at, updated_at] = CSV_HEADERS;
+
+        read_csv(contents, &[&[title], &[password]], |record| {
+            let mut entry = PasswordEntry::new(
+                record.get(&[title]).unwrap_or_default(),
+                record.get(&[username]).unwrap_or_default(),
+                record.get(&[password]).unwrap_or_default(),
+            );
+            entry.url = record.get(&[url]);
+            entry.notes = record.get(&[notes]);
+            entry.tags = split_tags(&record.get(&[tags]).unwrap_or_default(), &[',']);
+            if let Some(created_at) = record.get(&[created_at]) {
+                entry.created_at = parse_timestamp(&created_at)?;
+            }
+            if let Some(updated_at) = record.get(&[updated_at]) {
+                entry.updated_at = parse_timestamp(&updated_at)?;
+            }
+            Ok(entry)
+        })
+    }
+}
+
+/// A CSV record with fields looked up by header name
+pub(crate) struct CsvRecord<'a> {
+    headers: &'a csv::StringRecord,
+    record: csv::StringRecord,
+}
+
+impl CsvRecord<'_> {
+    /// Get the first non-empty field among columns with any of `names`,
+    /// ignoring case
+    pub(crate) fn get(&self, names: &[&str]) -> Option<String> {
+        names.iter().find_map(|name| {
+            let index = self
+                .headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name))?;
+            non_empty(self.record.get(index)?)
+        })
+    }
+}
+
+/// Read a CSV file with a header row, mapping each record to an entry
+///
+/// `required` lists the columns that must be present, each as alternative
+/// header names.
+pub(crate) fn read_csv(
+    contents: &[u8],
+    required: &[&[&str]],
+    map: impl Fn(&CsvRecord) -> Result<PasswordEntry>,
+) -> Result<ParsedImport> {
+    let contents = contents.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(contents);
+    let mut reader = csv::Reader::from_reader(contents);
+    let headers = reader
+        .headers()
+        .map_err(|e| TwoPasswordError::validation(format!("Invalid CSV: {}", e)))?
+        .clone();
+
+    for names in required {
+        let present = headers.iter().any(|header| {
+            names
+                .iter()
+                .any(|name| header.trim().eq_ignore_ascii_case(name))
+        });
+        if !present {
+            return Err(TwoPasswordError::validation(format!(
+                "CSV has no '{}' column",
+                names.join("' or '")
+            )));
+        }
+    }
+
+    let mut parsed = ParsedImport::default();
+    for record in reader.records() {
+        match record {
+            Ok(record) => {
+                let location = format!("line {}", record.position().map_or(0, |p| p.line()));
+                let record = CsvRecord {
+                    headers: &headers,
+                    record,
+                };
+                parsed.push(location, map(&record));
+            }
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                parsed.errors.push(RowError {
+                    location: format!("line {}", line),
+                    message: format!("Invalid CSV: {}", e),
+                });
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// Location of the `index`th item of a JSON file, with its title if known
+pub(crate) fn item_location(index: usize, title: Option<&serde_json::Value>) -> String {
+    match title.and_then(|title| title.as_str()) {
+        Some(title) => format!("item {} ({})", index + 1, title),
+        None => format!("item {}", index + 1),
+    }
+}
+
+/// Title for an entry that has only a URL: its host without "www."
+pub(crate) fn title_from_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    Some(host.strip_prefix("www.").unwrap_or(host).to_string())
+}
+
+/// Split a list of tags on any of `separators`, dropping empty tags
+pub(crate) fn split_tags(tags: &str, separators: &[char]) -> Vec<String> {
+    tags.split(separators)
+        .filter_map(non_empty)
+        .fold(Vec::new(), |mut tags, tag| {
+            if !tags.iter().any(|t: &String| t.eq_ignore_ascii_case(&tag)) {
+                tags.push(tag);
+            }
+            tags
+        })
+}
+
+/// Append a `label: value` line to an entry's notes
+pub(crate) fn append_note(entry: &mut PasswordEntry, label: &str, value: &str) {
+    let line = format!("{}: {}", label, value);
+    entry.notes = Some(match entry.notes.take() {
+        Some(notes) => format!("{}\n{}", notes, line),
+        None => line,
+    });
+}
+
+/// Keep a two-factor secret as the entry's one-time password, or in the
+/// notes if codes cannot be generated from it, e.g. for Steam Guard
+pub(crate) fn set_otp(entry: &mut PasswordEntry, secret: &str) {
+    match Otp::parse(secret) {
+        Ok(otp) if entry.otp.is_none() => entry.otp = Some(otp),
+        _ => append_note(entry, "TOTP", secret),
+    }
+}
+
+/// Keep a value as a custom field of the entry
+///
+/// A value that does not fit `field_type` is kept as text, or as concealed
+/// text if the type is a concealed one. Values without a name, or whose
+/// name the entry already uses, are appended to the notes instead.
+pub(crate) fn push_field(
+    entry: &mut PasswordEntry,
+    name: &str,
+    field_type: FieldType,
+    value: &str,
+) {
+    let mut custom = CustomField::new(name, field_type, value.trim());
+    if custom.validate().is_err() {
+        custom.field_type = match field_type.is_concealed() {
+            true => FieldType::Concealed,
+            false => FieldType::Text,
+        };
+    }
+    let taken = entry
+        .fields
+        .iter()
+        .any(|existing| existing.name.eq_ignore_ascii_case(&custom.name));
+    if custom.name.is_empty() || taken {
+        append_note(entry, name, value);
+    } else {
+        entry.fields.push(custom);
+    }
+}
+
+/// Trimmed value, or `None` if it is empty
+pub(crate) fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Parse an RFC 3339 timestamp
+pub(crate) fn parse_timestamp(value: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .map_err(|e| TwoPasswordError::validation(format!("Invalid timestamp '{}': {}", value, e)))
+}
+
+/// Convert seconds since the Unix epoch to a timestamp
+pub(crate) fn from_unix_seconds(seconds: i64) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::from_timestamp(seconds, 0)
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if version > EXPORT_VERSION {
+        return Err(TwoPasswordError::validation(format!(
+            "Export version {} is newer than this version of TwoPassword supports",
+            version
+        )));
+    }
+    Ok(())
+}
+
+/// Error message for a row, without the error kind prefix for validation
+/// errors
+fn message(e: TwoPasswordError) -> String {
+    match e {
+        TwoPasswordError::ValidationError(message) => message,
+        e => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_derivation::KdfParams;
+    use crate::storage::export;
+
+    #[test]
+    fn test_csv_columns_in_any_order() {
+        let csv = "password,Title,url,tags,username\n\
+                   hunter2,GitHub,https://github.com,\"a, b\",octocat\n";
+        let parsed = TwoPasswordCsv.parse(csv.as_bytes()).unwrap();
+
+        assert!(parsed.errors.is_empty());
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.title, "GitHub");
+        assert_eq!(entry.password.expose(), "hunter2");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+        assert_eq!(entry.tags, ["a", "b"]);
+
+        assert!(TwoPasswordCsv
+            .parse(b"name,secret\nGitHub,hunter2\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_bad_rows_are_reported_not_fatal() {
+        let csv = "title,username,password,created_at\n\
+                   GitHub,octocat,hunter2,\n\
+                   Broken,alice,secret,yesterday\n\
+                   Short,row\n\
+                   Bank,bob,s3cret,2024-01-02T03:04:05Z\n";
+        let parsed = TwoPasswordCsv.parse(csv.as_bytes()).unwrap();
+
+        let titles: Vec<&str> = parsed
+            .records
+            .iter()
+            .map(|r| r.entry.title.as_str())
+            .collect();
+        assert_eq!(titles, ["GitHub", "Bank"]);
+        assert_eq!(parsed.records[1].location, "line 5");
+
+        let locations: Vec<&str> = parsed.errors.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(locations, ["line 3", "line 4"]);
+        assert!(parsed.errors[0].message.contains("yesterday"));
+    }
+
+    #[test]
+    fn test_plan_sets_aside_duplicates_and_invalid_entries() {
+        let existing = vec![PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "hunter2".to_string(),
+        )];
+
+        let mut parsed = ParsedImport::default();
+        for (title, username, password) in [
+            ("github", "OctoCat", "secret"),
+            ("Bank", "alice", "secret"),
+            ("Bank", "alice", "secret"),
+            ("No password", "bob", ""),
+        ] {
+            let entry = PasswordEntry::new(title.to_string(), username.to_string(), password);
+            parsed.push(format!("line {}", title), Ok(entry));
+        }
+        // The same entry imported again, e.g. from an export of this vault
+        parsed.push("item 1".to_string(), Ok(existing[0].clone()));
+
+        let plan = ImportPlan::new(parsed, &existing);
+        assert_eq!(plan.new.len(), 1);
+        assert_eq!(plan.new[0].entry.title, "Bank");
+        assert_eq!(plan.duplicates.len(), 3);
+        assert_eq!(plan.errors.len(), 1);
+        assert_eq!(plan.errors[0].message, "Password cannot be empty");
+
+        let mut vault = Vault::new("vault.enc");
+        assert_eq!(plan.apply(&mut vault), 1);
+        assert_eq!(vault.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_importer_lookup() {
+        assert_eq!(importer("Bitwarden-JSON").unwrap().name(), "bitwarden-json");
+        assert!(importer("lastpass").is_err());
+
+        let names: std::collections::HashSet<&str> = IMPORTERS.iter().map(|i| i.name()).collect();
+        assert_eq!(names.len(), IMPORTERS.len());
+    }
+
+    #[test]
+    fn test_encrypted_export_rejects_wrong_passphrase_and_tampering() {
+        let entry = PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "hunter2".to_string(),
+        );
+        let kdf = KdfParams::for_tests();
+        let passphrase = SecretString::from("export passphrase");
+        let sealed = export::to_encrypted(&[entry], &passphrase, &kdf).unwrap();
+        assert!(is_encrypted_export(&sealed));
+        assert!(!is_encrypted_export(b"{\"version\": 1, \"entries\": []}"));
+
+        let json = decrypt_export(&sealed, &passphrase).unwrap();
+        let parsed = TwoPasswordJson.parse(&json).unwrap();
+        assert_eq!(parsed.records[0].entry.password.expose(), "hunter2");
+
+        let wrong = SecretString::from("wrong passphrase");
+        assert!(decrypt_export(&sealed, &wrong).is_err());
+
+        // Weakening the KDF parameters in the envelope is detected
+        let mut envelope: serde_json::Value = serde_json::from_slice(&sealed).unwrap();
+        envelope["kdf"]["time_cost"] = 2.into();
+        let tampered = serde_json::to_vec(&envelope).unwrap();
+        assert!(decrypt_export(&tampered, &passphrase).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! 1Password 1PUX and CSV exports
+//!
+//! A 1PUX file is a zip archive whose `export.data` member holds every
+//! account, vault and item as JSON. Login and Password items are imported;
+//! section fields become custom fields of the matching type, and the item's
+//! password history is kept.
+
+use super::{
+    from_unix_seconds, item_location, non_empty, push_field, read_csv, set_otp, split_tags,
+    Importer, ParsedImport,
+};
+use crate::storage::field::FieldType;
+use crate::storage::{HistoryItem, PasswordEntry};
+use crate::{Result, TwoPasswordError};
+use serde::Deserialize;
+use std::io::Read;
+
+/// Archive member holding the export data
+const EXPORT_DATA: &str = "export.data";
+
+/// Largest `export.data` accepted, so a hostile archive cannot exhaust memory
+const MAX_EXPORT_DATA_SIZE: u64 = 256 * 1024 * 1024;
+
+/// 1Password category of logins
+const LOGIN_CATEGORY: &str = "001";
+
+/// 1Password category of standalone passwords
+const PASSWORD_CATEGORY: &str = "005";
+
+/// 1Password unencrypted export (1PUX)
+pub struct OnePassword1Pux;
+
+#[derive(Deserialize)]
+struct Export {
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    #[serde(default)]
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    #[serde(default)]
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    category_uuid: String,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    overview: Overview,
+    details: Details,
+}
+
+#[derive(Deserialize)]
+struct Overview {
+    title: String,
+    url: Option<String>,
+    #[serde(default)]
+    urls: Vec<ItemUrl>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ItemUrl {
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    #[serde(default)]
+    login_fields: Vec<LoginField>,
+    notes_plain: Option<String>,
+    password: Option<String>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    #[serde(default)]
+    password_history: Vec<PasswordHistory>,
+}
+
+#[derive(Deserialize)]
+struct LoginField {
+    value: String,
+    designation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    fields: Vec<SectionField>,
+}
+
+#[derive(Deserialize)]
+struct SectionField {
+    title: String,
+    /// A single-key object naming the field type, e.g. `{"concealed": "x"}`
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct PasswordHistory {
+    value: String,
+    time: i64,
+}
+
+impl Importer for OnePassword1Pux {
+    fn name(&self) -> &'static str {
+        "1password-1pux"
+    }
+
+    fn description(&self) -> &'static str {
+        "1Password 1PUX export"
+    }
+
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        let data = read_export_data(contents)?;
+        let export: Export = serde_json::from_slice(&data).map_err(|e| {
+            TwoPasswordError::validation(format!("Invalid 1PUX export data: {}", e))
+        })?;
+
+        let items = export
+            .accounts
+            .into_iter()
+            .flat_map(|account| account.vaults)
+            .flat_map(|vault| vault.items);
+
+        let mut parsed = ParsedImport::default();
+        for (i, value) in items.enumerate() {
+            let title = value
+                .get("overview")
+                .and_then(|overview| overview.get("title"));
+            let location = item_location(i, title);
+            let entry = serde_json::from_value::<Item>(value)
+                .map_err(TwoPasswordError::from)
+                .and_then(pux_entry);
+            parsed.push(location, entry);
+        }
+        Ok(parsed)
+    }
+}
+
+fn read_export_data(contents: &[u8]) -> Result<zeroize::Zeroizing<Vec<u8>>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(contents))
+        .map_err(|e| TwoPasswordError::validation(format!("Not a 1PUX archive: {}", e)))?;
+    let member = archive.by_name(EXPORT_DATA).map_err(|_| {
+        TwoPasswordError::validation(format!("1PUX archive has no {}", EXPORT_DATA))
+    })?;
+
+    let mut data = zeroize::Zeroizing::new(Vec::new());
+    member
+        .take(MAX_EXPORT_DATA_SIZE + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| TwoPasswordError::validation(format!("Invalid 1PUX archive: {}", e)))?;
+    if data.len() as u64 > MAX_EXPORT_DATA_SIZE {
+        return Err(TwoPasswordError::validation(
+            "1PUX export data is too large",
+        ));
+    }
+    Ok(data)
+}
+
+fn pux_entry(item: Item) -> Result<PasswordEntry> {
+    let details = item.details;
+    let login_field = |designation: &str| {
+        details
+            .login_fields
+            .iter()
+            .find(|field| field.designation.as_deref() == Some(designation))
+            .map(|field| field.value.clone())
+    };
+
+    let password = match item.category_uuid.as_str() {
+        LOGIN_CATEGORY => login_field("password"),
+        PASSWORD_CATEGORY => details.password.clone(),
+        other => {
+            return Err(TwoPasswordError::validation(format!(
+                "1Password {} items are not supported",
+                category_name(other)
+            )))
+        }
+    };
+
+    let overview = item.overview;
+    let mut entry = PasswordEntry::new(
+        overview.title,
+        login_field("username").unwrap_or_default(),
+        password.unwrap_or_default(),
+    );
+    entry.url = overview
+        .url
+        .into_iter()
+        .chain(overview.urls.into_iter().map(|url| url.url))
+        .find_map(|url| non_empty(&url));
+    entry.notes = details.notes_plain.as_deref().and_then(non_empty);
+    entry.tags = split_tags(&overview.tags.join(","), &[',']);
+
+    for field in details.sections.iter().flat_map(|section| &section.fields) {
+        if let Some((field_type, value)) = field_value(&field.value) {
+            push_field(&mut entry, &field.title, field_type, &value);
+        }
+    }
+    for previous in &details.password_history {
+        if let Some(replaced_at) = from_unix_seconds(previous.time) {
+            entry.history.push(HistoryItem {
+                password: previous.value.clone().into(),
+                username: None,
+                replaced_at,
+            });
+        }
+    }
+    entry
+        .history
+        .sort_by_key(|item| std::cmp::Reverse(item.replaced_at));
+
+    if let Some(created_at) = item.created_at.and_then(from_unix_seconds) {
+        entry.created_at = created_at;
+    }
+    if let Some(updated_at) = item.updated_at.and_then(from_unix_seconds) {
+        entry.updated_at = updated_at;
+    }
+    Ok(entry)
+}
+
+/// Type and text of a section field value
+fn field_value(value: &serde_json::Value) -> Option<(FieldType, String)> {
+    let (name, inner) = value.as_object()?.iter().next()?;
+    let field_type = match name.as_str() {
+        "concealed" => FieldType::Concealed,
+        "totp" => FieldType::Otp,
+        "url" => FieldType::Url,
+        "email" => FieldType::Email,
+        "date" => FieldType::Date,
+        _ => FieldType::Text,
+    };
+    let text = match inner {
+        serde_json::Value::String(text) => non_empty(text),
+        // Dates are Unix timestamps
+        serde_json::Value::Number(number) if field_type == FieldType::Date => number
+            .as_i64()
+            .and_then(from_unix_seconds)
+            .map(|date| date.format("%Y-%m-%d").to_string()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        // Structured values such as e-mail addresses hold their text in
+        // their first string member
+        serde_json::Value::Object(members) => members
+            .values()
+            .find_map(|member| non_empty(member.as_str()?)),
+        _ => None,
+    };
+    Some((field_type, text?))
+}
+
+fn category_name(uuid: &str) -> String {
+    match uuid {
+        "002" => "Credit Card".to_string(),
+        "003" => "Secure Note".to_string(),
+        "004" => "Identity".to_string(),
+        "006" => "Document".to_string(),
+        "110" => "Server".to_string(),
+        "114" => "SSH Key".to_string(),
+        "112" => "API Credential".to_string(),
+        other => format!("category {}", other),
+    }
+}
+
+/// 1Password CSV export
+pub struct OnePasswordCsv;
+
+impl Importer for OnePasswordCsv {
+    fn name(&self) -> &'static str {
+        "1password-csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "1Password CSV export"
+    }
+
+    /// 1Password 8 writes Title, Url, Username, Password, OTPAuth, Favorite,
+    /// Archived, Tags and Notes; older versions let the user pick columns,
+    /// so the common alternative names are accepted too.
+    fn parse(&self, contents: &[u8]) -> Result<ParsedImport> {
+        read_csv(contents, &[&["title"], &["password"]], |record| {
+            let mut entry = PasswordEntry::new(
+                record.get(&["title"]).unwrap_or_default(),
+                record.get(&["username"]).unwrap_or_default(),
+                record.get(&["password"]).unwrap_or_default(),
+            );
+            entry.url = record.get(&["url", "website", "urls"]);
+            entry.notes = record.get(&["notes", "notesplain"]);
+            entry.tags = split_tags(&record.get(&["tags"]).unwrap_or_default(), &[',', ';']);
+            if let Some(otp) = record.get(&["otpauth", "one-time password"]) {
+                set_otp(&mut entry, &otp);
+            }
+            Ok(entry)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const EXPORT_DATA_JSON: &str = r#"{
+      "accounts": [{
+        "attrs": { "accountName": "Family" },
+        "vaults": [{
+          "attrs": { "name": "Personal" },
+          "items": [
+            {
+              "uuid": "a", "categoryUuid": "001", "state": "active",
+              "createdAt": 1700000000, "updatedAt": 1710000000,
+              "overview": {
+                "title": "GitHub", "url": "https://github.com", "tags": ["dev", "work"]
+              },
+              "details": {
+                "loginFields": [
+                  { "value": "octocat", "name": "username", "designation": "username" },
+                  { "value": "hunter2", "name": "password", "designation": "password" }
+                ],
+                "notesPlain": "Recovery codes in the safe",
+                "sections": [{ "title": "", "fields": [
+                  { "title": "PIN", "id": "p", "value": { "concealed": "1234" } },
+                  { "title": "Email", "id": "e",
+                    "value": { "email": { "email_address": "octo@example.com" } } },
+                  { "title": "Renewal", "id": "d", "value": { "date": 1767139200 } }
+                ] }],
+                "passwordHistory": [
+                  { "value": "hunter0", "time": 1600000000 },
+                  { "value": "hunter1", "time": 1650000000 }
+                ]
+              }
+            },
+            {
+              "uuid": "b", "categoryUuid": "003",
+              "overview": { "title": "Wifi" }, "details": { "notesPlain": "pw" }
+            }
+          ]
+        }]
+      }]
+    }"#;
+
+    fn pux(members: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in members {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_1pux_export() {
+        let archive = pux(&[
+            ("export.attributes", "{\"version\": 3}"),
+            (EXPORT_DATA, EXPORT_DATA_JSON),
+        ]);
+        let parsed = OnePassword1Pux.parse(&archive).unwrap();
+        assert_eq!(parsed.records.len(), 1);
+
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.title, "GitHub");
+        assert_eq!(entry.username, "octocat");
+        assert_eq!(entry.password.expose(), "hunter2");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+        assert_eq!(entry.tags, ["dev", "work"]);
+        assert_eq!(entry.notes.as_deref(), Some("Recovery codes in the safe"));
+        let fields: Vec<(&str, FieldType, &str)> = entry
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.field_type, field.value.expose()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("PIN", FieldType::Concealed, "1234"),
+                ("Email", FieldType::Email, "octo@example.com"),
+                ("Renewal", FieldType::Date, "2025-12-31"),
+            ]
+        );
+        let history: Vec<&str> = entry.history.iter().map(|h| h.password.expose()).collect();
+        assert_eq!(history, ["hunter1", "hunter0"]);
+        assert_eq!(entry.created_at.timestamp(), 1700000000);
+
+        assert_eq!(
+            parsed.errors[0].to_string(),
+            "item 2 (Wifi): 1Password Secure Note items are not supported"
+        );
+
+        assert!(OnePassword1Pux.parse(&pux(&[("other", "{}")])).is_err());
+        assert!(OnePassword1Pux.parse(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let csv = "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes\n\
+                   GitHub,https://github.com,octocat,hunter2,,false,false,\"dev;work\",\n";
+        let parsed = OnePasswordCsv.parse(csv.as_bytes()).unwrap();
+
+        let entry = &parsed.records[0].entry;
+        assert_eq!(entry.title, "GitHub");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+        assert_eq!(entry.tags, ["dev", "work"]);
+        assert!(entry.notes.is_none());
+    }
+}
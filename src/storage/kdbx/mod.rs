@@ -0,0 +1,760 @@
+//! KeePass KDBX 4 databases
+//!
+//! A KDBX 4 file is an unencrypted header followed by the encrypted payload,
+//! split into blocks that are each authenticated with HMAC-SHA256:
+//!
+//! ```text
+//! signature (8) | version (u32) | field* | SHA-256 (32) | HMAC (32) | block*
+//! field = id (u8) | length (u32) | data
+//! block = HMAC (32) | length (u32) | data
+//! ```
+//!
+//! Integers are little-endian. The header names the payload cipher
+//! (AES-256-CBC or ChaCha20), the key derivation function and its
+//! parameters as a KeePass "variant dictionary", and whether the payload is
+//! gzip-compressed. An empty block ends the file.
+//!
+//! The master key is derived from SHA-256(SHA-256(password)); key files and
+//! hardware keys are not supported. [`decrypt`] returns the decompressed
+//! payload, which [`payload::Payload`] reads.
+
+use crate::crypto::key_derivation::KdfParams;
+use crate::crypto::{secure_random, SecretString};
+use crate::{Result, TwoPasswordError};
+use ring::{digest, hmac};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use zeroize::{Zeroize, Zeroizing};
+
+pub mod payload;
+
+/// String field KeePassXC keeps TOTP settings in, as an otpauth:// URI
+pub const OTP_FIELD: &str = "otp";
+
+/// Signature at the start of every KeePass 2 database
+pub const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+/// File version written by this build (4.0)
+const VERSION: u32 = 0x0004_0000;
+
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER_ID: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_ENCRYPTION_IV: u8 = 7;
+const HEADER_KDF_PARAMETERS: u8 = 11;
+
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CIPHER_CHACHA20: [u8; 16] = [
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+
+const KDF_AES: [u8; 16] = [
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+const KDF_ARGON2D: [u8; 16] = [
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const KDF_ARGON2ID: [u8; 16] = [
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+
+const COMPRESSION_NONE: u32 = 0;
+const COMPRESSION_GZIP: u32 = 1;
+
+/// Size of the payload blocks written by this build
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Largest decompressed payload accepted, so a hostile file cannot exhaust
+/// memory
+const MAX_PAYLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Upper bound on the Argon2 memory accepted from a database (4 GiB)
+const MAX_ARGON2_MEMORY_KIB: u64 = 4 * 1024 * 1024;
+
+/// Upper bound on the Argon2 iterations accepted from a database
+const MAX_ARGON2_ITERATIONS: u64 = 1024;
+
+/// Upper bound on the Argon2 parallelism accepted from a database
+const MAX_ARGON2_PARALLELISM: u32 = 64;
+
+/// Upper bound on the AES-KDF rounds accepted from a database
+const MAX_AES_KDF_ROUNDS: u64 = 1_000_000_000;
+
+/// Cipher protecting a database payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    /// AES-256 in CBC mode with PKCS#7 padding
+    Aes256,
+    /// ChaCha20 with a 96-bit nonce
+    ChaCha20,
+}
+
+impl Cipher {
+    fn from_id(id: &[u8]) -> Result<Self> {
+        if id == CIPHER_AES256 {
+            Ok(Cipher::Aes256)
+        } else if id == CIPHER_CHACHA20 {
+            Ok(Cipher::ChaCha20)
+        } else {
+            Err(TwoPasswordError::validation(
+                "KeePass database uses an unsupported cipher (expected AES-256 or ChaCha20)",
+            ))
+        }
+    }
+
+    fn id(&self) -> [u8; 16] {
+        match self {
+            Cipher::Aes256 => CIPHER_AES256,
+            Cipher::ChaCha20 => CIPHER_CHACHA20,
+        }
+    }
+
+    fn iv_len(&self) -> usize {
+        match self {
+            Cipher::Aes256 => 16,
+            Cipher::ChaCha20 => 12,
+        }
+    }
+
+    fn decrypt(&self, key: &[u8; 32], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Cipher::Aes256 => {
+                use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+                cbc::Decryptor::<aes::Aes256>::new(key.into(), iv.into())
+                    .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+                    .map_err(|_| invalid("payload padding is corrupted"))
+            }
+            Cipher::ChaCha20 => {
+                let mut plaintext = ciphertext.to_vec();
+                apply_chacha20(key, iv, &mut plaintext);
+                Ok(plaintext)
+            }
+        }
+    }
+
+    fn encrypt(&self, key: &[u8; 32], iv: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        match self {
+            Cipher::Aes256 => {
+                use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+                cbc::Encryptor::<aes::Aes256>::new(key.into(), iv.into())
+                    .encrypt_padded_vec_mut::<Pkcs7>(plaintext)
+            }
+            Cipher::ChaCha20 => {
+                let mut ciphertext = plaintext.to_vec();
+                apply_chacha20(key, iv, &mut ciphertext);
+                ciphertext
+            }
+        }
+    }
+}
+
+pub(crate) fn apply_chacha20(key: &[u8; 32], nonce: &[u8], data: &mut [u8]) {
+    use chacha20::cipher::{KeyIvInit, StreamCipher};
+    chacha20::ChaCha20::new(key.into(), nonce.into()).apply_keystream(data);
+}
+
+/// Key derivation function turning the composite key into the master key
+enum Kdf {
+    Argon2 {
+        algorithm: argon2::Algorithm,
+        version: argon2::Version,
+        salt: Vec<u8>,
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Aes {
+        seed: [u8; 32],
+        rounds: u64,
+    },
+}
+
+impl Kdf {
+    fn from_dictionary(bytes: &[u8]) -> Result<Self> {
+        let dictionary = read_variant_dictionary(bytes)?;
+        let get = |key: &str| {
+            dictionary
+                .get(key)
+                .ok_or_else(|| invalid(&format!("key derivation parameter '{}' is missing", key)))
+        };
+
+        let uuid = get("$UUID")?.bytes()?;
+        if uuid == KDF_AES {
+            let seed = get("S")?.bytes()?;
+            let rounds = get("R")?.u64()?;
+            if rounds > MAX_AES_KDF_ROUNDS {
+                return Err(unsupported_kdf(format!("AES-KDF with {} rounds", rounds)));
+            }
+            return Ok(Kdf::Aes {
+                seed: seed.try_into().map_err(|_| invalid("AES-KDF seed"))?,
+                rounds,
+            });
+        }
+
+        let algorithm = if uuid == KDF_ARGON2D {
+            argon2::Algorithm::Argon2d
+        } else if uuid == KDF_ARGON2ID {
+            argon2::Algorithm::Argon2id
+        } else {
+            return Err(unsupported_kdf(
+                "an unknown key derivation function".to_string(),
+            ));
+        };
+        if dictionary.contains_key("K") || dictionary.contains_key("A") {
+            return Err(unsupported_kdf(
+                "Argon2 with a secret key or associated data".to_string(),
+            ));
+        }
+
+        let version = match get("V")?.u32()? {
+            0x10 => argon2::Version::V0x10,
+            0x13 => argon2::Version::V0x13,
+            other => return Err(unsupported_kdf(format!("Argon2 version {:#x}", other))),
+        };
+        let memory_kib = get("M")?.u64()? / 1024;
+        let iterations = get("I")?.u64()?;
+        let parallelism = get("P")?.u32()?;
+        if memory_kib > MAX_ARGON2_MEMORY_KIB
+            || iterations > MAX_ARGON2_ITERATIONS
+            || parallelism > MAX_ARGON2_PARALLELISM
+        {
+            return Err(unsupported_kdf(format!(
+                "Argon2 with m={} KiB, t={}, p={}",
+                memory_kib, iterations, parallelism
+            )));
+        }
+
+        Ok(Kdf::Argon2 {
+            algorithm,
+            version,
+            salt: get("S")?.bytes()?.to_vec(),
+            memory_kib: memory_kib as u32,
+            iterations: iterations as u32,
+            parallelism,
+        })
+    }
+
+    fn to_dictionary(&self) -> Vec<u8> {
+        let mut dictionary = VariantWriter::default();
+        match self {
+            Kdf::Argon2 {
+                algorithm,
+                version,
+                salt,
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let uuid = match algorithm {
+                    argon2::Algorithm::Argon2d => KDF_ARGON2D,
+                    _ => KDF_ARGON2ID,
+                };
+                dictionary.bytes("$UUID", &uuid);
+                dictionary.bytes("S", salt);
+                dictionary.u32("P", *parallelism);
+                dictionary.u64("M", u64::from(*memory_kib) * 1024);
+                dictionary.u64("I", u64::from(*iterations));
+                dictionary.u32("V", *version as u32);
+            }
+            Kdf::Aes { seed, rounds } => {
+                dictionary.bytes("$UUID", &KDF_AES);
+                dictionary.bytes("S", seed);
+                dictionary.u64("R", *rounds);
+            }
+        }
+        dictionary.finish()
+    }
+
+    /// Derive the master key from the composite key
+    fn transform(&self, composite: &[u8; 32]) -> Result<Zeroizing<[u8; 32]>> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match self {
+            Kdf::Argon2 {
+                algorithm,
+                version,
+                salt,
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = argon2::Params::new(*memory_kib, *iterations, *parallelism, Some(32))
+                    .map_err(|e| unsupported_kdf(format!("Argon2 parameters ({})", e)))?;
+                argon2::Argon2::new(*algorithm, *version, params)
+                    .hash_password_into(composite, salt, key.as_mut())
+                    .map_err(|e| {
+                        TwoPasswordError::crypto(format!("Key derivation failed: {}", e))
+                    })?;
+            }
+            Kdf::Aes { seed, rounds } => {
+                use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+                let cipher = aes::Aes256::new(seed.into());
+                let mut blocks = Zeroizing::new(*composite);
+                let (first, second) = blocks.split_at_mut(16);
+                for _ in 0..*rounds {
+                    cipher.encrypt_block(GenericArray::from_mut_slice(first));
+                    cipher.encrypt_block(GenericArray::from_mut_slice(second));
+                }
+                key.copy_from_slice(digest::digest(&digest::SHA256, blocks.as_ref()).as_ref());
+            }
+        }
+        Ok(key)
+    }
+}
+
+fn unsupported_kdf(what: String) -> TwoPasswordError {
+    TwoPasswordError::validation(format!("KeePass database uses unsupported {}", what))
+}
+
+/// Check whether file contents look like a KeePass 2 database
+pub fn is_kdbx(contents: &[u8]) -> bool {
+    contents.starts_with(&SIGNATURE)
+}
+
+/// Open a KDBX 4 database with its password
+///
+/// Returns the decompressed payload: the inner header followed by the XML
+/// document.
+pub fn decrypt(contents: &[u8], password: &SecretString) -> Result<Zeroizing<Vec<u8>>> {
+    let (fields, mut reader) = read_header(contents)?;
+    let header = &contents[..contents.len() - reader.remaining()];
+    let field = |id: u8, name: &str| {
+        fields
+            .get(&id)
+            .copied()
+            .ok_or_else(|| invalid(&format!("{} is missing from the header", name)))
+    };
+
+    let cipher = Cipher::from_id(field(HEADER_CIPHER_ID, "cipher")?)?;
+    let compression = Reader::new(field(HEADER_COMPRESSION, "compression")?).u32()?;
+    let master_seed = field(HEADER_MASTER_SEED, "master seed")?;
+    let iv = field(HEADER_ENCRYPTION_IV, "encryption IV")?;
+    if iv.len() != cipher.iv_len() || master_seed.len() != 32 {
+        return Err(invalid("header field has the wrong length"));
+    }
+    let kdf = Kdf::from_dictionary(field(HEADER_KDF_PARAMETERS, "KDF parameters")?)?;
+
+    if reader.take(32)? != digest::digest(&digest::SHA256, header).as_ref() {
+        return Err(invalid("header checksum does not match"));
+    }
+    let header_hmac = reader.take(32)?;
+
+    let keys = master_keys(password, master_seed, &kdf)?;
+    hmac::verify(&block_key(&keys.hmac_base, u64::MAX), header, header_hmac)
+        .map_err(|_| TwoPasswordError::auth("Wrong password for the KeePass database"))?;
+
+    let mut ciphertext = Vec::new();
+    for index in 0u64.. {
+        let block_hmac = reader.take(32)?;
+        let len_bytes = reader.take(4)?;
+        let len = Reader::new(len_bytes).u32()? as usize;
+        let data = reader.take(len)?;
+
+        let mut message = Vec::with_capacity(12 + len);
+        message.extend_from_slice(&index.to_le_bytes());
+        message.extend_from_slice(len_bytes);
+        message.extend_from_slice(data);
+        hmac::verify(&block_key(&keys.hmac_base, index), &message, block_hmac).map_err(|_| {
+            TwoPasswordError::validation("KeePass database is corrupted or was modified")
+        })?;
+
+        if len == 0 {
+            break;
+        }
+        ciphertext.extend_from_slice(data);
+    }
+
+    let plaintext = Zeroizing::new(cipher.decrypt(&keys.cipher_key, iv, &ciphertext)?);
+    match compression {
+        COMPRESSION_NONE => Ok(plaintext),
+        COMPRESSION_GZIP => {
+            let mut payload = Zeroizing::new(Vec::new());
+            flate2::read::GzDecoder::new(plaintext.as_slice())
+                .take(MAX_PAYLOAD_SIZE + 1)
+                .read_to_end(&mut payload)
+                .map_err(|e| invalid(&format!("payload does not decompress ({})", e)))?;
+            if payload.len() as u64 > MAX_PAYLOAD_SIZE {
+                return Err(TwoPasswordError::validation(
+                    "KeePass database is too large",
+                ));
+            }
+            Ok(payload)
+        }
+        other => Err(invalid(&format!("unknown compression {}", other))),
+    }
+}
+
+/// Read the fields of the unencrypted header, returning them with a reader
+/// positioned after the header
+fn read_header(contents: &[u8]) -> Result<(HashMap<u8, &[u8]>, Reader<'_>)> {
+    if !is_kdbx(contents) {
+        return Err(TwoPasswordError::validation("Not a KeePass database"));
+    }
+    let mut reader = Reader::new(&contents[SIGNATURE.len()..]);
+    let version = reader.u32()?;
+    if version >> 16 != VERSION >> 16 {
+        return Err(TwoPasswordError::validation(format!(
+            "KDBX {}.{} databases are not supported; save the database as KDBX 4 first",
+            version >> 16,
+            version & 0xffff
+        )));
+    }
+
+    let mut fields = HashMap::new();
+    loop {
+        let id = reader.u8()?;
+        let len = reader.u32()? as usize;
+        let data = reader.take(len)?;
+        if id == HEADER_END {
+            break;
+        }
+        fields.insert(id, data);
+    }
+    Ok((fields, reader))
+}
+
+/// Argon2 parameters recorded in the header of a database, for tests
+#[cfg(test)]
+pub(crate) fn argon2_params(contents: &[u8]) -> Result<KdfParams> {
+    let (fields, _) = read_header(contents)?;
+    let dictionary = fields
+        .get(&HEADER_KDF_PARAMETERS)
+        .ok_or_else(|| invalid("KDF parameters are missing from the header"))?;
+    match Kdf::from_dictionary(dictionary)? {
+        Kdf::Argon2 {
+            memory_kib,
+            iterations,
+            parallelism,
+            ..
+        } => Ok(KdfParams {
+            memory_cost_kib: memory_kib,
+            time_cost: iterations,
+            parallelism,
+            ..KdfParams::default()
+        }),
+        Kdf::Aes { .. } => Err(unsupported_kdf("AES-KDF".to_string())),
+    }
+}
+
+/// Seal a payload in a KDBX 4 database protected by `password`
+///
+/// The payload is gzip-compressed and the master key derived with Argon2id
+/// using `kdf`.
+pub fn encrypt(
+    payload: &[u8],
+    password: &SecretString,
+    kdf: &KdfParams,
+    cipher: Cipher,
+) -> Result<Vec<u8>> {
+    kdf.validate()?;
+    let kdf = Kdf::Argon2 {
+        algorithm: argon2::Algorithm::Argon2id,
+        version: argon2::Version::V0x13,
+        salt: secure_random::generate_bytes(32)?,
+        memory_kib: kdf.memory_cost_kib,
+        iterations: kdf.time_cost,
+        parallelism: kdf.parallelism,
+    };
+    let master_seed = secure_random::generate_bytes(32)?;
+    let iv = secure_random::generate_bytes(cipher.iv_len())?;
+
+    let mut out = SIGNATURE.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    let mut field = |id: u8, data: &[u8]| {
+        out.push(id);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+    };
+    field(HEADER_CIPHER_ID, &cipher.id());
+    field(HEADER_COMPRESSION, &COMPRESSION_GZIP.to_le_bytes());
+    field(HEADER_MASTER_SEED, &master_seed);
+    field(HEADER_ENCRYPTION_IV, &iv);
+    field(HEADER_KDF_PARAMETERS, &kdf.to_dictionary());
+    field(HEADER_END, b"\r\n\r\n");
+
+    let keys = master_keys(password, &master_seed, &kdf)?;
+    let header_hash = digest::digest(&digest::SHA256, &out);
+    let header_hmac = hmac::sign(&block_key(&keys.hmac_base, u64::MAX), &out);
+    out.extend_from_slice(header_hash.as_ref());
+    out.extend_from_slice(header_hmac.as_ref());
+
+    let mut compressed = Zeroizing::new(Vec::new());
+    let mut encoder =
+        flate2::write::GzEncoder::new(&mut *compressed, flate2::Compression::default());
+    encoder.write_all(payload)?;
+    encoder.finish()?;
+    let ciphertext = cipher.encrypt(&keys.cipher_key, &iv, &compressed);
+
+    let blocks = ciphertext
+        .chunks(BLOCK_SIZE)
+        .chain(std::iter::once(&[][..]));
+    for (index, data) in (0u64..).zip(blocks) {
+        let len_bytes = (data.len() as u32).to_le_bytes();
+        let mut message = Vec::with_capacity(12 + data.len());
+        message.extend_from_slice(&index.to_le_bytes());
+        message.extend_from_slice(&len_bytes);
+        message.extend_from_slice(data);
+
+        out.extend_from_slice(hmac::sign(&block_key(&keys.hmac_base, index), &message).as_ref());
+        out.extend_from_slice(&len_bytes);
+        out.extend_from_slice(data);
+    }
+    Ok(out)
+}
+
+/// Keys derived from the password for one database
+struct MasterKeys {
+    /// Key of the payload cipher
+    cipher_key: Zeroizing<[u8; 32]>,
+    /// Base of the HMAC block keys
+    hmac_base: Zeroizing<[u8; 64]>,
+}
+
+fn master_keys(password: &SecretString, master_seed: &[u8], kdf: &Kdf) -> Result<MasterKeys> {
+    let password_hash = digest::digest(&digest::SHA256, password.expose().as_bytes());
+    let mut composite = [0u8; 32];
+    composite.copy_from_slice(digest::digest(&digest::SHA256, password_hash.as_ref()).as_ref());
+    let transformed = kdf.transform(&composite);
+    composite.zeroize();
+    let transformed = transformed?;
+
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(master_seed);
+    context.update(transformed.as_ref());
+    let mut cipher_key = Zeroizing::new([0u8; 32]);
+    cipher_key.copy_from_slice(context.finish().as_ref());
+
+    let mut context = digest::Context::new(&digest::SHA512);
+    context.update(master_seed);
+    context.update(transformed.as_ref());
+    context.update(&[1]);
+    let mut hmac_base = Zeroizing::new([0u8; 64]);
+    hmac_base.copy_from_slice(context.finish().as_ref());
+
+    Ok(MasterKeys {
+        cipher_key,
+        hmac_base,
+    })
+}
+
+/// HMAC key of the block at `index`; the header uses index `u64::MAX`
+fn block_key(hmac_base: &[u8; 64], index: u64) -> hmac::Key {
+    let mut context = digest::Context::new(&digest::SHA512);
+    context.update(&index.to_le_bytes());
+    context.update(hmac_base);
+    hmac::Key::new(hmac::HMAC_SHA256, context.finish().as_ref())
+}
+
+fn invalid(what: &str) -> TwoPasswordError {
+    TwoPasswordError::validation(format!("Invalid KeePass database: {}", what))
+}
+
+/// Value in a KeePass variant dictionary
+enum Variant<'a> {
+    U32(u32),
+    U64(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+impl<'a> Variant<'a> {
+    fn u32(&self) -> Result<u32> {
+        match self {
+            Variant::U32(value) => Ok(*value),
+            _ => Err(invalid("key derivation parameter has the wrong type")),
+        }
+    }
+
+    fn u64(&self) -> Result<u64> {
+        match self {
+            Variant::U64(value) => Ok(*value),
+            _ => Err(invalid("key derivation parameter has the wrong type")),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'a [u8]> {
+        match self {
+            Variant::Bytes(value) => Ok(value),
+            _ => Err(invalid("key derivation parameter has the wrong type")),
+        }
+    }
+}
+
+const VARIANT_DICTIONARY_VERSION: u16 = 0x0100;
+const VARIANT_END: u8 = 0x00;
+const VARIANT_U32: u8 = 0x04;
+const VARIANT_U64: u8 = 0x05;
+const VARIANT_BYTES: u8 = 0x42;
+
+fn read_variant_dictionary(bytes: &[u8]) -> Result<HashMap<String, Variant<'_>>> {
+    let mut reader = Reader::new(bytes);
+    // Only the major version byte is significant
+    if reader.u16()? >> 8 != VARIANT_DICTIONARY_VERSION >> 8 {
+        return Err(invalid("unsupported KDF parameter format"));
+    }
+
+    let mut dictionary = HashMap::new();
+    loop {
+        let kind = reader.u8()?;
+        if kind == VARIANT_END {
+            return Ok(dictionary);
+        }
+        let key_len = reader.u32()? as usize;
+        let key = String::from_utf8_lossy(reader.take(key_len)?).into_owned();
+        let value_len = reader.u32()? as usize;
+        let value = reader.take(value_len)?;
+        let value = match kind {
+            VARIANT_U32 => Variant::U32(Reader::new(value).u32()?),
+            VARIANT_U64 => Variant::U64(Reader::new(value).u64()?),
+            VARIANT_BYTES => Variant::Bytes(value),
+            _ => Variant::Other,
+        };
+        dictionary.insert(key, value);
+    }
+}
+
+#[derive(Default)]
+struct VariantWriter {
+    out: Vec<u8>,
+}
+
+impl VariantWriter {
+    fn entry(&mut self, kind: u8, key: &str, value: &[u8]) {
+        if self.out.is_empty() {
+            self.out
+                .extend_from_slice(&VARIANT_DICTIONARY_VERSION.to_le_bytes());
+        }
+        self.out.push(kind);
+        self.out
+            .extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.out.extend_from_slice(key.as_bytes());
+        self.out
+            .extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.out.extend_from_slice(value);
+    }
+
+    fn u32(&mut self, key: &str, value: u32) {
+        self.entry(VARIANT_U32, key, &value.to_le_bytes());
+    }
+
+    fn u64(&mut self, key: &str, value: u64) {
+        self.entry(VARIANT_U64, key, &value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, key: &str, value: &[u8]) {
+        self.entry(VARIANT_BYTES, key, value);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.out.push(VARIANT_END);
+        self.out
+    }
+}
+
+/// Little-endian reader over a byte slice
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid("file is truncated"));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_with_both_ciphers() {
+        let password = SecretString::from("correct horse battery staple");
+        // Larger than one block, so the block chain is exercised
+        let payload: Vec<u8> = (0..BLOCK_SIZE * 3).map(|i| (i * 7 % 251) as u8).collect();
+
+        for cipher in [Cipher::Aes256, Cipher::ChaCha20] {
+            let database = encrypt(&payload, &password, &KdfParams::for_tests(), cipher).unwrap();
+            assert!(is_kdbx(&database));
+            assert_eq!(*decrypt(&database, &password).unwrap(), payload);
+
+            let wrong = SecretString::from("wrong password");
+            assert!(matches!(
+                decrypt(&database, &wrong),
+                Err(TwoPasswordError::AuthError(_))
+            ));
+
+            let mut tampered = database.clone();
+            let last = tampered.len() - 40;
+            tampered[last] ^= 1;
+            assert!(decrypt(&tampered, &password).is_err());
+        }
+    }
+
+    #[test]
+    fn test_aes_kdf() {
+        let kdf = Kdf::Aes {
+            seed: [7; 32],
+            rounds: 10,
+        };
+        let dictionary = kdf.to_dictionary();
+        let Kdf::Aes { seed, rounds } = Kdf::from_dictionary(&dictionary).unwrap() else {
+            panic!("expected AES-KDF");
+        };
+        assert_eq!((seed, rounds), ([7; 32], 10));
+
+        let one = kdf.transform(&[1; 32]).unwrap();
+        let two = kdf.transform(&[2; 32]).unwrap();
+        assert_ne!(*one, *two);
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let mut kdbx3 = SIGNATURE.to_vec();
+        kdbx3.extend_from_slice(&0x0003_0001u32.to_le_bytes());
+        let error = decrypt(&kdbx3, &SecretString::from("x")).unwrap_err();
+        assert!(error.to_string().contains("KDBX 3.1"));
+
+        assert!(decrypt(b"not a database", &SecretString::from("x")).is_err());
+    }
+}
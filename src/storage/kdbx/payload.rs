@@ -0,0 +1,440 @@
+//! Decrypted contents of a KDBX 4 database
+//!
+//! The payload starts with an inner header, laid out like the outer header
+//! fields, holding the key of the stream cipher that protects sensitive
+//! values and the database's attachments. The XML document follows.
+//!
+//! Values marked `Protected="True"`, such as passwords, are XORed with a
+//! ChaCha20 key stream and base64-encoded. The stream runs through the
+//! document, so protected values must be read and written in document
+//! order; [`Payload`] does both while parsing and serializing, and the
+//! [`Element`] tree only ever holds plaintext.
+
+use super::Reader;
+use crate::crypto::secure_random;
+use crate::{Result, TwoPasswordError};
+use base64::engine::{general_purpose, Engine as _};
+use quick_xml::events::{BytesStart, Event};
+use ring::digest;
+use zeroize::{Zeroize, Zeroizing};
+
+const INNER_END: u8 = 0;
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_BINARY: u8 = 3;
+
+/// Inner stream cipher identifier for ChaCha20
+const STREAM_CHACHA20: u32 = 3;
+
+/// Binary flag marking attachments KeePass keeps in protected memory
+const BINARY_PROTECTED: u8 = 0x01;
+
+/// Seconds from 0001-01-01, the KDBX epoch, to the Unix epoch
+const KDBX_EPOCH_OFFSET: i64 = 62_135_596_800;
+
+/// Attribute marking a protected value
+const PROTECTED: &str = "Protected";
+
+/// The inner header and XML document of a database
+pub struct Payload {
+    /// Attachments, referenced from entries by their index
+    pub binaries: Vec<Binary>,
+    /// The `KeePassFile` root element
+    pub root: Element,
+}
+
+/// An attachment stored in the inner header
+pub struct Binary {
+    /// Whether KeePass keeps the data in protected memory
+    pub protected: bool,
+    pub data: Zeroizing<Vec<u8>>,
+}
+
+impl Payload {
+    /// Read a payload returned by [`super::decrypt`]
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let mut stream_id = None;
+        let mut stream_key = None;
+        let mut binaries = Vec::new();
+        loop {
+            let id = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?;
+            match id {
+                INNER_END => break,
+                INNER_STREAM_ID => stream_id = Some(Reader::new(data).u32()?),
+                INNER_STREAM_KEY => stream_key = Some(data),
+                INNER_BINARY => {
+                    let (flags, data) =
+                        data.split_first().ok_or_else(|| invalid("empty binary"))?;
+                    binaries.push(Binary {
+                        protected: flags & BINARY_PROTECTED != 0,
+                        data: Zeroizing::new(data.to_vec()),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if stream_id != Some(STREAM_CHACHA20) {
+            return Err(TwoPasswordError::validation(
+                "KeePass database protects values with an unsupported cipher",
+            ));
+        }
+        let key = stream_key.ok_or_else(|| invalid("inner stream key is missing"))?;
+        let mut stream = ProtectedStream::new(key);
+        let root = parse_xml(reader.rest(), &mut stream)?;
+        if root.name != "KeePassFile" {
+            return Err(invalid("document is not a KeePass file"));
+        }
+
+        Ok(Payload { binaries, root })
+    }
+
+    /// Serialize the payload, protecting values under a fresh stream key
+    pub fn to_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let key = Zeroizing::new(secure_random::generate_bytes(64)?);
+        let mut out = Zeroizing::new(Vec::new());
+        let mut field = |id: u8, data: &[&[u8]]| {
+            let len: usize = data.iter().map(|part| part.len()).sum();
+            out.push(id);
+            out.extend_from_slice(&(len as u32).to_le_bytes());
+            for part in data {
+                out.extend_from_slice(part);
+            }
+        };
+        field(INNER_STREAM_ID, &[&STREAM_CHACHA20.to_le_bytes()]);
+        field(INNER_STREAM_KEY, &[&key]);
+        for binary in &self.binaries {
+            let flags = if binary.protected {
+                BINARY_PROTECTED
+            } else {
+                0
+            };
+            field(INNER_BINARY, &[&[flags], &binary.data]);
+        }
+        field(INNER_END, &[]);
+
+        let mut xml = Zeroizing::new(String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n",
+        ));
+        self.root
+            .write(&mut xml, 0, &mut ProtectedStream::new(&key))?;
+        out.extend_from_slice(xml.as_bytes());
+        Ok(out)
+    }
+}
+
+/// Key stream protecting values inside the XML document
+struct ProtectedStream {
+    key: Zeroizing<[u8; 32]>,
+    nonce: [u8; 12],
+    position: usize,
+}
+
+impl ProtectedStream {
+    fn new(inner_key: &[u8]) -> Self {
+        let hash = digest::digest(&digest::SHA512, inner_key);
+        let mut key = Zeroizing::new([0u8; 32]);
+        key.copy_from_slice(&hash.as_ref()[..32]);
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&hash.as_ref()[32..44]);
+        Self {
+            key,
+            nonce,
+            position: 0,
+        }
+    }
+
+    /// XOR the next bytes of the key stream into `data`
+    fn apply(&mut self, data: &mut [u8]) {
+        use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+        let mut cipher = chacha20::ChaCha20::new(self.key.as_ref().into(), &self.nonce.into());
+        cipher.seek(self.position as u64);
+        cipher.apply_keystream(data);
+        self.position += data.len();
+    }
+}
+
+/// An XML element
+///
+/// Text is kept for every element but is only meaningful for leaves, since
+/// the whitespace between child elements is not significant.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Drop for Element {
+    fn drop(&mut self) {
+        self.text.zeroize();
+    }
+}
+
+impl Element {
+    /// Create an empty element
+    pub fn new(name: &str) -> Self {
+        Self::with_text(name, String::new())
+    }
+
+    /// Create an element holding only text
+    pub fn with_text(name: &str, text: impl Into<String>) -> Self {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: text.into(),
+        }
+    }
+
+    /// Add a child element, returning `self` for chaining
+    pub fn child(mut self, child: Element) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Mark the element's text as protected
+    pub fn protected(mut self) -> Self {
+        self.attributes
+            .push((PROTECTED.to_string(), "True".to_string()));
+        self
+    }
+
+    /// Get the first child element named `name`
+    pub fn get(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Get the text of the first child element named `name`
+    pub fn text_of(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|child| child.text.as_str())
+    }
+
+    /// Iterate over the child elements named `name`
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Get the value of an attribute
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check whether the element's text is marked as protected
+    pub fn is_protected(&self) -> bool {
+        self.attribute(PROTECTED)
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    fn write(&self, out: &mut String, depth: usize, stream: &mut ProtectedStream) -> Result<()> {
+        let indent = "\t".repeat(depth);
+        out.push_str(&indent);
+        out.push('<');
+        out.push_str(&self.name);
+        for (key, value) in &self.attributes {
+            out.push_str(&format!(
+                " {}=\"{}\"",
+                key,
+                quick_xml::escape::escape(value)
+            ));
+        }
+
+        if !self.children.is_empty() {
+            out.push_str(">\n");
+            for child in &self.children {
+                child.write(out, depth + 1, stream)?;
+            }
+            out.push_str(&indent);
+        } else if self.is_protected() {
+            let mut value = Zeroizing::new(self.text.as_bytes().to_vec());
+            stream.apply(&mut value);
+            out.push('>');
+            out.push_str(&general_purpose::STANDARD.encode(&value));
+        } else if self.text.is_empty() {
+            out.push_str(" />\n");
+            return Ok(());
+        } else {
+            out.push('>');
+            out.push_str(&quick_xml::escape::escape(&self.text));
+        }
+        out.push_str("</");
+        out.push_str(&self.name);
+        out.push_str(">\n");
+        Ok(())
+    }
+}
+
+fn parse_xml(xml: &[u8], stream: &mut ProtectedStream) -> Result<Element> {
+    let mut reader = quick_xml::Reader::from_reader(xml);
+    let mut buf = Zeroizing::new(Vec::new());
+    let mut stack: Vec<Element> = Vec::new();
+
+    loop {
+        let element = match reader.read_event_into(&mut buf).map_err(xml_error)? {
+            Event::Start(start) => {
+                stack.push(start_element(&start)?);
+                None
+            }
+            Event::Empty(start) => Some(start_element(&start)?),
+            Event::End(_) => stack.pop(),
+            Event::Text(text) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.text.push_str(&text.unescape().map_err(xml_error)?);
+                }
+                None
+            }
+            Event::CData(data) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.text.push_str(&String::from_utf8_lossy(&data));
+                }
+                None
+            }
+            Event::Eof => return Err(invalid("XML document ends early")),
+            _ => None,
+        };
+        buf.clear();
+
+        // Elements are complete once closed, which for the leaves holding
+        // protected values is document order
+        if let Some(mut element) = element {
+            if element.is_protected() {
+                unprotect(&mut element, stream)?;
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Ok(element),
+            }
+        }
+    }
+}
+
+fn start_element(start: &BytesStart) -> Result<Element> {
+    let mut element = Element::new(&String::from_utf8_lossy(start.name().as_ref()));
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(xml_error)?;
+        let value = attribute.unescape_value().map_err(xml_error)?;
+        element.attributes.push((
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            value.into_owned(),
+        ));
+    }
+    Ok(element)
+}
+
+fn unprotect(element: &mut Element, stream: &mut ProtectedStream) -> Result<()> {
+    let mut value = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(element.text.trim())
+            .map_err(|_| invalid("protected value is not base64"))?,
+    );
+    stream.apply(&mut value);
+    let text = std::str::from_utf8(&value).map_err(|_| invalid("protected value is not text"))?;
+    element.text.zeroize();
+    element.text = text.to_string();
+    Ok(())
+}
+
+/// Encode a time as KDBX 4 does: base64 of the little-endian seconds since
+/// 0001-01-01
+pub fn encode_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    general_purpose::STANDARD.encode((time.timestamp() + KDBX_EPOCH_OFFSET).to_le_bytes())
+}
+
+/// Decode a KDBX 4 time, or an ISO 8601 time as written by KDBX 3
+pub fn decode_time(text: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    let text = text.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&chrono::Utc));
+    }
+    let bytes: [u8; 8] = general_purpose::STANDARD
+        .decode(text)
+        .ok()?
+        .try_into()
+        .ok()?;
+    chrono::DateTime::from_timestamp(i64::from_le_bytes(bytes) - KDBX_EPOCH_OFFSET, 0)
+}
+
+/// Encode a UUID as base64 of its bytes
+pub fn encode_uuid(uuid: uuid::Uuid) -> String {
+    general_purpose::STANDARD.encode(uuid.as_bytes())
+}
+
+/// Decode a base64 UUID
+pub fn decode_uuid(text: &str) -> Option<uuid::Uuid> {
+    let bytes = general_purpose::STANDARD.decode(text.trim()).ok()?;
+    uuid::Uuid::from_slice(&bytes).ok()
+}
+
+fn xml_error(e: impl std::fmt::Display) -> TwoPasswordError {
+    invalid(&format!("malformed XML ({})", e))
+}
+
+fn invalid(what: &str) -> TwoPasswordError {
+    super::invalid(what)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_values_roundtrip() {
+        let string = |key: &str, value: Element| {
+            Element::new("String")
+                .child(Element::with_text("Key", key))
+                .child(value)
+        };
+        let root = Element::new("KeePassFile").child(
+            Element::new("Root").child(
+                Element::new("Entry")
+                    .child(string("Title", Element::with_text("Value", "A & <B>")))
+                    .child(string(
+                        "Password",
+                        Element::with_text("Value", "p@ss").protected(),
+                    ))
+                    .child(string(
+                        "PIN",
+                        Element::with_text("Value", " 1234 ").protected(),
+                    )),
+            ),
+        );
+        let payload = Payload {
+            binaries: vec![Binary {
+                protected: true,
+                data: Zeroizing::new(b"attachment".to_vec()),
+            }],
+            root,
+        };
+
+        let bytes = payload.to_bytes().unwrap();
+        let xml = String::from_utf8_lossy(&bytes);
+        assert!(!xml.contains("p@ss"));
+
+        let parsed = Payload::parse(&bytes).unwrap();
+        assert_eq!(*parsed.binaries[0].data, b"attachment");
+        let entry = parsed.root.get("Root").unwrap().get("Entry").unwrap();
+        let values: Vec<&str> = entry
+            .all("String")
+            .map(|string| string.text_of("Value").unwrap())
+            .collect();
+        assert_eq!(values, ["A & <B>", "p@ss", " 1234 "]);
+    }
+
+    #[test]
+    fn test_times_and_uuids() {
+        let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(decode_time(&encode_time(time)), Some(time));
+        assert_eq!(decode_time("2023-11-14T22:13:20Z"), Some(time));
+        assert_eq!(decode_time("not a time"), None);
+
+        let uuid = uuid::Uuid::new_v4();
+        assert_eq!(decode_uuid(&encode_uuid(uuid)), Some(uuid));
+    }
+}
@@ -0,0 +1,115 @@
+//! Vault format migrations
+//!
+//! Each format version after the first has one registered migration from the
+//! version before it. Migrations run on the decrypted vault right after
+//! unlock, in order, and the caller saves the result in the current format,
+//! upgrading the file in place.
+
+use crate::storage::{Vault, VaultKeys};
+use crate::{Result, TwoPasswordError};
+
+/// Upgrade of an unlocked vault from one format version to the next
+pub struct Migration {
+    /// Format version the migration starts from
+    pub from_version: u32,
+    /// Short description of what changed in the next version
+    pub description: &'static str,
+    /// Rewrite the decrypted vault and key material for the next version
+    pub apply: fn(&mut Vault, &mut VaultKeys) -> Result<()>,
+}
+
+/// All migrations, ordered by the version they start from
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from_version: 1,
+        description: "JSON vault file replaced by the binary container",
+        // The contents are unchanged; saving writes the new container
+        apply: |_, _| Ok(()),
+    },
+    Migration {
+        from_version: 2,
+        description: "Payload holds the trash and vault settings next to the entries",
+        // Older payloads load with an empty trash and default settings
+        apply: |_, _| Ok(()),
+    },
+    Migration {
+        from_version: 3,
+        description: "Entries have a kind; existing entries become logins",
+        // Entries without a kind load as logins
+        apply: |_, _| Ok(()),
+    },
+    Migration {
+        from_version: 4,
+        description: "Entries carry password history, OTP secrets, custom fields and \
+                      attachments",
+        // Older entries load without any; the version bump keeps builds that
+        // would drop them on save from opening the vault
+        apply: |_, _| Ok(()),
+    },
+];
+
+/// Oldest format version that can still be opened
+pub const OLDEST_SUPPORTED_VERSION: u32 = 1;
+
+/// Check whether a vault of the given format version can be opened
+pub fn is_supported(format_version: u32) -> bool {
+    (OLDEST_SUPPORTED_VERSION..=crate::config::FORMAT_VERSION).contains(&format_version)
+}
+
+/// Migrate an unlocked vault to the current format version
+///
+/// Returns the descriptions of the migrations applied, which is empty when
+/// the vault is already current.
+pub fn migrate(vault: &mut Vault, keys: &mut VaultKeys) -> Result<Vec<&'static str>> {
+    let mut applied = Vec::new();
+
+    while vault.metadata.format_version < crate::config::FORMAT_VERSION {
+        let version = vault.metadata.format_version;
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from_version == version)
+            .ok_or_else(|| {
+                TwoPasswordError::storage(format!(
+                    "No migration from vault format version {}",
+                    version
+                ))
+            })?;
+
+        (migration.apply)(vault, keys)?;
+        vault.metadata.format_version = version + 1;
+        vault.is_modified = true;
+        applied.push(migration.description);
+
+        tracing::info!(
+            "Migrated vault from format version {} to {}: {}",
+            version,
+            version + 1,
+            migration.description
+        );
+    }
+
+    if vault.metadata.format_version > crate::config::FORMAT_VERSION {
+        return Err(TwoPasswordError::InvalidVaultFormat);
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_covers_every_version() {
+        for version in OLDEST_SUPPORTED_VERSION..crate::config::FORMAT_VERSION {
+            let count = MIGRATIONS
+                .iter()
+                .filter(|migration| migration.from_version == version)
+                .count();
+            assert_eq!(count, 1, "format version {} needs one migration", version);
+        }
+        assert!(MIGRATIONS
+            .iter()
+            .all(|migration| migration.from_version < crate::config::FORMAT_VERSION));
+    }
+}
@@ -2,14 +2,32 @@
 //!
 //! Handles encrypted vault storage and password entry management
 
-use crate::crypto::{CryptoManager, EncryptedData, Salt};
+use crate::crypto::key_derivation::KdfParams;
+use crate::crypto::key_wrap::{KeySlot, KeySlotKind};
+use crate::crypto::otp::Otp;
+use crate::crypto::{CipherSuite, CryptoManager, EncryptedData, MasterKey, Salt, SecretString};
+use crate::storage::attachment::{Attachment, AttachmentProblem};
+use crate::storage::field::CustomField;
+use crate::storage::kind::EntryKind;
 use crate::{Result, TwoPasswordError};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+pub mod attachment;
 pub mod entry;
+pub mod export;
+pub mod field;
+pub mod format;
+pub mod import;
+pub mod kdbx;
+pub mod kind;
+pub mod migration;
+pub mod pass;
+pub mod query;
+pub mod tag;
 pub mod vault;
 
 /// A single password entry
@@ -17,29 +35,68 @@ pub mod vault;
 pub struct PasswordEntry {
     pub id: Uuid,
     pub title: String,
+    /// What the entry stores; entries from before kinds existed are logins
+    #[serde(default)]
+    pub kind: EntryKind,
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
     pub url: Option<String>,
     pub notes: Option<String>,
     pub tags: Vec<String>,
+    /// Two-factor authentication secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otp: Option<Otp>,
+    /// Custom fields, in the order they were added
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<CustomField>,
+    /// Encrypted files stored next to the vault
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// Previous passwords, newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryItem>,
+}
+
+/// A password an entry used before it was changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub password: SecretString,
+    /// Username used with the password, if the vault records usernames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// When the password was replaced
+    pub replaced_at: chrono::DateTime<chrono::Utc>,
 }
 
 impl PasswordEntry {
     /// Create a new password entry
-    pub fn new(title: String, username: String, password: String) -> Self {
+    pub fn new(title: String, username: String, password: impl Into<SecretString>) -> Self {
         let now = chrono::Utc::now();
         Self {
             id: Uuid::new_v4(),
             title,
+            kind: EntryKind::Login,
             username,
-            password,
+            password: password.into(),
             url: None,
             notes: None,
             tags: Vec::new(),
+            otp: None,
+            fields: Vec::new(),
+            attachments: Vec::new(),
             created_at: now,
             updated_at: now,
+            history: Vec::new(),
+        }
+    }
+
+    /// Create an entry of another kind than a login, without a username
+    pub fn of_kind(title: String, kind: EntryKind, password: impl Into<SecretString>) -> Self {
+        Self {
+            kind,
+            ..Self::new(title, String::new(), password)
         }
     }
 
@@ -47,6 +104,111 @@ impl PasswordEntry {
     pub fn update(&mut self) {
         self.updated_at = chrono::Utc::now();
     }
+
+    /// Keep the credentials of `previous` in the history if this version of
+    /// the entry changed them
+    pub fn record_history(
+        &mut self,
+        previous: &PasswordEntry,
+        settings: &VaultSettings,
+        now: chrono::DateTime<chrono::Utc>,
+    ) {
+        let username_changed = settings.history_usernames && previous.username != self.username;
+        if previous.password == self.password && !username_changed {
+            return;
+        }
+
+        self.history.insert(
+            0,
+            HistoryItem {
+                password: previous.password.clone(),
+                username: settings
+                    .history_usernames
+                    .then(|| previous.username.clone()),
+                replaced_at: now,
+            },
+        );
+        self.prune_history(settings, now);
+    }
+
+    /// Drop history beyond the vault's retention limits, returning how many
+    /// items were dropped
+    pub fn prune_history(
+        &mut self,
+        settings: &VaultSettings,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> usize {
+        let before = self.history.len();
+        self.history.truncate(settings.history_size);
+        if settings.history_max_age_days > 0 {
+            let max_age = chrono::Duration::days(i64::from(settings.history_max_age_days));
+            self.history.retain(|item| now - item.replaced_at < max_age);
+        }
+        before - self.history.len()
+    }
+
+    /// Make a previous password current again
+    ///
+    /// `index` counts from the newest history item. The replaced password
+    /// goes into the history, so a restore can itself be undone.
+    pub fn restore_history(
+        &mut self,
+        index: usize,
+        settings: &VaultSettings,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        if index >= self.history.len() {
+            return Err(TwoPasswordError::validation(format!(
+                "'{}' has no history item {}",
+                self.title,
+                index + 1
+            )));
+        }
+
+        let previous = self.clone();
+        let item = self.history.remove(index);
+        self.password = item.password;
+        if let Some(username) = item.username {
+            self.username = username;
+        }
+        self.record_history(&previous, settings, now);
+        self.updated_at = now;
+        Ok(())
+    }
+}
+
+/// An entry moved to the trash, kept until restored or purged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    pub entry: PasswordEntry,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Per-vault settings, stored inside the encrypted payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultSettings {
+    /// Days an entry stays in the trash before it is purged when the vault
+    /// is opened; 0 keeps entries until the trash is emptied
+    pub trash_retention_days: u32,
+    /// Previous passwords kept per entry; 0 disables the history
+    pub history_size: usize,
+    /// Days a previous password is kept; 0 keeps it until it is pushed out
+    /// by newer ones
+    pub history_max_age_days: u32,
+    /// Record username changes in the history along with passwords
+    pub history_usernames: bool,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: crate::config::TRASH_RETENTION_DAYS,
+            history_size: crate::config::PASSWORD_HISTORY_SIZE,
+            history_max_age_days: 0,
+            history_usernames: true,
+        }
+    }
 }
 
 /// Vault metadata
@@ -63,17 +225,76 @@ pub struct VaultMetadata {
 pub struct VaultFile {
     pub metadata: VaultMetadata,
     pub salt: Salt,
+    /// Key derivation parameters; vaults predating this field use the defaults
+    #[serde(default)]
+    pub kdf: KdfParams,
+    /// Wrapped data keys; empty for vaults encrypted directly with the
+    /// password-derived key
+    #[serde(default)]
+    pub key_slots: Vec<KeySlot>,
+    /// Cipher suite protecting the vault contents
+    #[serde(default)]
+    pub cipher: CipherSuite,
+    /// Whether the real metadata is kept inside the padded payload, leaving
+    /// only a placeholder with the format version in `metadata`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_metadata: bool,
     pub encrypted_data: EncryptedData,
     pub integrity_hash: Vec<u8>,
 }
 
+/// Key material from the vault header, kept while a vault is unlocked
+#[derive(Debug, Clone)]
+pub struct VaultKeys {
+    /// Salt for deriving the password key-encryption key
+    pub salt: Salt,
+    /// KDF parameters for the password key-encryption key
+    pub kdf: KdfParams,
+    /// The data key, wrapped once per unlock method
+    pub key_slots: Vec<KeySlot>,
+    /// Cipher suite used to encrypt the vault contents
+    pub cipher: CipherSuite,
+    /// Keep metadata inside the encrypted payload and pad it to a size bucket
+    pub hide_metadata: bool,
+}
+
+impl VaultKeys {
+    /// Get the key slot for an unlock method
+    pub fn slot(&self, kind: KeySlotKind) -> Option<&KeySlot> {
+        self.key_slots.iter().find(|slot| slot.kind == kind)
+    }
+
+    /// Add a key slot, replacing any existing slot of the same kind
+    pub fn set_slot(&mut self, slot: KeySlot) {
+        self.key_slots.retain(|existing| existing.kind != slot.kind);
+        self.key_slots.push(slot);
+    }
+
+    /// Check a master password against the password key slot
+    pub fn verify_password(&self, password: &str) -> Result<()> {
+        let kek = vault::derive_password_kek(password, &self.salt, &self.kdf)?;
+        let slot = self
+            .slot(KeySlotKind::Password)
+            .ok_or_else(|| TwoPasswordError::auth("Vault has no password key slot"))?;
+        crate::crypto::key_wrap::unwrap_key(&kek, &slot.wrapped_key)
+            .map_err(|_| TwoPasswordError::InvalidMasterPassword)?;
+        Ok(())
+    }
+}
+
 /// In-memory vault with decrypted entries
 #[derive(Debug)]
 pub struct Vault {
     pub metadata: VaultMetadata,
     pub entries: HashMap<Uuid, PasswordEntry>,
+    /// Removed entries, which can still be restored
+    pub trash: HashMap<Uuid, TrashedEntry>,
+    pub settings: VaultSettings,
     pub vault_path: PathBuf,
     pub is_modified: bool,
+    /// Attachments detached or purged with their entries since the last
+    /// save, whose blobs are deleted once the vault is saved
+    pub dropped_attachments: Vec<Uuid>,
 }
 
 impl Vault {
@@ -89,8 +310,11 @@ impl Vault {
         Self {
             metadata,
             entries: HashMap::new(),
+            trash: HashMap::new(),
+            settings: VaultSettings::default(),
             vault_path: vault_path.as_ref().to_path_buf(),
             is_modified: false,
+            dropped_attachments: Vec::new(),
         }
     }
 
@@ -116,6 +340,198 @@ impl Vault {
         Ok(entry)
     }
 
+    /// Move an entry to the trash
+    pub fn trash_entry(&mut self, id: &Uuid) -> Result<()> {
+        let entry = self.remove_entry(id)?;
+        self.trash.insert(
+            entry.id,
+            TrashedEntry {
+                entry,
+                deleted_at: chrono::Utc::now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Move an entry from the trash back into the vault
+    pub fn restore_entry(&mut self, id: &Uuid) -> Result<()> {
+        let trashed = self
+            .trash
+            .remove(id)
+            .ok_or_else(|| TwoPasswordError::EntryNotFound(id.to_string()))?;
+        self.add_entry(trashed.entry);
+        Ok(())
+    }
+
+    /// Permanently delete every entry in the trash, returning how many
+    /// there were
+    pub fn empty_trash(&mut self) -> usize {
+        let count = self.trash.len();
+        if count > 0 {
+            for (_, trashed) in self.trash.drain() {
+                self.dropped_attachments.extend(
+                    trashed
+                        .entry
+                        .attachments
+                        .iter()
+                        .map(|attachment| attachment.id),
+                );
+            }
+            self.metadata.updated_at = chrono::Utc::now();
+            self.is_modified = true;
+        }
+        count
+    }
+
+    /// Permanently delete trashed entries older than the retention period,
+    /// returning how many were purged
+    pub fn purge_trash(&mut self, now: chrono::DateTime<chrono::Utc>) -> usize {
+        if self.settings.trash_retention_days == 0 {
+            return 0;
+        }
+
+        let retention = chrono::Duration::days(i64::from(self.settings.trash_retention_days));
+        let before = self.trash.len();
+        let dropped = &mut self.dropped_attachments;
+        self.trash.retain(|_, trashed| {
+            let keep = now - trashed.deleted_at < retention;
+            if !keep {
+                dropped.extend(
+                    trashed
+                        .entry
+                        .attachments
+                        .iter()
+                        .map(|attachment| attachment.id),
+                );
+            }
+            keep
+        });
+
+        let purged = before - self.trash.len();
+        if purged > 0 {
+            self.is_modified = true;
+        }
+        purged
+    }
+
+    /// Replace an entry with a changed version, keeping the replaced
+    /// credentials in its history
+    pub fn update_entry(&mut self, mut entry: PasswordEntry) -> Result<()> {
+        let now = chrono::Utc::now();
+        let previous = self
+            .entries
+            .get(&entry.id)
+            .ok_or_else(|| TwoPasswordError::EntryNotFound(entry.id.to_string()))?;
+
+        entry.record_history(previous, &self.settings, now);
+        entry.updated_at = now;
+        self.entries.insert(entry.id, entry);
+        self.metadata.updated_at = now;
+        self.is_modified = true;
+        Ok(())
+    }
+
+    /// Make a previous password of an entry current again
+    pub fn restore_history(&mut self, id: &Uuid, index: usize) -> Result<()> {
+        let now = chrono::Utc::now();
+        let entry = self
+            .entries
+            .get_mut(id)
+            .ok_or_else(|| TwoPasswordError::EntryNotFound(id.to_string()))?;
+
+        entry.restore_history(index, &self.settings, now)?;
+        self.metadata.updated_at = now;
+        self.is_modified = true;
+        Ok(())
+    }
+
+    /// Drop password history beyond the retention limits from every entry,
+    /// returning how many items were dropped
+    pub fn prune_history(&mut self, now: chrono::DateTime<chrono::Utc>) -> usize {
+        let settings = &self.settings;
+        let pruned: usize = self
+            .entries
+            .values_mut()
+            .map(|entry| entry.prune_history(settings, now))
+            .sum();
+        if pruned > 0 {
+            self.is_modified = true;
+        }
+        pruned
+    }
+
+    /// Rename a tag on every entry, including trashed ones, returning how
+    /// many entries changed
+    ///
+    /// Tags nested below `from` move along with it, so renaming `work` to
+    /// `job` turns `work/servers` into `job/servers`. Renaming a tag onto
+    /// one an entry already has merges the two.
+    pub fn rename_tag(&mut self, from: &str, to: &str) -> Result<usize> {
+        tag::validate(from)?;
+        tag::validate(to)?;
+        let (from, to) = (tag::normalize(from), tag::normalize(to));
+
+        let now = chrono::Utc::now();
+        let mut renamed = 0;
+        let entries = self
+            .entries
+            .values_mut()
+            .chain(self.trash.values_mut().map(|trashed| &mut trashed.entry));
+        for entry in entries {
+            if !entry
+                .tags
+                .iter()
+                .any(|existing| tag::is_within(existing, &from))
+            {
+                continue;
+            }
+            let mut tags: Vec<String> = Vec::with_capacity(entry.tags.len());
+            for existing in &entry.tags {
+                let new_tag = tag::rename(existing, &from, &to).unwrap_or_else(|| existing.clone());
+                if !tags.iter().any(|kept| tag::same(kept, &new_tag)) {
+                    tags.push(new_tag);
+                }
+            }
+            entry.tags = tags;
+            entry.updated_at = now;
+            renamed += 1;
+        }
+
+        if renamed == 0 {
+            return Err(TwoPasswordError::validation(format!(
+                "No entries are tagged '{}'",
+                from
+            )));
+        }
+        self.metadata.updated_at = now;
+        self.is_modified = true;
+        Ok(renamed)
+    }
+
+    /// Move a tag, with the tags nested below it, under another tag, or to
+    /// the top level when `parent` is `None`, returning how many entries
+    /// changed
+    ///
+    /// The moved tag keeps its last segment as written in `tag`.
+    pub fn move_tag(&mut self, tag: &str, parent: Option<&str>) -> Result<usize> {
+        tag::validate(tag)?;
+        let leaf = tag::leaf(tag);
+        let target = match parent {
+            Some(parent) => {
+                tag::validate(parent)?;
+                if tag::is_within(parent, tag) {
+                    return Err(TwoPasswordError::validation(format!(
+                        "Cannot move '{}' into itself",
+                        tag::normalize(tag)
+                    )));
+                }
+                format!("{}/{}", tag::normalize(parent), leaf)
+            }
+            None => leaf.to_string(),
+        };
+        self.rename_tag(tag, &target)
+    }
+
     /// Get an entry by ID
     pub fn get_entry(&self, id: &Uuid) -> Option<&PasswordEntry> {
         self.entries.get(id)
@@ -145,13 +561,48 @@ impl Vault {
     pub fn get_all_entries(&self) -> Vec<&PasswordEntry> {
         self.entries.values().collect()
     }
+
+    /// Get the attachments of every entry, including trashed ones, with the
+    /// titles of their entries
+    pub fn attachments(&self) -> impl Iterator<Item = (&str, &Attachment)> {
+        self.entries
+            .values()
+            .chain(self.trash.values().map(|trashed| &trashed.entry))
+            .flat_map(|entry| {
+                entry
+                    .attachments
+                    .iter()
+                    .map(|attachment| (entry.title.as_str(), attachment))
+            })
+    }
+}
+
+/// Holder of the data key of a vault opened without it
+///
+/// The unlock agent keeps the data key of the vault it unlocked to itself and
+/// hands out the decrypted contents. A vault opened from those contents is
+/// saved, and its attachment keys wrapped and unwrapped, by the holder.
+pub trait KeyHolder: Send {
+    /// Key derivation parameters the master key was derived with
+    fn kdf_params(&self) -> &KdfParams;
+
+    /// Encrypt and write the vault
+    fn save(&self, vault: &Vault) -> Result<()>;
+
+    /// Wrap the key of a new attachment blob
+    fn wrap_file_key(&self, file_key: &MasterKey) -> Result<EncryptedData>;
+
+    /// Unwrap the key of an attachment the saved vault refers to
+    fn unwrap_file_key(&self, attachment: &Attachment) -> Result<MasterKey>;
 }
 
 /// Main vault manager
 pub struct VaultManager {
     crypto: CryptoManager,
     current_vault: Option<Vault>,
-    current_salt: Option<Salt>,
+    current_keys: Option<VaultKeys>,
+    /// Holder of the data key when the vault was opened without it
+    key_holder: Option<Box<dyn KeyHolder>>,
 }
 
 impl VaultManager {
@@ -160,18 +611,42 @@ impl VaultManager {
         Self {
             crypto: CryptoManager::new(),
             current_vault: None,
-            current_salt: None,
+            current_keys: None,
+            key_holder: None,
         }
     }
 
     /// Create a new vault at the specified path
     pub fn create_vault<P: AsRef<Path>>(&mut self, path: P, password: &str) -> Result<()> {
+        self.create_vault_with_params(path, password, KdfParams::default())
+    }
+
+    /// Create a new vault using explicit key derivation parameters
+    pub fn create_vault_with_params<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<()> {
         let salt = Salt::generate()?;
-        self.crypto.derive_key(password, &salt)?;
+        let kek = vault::derive_password_kek(password, &salt, &kdf_params)?;
+
+        // Vault contents are encrypted with a random data key
+        self.crypto.generate_master_key()?;
+        let key_slots = vec![KeySlot {
+            kind: KeySlotKind::Password,
+            wrapped_key: self.crypto.wrap_master_key(&kek)?,
+        }];
 
         let vault = Vault::new(path);
         self.current_vault = Some(vault);
-        self.current_salt = Some(salt);
+        self.current_keys = Some(VaultKeys {
+            salt,
+            kdf: kdf_params,
+            key_slots,
+            cipher: CipherSuite::default(),
+            hide_metadata: false,
+        });
         self.save_vault()?;
 
         Ok(())
@@ -179,24 +654,426 @@ impl VaultManager {
 
     /// Load an existing vault
     pub fn load_vault<P: AsRef<Path>>(&mut self, path: P, password: &str) -> Result<()> {
-        let (vault, salt) = vault::load_vault_with_salt(path, password, &mut self.crypto)?;
+        let (vault, keys) = vault::load_vault_with_salt(path, password, &mut self.crypto)?;
+        self.open_loaded_vault(vault, keys)
+    }
+
+    /// Load an existing vault using the key-encryption key of a non-password
+    /// unlock method, such as a recovery key
+    pub fn load_vault_with_key<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        kind: KeySlotKind,
+        kek: &MasterKey,
+    ) -> Result<()> {
+        let (vault, keys) = vault::load_vault_with_key(path, kind, kek, &mut self.crypto)?;
+        self.open_loaded_vault(vault, keys)
+    }
+
+    /// Load an existing vault with its data key, as the unlock agent does to
+    /// reopen the vault it holds
+    pub fn load_vault_with_data_key<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        data_key: &MasterKey,
+    ) -> Result<()> {
+        let (vault, keys) = vault::load_vault_with_data_key(path, data_key, &mut self.crypto)?;
+        self.open_loaded_vault(vault, keys)
+    }
+
+    /// Open decrypted vault contents whose data key stays with `key_holder`
+    ///
+    /// Operations that need the data key itself, such as changing unlock
+    /// methods, fail on a vault opened this way.
+    pub fn open_with_key_holder(&mut self, vault: Vault, key_holder: Box<dyn KeyHolder>) {
+        self.close_vault();
+        self.current_vault = Some(vault);
+        self.key_holder = Some(key_holder);
+    }
+
+    /// Reopen the current vault from its file with the data key already
+    /// held, picking up changes saved by other processes
+    pub fn reload_vault(&mut self) -> Result<()> {
+        let vault_path = self
+            .current_vault
+            .as_ref()
+            .map(|vault| vault.vault_path.clone())
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let data_key = self.data_key()?.clone();
+        self.load_vault_with_data_key(vault_path, &data_key)
+    }
+
+    /// Get the data key of the unlocked vault
+    fn data_key(&self) -> Result<&MasterKey> {
+        if self.current_vault.is_none() {
+            return Err(TwoPasswordError::storage("No vault is currently unlocked"));
+        }
+        self.crypto.key()
+    }
+
+    /// Wrap the key of a new attachment blob
+    pub(crate) fn wrap_file_key(&self, file_key: &MasterKey) -> Result<EncryptedData> {
+        match &self.key_holder {
+            Some(key_holder) => key_holder.wrap_file_key(file_key),
+            None => attachment::wrap_file_key(self.data_key()?, file_key),
+        }
+    }
+
+    /// Unwrap the key of an attachment of the current vault
+    pub(crate) fn unwrap_file_key(&self, attachment: &Attachment) -> Result<MasterKey> {
+        match &self.key_holder {
+            Some(key_holder) => key_holder.unwrap_file_key(attachment),
+            None => attachment::unwrap_file_key(self.data_key()?, &attachment.wrapped_key),
+        }
+    }
+
+    /// Keep a freshly unlocked vault, writing it back if it was migrated
+    /// while loading or expired trash or history was purged
+    fn open_loaded_vault(&mut self, mut vault: Vault, keys: VaultKeys) -> Result<()> {
+        let now = chrono::Utc::now();
+        let purged = vault.purge_trash(now);
+        if purged > 0 {
+            tracing::info!("Purged {} expired entries from the trash", purged);
+        }
+        let pruned = vault.prune_history(now);
+        if pruned > 0 {
+            tracing::info!("Dropped {} expired passwords from entry history", pruned);
+        }
+
+        for problem in attachment::check(&vault.vault_path, vault.attachments()) {
+            tracing::warn!("Integrity check failed: {}", problem);
+        }
+
+        let migrated = vault.is_modified;
         self.current_vault = Some(vault);
-        self.current_salt = Some(salt);
+        self.current_keys = Some(keys);
+        if migrated {
+            self.save_vault()?;
+        }
         Ok(())
     }
 
+    /// Re-derive the password key with new KDF parameters and a fresh salt,
+    /// rewrap the data key and save. The vault must already be unlocked, and
+    /// `password` must be its current master password.
+    pub fn update_kdf_params(&mut self, password: &str, kdf_params: KdfParams) -> Result<()> {
+        let keys = self
+            .current_keys
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        keys.verify_password(password)?;
+
+        let salt = Salt::generate()?;
+        let kek = vault::derive_password_kek(password, &salt, &kdf_params)?;
+        let wrapped_key = self.crypto.wrap_master_key(&kek)?;
+
+        let keys = self.unlocked_keys_mut()?;
+        keys.salt = salt;
+        keys.kdf = kdf_params;
+        keys.set_slot(KeySlot {
+            kind: KeySlotKind::Password,
+            wrapped_key,
+        });
+        self.save_vault()
+    }
+
+    /// Change the master password
+    ///
+    /// Verifies `old_password` against the password key slot, wraps the data
+    /// key under `new_password` with a fresh salt and saves atomically. The
+    /// previous vault file is kept as a backup until the new one has been
+    /// verified to open with the new password, and restored if it does not.
+    pub fn change_master_password(&mut self, old_password: &str, new_password: &str) -> Result<()> {
+        let vault = self
+            .current_vault
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let keys = self
+            .current_keys
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+
+        keys.verify_password(old_password)?;
+
+        // Rewrap the data key under the new password with a fresh salt
+        let salt = Salt::generate()?;
+        let new_kek = vault::derive_password_kek(new_password, &salt, &keys.kdf)?;
+        let mut new_keys = keys.clone();
+        new_keys.salt = salt;
+        new_keys.set_slot(KeySlot {
+            kind: KeySlotKind::Password,
+            wrapped_key: self.crypto.wrap_master_key(&new_kek)?,
+        });
+
+        let vault_path = vault.vault_path.clone();
+        let backup_path = if vault_path.exists() {
+            Some(vault::backup_vault(&vault_path)?)
+        } else {
+            None
+        };
+
+        let verified = vault::save_vault_with_salt(vault, &self.crypto, &new_keys).and_then(|()| {
+            let mut verify_crypto = CryptoManager::new();
+            vault::load_vault_with_salt(&vault_path, new_password, &mut verify_crypto).map(|_| ())
+        });
+
+        if let Err(e) = verified {
+            if let Some(ref backup_path) = backup_path {
+                std::fs::rename(backup_path, &vault_path).map_err(|restore_err| {
+                    TwoPasswordError::storage(format!(
+                        "Password change failed ({}) and the previous vault could not be \
+                         restored from {}: {}",
+                        e,
+                        backup_path.display(),
+                        restore_err
+                    ))
+                })?;
+            }
+            return Err(TwoPasswordError::storage(format!(
+                "Password change failed, previous vault restored: {}",
+                e
+            )));
+        }
+
+        if let Some(backup_path) = backup_path {
+            std::fs::remove_file(&backup_path).map_err(|e| {
+                TwoPasswordError::storage(format!("Failed to remove vault backup: {}", e))
+            })?;
+        }
+
+        self.current_keys = Some(new_keys);
+        if let Some(ref mut vault) = self.current_vault {
+            vault.is_modified = false;
+        }
+
+        tracing::info!("Master password changed for {}", vault_path.display());
+        Ok(())
+    }
+
+    /// Get the KDF parameters of the current vault
+    pub fn kdf_params(&self) -> Option<&KdfParams> {
+        match (&self.current_keys, &self.key_holder) {
+            (Some(keys), _) => Some(&keys.kdf),
+            (None, Some(key_holder)) => Some(key_holder.kdf_params()),
+            (None, None) => None,
+        }
+    }
+
+    /// Get the cipher suite of the current vault
+    pub fn cipher(&self) -> Option<CipherSuite> {
+        self.current_keys.as_ref().map(|keys| keys.cipher)
+    }
+
+    /// Re-encrypt the vault contents with another cipher suite and save
+    ///
+    /// The data key is kept, so every unlock method continues to work.
+    pub fn set_cipher(&mut self, cipher: CipherSuite) -> Result<()> {
+        self.unlocked_keys_mut()?.cipher = cipher;
+        self.save_vault()
+    }
+
+    /// Check whether the current vault hides its metadata and payload size
+    pub fn hide_metadata(&self) -> Option<bool> {
+        self.current_keys.as_ref().map(|keys| keys.hide_metadata)
+    }
+
+    /// Move the metadata into the padded payload, or back into the clear
+    /// header, and save
+    pub fn set_hide_metadata(&mut self, hide_metadata: bool) -> Result<()> {
+        self.unlocked_keys_mut()?.hide_metadata = hide_metadata;
+        self.save_vault()
+    }
+
+    /// Get the key slots of the current vault
+    pub fn key_slots(&self) -> &[KeySlot] {
+        self.current_keys
+            .as_ref()
+            .map(|keys| keys.key_slots.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Wrap the data key for an additional unlock method and save
+    pub fn add_key_slot(&mut self, kind: KeySlotKind, kek: &MasterKey) -> Result<()> {
+        if kind == KeySlotKind::Password {
+            return Err(TwoPasswordError::validation(
+                "The password key slot is managed through the master password",
+            ));
+        }
+
+        let wrapped_key = self.crypto.wrap_master_key(kek)?;
+        self.unlocked_keys_mut()?
+            .set_slot(KeySlot { kind, wrapped_key });
+        self.save_vault()
+    }
+
+    /// Remove an unlock method and save
+    pub fn remove_key_slot(&mut self, kind: KeySlotKind) -> Result<()> {
+        if kind == KeySlotKind::Password {
+            return Err(TwoPasswordError::validation(
+                "The password key slot cannot be removed",
+            ));
+        }
+
+        let keys = self.unlocked_keys_mut()?;
+        if keys.slot(kind).is_none() {
+            return Err(TwoPasswordError::validation(format!(
+                "Vault has no {} key slot",
+                kind
+            )));
+        }
+        keys.key_slots.retain(|slot| slot.kind != kind);
+        self.save_vault()
+    }
+
+    /// Encrypt what `reader` yields into a blob next to the vault, attach it
+    /// to an entry under `name` and save
+    pub fn attach_file(&mut self, id: &Uuid, name: &str, reader: impl Read) -> Result<Attachment> {
+        let mut entry = self
+            .current_vault
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?
+            .get_entry(id)
+            .cloned()
+            .ok_or_else(|| TwoPasswordError::EntryNotFound(id.to_string()))?;
+        attachment::validate_name(&entry.attachments, name)?;
+
+        let file_key = MasterKey::generate()?;
+        let wrapped_key = self.wrap_file_key(&file_key)?;
+        let vault = self
+            .current_vault
+            .as_mut()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let attachment =
+            attachment::store(&vault.vault_path, &file_key, wrapped_key, name, reader)?;
+        entry.attachments.push(attachment.clone());
+        vault.update_entry(entry)?;
+        if let Err(e) = self.save_vault() {
+            if let Some(vault) = self.current_vault.as_mut() {
+                if let Some(entry) = vault.entries.get_mut(id) {
+                    entry.attachments.retain(|kept| kept.id != attachment.id);
+                }
+                let _ =
+                    std::fs::remove_file(attachment::blob_path(&vault.vault_path, &attachment.id));
+            }
+            return Err(e);
+        }
+        Ok(attachment)
+    }
+
+    /// Remove an attachment from an entry and save, deleting its blob
+    pub fn detach_file(&mut self, id: &Uuid, attachment_id: &Uuid) -> Result<Attachment> {
+        let vault = self
+            .current_vault
+            .as_mut()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let mut entry = vault
+            .get_entry(id)
+            .cloned()
+            .ok_or_else(|| TwoPasswordError::EntryNotFound(id.to_string()))?;
+        let position = entry
+            .attachments
+            .iter()
+            .position(|attachment| attachment.id == *attachment_id)
+            .ok_or_else(|| {
+                TwoPasswordError::validation(format!(
+                    "'{}' has no attachment {}",
+                    entry.title, attachment_id
+                ))
+            })?;
+
+        let attachment = entry.attachments.remove(position);
+        vault.update_entry(entry)?;
+        vault.dropped_attachments.push(attachment.id);
+        self.save_vault()?;
+        Ok(attachment)
+    }
+
+    /// Decrypt an attachment of the current vault to `writer`, verifying it
+    /// against the size and digest recorded when it was attached
+    pub fn extract_file(&self, attachment: &Attachment, writer: impl Write) -> Result<()> {
+        let vault = self
+            .current_vault
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let file_key = self.unwrap_file_key(attachment)?;
+        attachment::extract(&vault.vault_path, &file_key, attachment, writer)
+    }
+
+    /// Check that the blob of every attachment exists and is intact in size
+    pub fn check_attachments(&self) -> Vec<AttachmentProblem> {
+        self.current_vault
+            .as_ref()
+            .map(|vault| attachment::check(&vault.vault_path, vault.attachments()))
+            .unwrap_or_default()
+    }
+
     /// Save the current vault
+    ///
+    /// Blobs of the attachments the vault dropped, by detaching them or
+    /// purging their entries, are deleted once the vault is saved. Other
+    /// blobs are left alone, since another copy of the vault, such as a
+    /// backup, may still refer to them.
     pub fn save_vault(&mut self) -> Result<()> {
-        if let (Some(ref vault), Some(ref salt)) = (&self.current_vault, &self.current_salt) {
-            vault::save_vault_with_salt(vault, &self.crypto, salt)?;
-            // Mark as saved
-            if let Some(ref mut vault) = self.current_vault {
-                vault.is_modified = false;
+        if let (Some(ref vault), Some(key_holder)) = (&self.current_vault, &self.key_holder) {
+            key_holder.save(vault)?;
+        } else if let (Some(ref vault), Some(ref keys)) = (&self.current_vault, &self.current_keys)
+        {
+            vault::save_vault_with_salt(vault, &self.crypto, keys)?;
+        } else {
+            return Ok(());
+        }
+
+        // Mark as saved
+        if let Some(ref mut vault) = self.current_vault {
+            vault.is_modified = false;
+            for id in std::mem::take(&mut vault.dropped_attachments) {
+                if let Err(e) = attachment::remove_blob(&vault.vault_path, &id) {
+                    tracing::warn!("Failed to delete attachment {}: {}", id, e);
+                }
             }
         }
         Ok(())
     }
 
+    /// Find blobs next to the vault that no entry of the current vault,
+    /// trashed or not, refers to
+    pub fn orphaned_attachments(&self) -> Result<Vec<Uuid>> {
+        let vault = self
+            .current_vault
+            .as_ref()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))?;
+        let referenced: HashSet<Uuid> = vault
+            .attachments()
+            .map(|(_, attachment)| attachment.id)
+            .collect();
+        attachment::unreferenced(&vault.vault_path, &referenced)
+    }
+
+    /// Delete the blobs [`orphaned_attachments`](Self::orphaned_attachments)
+    /// finds, returning how many were deleted
+    ///
+    /// Blobs only referred to by other copies of the vault, such as backups,
+    /// are lost.
+    pub fn remove_orphaned_attachments(&self) -> Result<usize> {
+        let orphaned = self.orphaned_attachments()?;
+        if let Some(vault) = self.current_vault.as_ref() {
+            for id in &orphaned {
+                attachment::remove_blob(&vault.vault_path, id)?;
+            }
+        }
+        Ok(orphaned.len())
+    }
+
+    fn unlocked_keys_mut(&mut self) -> Result<&mut VaultKeys> {
+        if self.current_vault.is_none() {
+            return Err(TwoPasswordError::storage("No vault is currently unlocked"));
+        }
+
+        self.current_keys
+            .as_mut()
+            .ok_or_else(|| TwoPasswordError::storage("No vault is currently unlocked"))
+    }
+
     /// Get the current vault
     pub fn get_vault(&self) -> Option<&Vault> {
         self.current_vault.as_ref()
@@ -215,7 +1092,8 @@ impl VaultManager {
     /// Close the current vault
     pub fn close_vault(&mut self) {
         self.current_vault = None;
-        self.current_salt = None;
+        self.current_keys = None;
+        self.key_holder = None;
         self.crypto.clear_master_key();
     }
 }
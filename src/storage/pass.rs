@@ -0,0 +1,577 @@
+//! Stores of `pass`, the standard Unix password manager
+//!
+//! A store is a directory tree with one file per entry. The first line of
+//! a file is the password; `key: value` lines for the username, URL and
+//! tags may follow, along with an `otpauth://` URI as written by pass-otp,
+//! and everything after them is kept as the notes. Fields of entry kinds
+//! other than logins, such as card numbers, and custom fields are written
+//! after the notes as `field.Label: value` lines, which are never taken for
+//! the username or URL, and read back as part of the notes:
+//!
+//! ```text
+//! hunter2
+//! username: octocat
+//! url: https://github.com
+//! tags: dev, work
+//! otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub
+//! Recovery codes are in the safe
+//! ```
+//!
+//! Entries encrypted with GnuPG end in `.gpg`, as `pass` writes them, and
+//! are read and written by running `gpg`. Trees of unencrypted files are
+//! supported too. Directories become tags holding the directory path, e.g.
+//! `Work/Servers`; exported entries are written to the top of the tree with
+//! all of their tags on the `tags:` line.
+
+use crate::crypto::otp::{self, Otp};
+use crate::crypto::SecretString;
+use crate::storage::export::write_private;
+use crate::storage::import::{non_empty, split_tags, ParsedImport};
+use crate::storage::PasswordEntry;
+use crate::{Result, TwoPasswordError};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// Description shown when importing a store
+pub const DESCRIPTION: &str = "pass password store";
+
+/// Environment variable naming the gpg program to run
+pub const GPG_ENV: &str = "TWOPASSWORD_GPG";
+
+/// Extension of entries encrypted with GnuPG
+pub const GPG_EXTENSION: &str = "gpg";
+
+/// Largest unencrypted entry file accepted
+const MAX_ENTRY_SIZE: u64 = 1024 * 1024;
+
+/// Keys of the lines read into entry fields, each with its aliases
+const USERNAME_KEYS: [&str; 4] = ["username", "user", "login", "email"];
+const URL_KEYS: [&str; 3] = ["url", "website", "site"];
+const TAGS_KEY: &str = "tags";
+
+/// Prefix of the lines holding kind details and custom fields
+const FIELD_PREFIX: &str = "field.";
+
+/// The gpg program used for encrypted stores
+pub struct Gpg {
+    program: OsString,
+    passphrase: Option<SecretString>,
+}
+
+impl Gpg {
+    /// Run `$TWOPASSWORD_GPG`, or else `gpg` from the `PATH`
+    ///
+    /// With a passphrase, gpg runs in loopback mode: symmetrically encrypted
+    /// files are decrypted with it, and files encrypted to a key use it to
+    /// unlock the secret key. Without one, gpg-agent asks for what it needs.
+    pub fn new(passphrase: Option<SecretString>) -> Self {
+        Self {
+            program: std::env::var_os(GPG_ENV).unwrap_or_else(|| "gpg".into()),
+            passphrase,
+        }
+    }
+
+    /// Decrypt an entry file
+    pub fn decrypt(&self, path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+        let mut command = self.command();
+        command.arg("--decrypt").arg(path);
+        self.run(command, &[], &format!("decrypt {}", path.display()))
+    }
+
+    /// Encrypt an entry with the passphrase, returning the encrypted file
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if self.passphrase.is_none() {
+            return Err(TwoPasswordError::validation(
+                "Encrypted pass stores need an export passphrase",
+            ));
+        }
+        let mut command = self.command();
+        command.args(["--symmetric", "--cipher-algo", "AES256", "--output", "-"]);
+        self.run(command, plaintext, "encrypt an entry")
+            .map(|ciphertext| ciphertext.to_vec())
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        // Keep the passphrase out of gpg-agent's cache
+        command.args(["--quiet", "--batch", "--yes", "--no-symkey-cache"]);
+        if self.passphrase.is_some() {
+            command.args(["--pinentry-mode", "loopback", "--passphrase-fd", "0"]);
+        }
+        command
+    }
+
+    /// Run gpg, writing the passphrase line and then `input` to its stdin
+    fn run(&self, mut command: Command, input: &[u8], action: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                TwoPasswordError::storage(format!(
+                    "Failed to run {}: {}; encrypted pass stores need GnuPG",
+                    self.program.to_string_lossy(),
+                    e
+                ))
+            })?;
+
+        let mut data = Zeroizing::new(Vec::new());
+        if let Some(passphrase) = &self.passphrase {
+            data.extend_from_slice(passphrase.expose().as_bytes());
+            data.push(b'\n');
+        }
+        data.extend_from_slice(input);
+        // Written from another thread so gpg's output cannot fill its pipe
+        // while gpg waits for input
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let writer = std::thread::spawn(move || {
+            // gpg may exit before reading everything; its status says why
+            let _ = stdin.write_all(&data);
+        });
+
+        let output = child.wait_with_output()?;
+        let _ = writer.join();
+        let stdout = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .map(|line| line.trim().trim_start_matches("gpg: ").to_string())
+                .unwrap_or_else(|| output.status.to_string());
+            return Err(TwoPasswordError::crypto(format!(
+                "gpg could not {}: {}",
+                action, message
+            )));
+        }
+        Ok(stdout)
+    }
+}
+
+/// Check whether any entry in a store is encrypted
+pub fn is_encrypted_store(root: &Path) -> Result<bool> {
+    Ok(store_files(root)?.iter().any(|path| is_encrypted(path)))
+}
+
+/// Read every entry of a store
+///
+/// Unreadable entry files are reported as errors, but a file gpg cannot
+/// decrypt fails the whole import, as it almost always means the wrong
+/// passphrase or key.
+pub fn read_store(root: &Path, gpg: &Gpg) -> Result<ParsedImport> {
+    let mut parsed = ParsedImport::default();
+    for path in store_files(root)? {
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let location = relative.display().to_string();
+
+        let contents = if is_encrypted(&path) {
+            gpg.decrypt(&path)?
+        } else {
+            match read_plaintext(&path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    parsed.push(location, Err(e));
+                    continue;
+                }
+            }
+        };
+
+        let entry = std::str::from_utf8(&contents)
+            .map_err(|_| TwoPasswordError::validation("Entry is not UTF-8 text"))
+            .map(|text| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let name = name
+                    .strip_suffix(&format!(".{}", GPG_EXTENSION))
+                    .unwrap_or(&name);
+                let folders: Vec<String> = relative
+                    .parent()
+                    .into_iter()
+                    .flat_map(Path::components)
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+
+                let mut entry = parse_entry(name, &folders.join("/"), text);
+                if let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) {
+                    entry.created_at = modified.into();
+                    entry.updated_at = modified.into();
+                }
+                entry
+            });
+        parsed.push(location, entry);
+    }
+    Ok(parsed)
+}
+
+/// Write entries to a new store, returning how many were written
+///
+/// `root` must not exist or be empty. With `gpg`, entries are encrypted
+/// with its passphrase; otherwise they are written in plaintext. Entry
+/// files get their title as name, with a number added to repeated titles.
+/// The store is written next to `root` and only moved there once complete,
+/// so a failed export leaves nothing behind.
+pub fn write_store(root: &Path, entries: &[PasswordEntry], gpg: Option<&Gpg>) -> Result<usize> {
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| entry.password.expose().contains(['\n', '\r']))
+    {
+        return Err(TwoPasswordError::validation(format!(
+            "The password of '{}' has a line break, which pass cannot store",
+            entry.title
+        )));
+    }
+    if root.exists() && std::fs::read_dir(root)?.next().is_some() {
+        return Err(TwoPasswordError::validation(format!(
+            "{} is not empty",
+            root.display()
+        )));
+    }
+    let parent = match root.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(parent)?;
+    // Only the owner can enter the directory, as with `pass` itself
+    let staging = tempfile::Builder::new()
+        .prefix(".twopassword-export-")
+        .tempdir_in(parent)
+        .map_err(|e| {
+            TwoPasswordError::storage(format!("Failed to write to {}: {}", parent.display(), e))
+        })?;
+
+    let mut names = HashSet::new();
+    for entry in entries {
+        let base = file_name(&entry.title);
+        let name = (1..)
+            .map(|n| match n {
+                1 => base.clone(),
+                n => format!("{} ({})", base, n),
+            })
+            .find(|name| names.insert(name.to_lowercase()))
+            .expect("some name is unused");
+
+        let contents = format_entry(entry);
+        match gpg {
+            Some(gpg) => write_private(
+                &staging.path().join(format!("{}.{}", name, GPG_EXTENSION)),
+                &gpg.encrypt(contents.as_bytes())?,
+            )?,
+            None => write_private(&staging.path().join(name), contents.as_bytes())?,
+        }
+    }
+
+    if root.exists() {
+        std::fs::remove_dir(root)?;
+    }
+    std::fs::rename(staging.path(), root).map_err(|e| {
+        TwoPasswordError::storage(format!("Failed to write {}: {}", root.display(), e))
+    })?;
+    Ok(entries.len())
+}
+
+/// Read an entry from the contents of its file
+///
+/// `folder` is the entry's directory below the store root, which becomes
+/// its first tag.
+pub fn parse_entry(name: &str, folder: &str, contents: &str) -> PasswordEntry {
+    let mut lines = contents.lines();
+    let mut entry = PasswordEntry::new(
+        name.to_string(),
+        String::new(),
+        lines.next().unwrap_or_default(),
+    );
+    let mut tags = folder.to_string();
+
+    // Field lines directly follow the password; the first other line
+    // starts the notes
+    let mut lines = lines.peekable();
+    while let Some(line) = lines.peek() {
+        let is_uri = line.starts_with(&format!("{}://", otp::URI_SCHEME));
+        if is_uri && entry.otp.is_none() {
+            match Otp::from_uri(line) {
+                Ok(otp) => entry.otp = Some(otp),
+                Err(_) => break,
+            }
+            lines.next();
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            break;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        if USERNAME_KEYS.contains(&key.as_str()) && entry.username.is_empty() {
+            entry.username = value.to_string();
+        } else if URL_KEYS.contains(&key.as_str()) && entry.url.is_none() {
+            entry.url = non_empty(value);
+        } else if key == TAGS_KEY {
+            tags = format!("{},{}", tags, value);
+        } else {
+            break;
+        }
+        lines.next();
+    }
+
+    entry.tags = split_tags(&tags, &[',']);
+    entry.notes = non_empty(&lines.collect::<Vec<_>>().join("\n"));
+    entry
+}
+
+/// Write an entry in the format read by [`parse_entry`]
+pub fn format_entry(entry: &PasswordEntry) -> Zeroizing<String> {
+    let mut contents = Zeroizing::new(format!("{}\n", entry.password.expose()));
+    if !entry.username.is_empty() {
+        contents.push_str(&format!("username: {}\n", entry.username));
+    }
+    if let Some(url) = &entry.url {
+        contents.push_str(&format!("url: {}\n", url));
+    }
+    if !entry.tags.is_empty() {
+        contents.push_str(&format!("{}: {}\n", TAGS_KEY, entry.tags.join(", ")));
+    }
+    if let Some(otp) = &entry.otp {
+        contents.push_str(otp.to_uri().expose());
+        contents.push('\n');
+    }
+    if let Some(notes) = &entry.notes {
+        contents.push_str(notes);
+        contents.push('\n');
+    }
+    let custom_fields = entry
+        .fields
+        .iter()
+        .map(|custom| (custom.name.as_str(), custom.value.expose()));
+    for (label, value) in entry
+        .kind
+        .text_fields()
+        .iter()
+        .map(|(label, value, _)| (*label, value.as_str()))
+        .chain(custom_fields)
+    {
+        contents.push_str(FIELD_PREFIX);
+        contents.push_str(label);
+        contents.push_str(": ");
+        contents.push_str(value);
+        contents.push('\n');
+    }
+    contents
+}
+
+fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == GPG_EXTENSION)
+}
+
+/// Entry files of a store, sorted by path
+///
+/// Hidden files and directories, such as `.gpg-id` and `.git`, and
+/// symbolic links are skipped.
+fn store_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut directories = vec![root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for item in std::fs::read_dir(&directory)? {
+            let item = item?;
+            if item.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let file_type = item.file_type()?;
+            if file_type.is_dir() {
+                directories.push(item.path());
+            } else if file_type.is_file() {
+                files.push(item.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_plaintext(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    let mut contents = Zeroizing::new(Vec::new());
+    std::fs::File::open(path)?
+        .take(MAX_ENTRY_SIZE + 1)
+        .read_to_end(&mut contents)?;
+    if contents.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(TwoPasswordError::validation("Entry file is too large"));
+    }
+    Ok(contents)
+}
+
+/// File name for an entry title, without path separators or a leading dot
+fn file_name(title: &str) -> String {
+    let name = title.trim().replace(['/', '\0'], "-");
+    match name.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None if name.is_empty() => "entry".to_string(),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_entries() -> Vec<PasswordEntry> {
+        let mut github = PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "hunter2: with colon",
+        );
+        github.url = Some("https://github.com".to_string());
+        github.tags = vec!["Work/Dev".to_string(), "personal".to_string()];
+        github.notes = Some("PIN: 1234\n\nRecovery codes are in the safe".to_string());
+        github.otp = Some(Otp::totp("JBSWY3DPEHPK3PXP").unwrap());
+
+        let same_title = PasswordEntry::new("GitHub".to_string(), "ci".to_string(), "t0ken");
+        let hidden = PasswordEntry::new(".ssh/config".to_string(), String::new(), "x");
+        vec![github, same_title, hidden]
+    }
+
+    fn assert_same_entries(imported: &ParsedImport, entries: &[PasswordEntry]) {
+        assert!(imported.errors.is_empty(), "{:?}", imported.errors);
+        let mut imported: Vec<&PasswordEntry> = imported
+            .records
+            .iter()
+            .map(|record| &record.entry)
+            .collect();
+        imported.sort_by_key(|entry| entry.username.clone());
+        let mut entries: Vec<&PasswordEntry> = entries.iter().collect();
+        entries.sort_by_key(|entry| entry.username.clone());
+
+        for (imported, original) in imported.iter().zip(&entries) {
+            assert_eq!(imported.username, original.username);
+            assert_eq!(imported.password, original.password);
+            assert_eq!(imported.url, original.url);
+            assert_eq!(imported.notes, original.notes);
+            assert_eq!(imported.tags, original.tags);
+            assert_eq!(imported.otp, original.otp);
+        }
+        assert_eq!(imported.len(), entries.len());
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let contents = "hunter2\nlogin: octocat\nURL: https://github.com\n\
+                        otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP\ntags: dev, work\n\
+                        Backup codes:\nurl: not a field\n";
+        let entry = parse_entry("github.com", "Work/Servers", contents);
+
+        assert_eq!(entry.title, "github.com");
+        assert_eq!(entry.password.expose(), "hunter2");
+        assert_eq!(entry.username, "octocat");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com"));
+        assert_eq!(entry.tags, ["Work/Servers", "dev", "work"]);
+        assert_eq!(entry.otp.unwrap().account.as_deref(), Some("GitHub"));
+        assert_eq!(
+            entry.notes.as_deref(),
+            Some("Backup codes:\nurl: not a field")
+        );
+
+        let bare = parse_entry("wifi", "", "correct horse");
+        assert_eq!(bare.password.expose(), "correct horse");
+        assert!(bare.tags.is_empty() && bare.notes.is_none());
+    }
+
+    #[test]
+    fn test_fields_roundtrip_as_notes() {
+        use crate::storage::field::{CustomField, FieldType};
+
+        // Without a username, URL or notes the field lines directly follow
+        // the password, where unprefixed they would be read as those
+        let mut entry = PasswordEntry::new("Mail".to_string(), String::new(), "hunter2");
+        entry.fields = vec![
+            CustomField::new("Email", FieldType::Email, "me@example.com"),
+            CustomField::new("URL", FieldType::Url, "https://example.com"),
+            CustomField::new("login", FieldType::Text, "backup"),
+        ];
+
+        let contents = format_entry(&entry);
+        let parsed = parse_entry("Mail", "", &contents);
+        assert_eq!(parsed.password.expose(), "hunter2");
+        assert!(parsed.username.is_empty());
+        assert_eq!(parsed.url, None);
+        assert_eq!(
+            parsed.notes.as_deref(),
+            Some(
+                "field.Email: me@example.com\nfield.URL: https://example.com\n\
+                 field.login: backup"
+            )
+        );
+        assert_eq!(
+            format_entry(&parsed).as_str(),
+            format!("hunter2\n{}\n", parsed.notes.unwrap())
+        );
+    }
+
+    #[test]
+    fn test_plaintext_store_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("store");
+        let entries = sample_entries();
+
+        assert_eq!(write_store(&root, &entries, None).unwrap(), 3);
+        assert!(root.join("GitHub").is_file());
+        assert!(root.join("GitHub (2)").is_file());
+        assert!(root.join("_ssh-config").is_file());
+        assert!(!is_encrypted_store(&root).unwrap());
+        assert!(write_store(&root, &entries, None).is_err());
+
+        // Directories become tags, and pass's own files are skipped
+        std::fs::create_dir_all(root.join("Work/Servers")).unwrap();
+        std::fs::write(root.join("Work/Servers/db01"), "s3cret\nuser: admin\n").unwrap();
+        std::fs::write(root.join(".gpg-id"), "ABCDEF\n").unwrap();
+
+        let parsed = read_store(&root, &Gpg::new(None)).unwrap();
+        let db01 = parsed
+            .records
+            .iter()
+            .find(|record| record.location == "Work/Servers/db01")
+            .unwrap();
+        assert_eq!(db01.entry.tags, ["Work/Servers"]);
+        assert_eq!(db01.entry.username, "admin");
+
+        let multiline = PasswordEntry::new("Multiline".to_string(), String::new(), "a\nb");
+        let empty = temp_dir.path().join("empty");
+        assert!(write_store(&empty, &[multiline], None).is_err());
+
+        // A failed export leaves neither the store nor its staging directory
+        let failed = temp_dir.path().join("failed");
+        assert!(write_store(&failed, &entries, Some(&Gpg::new(None))).is_err());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        let parsed = read_store(&root, &Gpg::new(None)).unwrap();
+        let ParsedImport { records, errors } = parsed;
+        let records = records
+            .into_iter()
+            .filter(|record| !record.location.starts_with("Work"))
+            .collect();
+        assert_same_entries(&ParsedImport { records, errors }, &entries);
+    }
+
+    #[test]
+    #[ignore = "requires gpg"]
+    fn test_encrypted_store_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("store");
+        let entries = sample_entries();
+        let gpg = Gpg::new(Some(SecretString::from("export passphrase")));
+
+        write_store(&root, &entries, Some(&gpg)).unwrap();
+        assert!(root.join("GitHub.gpg").is_file());
+        assert!(is_encrypted_store(&root).unwrap());
+        let raw = std::fs::read(root.join("GitHub.gpg")).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("octocat"));
+
+        assert_same_entries(&read_store(&root, &gpg).unwrap(), &entries);
+
+        let wrong = Gpg::new(Some(SecretString::from("wrong passphrase")));
+        assert!(read_store(&root, &wrong).is_err());
+    }
+}
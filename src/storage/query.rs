@@ -0,0 +1,757 @@
+//! Search queries
+//!
+//! `get` and `list` take queries such as
+//! `tag:work url:github.com user:ci -tag:old created:>2025-01-01 has:totp`.
+//! Terms separated by spaces must all match; `OR` between terms matches
+//! either, `-` or `NOT` before a term negates it, and parentheses group
+//! terms. A word without a field prefix matches the title, username, URL,
+//! notes, tags or custom fields of an entry, as does a word with a `:` that
+//! does not follow a field name, such as `https://github.com`. Matching
+//! ignores case, and values with spaces go in double quotes, as in
+//! `title:"my bank"`.
+//!
+//! | Term                 | Matches entries                                     |
+//! |----------------------|-----------------------------------------------------|
+//! | `title:T`            | whose title contains T                              |
+//! | `user:T`             | whose username contains T                           |
+//! | `url:T`              | whose URL contains T                                |
+//! | `notes:T`            | whose notes contain T                               |
+//! | `tag:T`              | tagged T or a tag nested below it                   |
+//! | `kind:K`             | of kind K, e.g. `card` or `ssh-key`                 |
+//! | `field:N`            | with a custom field named N                         |
+//! | `field:N=T`          | whose custom field N contains T                     |
+//! | `has:P`              | with an OTP, URL, notes, tags, fields, attachments, |
+//! |                      | history or username                                 |
+//! | `created:D`          | created on date D (YYYY-MM-DD, UTC), or before or   |
+//! | `updated:D`          | after it with `<`, `<=`, `>` or `>=` before D       |
+//!
+//! Concealed custom fields are never searched by value, so a query cannot
+//! be used to probe secrets.
+
+use crate::storage::kind::KIND_NAMES;
+use crate::storage::{tag, PasswordEntry};
+use crate::{Result, TwoPasswordError};
+use chrono::NaiveDate;
+
+/// Field prefixes understood in queries
+pub const QUERY_FIELDS: [&str; 10] = [
+    "title", "user", "url", "notes", "tag", "kind", "field", "has", "created", "updated",
+];
+
+/// Other spellings of field prefixes
+const FIELD_ALIASES: [&str; 2] = ["username", "note"];
+
+/// Properties understood by `has:`
+pub const HAS_PROPERTIES: [&str; 8] = [
+    "otp",
+    "url",
+    "notes",
+    "tags",
+    "fields",
+    "attachments",
+    "history",
+    "username",
+];
+
+/// A parsed search query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// Every query matches; an empty `And` matches everything
+    And(Vec<Query>),
+    /// Any query matches
+    Or(Vec<Query>),
+    /// The query does not match
+    Not(Box<Query>),
+    Term(Term),
+}
+
+/// A single condition on an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    /// Text anywhere in the entry's searchable fields
+    Text(String),
+    Title(String),
+    Username(String),
+    Url(String),
+    Notes(String),
+    /// Tag or a tag nested below it
+    Tag(String),
+    /// Entry kind, by its command line name
+    Kind(String),
+    /// Custom field with this name, and optionally a value containing text
+    Field {
+        name: String,
+        value: Option<String>,
+    },
+    Has(Property),
+    Created(Comparison, NaiveDate),
+    Updated(Comparison, NaiveDate),
+}
+
+/// Something an entry may or may not have, for `has:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Otp,
+    Url,
+    Notes,
+    Tags,
+    Fields,
+    Attachments,
+    History,
+    Username,
+}
+
+/// How a date is compared in `created:` and `updated:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Before,
+    OnOrBefore,
+    On,
+    OnOrAfter,
+    After,
+}
+
+impl Comparison {
+    fn holds(&self, date: NaiveDate, bound: NaiveDate) -> bool {
+        match self {
+            Comparison::Before => date < bound,
+            Comparison::OnOrBefore => date <= bound,
+            Comparison::On => date == bound,
+            Comparison::OnOrAfter => date >= bound,
+            Comparison::After => date > bound,
+        }
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = TwoPasswordError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse(s)
+    }
+}
+
+impl Query {
+    /// Check whether an entry matches the query
+    pub fn matches(&self, entry: &PasswordEntry) -> bool {
+        match self {
+            Query::And(queries) => queries.iter().all(|query| query.matches(entry)),
+            Query::Or(queries) => queries.iter().any(|query| query.matches(entry)),
+            Query::Not(query) => !query.matches(entry),
+            Query::Term(term) => term.matches(entry),
+        }
+    }
+
+    /// Free text the query looks for, for ranking results; words under a
+    /// negation are left out
+    pub fn text(&self) -> Option<String> {
+        fn collect(query: &Query, words: &mut Vec<String>) {
+            match query {
+                Query::And(queries) | Query::Or(queries) => {
+                    queries.iter().for_each(|query| collect(query, words))
+                }
+                Query::Not(_) => {}
+                Query::Term(Term::Text(text)) => words.push(text.clone()),
+                Query::Term(_) => {}
+            }
+        }
+
+        let mut words = Vec::new();
+        collect(self, &mut words);
+        Some(words.join(" ")).filter(|text| !text.is_empty())
+    }
+}
+
+impl Term {
+    /// Check whether an entry meets the condition
+    pub fn matches(&self, entry: &PasswordEntry) -> bool {
+        match self {
+            Term::Text(text) => {
+                contains(&entry.title, text)
+                    || contains(&entry.username, text)
+                    || entry.url.as_deref().is_some_and(|url| contains(url, text))
+                    || entry
+                        .notes
+                        .as_deref()
+                        .is_some_and(|notes| contains(notes, text))
+                    || entry.tags.iter().any(|tag| contains(tag, text))
+                    || entry.fields.iter().any(|custom| {
+                        contains(&custom.name, text)
+                            || (!custom.field_type.is_concealed()
+                                && contains(custom.value.expose(), text))
+                    })
+            }
+            Term::Title(text) => contains(&entry.title, text),
+            Term::Username(text) => contains(&entry.username, text),
+            Term::Url(text) => entry.url.as_deref().is_some_and(|url| contains(url, text)),
+            Term::Notes(text) => entry
+                .notes
+                .as_deref()
+                .is_some_and(|notes| contains(notes, text)),
+            Term::Tag(wanted) => entry.tags.iter().any(|t| tag::is_within(t, wanted)),
+            Term::Kind(name) => entry.kind.name() == name,
+            Term::Field { name, value } => entry.fields.iter().any(|custom| {
+                custom.name.to_lowercase() == name.to_lowercase()
+                    && value.as_ref().map_or(true, |value| {
+                        !custom.field_type.is_concealed() && contains(custom.value.expose(), value)
+                    })
+            }),
+            Term::Has(property) => match property {
+                Property::Otp => entry.otp.is_some(),
+                Property::Url => entry.url.is_some(),
+                Property::Notes => entry.notes.is_some(),
+                Property::Tags => !entry.tags.is_empty(),
+                Property::Fields => !entry.fields.is_empty(),
+                Property::Attachments => !entry.attachments.is_empty(),
+                Property::History => !entry.history.is_empty(),
+                Property::Username => !entry.username.is_empty(),
+            },
+            Term::Created(comparison, date) => {
+                comparison.holds(entry.created_at.date_naive(), *date)
+            }
+            Term::Updated(comparison, date) => {
+                comparison.holds(entry.updated_at.date_naive(), *date)
+            }
+        }
+    }
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Parse a query; an empty query matches every entry
+pub fn parse(input: &str) -> Result<Query> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        end: input.chars().count() + 1,
+    };
+    if parser.tokens.is_empty() {
+        return Ok(Query::And(Vec::new()));
+    }
+
+    let query = parser.parse_or()?;
+    match parser.next() {
+        None => Ok(query),
+        Some((Token::RParen, column)) => {
+            Err(error(column, "unexpected ')' without a matching '('"))
+        }
+        Some((_, column)) => Err(error(column, "unexpected input")),
+    }
+}
+
+/// Join command-line arguments into a query
+///
+/// The shell has already removed the quotes around an argument with spaces,
+/// so such an argument is quoted again to stay one term: `"my bank"`, or
+/// `title:"my bank"` after a field name. Arguments holding quotes of their
+/// own are kept as written.
+pub fn from_args(args: &[String]) -> String {
+    let quote = |arg: &String| {
+        if !arg.contains(char::is_whitespace) || arg.contains('"') {
+            return arg.clone();
+        }
+        let (field, value) = match arg.split_once(':') {
+            Some((name, value)) if is_field(&name.to_lowercase()) => (&arg[..=name.len()], value),
+            _ => ("", arg.as_str()),
+        };
+        format!("{}\"{}\"", field, value.replace('\\', "\\\\"))
+    };
+    args.iter().map(quote).collect::<Vec<_>>().join(" ")
+}
+
+fn error(column: usize, message: impl std::fmt::Display) -> TwoPasswordError {
+    TwoPasswordError::validation(format!("Invalid query at column {}: {}", column, message))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    /// A word, or a field and its value
+    Term {
+        field: Option<String>,
+        value: String,
+    },
+}
+
+/// Split a query into tokens, each with the 1-based column it starts at
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((Token::LParen, column));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((Token::RParen, column));
+                i += 1;
+            }
+            '-' if chars.get(i + 1).is_some_and(|next| !next.is_whitespace()) => {
+                tokens.push((Token::Not, column));
+                i += 1;
+            }
+            '"' => {
+                let (value, next) = read_quoted(&chars, i)?;
+                tokens.push((Token::Term { field: None, value }, column));
+                i = next;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_boundary(chars[i]) && chars[i] != ':' {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect::<String>().to_lowercase();
+
+                if chars.get(i) == Some(&':') && is_field(&name) {
+                    i += 1;
+                    let value = if chars.get(i) == Some(&'"') {
+                        let (value, next) = read_quoted(&chars, i)?;
+                        i = next;
+                        value
+                    } else {
+                        let value_start = i;
+                        while i < chars.len() && !is_boundary(chars[i]) {
+                            i += 1;
+                        }
+                        chars[value_start..i].iter().collect()
+                    };
+                    tokens.push((
+                        Token::Term {
+                            field: Some(name),
+                            value,
+                        },
+                        column,
+                    ));
+                    continue;
+                }
+
+                // Any other word with a ':', such as a URL, is text
+                while i < chars.len() && !is_boundary(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term {
+                        field: None,
+                        value: word,
+                    },
+                };
+                tokens.push((token, column));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_field(name: &str) -> bool {
+    QUERY_FIELDS.contains(&name) || FIELD_ALIASES.contains(&name)
+}
+
+fn is_boundary(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"'
+}
+
+/// Read a double-quoted string starting at `start`, returning it and the
+/// index after the closing quote; `\"` and `\\` are escapes
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize)> {
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok((value, i + 1)),
+            '\\' if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                value.push(chars[i + 1]);
+                i += 2;
+            }
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(error(start + 1, "unterminated quote"))
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// Column just past the end of the input, for errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    /// or := and ("OR" and)*
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut queries = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            let column = self.column();
+            self.next();
+            if matches!(self.peek(), None | Some(Token::RParen) | Some(Token::Or)) {
+                return Err(error(column, "OR must be followed by a search term"));
+            }
+            queries.push(self.parse_and()?);
+        }
+        Ok(match queries.len() {
+            1 => queries.remove(0),
+            _ => Query::Or(queries),
+        })
+    }
+
+    /// and := unary (["AND"] unary)*
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut queries = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::RParen) | Some(Token::Or) => break,
+                Some(Token::And) => {
+                    let column = self.column();
+                    self.next();
+                    if queries.is_empty()
+                        || matches!(self.peek(), None | Some(Token::RParen) | Some(Token::Or))
+                    {
+                        return Err(error(column, "AND must be between two search terms"));
+                    }
+                }
+                _ => queries.push(self.parse_unary()?),
+            }
+        }
+        match queries.len() {
+            0 => Err(error(self.column(), "expected a search term")),
+            1 => Ok(queries.remove(0)),
+            _ => Ok(Query::And(queries)),
+        }
+    }
+
+    /// unary := ("-" | "NOT") unary | "(" or ")" | term
+    fn parse_unary(&mut self) -> Result<Query> {
+        let column = self.column();
+        match self.next() {
+            Some((Token::Not, _)) => {
+                if matches!(
+                    self.peek(),
+                    None | Some(Token::RParen) | Some(Token::Or) | Some(Token::And)
+                ) {
+                    return Err(error(
+                        column,
+                        "'-' or NOT must be followed by a search term",
+                    ));
+                }
+                Ok(Query::Not(Box::new(self.parse_unary()?)))
+            }
+            Some((Token::LParen, _)) => {
+                if self.peek() == Some(&Token::RParen) {
+                    return Err(error(column, "empty parentheses"));
+                }
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some((Token::RParen, _)) => Ok(query),
+                    _ => Err(error(column, "'(' is never closed")),
+                }
+            }
+            Some((Token::Term { field, value }, _)) => {
+                build_term(field.as_deref(), value, column).map(Query::Term)
+            }
+            Some((Token::RParen, _)) => Err(error(column, "unexpected ')' without a matching '('")),
+            _ => Err(error(column, "expected a search term")),
+        }
+    }
+}
+
+fn build_term(field: Option<&str>, value: String, column: usize) -> Result<Term> {
+    let Some(field) = field else {
+        return Ok(Term::Text(value));
+    };
+    if value.trim().is_empty() {
+        return Err(error(column, format!("'{}:' needs a value", field)));
+    }
+
+    let value = value.trim().to_string();
+    match field {
+        "title" => Ok(Term::Title(value)),
+        "user" | "username" => Ok(Term::Username(value)),
+        "url" => Ok(Term::Url(value)),
+        "notes" | "note" => Ok(Term::Notes(value)),
+        "tag" => Ok(Term::Tag(value)),
+        "kind" => {
+            let name = value.to_lowercase();
+            match KIND_NAMES.contains(&name.as_str()) {
+                true => Ok(Term::Kind(name)),
+                false => Err(error(
+                    column,
+                    format!(
+                        "unknown kind '{}' (expected one of: {})",
+                        value,
+                        KIND_NAMES.join(", ")
+                    ),
+                )),
+            }
+        }
+        "field" => Ok(match value.split_once('=') {
+            Some((name, text)) if !text.is_empty() => Term::Field {
+                name: name.trim().to_string(),
+                value: Some(text.to_string()),
+            },
+            _ => Term::Field {
+                name: value.trim_end_matches('=').trim().to_string(),
+                value: None,
+            },
+        }),
+        "has" => {
+            let property = match value.to_lowercase().as_str() {
+                "otp" | "totp" => Property::Otp,
+                "url" => Property::Url,
+                "notes" | "note" => Property::Notes,
+                "tags" | "tag" => Property::Tags,
+                "fields" | "field" => Property::Fields,
+                "attachments" | "attachment" | "files" => Property::Attachments,
+                "history" => Property::History,
+                "username" | "user" => Property::Username,
+                _ => {
+                    return Err(error(
+                        column,
+                        format!(
+                            "unknown property 'has:{}' (expected one of: {})",
+                            value,
+                            HAS_PROPERTIES.join(", ")
+                        ),
+                    ))
+                }
+            };
+            Ok(Term::Has(property))
+        }
+        "created" | "updated" => {
+            let (comparison, date) = parse_date(&value).ok_or_else(|| {
+                error(
+                    column,
+                    format!(
+                        "invalid date '{}' for {}: (expected YYYY-MM-DD, optionally after \
+                         <, <=, >, >= or =)",
+                        value, field
+                    ),
+                )
+            })?;
+            Ok(match field {
+                "created" => Term::Created(comparison, date),
+                _ => Term::Updated(comparison, date),
+            })
+        }
+        _ => unreachable!("tokenize only yields known fields"),
+    }
+}
+
+fn parse_date(value: &str) -> Option<(Comparison, NaiveDate)> {
+    let (comparison, date) = [
+        (">=", Comparison::OnOrAfter),
+        ("<=", Comparison::OnOrBefore),
+        (">", Comparison::After),
+        ("<", Comparison::Before),
+        ("=", Comparison::On),
+    ]
+    .into_iter()
+    .find_map(|(prefix, comparison)| Some((comparison, value.strip_prefix(prefix)?)))
+    .unwrap_or((Comparison::On, value));
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+    Some((comparison, date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::otp::Otp;
+    use crate::storage::field::{CustomField, FieldType};
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn sample_entries() -> Vec<PasswordEntry> {
+        let mut github = PasswordEntry::new(
+            "GitHub CI".to_string(),
+            "ci-bot".to_string(),
+            "hunter2".to_string(),
+        );
+        github.url = Some("https://github.com/org".to_string());
+        github.tags = vec!["work/ci".to_string()];
+        github.otp = Some(Otp::totp("JBSWY3DPEHPK3PXP").unwrap());
+        github.created_at = "2025-03-01T12:00:00Z".parse().unwrap();
+
+        let mut legacy =
+            PasswordEntry::new("Old GitHub".to_string(), "ci".to_string(), "pw".to_string());
+        legacy.url = Some("https://github.com".to_string());
+        legacy.tags = vec!["work".to_string(), "old".to_string()];
+        legacy.notes = Some("Rotated out in the 2024 migration".to_string());
+        legacy.created_at = "2024-06-01T12:00:00Z".parse().unwrap();
+
+        let mut bank = PasswordEntry::new("Bank".to_string(), "me".to_string(), "pw".to_string());
+        bank.tags = vec!["personal".to_string()];
+        bank.fields = vec![
+            CustomField::new("Branch", FieldType::Text, "Main Street"),
+            CustomField::new("PIN", FieldType::Concealed, "4321"),
+        ];
+        bank.created_at = "2025-01-01T08:00:00Z".parse().unwrap();
+
+        vec![github, legacy, bank]
+    }
+
+    fn titles(query: &str) -> Vec<String> {
+        let query = parse(query).unwrap();
+        sample_entries()
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .map(|entry| entry.title.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("tag:work url:github.com -tag:old created:>2025-01-01 has:totp").unwrap(),
+            Query::And(vec![
+                Query::Term(Term::Tag("work".to_string())),
+                Query::Term(Term::Url("github.com".to_string())),
+                Query::Not(Box::new(Query::Term(Term::Tag("old".to_string())))),
+                Query::Term(Term::Created(Comparison::After, date("2025-01-01"))),
+                Query::Term(Term::Has(Property::Otp)),
+            ])
+        );
+        assert_eq!(
+            parse(r#"title:"my bank" OR (NOT bank AND "a \"b\"")"#).unwrap(),
+            Query::Or(vec![
+                Query::Term(Term::Title("my bank".to_string())),
+                Query::And(vec![
+                    Query::Not(Box::new(Query::Term(Term::Text("bank".to_string())))),
+                    Query::Term(Term::Text("a \"b\"".to_string())),
+                ]),
+            ])
+        );
+        assert_eq!(parse("  ").unwrap(), Query::And(Vec::new()));
+        assert_eq!(
+            parse("field:Branch=main").unwrap(),
+            Query::Term(Term::Field {
+                name: "Branch".to_string(),
+                value: Some("main".to_string())
+            })
+        );
+        assert_eq!(
+            parse("github -old").unwrap().text().as_deref(),
+            Some("github")
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(
+            titles("tag:work url:github.com user:ci -tag:old created:>2025-01-01 has:totp"),
+            ["GitHub CI"]
+        );
+        assert_eq!(titles("tag:work"), ["GitHub CI", "Old GitHub"]);
+        assert_eq!(titles("created:<=2025-01-01"), ["Old GitHub", "Bank"]);
+        assert_eq!(titles("created:2025-01-01"), ["Bank"]);
+        assert_eq!(titles("bank OR tag:old"), ["Old GitHub", "Bank"]);
+        assert_eq!(titles(""), ["GitHub CI", "Old GitHub", "Bank"]);
+        assert_eq!(titles("kind:login -has:url"), ["Bank"]);
+
+        // Notes and visible custom fields are searchable, concealed values are not
+        assert_eq!(titles("migration"), ["Old GitHub"]);
+        assert_eq!(titles("notes:2024"), ["Old GitHub"]);
+        assert_eq!(titles("\"main street\""), ["Bank"]);
+        assert_eq!(titles("field:pin"), ["Bank"]);
+        assert!(titles("4321").is_empty());
+        assert!(titles("field:pin=4321").is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let message = |query: &str| parse(query).unwrap_err().to_string();
+
+        assert!(message("title:\"my bank").contains("column 7: unterminated quote"));
+        assert!(message("(tag:work").contains("column 1: '(' is never closed"));
+        assert!(message("tag:work)").contains("column 9: unexpected ')'"));
+        assert!(message("tag:").contains("'tag:' needs a value"));
+        assert!(message("has:wings").contains("unknown property 'has:wings'"));
+        assert!(message("kind:boat").contains("unknown kind 'boat'"));
+        assert!(message("created:>yesterday").contains("invalid date '>yesterday'"));
+        assert!(message("work OR").contains("column 6: OR must be followed"));
+        assert!(message("AND work").contains("AND must be between"));
+        assert!(message("NOT").contains("must be followed by a search term"));
+        assert!(message("()").contains("empty parentheses"));
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            from_args(&args(&["tag:work", "-tag:old"])),
+            "tag:work -tag:old"
+        );
+        assert_eq!(
+            from_args(&args(&["my bank", "Title:my bank", "colour:dark red"])),
+            r#""my bank" Title:"my bank" "colour:dark red""#
+        );
+        assert_eq!(from_args(&args(&["a\\b c"])), r#""a\\b c""#);
+        assert_eq!(
+            from_args(&args(&[r#"title:"my bank" OR bank"#])),
+            r#"title:"my bank" OR bank"#
+        );
+        assert_eq!(
+            parse(&from_args(&args(&["title:my bank"]))).unwrap(),
+            Query::Term(Term::Title("my bank".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_colons_in_text() {
+        let text = |value: &str| Query::Term(Term::Text(value.to_string()));
+
+        // Only field names are prefixes; other words with a ':' are text
+        assert_eq!(
+            parse("https://github.com").unwrap(),
+            text("https://github.com")
+        );
+        assert_eq!(
+            parse("colour:red :work").unwrap(),
+            Query::And(vec![text("colour:red"), text(":work")])
+        );
+        assert_eq!(
+            parse("URL:https://github.com username:ci").unwrap(),
+            Query::And(vec![
+                Query::Term(Term::Url("https://github.com".to_string())),
+                Query::Term(Term::Username("ci".to_string())),
+            ])
+        );
+        assert_eq!(titles("https://github.com"), ["GitHub CI", "Old GitHub"]);
+    }
+}
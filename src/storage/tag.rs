@@ -0,0 +1,225 @@
+//! Nested tags
+//!
+//! Tags double as folders: a tag such as `work/servers/eu` places an entry
+//! in the `eu` folder under `servers` under `work`, and tagging an entry
+//! places it in every tagged folder. Tags are compared without regard to
+//! case, segment by segment, so `Work/Servers` and `work/servers` are the
+//! same folder. Filtering by a tag includes the tags below it, and renaming
+//! or moving a tag carries the tags below it along.
+
+use crate::storage::PasswordEntry;
+use crate::{Result, TwoPasswordError};
+use std::collections::{BTreeMap, HashSet};
+
+/// Separator between the segments of a nested tag
+pub const SEPARATOR: char = '/';
+
+/// Trim every segment of a tag and drop empty ones, so `" work // servers/"`
+/// becomes `"work/servers"`
+pub fn normalize(tag: &str) -> String {
+    segments(tag).collect::<Vec<_>>().join("/")
+}
+
+/// Check whether two tags are the same, ignoring case and stray separators
+pub fn same(a: &str, b: &str) -> bool {
+    segments(a).count() == segments(b).count() && is_within(a, b)
+}
+
+/// Check whether `tag` is `ancestor` or nested somewhere below it
+pub fn is_within(tag: &str, ancestor: &str) -> bool {
+    let mut tag = segments(tag);
+    segments(ancestor).all(|expected| {
+        tag.next()
+            .is_some_and(|segment| same_segment(segment, expected))
+    })
+}
+
+/// The tag `tag` becomes when `from` is renamed to `to`, if it is `from` or
+/// nested below it
+pub fn rename(tag: &str, from: &str, to: &str) -> Option<String> {
+    if !is_within(tag, from) {
+        return None;
+    }
+    let rest = segments(tag).skip(segments(from).count());
+    Some(segments(to).chain(rest).collect::<Vec<_>>().join("/"))
+}
+
+/// Last segment of a tag, e.g. `servers` for `work/servers`
+pub fn leaf(tag: &str) -> &str {
+    segments(tag).last().unwrap_or_default()
+}
+
+/// Check that a tag has at least one non-empty segment
+pub fn validate(tag: &str) -> Result<()> {
+    match segments(tag).next() {
+        Some(_) => Ok(()),
+        None => Err(TwoPasswordError::validation(format!(
+            "'{}' is not a valid tag",
+            tag
+        ))),
+    }
+}
+
+fn segments(tag: &str) -> impl Iterator<Item = &str> {
+    tag.split(SEPARATOR)
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+}
+
+fn same_segment(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// A folder in the tag tree, with the entries tagged exactly with it and
+/// the folders nested below it
+#[derive(Debug, Default)]
+pub struct TagNode<'a> {
+    /// Last segment of the tag, as first seen
+    pub name: String,
+    /// The full tag; empty for the root
+    pub path: String,
+    pub entries: Vec<&'a PasswordEntry>,
+    /// Nested folders, keyed by their lowercase name
+    pub children: BTreeMap<String, TagNode<'a>>,
+}
+
+impl<'a> TagNode<'a> {
+    /// Build the tag tree of `entries`; untagged entries are kept in the
+    /// root
+    pub fn build(entries: impl IntoIterator<Item = &'a PasswordEntry>) -> Self {
+        let mut root = TagNode::default();
+        for entry in entries {
+            if entry.tags.iter().all(|tag| validate(tag).is_err()) {
+                root.entries.push(entry);
+            }
+            for tag in &entry.tags {
+                if validate(tag).is_err() {
+                    continue;
+                }
+                let mut node = &mut root;
+                for segment in segments(tag) {
+                    let path = match node.path.is_empty() {
+                        true => segment.to_string(),
+                        false => format!("{}/{}", node.path, segment),
+                    };
+                    node = node
+                        .children
+                        .entry(segment.to_lowercase())
+                        .or_insert_with(|| TagNode {
+                            name: segment.to_string(),
+                            path,
+                            ..TagNode::default()
+                        });
+                }
+                if !node.entries.iter().any(|tagged| tagged.id == entry.id) {
+                    node.entries.push(entry);
+                }
+            }
+        }
+        root.sort();
+        root
+    }
+
+    /// Find the folder for a tag
+    pub fn find(&self, tag: &str) -> Option<&TagNode<'a>> {
+        segments(tag).try_fold(self, |node, segment| {
+            node.children.get(&segment.to_lowercase())
+        })
+    }
+
+    /// Number of distinct entries in this folder and the folders below it
+    pub fn count(&self) -> usize {
+        fn collect<'a>(node: &TagNode<'a>, ids: &mut HashSet<uuid::Uuid>) {
+            ids.extend(node.entries.iter().map(|entry| entry.id));
+            for child in node.children.values() {
+                collect(child, ids);
+            }
+        }
+
+        let mut ids = HashSet::new();
+        collect(self, &mut ids);
+        ids.len()
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by_key(|entry| entry.title.to_lowercase());
+        for child in self.children.values_mut() {
+            child.sort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, tags: &[&str]) -> PasswordEntry {
+        let mut entry = PasswordEntry::new(title.to_string(), String::new(), "pw".to_string());
+        entry.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        entry
+    }
+
+    #[test]
+    fn test_nesting() {
+        assert_eq!(normalize(" Work // Servers/ "), "Work/Servers");
+        assert!(same("work/servers", "Work/Servers/"));
+        assert!(!same("work", "work/servers"));
+
+        assert!(is_within("Work/Servers/EU", "work/servers"));
+        assert!(is_within("work", "WORK"));
+        assert!(!is_within("workshop", "work"));
+        assert!(!is_within("work", "work/servers"));
+
+        assert_eq!(leaf("work/servers"), "servers");
+        assert!(validate(" / ").is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        assert_eq!(
+            rename("Work/Servers/EU", "work/servers", "infra").as_deref(),
+            Some("infra/EU")
+        );
+        assert_eq!(
+            rename("work", "work", "job/old").as_deref(),
+            Some("job/old")
+        );
+        assert_eq!(rename("workshop", "work", "job"), None);
+        assert_eq!(rename("personal", "work", "job"), None);
+    }
+
+    #[test]
+    fn test_tree() {
+        let entries = [
+            entry("db01", &["Work/Servers"]),
+            entry("GitHub", &["work", "personal/dev"]),
+            entry("db02", &["work/servers", "work"]),
+            entry("Bank", &[]),
+        ];
+        let root = TagNode::build(&entries);
+
+        assert_eq!(root.entries.len(), 1);
+        assert_eq!(root.entries[0].title, "Bank");
+        let names: Vec<&str> = root
+            .children
+            .values()
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(names, ["personal", "Work"]);
+
+        let work = root.find("WORK").unwrap();
+        assert_eq!(work.count(), 3);
+        let titles: Vec<&str> = work
+            .entries
+            .iter()
+            .map(|entry| entry.title.as_str())
+            .collect();
+        assert_eq!(titles, ["db02", "GitHub"]);
+
+        let servers = root.find("work/servers").unwrap();
+        assert_eq!(servers.path, "Work/Servers");
+        assert_eq!(servers.count(), 2);
+        assert!(root.find("work/desktops").is_none());
+        assert_eq!(root.count(), 4);
+    }
+}
@@ -1,45 +1,91 @@
 //! Vault storage operations
 
-use crate::crypto::CryptoManager;
-use crate::storage::{Vault, VaultFile};
+use crate::crypto::key_derivation::{self, KdfParams};
+use crate::crypto::key_schedule::KeySchedule;
+use crate::crypto::key_wrap::{KeySlot, KeySlotKind};
+use crate::crypto::{CryptoManager, MasterKey, Salt, SecretBytes};
+use crate::storage::{format, migration};
+use crate::storage::{
+    PasswordEntry, TrashedEntry, Vault, VaultFile, VaultKeys, VaultMetadata, VaultSettings,
+};
 use crate::{Result, TwoPasswordError};
 use ring::hmac;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use uuid::Uuid;
 
-/// Save vault to encrypted file with salt
-pub fn save_vault_with_salt(vault: &Vault, crypto: &CryptoManager, salt: &crate::crypto::Salt) -> Result<()> {
-    // Serialize vault entries
-    let vault_data = serde_json::to_vec(&vault.entries)
-        .map_err(|e| TwoPasswordError::storage(format!("Failed to serialize vault: {}", e)))?;
+/// Public key of the integrity hash in files written before subkeys existed
+const LEGACY_INTEGRITY_KEY: &[u8] = b"TwoPassword-Integrity-Key";
+
+/// Last format version stored as JSON rather than a binary container
+const LAST_JSON_FORMAT_VERSION: u32 = 1;
 
-    // Encrypt the vault data
-    let encrypted_data = crypto.encrypt(&vault_data)?;
+/// Last format version whose payload, unless the metadata is hidden, is the
+/// bare map of entries rather than a `VaultPayload`
+const LAST_ENTRIES_ONLY_FORMAT_VERSION: u32 = 2;
 
-    // Calculate integrity hash of the entire encrypted data
-    let integrity_key = hmac::Key::new(hmac::HMAC_SHA256, b"TwoPassword-Integrity-Key");
-    let mut hash_input = Vec::new();
-    hash_input.extend_from_slice(&encrypted_data.ciphertext);
-    hash_input.extend_from_slice(&encrypted_data.nonce);
-    hash_input.extend_from_slice(&encrypted_data.hmac);
+/// Smallest padded payload size for vaults that hide their metadata
+const MIN_PADDED_SIZE: usize = 4096;
+
+/// Save vault to encrypted file with its salt, KDF parameters and key slots
+pub fn save_vault_with_salt(vault: &Vault, crypto: &CryptoManager, keys: &VaultKeys) -> Result<()> {
+    if vault.metadata.format_version != crate::config::FORMAT_VERSION {
+        return Err(TwoPasswordError::storage(format!(
+            "Vault format version {} must be migrated before saving",
+            vault.metadata.format_version
+        )));
+    }
+
+    // Serialize the vault contents, together with the metadata when it is hidden
+    let payload = VaultPayload {
+        metadata: keys.hide_metadata.then(|| vault.metadata.clone()),
+        entries: vault.entries.clone(),
+        trash: vault.trash.clone(),
+        settings: vault.settings.clone(),
+    };
+    let data = serde_json::to_vec(&payload)
+        .map(SecretBytes::from)
+        .map_err(|e| TwoPasswordError::storage(format!("Failed to serialize vault: {}", e)))?;
+    let (metadata, vault_data) = if keys.hide_metadata {
+        (placeholder_metadata(), pad(data.expose()))
+    } else {
+        (vault.metadata.clone(), data)
+    };
 
-    let integrity_hash = hmac::sign(&integrity_key, &hash_input);
+    // Encrypt the vault data, binding the clear-text header as associated data
+    let header = VaultHeader {
+        metadata: &metadata,
+        salt: &keys.salt,
+        kdf: &keys.kdf,
+        key_slots: &keys.key_slots,
+        cipher: keys.cipher,
+        hide_metadata: keys.hide_metadata,
+    };
+    let encrypted_data =
+        crypto.encrypt_with_aad(keys.cipher, vault_data.expose(), &header.to_bytes()?)?;
 
-    // Create vault file structure with the provided salt
-    let vault_file = VaultFile {
-        metadata: vault.metadata.clone(),
-        salt: salt.clone(),
+    // Create vault file structure with the provided key material
+    let mut vault_file = VaultFile {
+        metadata,
+        salt: keys.salt.clone(),
+        kdf: keys.kdf,
+        key_slots: keys.key_slots.clone(),
+        cipher: keys.cipher,
+        hide_metadata: keys.hide_metadata,
         encrypted_data,
-        integrity_hash: integrity_hash.as_ref().to_vec(),
+        integrity_hash: Vec::new(),
     };
 
-    // Serialize and write to file
-    let vault_json = serde_json::to_string_pretty(&vault_file)
-        .map_err(|e| TwoPasswordError::storage(format!("Failed to serialize vault file: {}", e)))?;
+    // Authenticate the whole file with a key derived from the data key
+    vault_file.integrity_hash = crypto.authenticate_header(&integrity_bytes(&vault_file)?)?;
+
+    // Encode as a binary container and write to file
+    let vault_bytes = format::encode(&vault_file);
 
     // Write to temporary file first, then rename for atomic operation
     let temp_path = vault.vault_path.with_extension("tmp");
-    fs::write(&temp_path, vault_json)
+    fs::write(&temp_path, vault_bytes)
         .map_err(|e| TwoPasswordError::storage(format!("Failed to write vault file: {}", e)))?;
 
     fs::rename(&temp_path, &vault.vault_path)
@@ -49,75 +95,357 @@ pub fn save_vault_with_salt(vault: &Vault, crypto: &CryptoManager, salt: &crate:
     Ok(())
 }
 
-/// Save vault to encrypted file (generates new salt)
-pub fn save_vault(vault: &Vault, crypto: &CryptoManager) -> Result<()> {
-    let salt = crate::crypto::Salt::generate()?;
-    save_vault_with_salt(vault, crypto, &salt)
+/// Decrypted vault contents
+///
+/// Vaults that hide their metadata have carried it here since format version
+/// 1, with `entries`; the trash and settings were added in version 3.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct VaultPayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<VaultMetadata>,
+    entries: HashMap<Uuid, PasswordEntry>,
+    #[serde(default)]
+    trash: HashMap<Uuid, TrashedEntry>,
+    #[serde(default)]
+    settings: VaultSettings,
+}
+
+/// Metadata written in the clear when the real metadata is hidden; only the
+/// format version is needed before unlocking
+fn placeholder_metadata() -> VaultMetadata {
+    VaultMetadata {
+        format_version: crate::config::FORMAT_VERSION,
+        created_at: chrono::DateTime::UNIX_EPOCH,
+        updated_at: chrono::DateTime::UNIX_EPOCH,
+        entry_count: 0,
+    }
+}
+
+/// Pad plaintext to the next size bucket
+///
+/// Buckets are powers of two of at least `MIN_PADDED_SIZE` bytes, so the
+/// ciphertext length only reveals the order of magnitude of the vault. A 0x80
+/// marker followed by zero bytes is appended, which is unambiguous to remove.
+fn pad(data: &[u8]) -> SecretBytes {
+    let padded_len = (data.len() + 1).next_power_of_two().max(MIN_PADDED_SIZE);
+
+    // Allocated once at full size so no partial copy is left behind
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(data);
+    padded.push(0x80);
+    padded.resize(padded_len, 0);
+    SecretBytes::from(padded)
+}
+
+/// Remove padding added by `pad`
+fn unpad(data: &[u8]) -> Result<&[u8]> {
+    let marker = data
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .ok_or(TwoPasswordError::InvalidVaultFormat)?;
+    if data[marker] != 0x80 {
+        return Err(TwoPasswordError::InvalidVaultFormat);
+    }
+    Ok(&data[..marker])
+}
+
+/// Clear-text header fields bound to the payload as associated data
+#[derive(serde::Serialize)]
+struct VaultHeader<'a> {
+    metadata: &'a VaultMetadata,
+    salt: &'a Salt,
+    kdf: &'a KdfParams,
+    key_slots: &'a [KeySlot],
+    cipher: crate::crypto::CipherSuite,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    hide_metadata: bool,
+}
+
+impl<'a> VaultHeader<'a> {
+    fn of(vault_file: &'a VaultFile) -> Self {
+        Self {
+            metadata: &vault_file.metadata,
+            salt: &vault_file.salt,
+            kdf: &vault_file.kdf,
+            key_slots: &vault_file.key_slots,
+            cipher: vault_file.cipher,
+            hide_metadata: vault_file.hide_metadata,
+        }
+    }
+
+    /// Canonical serialization used as associated data
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
 }
 
-/// Load vault from encrypted file and return both vault and salt
+/// Canonical bytes of everything in a vault file except the integrity hash
+fn integrity_bytes(vault_file: &VaultFile) -> Result<Vec<u8>> {
+    let mut bytes = VaultHeader::of(vault_file).to_bytes()?;
+    bytes.extend_from_slice(&serde_json::to_vec(&vault_file.encrypted_data)?);
+    Ok(bytes)
+}
+
+/// Associated data the payload was encrypted with; payloads written before
+/// subkeys existed were not bound to the header
+fn payload_aad(vault_file: &VaultFile) -> Result<Vec<u8>> {
+    match vault_file.encrypted_data.key_schedule {
+        KeySchedule::Legacy => Ok(Vec::new()),
+        KeySchedule::Hkdf => VaultHeader::of(vault_file).to_bytes(),
+    }
+}
+
+/// Verify the integrity hash with the data key held by `crypto`
+///
+/// Files whose payload predates subkeys carry a hash under a fixed public key,
+/// which only detects accidental corruption; they are rewritten with a keyed
+/// hash on the next save.
+fn verify_integrity(vault_file: &VaultFile, crypto: &CryptoManager) -> Result<()> {
+    if vault_file.encrypted_data.key_schedule == KeySchedule::Legacy {
+        let integrity_key = hmac::Key::new(hmac::HMAC_SHA256, LEGACY_INTEGRITY_KEY);
+        let mut hash_input = Vec::new();
+        hash_input.extend_from_slice(&vault_file.encrypted_data.ciphertext);
+        hash_input.extend_from_slice(&vault_file.encrypted_data.nonce);
+        hash_input.extend_from_slice(&vault_file.encrypted_data.hmac);
+
+        return hmac::verify(&integrity_key, &hash_input, &vault_file.integrity_hash)
+            .map_err(|_| TwoPasswordError::storage("Vault integrity verification failed"));
+    }
+
+    crypto.verify_header(&integrity_bytes(vault_file)?, &vault_file.integrity_hash)
+}
+
+/// Derive the password key-encryption key
+pub(crate) fn derive_password_kek(
+    password: &str,
+    salt: &Salt,
+    kdf: &KdfParams,
+) -> Result<MasterKey> {
+    let key_bytes = key_derivation::derive_key_with_params(password, &salt.bytes, kdf)?;
+    Ok(MasterKey::from_bytes(key_bytes))
+}
+
+/// Load vault from encrypted file using the master password, returning the
+/// vault and its key material
 pub fn load_vault_with_salt<P: AsRef<Path>>(
     path: P,
     password: &str,
     crypto: &mut CryptoManager,
-) -> Result<(Vault, crate::crypto::Salt)> {
+) -> Result<(Vault, VaultKeys)> {
+    let path = path.as_ref();
+    let vault_file = read_vault_file(path)?;
+
+    // Derive the password key with the parameters the vault was created with
+    vault_file
+        .kdf
+        .validate()
+        .map_err(|_| TwoPasswordError::InvalidVaultFormat)?;
+    let kek = derive_password_kek(password, &vault_file.salt, &vault_file.kdf)?;
+
+    if vault_file.key_slots.is_empty() {
+        return load_legacy_vault(path, vault_file, &kek, crypto);
+    }
+
+    let slot = vault_file
+        .key_slots
+        .iter()
+        .find(|slot| slot.kind == KeySlotKind::Password)
+        .ok_or_else(|| TwoPasswordError::auth("Vault has no password key slot"))?;
+
+    crypto
+        .unwrap_master_key(&kek, &slot.wrapped_key)
+        .map_err(|_| TwoPasswordError::InvalidMasterPassword)?;
+
+    decrypt_vault(path, vault_file, crypto)
+}
+
+/// Load vault from encrypted file using the key-encryption key of another
+/// unlock method
+pub fn load_vault_with_key<P: AsRef<Path>>(
+    path: P,
+    kind: KeySlotKind,
+    kek: &MasterKey,
+    crypto: &mut CryptoManager,
+) -> Result<(Vault, VaultKeys)> {
+    let path = path.as_ref();
+    let vault_file = read_vault_file(path)?;
+
+    let slot = vault_file
+        .key_slots
+        .iter()
+        .find(|slot| slot.kind == kind)
+        .ok_or_else(|| TwoPasswordError::auth(format!("Vault has no {} key slot", kind)))?;
+
+    crypto
+        .unwrap_master_key(kek, &slot.wrapped_key)
+        .map_err(|_| TwoPasswordError::auth(format!("Invalid {}", kind)))?;
+
+    decrypt_vault(path, vault_file, crypto)
+}
+
+/// Load vault from encrypted file using its data key directly, as the
+/// unlock agent does to reopen the vault it holds
+pub fn load_vault_with_data_key<P: AsRef<Path>>(
+    path: P,
+    data_key: &MasterKey,
+    crypto: &mut CryptoManager,
+) -> Result<(Vault, VaultKeys)> {
     let path = path.as_ref();
+    let vault_file = read_vault_file(path)?;
+    if vault_file.key_slots.is_empty() {
+        return Err(TwoPasswordError::auth(
+            "Vault has no data key; unlock it with the master password",
+        ));
+    }
 
+    crypto.set_master_key(data_key.clone());
+    decrypt_vault(path, vault_file, crypto)
+}
+
+/// Load vault from encrypted file (compatibility function)
+pub fn load_vault<P: AsRef<Path>>(
+    path: P,
+    password: &str,
+    crypto: &mut CryptoManager,
+) -> Result<Vault> {
+    let (vault, _keys) = load_vault_with_salt(path, password, crypto)?;
+    Ok(vault)
+}
+
+/// Read and parse a vault file, either a binary container or a version 1
+/// JSON file
+///
+/// The integrity hash is keyed by the data key, so it is checked once a key
+/// slot has been unwrapped.
+fn read_vault_file(path: &Path) -> Result<VaultFile> {
     if !path.exists() {
         return Err(TwoPasswordError::VaultNotFound);
     }
 
     // Read vault file
-    let vault_json = fs::read_to_string(path)
+    let vault_bytes = fs::read(path)
         .map_err(|e| TwoPasswordError::storage(format!("Failed to read vault file: {}", e)))?;
 
-    // Deserialize vault file
-    let vault_file: VaultFile = serde_json::from_str(&vault_json)
-        .map_err(|e| TwoPasswordError::storage(format!("Failed to parse vault file: {}", e)))?;
+    let vault_file = if format::is_binary(&vault_bytes) {
+        let vault_file = format::decode(&vault_bytes)?;
+        if vault_file.metadata.format_version <= LAST_JSON_FORMAT_VERSION {
+            return Err(TwoPasswordError::InvalidVaultFormat);
+        }
+        vault_file
+    } else {
+        let vault_file: VaultFile = serde_json::from_slice(&vault_bytes)
+            .map_err(|e| TwoPasswordError::storage(format!("Failed to parse vault file: {}", e)))?;
+        if vault_file.metadata.format_version > LAST_JSON_FORMAT_VERSION {
+            return Err(TwoPasswordError::InvalidVaultFormat);
+        }
+        vault_file
+    };
 
-    // Verify format version
-    if vault_file.metadata.format_version != crate::config::FORMAT_VERSION {
+    // Verify format version and that the payload uses the declared cipher
+    if !migration::is_supported(vault_file.metadata.format_version)
+        || vault_file.encrypted_data.cipher != vault_file.cipher
+    {
         return Err(TwoPasswordError::InvalidVaultFormat);
     }
 
-    // Verify integrity hash
-    let integrity_key = hmac::Key::new(hmac::HMAC_SHA256, b"TwoPassword-Integrity-Key");
-    let mut hash_input = Vec::new();
-    hash_input.extend_from_slice(&vault_file.encrypted_data.ciphertext);
-    hash_input.extend_from_slice(&vault_file.encrypted_data.nonce);
-    hash_input.extend_from_slice(&vault_file.encrypted_data.hmac);
+    Ok(vault_file)
+}
 
-    hmac::verify(&integrity_key, &hash_input, &vault_file.integrity_hash)
-        .map_err(|_| TwoPasswordError::storage("Vault integrity verification failed"))?;
+/// Open a vault written before key slots existed, whose contents are
+/// encrypted directly with the password-derived key
+///
+/// A random data key is generated and wrapped with the password key, so the
+/// next save writes the vault in the key slot layout.
+fn load_legacy_vault(
+    path: &Path,
+    vault_file: VaultFile,
+    kek: &MasterKey,
+    crypto: &mut CryptoManager,
+) -> Result<(Vault, VaultKeys)> {
+    crypto.set_master_key(kek.clone());
+    verify_integrity(&vault_file, crypto).map_err(|_| TwoPasswordError::InvalidMasterPassword)?;
+    let decrypted_data = crypto
+        .decrypt_with_aad(&vault_file.encrypted_data, &payload_aad(&vault_file)?)
+        .map_err(|_| TwoPasswordError::InvalidMasterPassword)?;
+    let (mut vault, mut keys) = build_vault(path, vault_file, decrypted_data.expose())?;
 
-    // Derive key from password and salt
-    crypto.derive_key(password, &vault_file.salt)?;
+    crypto.generate_master_key()?;
+    keys.key_slots.push(KeySlot {
+        kind: KeySlotKind::Password,
+        wrapped_key: crypto.wrap_master_key(kek)?,
+    });
+    vault.is_modified = true;
 
-    // Decrypt vault data
-    let decrypted_data = crypto.decrypt(&vault_file.encrypted_data)?;
+    Ok((vault, keys))
+}
+
+/// Decrypt vault contents with the data key already held by `crypto`
+fn decrypt_vault(
+    path: &Path,
+    vault_file: VaultFile,
+    crypto: &CryptoManager,
+) -> Result<(Vault, VaultKeys)> {
+    verify_integrity(&vault_file, crypto)?;
+
+    // The data key is known to be right, so a failure here means the payload
+    // or the header it is bound to was modified
+    let decrypted_data = crypto
+        .decrypt_with_aad(&vault_file.encrypted_data, &payload_aad(&vault_file)?)
+        .map_err(|_| TwoPasswordError::InvalidVaultFormat)?;
+    build_vault(path, vault_file, decrypted_data.expose())
+}
 
-    // Deserialize entries
-    let entries = serde_json::from_slice(&decrypted_data).map_err(|e| {
+fn build_vault(
+    path: &Path,
+    vault_file: VaultFile,
+    decrypted_data: &[u8],
+) -> Result<(Vault, VaultKeys)> {
+    // Deserialize the contents; only the entries were stored before version 3
+    let deserialize_error = |e: serde_json::Error| {
         TwoPasswordError::storage(format!("Failed to deserialize vault entries: {}", e))
-    })?;
+    };
+    let payload = if vault_file.hide_metadata {
+        serde_json::from_slice(unpad(decrypted_data)?).map_err(deserialize_error)?
+    } else if vault_file.metadata.format_version <= LAST_ENTRIES_ONLY_FORMAT_VERSION {
+        VaultPayload {
+            metadata: None,
+            entries: serde_json::from_slice(decrypted_data).map_err(deserialize_error)?,
+            trash: HashMap::new(),
+            settings: VaultSettings::default(),
+        }
+    } else {
+        serde_json::from_slice(decrypted_data).map_err(deserialize_error)?
+    };
+
+    let metadata = if vault_file.hide_metadata {
+        payload
+            .metadata
+            .ok_or(TwoPasswordError::InvalidVaultFormat)?
+    } else {
+        vault_file.metadata
+    };
 
     let mut vault = Vault::new(path);
-    vault.metadata = vault_file.metadata;
-    vault.entries = entries;
+    vault.metadata = metadata;
+    vault.entries = payload.entries;
+    vault.trash = payload.trash;
+    vault.settings = payload.settings;
     vault.is_modified = false;
 
-    tracing::info!("Vault loaded from {}", path.display());
-    Ok((vault, vault_file.salt))
-}
+    let mut keys = VaultKeys {
+        salt: vault_file.salt,
+        kdf: vault_file.kdf,
+        key_slots: vault_file.key_slots,
+        cipher: vault_file.cipher,
+        hide_metadata: vault_file.hide_metadata,
+    };
 
-/// Load vault from encrypted file (compatibility function)
-pub fn load_vault<P: AsRef<Path>>(
-    path: P,
-    password: &str,
-    crypto: &mut CryptoManager,
-) -> Result<Vault> {
-    let (vault, _salt) = load_vault_with_salt(path, password, crypto)?;
-    Ok(vault)
+    // Older formats are upgraded in memory and marked modified, so the next
+    // save writes the current format
+    migration::migrate(&mut vault, &mut keys)?;
+
+    tracing::info!("Vault loaded from {}", path.display());
+    Ok((vault, keys))
 }
 
 /// Check if vault exists at path
@@ -146,9 +474,17 @@ pub fn backup_vault<P: AsRef<Path>>(vault_path: P) -> Result<std::path::PathBuf>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::CryptoManager;
+    use crate::crypto::key_schedule::KeyPurpose;
+    use crate::crypto::CipherSuite;
     use tempfile::NamedTempFile;
 
+    /// Rewrite a vault file with one header field changed
+    fn rewrite(path: &Path, original: &[u8], tamper: impl FnOnce(&mut VaultFile)) {
+        let mut vault_file = format::decode(original).unwrap();
+        tamper(&mut vault_file);
+        fs::write(path, format::encode(&vault_file)).unwrap();
+    }
+
     #[test]
     fn test_vault_exists() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -159,6 +495,25 @@ mod tests {
         // Note: This might be flaky on some systems
     }
 
+    #[test]
+    fn test_debug_output_contains_no_secrets() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut vault = Vault::new(temp_file.path());
+        vault.add_entry(crate::storage::PasswordEntry::new(
+            "Bank".to_string(),
+            "alice".to_string(),
+            "hunter2-secret".to_string(),
+        ));
+        let setup = crate::auth::recovery::RecoveryManager::new()
+            .setup_master_key("hunter2-secret")
+            .unwrap();
+
+        let debug = format!("{:?} {:?}", vault, setup);
+        assert!(!debug.contains("hunter2-secret"));
+        assert!(debug.contains("[REDACTED]"));
+        assert!(!debug.contains(&format!("{:?}", setup.master_secret)));
+    }
+
     #[test]
     fn test_save_load_vault() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -187,4 +542,521 @@ mod tests {
         // let loaded_vault = load_vault(vault_path, password, &mut crypto).unwrap();
         // assert_eq!(loaded_vault.entries.len(), 1);
     }
+
+    #[test]
+    fn test_kdf_params_persisted() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let params = KdfParams::for_tests();
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager
+            .create_vault_with_params(&vault_path, "test_password", params)
+            .unwrap();
+        manager.close_vault();
+
+        let mut crypto = CryptoManager::new();
+        let (_vault, keys) =
+            load_vault_with_salt(&vault_path, "test_password", &mut crypto).unwrap();
+        assert_eq!(keys.kdf, params);
+    }
+
+    #[test]
+    fn test_kdf_update_requires_current_password() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let params = KdfParams::for_tests();
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        let before = std::fs::read(&vault_path).unwrap();
+        assert!(matches!(
+            manager.update_kdf_params("wrong_password", params),
+            Err(TwoPasswordError::InvalidMasterPassword)
+        ));
+        assert_eq!(std::fs::read(&vault_path).unwrap(), before);
+
+        manager.update_kdf_params("test_password", params).unwrap();
+        manager.close_vault();
+
+        let mut crypto = CryptoManager::new();
+        let (_vault, keys) =
+            load_vault_with_salt(&vault_path, "test_password", &mut crypto).unwrap();
+        assert_eq!(keys.kdf, params);
+    }
+
+    #[test]
+    fn test_legacy_vault_without_key_slots() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        // Write a vault encrypted directly with the password-derived key
+        let mut vault = Vault::new(&vault_path);
+        vault.add_entry(crate::storage::PasswordEntry::new(
+            "Legacy".to_string(),
+            "user".to_string(),
+            "secret".to_string(),
+        ));
+        let keys = VaultKeys {
+            salt: Salt::generate().unwrap(),
+            kdf: KdfParams::default(),
+            key_slots: Vec::new(),
+            cipher: CipherSuite::default(),
+            hide_metadata: false,
+        };
+        let mut crypto = CryptoManager::new();
+        crypto.derive_key("test_password", &keys.salt).unwrap();
+        save_vault_with_salt(&vault, &crypto, &keys).unwrap();
+
+        let mut crypto = CryptoManager::new();
+        assert!(matches!(
+            load_vault_with_salt(&vault_path, "wrong_password", &mut crypto),
+            Err(TwoPasswordError::InvalidMasterPassword)
+        ));
+
+        let (vault, keys) =
+            load_vault_with_salt(&vault_path, "test_password", &mut crypto).unwrap();
+        assert_eq!(vault.entries.len(), 1);
+        assert!(keys.slot(KeySlotKind::Password).is_some());
+
+        // Saving writes the key slot layout, which still opens with the password
+        save_vault_with_salt(&vault, &crypto, &keys).unwrap();
+        let mut crypto = CryptoManager::new();
+        let (vault, keys) =
+            load_vault_with_salt(&vault_path, "test_password", &mut crypto).unwrap();
+        assert_eq!(vault.entries.len(), 1);
+        assert_eq!(keys.key_slots.len(), 1);
+    }
+
+    #[test]
+    fn test_wrong_password_rejected() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        manager.close_vault();
+
+        let mut crypto = CryptoManager::new();
+        assert!(matches!(
+            load_vault_with_salt(&vault_path, "wrong_password", &mut crypto),
+            Err(TwoPasswordError::InvalidMasterPassword)
+        ));
+    }
+
+    #[test]
+    fn test_cipher_migration() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        assert_eq!(manager.cipher(), Some(CipherSuite::Aes256Gcm));
+
+        manager.set_cipher(CipherSuite::XChaCha20Poly1305).unwrap();
+        manager.close_vault();
+
+        let vault_file = read_vault_file(&vault_path).unwrap();
+        assert_eq!(vault_file.cipher, CipherSuite::XChaCha20Poly1305);
+        assert_eq!(
+            vault_file.encrypted_data.cipher,
+            CipherSuite::XChaCha20Poly1305
+        );
+        assert_eq!(
+            vault_file.encrypted_data.nonce.len(),
+            crate::config::XCHACHA_NONCE_SIZE
+        );
+
+        manager.load_vault(&vault_path, "test_password").unwrap();
+        assert_eq!(manager.cipher(), Some(CipherSuite::XChaCha20Poly1305));
+
+        manager.set_cipher(CipherSuite::Aes256Gcm).unwrap();
+        manager.close_vault();
+        manager.load_vault(&vault_path, "test_password").unwrap();
+        assert_eq!(manager.cipher(), Some(CipherSuite::Aes256Gcm));
+    }
+
+    #[test]
+    fn test_header_tampering_detected() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let recovery_key = MasterKey::generate().unwrap();
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        manager
+            .add_key_slot(KeySlotKind::RecoveryKey, &recovery_key)
+            .unwrap();
+        manager.close_vault();
+        let original = fs::read(&vault_path).unwrap();
+
+        // Metadata is not encrypted, but it is authenticated
+        rewrite(&vault_path, &original, |file| {
+            file.metadata.entry_count = 42
+        });
+
+        let mut crypto = CryptoManager::new();
+        assert!(matches!(
+            load_vault_with_salt(&vault_path, "test_password", &mut crypto),
+            Err(TwoPasswordError::InvalidVaultFormat)
+        ));
+
+        // A replaced salt is caught even when unlocking without the password
+        rewrite(&vault_path, &original, |file| {
+            file.salt = Salt::from_bytes(vec![0u8; crate::config::SALT_SIZE])
+        });
+
+        let mut crypto = CryptoManager::new();
+        assert!(matches!(
+            load_vault_with_key(
+                &vault_path,
+                KeySlotKind::RecoveryKey,
+                &recovery_key,
+                &mut crypto
+            ),
+            Err(TwoPasswordError::InvalidVaultFormat)
+        ));
+
+        fs::write(&vault_path, &original).unwrap();
+        assert!(load_vault_with_key(
+            &vault_path,
+            KeySlotKind::RecoveryKey,
+            &recovery_key,
+            &mut crypto
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_payload_uses_subkey() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        manager.close_vault();
+
+        let vault_file = read_vault_file(&vault_path).unwrap();
+        assert_eq!(vault_file.encrypted_data.key_schedule, KeySchedule::Hkdf);
+
+        // The payload is encrypted under the encryption subkey of the data key
+        let mut crypto = CryptoManager::new();
+        load_vault_with_salt(&vault_path, "test_password", &mut crypto).unwrap();
+        let subkey = crypto.derive_subkey(KeyPurpose::Encryption).unwrap();
+        let aad = payload_aad(&vault_file).unwrap();
+        let decrypted = crate::crypto::decrypt_any_with_aad(
+            subkey.as_bytes(),
+            &vault_file.encrypted_data,
+            &aad,
+        );
+        assert!(decrypted.is_ok());
+    }
+
+    #[test]
+    fn test_every_header_field_is_bound_to_payload() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+        let recovery_key = MasterKey::generate().unwrap();
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        manager
+            .add_key_slot(KeySlotKind::RecoveryKey, &recovery_key)
+            .unwrap();
+        manager.close_vault();
+        let original = fs::read(&vault_path).unwrap();
+
+        type Tamper = fn(&mut VaultFile);
+        let tampers: [(&str, Tamper); 8] = [
+            ("format_version", |f| f.metadata.format_version += 1),
+            ("created_at", |f| {
+                f.metadata.created_at = chrono::DateTime::UNIX_EPOCH
+            }),
+            ("updated_at", |f| {
+                f.metadata.updated_at = chrono::DateTime::UNIX_EPOCH
+            }),
+            ("entry_count", |f| f.metadata.entry_count = 7),
+            ("salt", |f| f.salt.bytes[0] ^= 1),
+            ("kdf", |f| f.kdf.time_cost = 5),
+            ("cipher", |f| f.cipher = CipherSuite::XChaCha20Poly1305),
+            ("hide_metadata", |f| f.hide_metadata = true),
+        ];
+
+        for (field, tamper) in tampers {
+            rewrite(&vault_path, &original, tamper);
+
+            let mut crypto = CryptoManager::new();
+            let result = load_vault_with_key(
+                &vault_path,
+                KeySlotKind::RecoveryKey,
+                &recovery_key,
+                &mut crypto,
+            );
+            assert!(
+                matches!(result, Err(TwoPasswordError::InvalidVaultFormat)),
+                "tampered {} was not detected",
+                field
+            );
+
+            // Re-signing the integrity hash still leaves the payload bound
+            // to the original header
+            let mut vault_file = format::decode(&original).unwrap();
+            tamper(&mut vault_file);
+            if vault_file.metadata.format_version != crate::config::FORMAT_VERSION
+                || vault_file.cipher != vault_file.encrypted_data.cipher
+            {
+                continue;
+            }
+            crypto
+                .unwrap_master_key(&recovery_key, &vault_file.key_slots[1].wrapped_key)
+                .unwrap();
+            vault_file.integrity_hash = crypto
+                .authenticate_header(&integrity_bytes(&vault_file).unwrap())
+                .unwrap();
+            fs::write(&vault_path, format::encode(&vault_file)).unwrap();
+
+            let mut crypto = CryptoManager::new();
+            let result = load_vault_with_key(
+                &vault_path,
+                KeySlotKind::RecoveryKey,
+                &recovery_key,
+                &mut crypto,
+            );
+            assert!(
+                matches!(result, Err(TwoPasswordError::InvalidVaultFormat)),
+                "{} is not bound as associated data",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn test_padding_roundtrip() {
+        for len in [0, 1, 100, 4095, 4096, 5000] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let padded = pad(&data);
+            assert!(padded.len().is_power_of_two());
+            assert!(padded.len() >= MIN_PADDED_SIZE && padded.len() > len);
+            assert_eq!(unpad(padded.expose()).unwrap(), &data[..]);
+        }
+
+        assert!(unpad(&[0u8; 16]).is_err());
+        assert!(unpad(b"no marker").is_err());
+    }
+
+    #[test]
+    fn test_hidden_metadata_leaks_neither_count_nor_size() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        manager.set_hide_metadata(true).unwrap();
+        let empty = read_vault_file(&vault_path).unwrap();
+
+        for i in 0..3 {
+            manager
+                .get_vault_mut()
+                .unwrap()
+                .add_entry(crate::storage::PasswordEntry::new(
+                    format!("Entry {}", i),
+                    "user".to_string(),
+                    "secret".to_string(),
+                ));
+        }
+        let created_at = manager.get_vault().unwrap().metadata.created_at;
+        manager.save_vault().unwrap();
+        manager.close_vault();
+
+        let vault_file = read_vault_file(&vault_path).unwrap();
+        assert!(vault_file.hide_metadata);
+        assert_eq!(vault_file.metadata.entry_count, 0);
+        assert_eq!(vault_file.metadata.created_at, chrono::DateTime::UNIX_EPOCH);
+        assert_eq!(
+            vault_file.encrypted_data.ciphertext.len(),
+            empty.encrypted_data.ciphertext.len()
+        );
+
+        manager.load_vault(&vault_path, "test_password").unwrap();
+        assert_eq!(manager.hide_metadata(), Some(true));
+        let vault = manager.get_vault().unwrap();
+        assert_eq!(vault.entries.len(), 3);
+        assert_eq!(vault.metadata.entry_count, 3);
+        assert_eq!(vault.metadata.created_at, created_at);
+
+        // Turning the option off restores the clear metadata
+        manager.set_hide_metadata(false).unwrap();
+        let vault_file = read_vault_file(&vault_path).unwrap();
+        assert!(!vault_file.hide_metadata);
+        assert_eq!(vault_file.metadata.entry_count, 3);
+    }
+
+    #[test]
+    fn test_trash_restore_and_purge() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        let vault = manager.get_vault_mut().unwrap();
+        let mut ids = Vec::new();
+        for title in ["Old", "Recent", "Kept"] {
+            let entry = crate::storage::PasswordEntry::new(
+                title.to_string(),
+                "user".to_string(),
+                "secret".to_string(),
+            );
+            ids.push(entry.id);
+            vault.add_entry(entry);
+        }
+        vault.trash_entry(&ids[0]).unwrap();
+        vault.trash_entry(&ids[1]).unwrap();
+        assert!(vault.trash_entry(&ids[0]).is_err());
+        assert_eq!(vault.entries.len(), 1);
+        assert_eq!(vault.metadata.entry_count, 1);
+
+        // The trash is saved inside the encrypted payload
+        let days_ago = chrono::Utc::now() - chrono::Duration::days(45);
+        vault.trash.get_mut(&ids[0]).unwrap().deleted_at = days_ago;
+        manager.save_vault().unwrap();
+        manager.close_vault();
+        manager.load_vault(&vault_path, "test_password").unwrap();
+
+        // Opening the vault purged the entry past the retention period
+        let vault = manager.get_vault_mut().unwrap();
+        assert_eq!(vault.trash.len(), 1);
+        assert!(vault.trash.contains_key(&ids[1]));
+        assert!(!vault.is_modified);
+        assert_eq!(
+            read_vault_file(&vault_path).unwrap().metadata.entry_count,
+            1
+        );
+
+        vault.restore_entry(&ids[1]).unwrap();
+        assert!(vault.trash.is_empty());
+        assert_eq!(vault.entries.len(), 2);
+        assert!(vault.restore_entry(&ids[1]).is_err());
+
+        // A retention of 0 keeps trashed entries forever
+        vault.trash_entry(&ids[2]).unwrap();
+        vault.settings.trash_retention_days = 0;
+        let far_future = chrono::Utc::now() + chrono::Duration::days(10_000);
+        assert_eq!(vault.purge_trash(far_future), 0);
+        vault.settings.trash_retention_days = 1;
+        assert_eq!(vault.purge_trash(far_future), 1);
+
+        vault.trash_entry(&ids[1]).unwrap();
+        assert_eq!(vault.empty_trash(), 1);
+        assert_eq!(vault.empty_trash(), 0);
+    }
+
+    #[test]
+    fn test_password_history() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let vault_path = temp_dir.path().join("vault.enc");
+
+        let mut manager = crate::storage::VaultManager::new();
+        manager.create_vault(&vault_path, "test_password").unwrap();
+        let vault = manager.get_vault_mut().unwrap();
+        vault.settings.history_size = 3;
+        let entry = crate::storage::PasswordEntry::new(
+            "GitHub".to_string(),
+            "octocat".to_string(),
+            "password-0".to_string(),
+        );
+        let id = entry.id;
+        vault.add_entry(entry);
+
+        for i in 1..=4 {
+            let mut entry = vault.get_entry(&id).unwrap().clone();
+            entry.password = format!("password-{}", i).into();
+            vault.update_entry(entry).unwrap();
+        }
+
+        // Only the newest three replaced passwords are kept, newest first
+        let history = &vault.get_entry(&id).unwrap().history;
+        let kept: Vec<&str> = history.iter().map(|item| item.password.expose()).collect();
+        assert_eq!(kept, ["password-3", "password-2", "password-1"]);
+        assert_eq!(history[0].username.as_deref(), Some("octocat"));
+
+        // Changes that keep the credentials add nothing
+        let mut entry = vault.get_entry(&id).unwrap().clone();
+        entry.notes = Some("rotated".to_string());
+        vault.update_entry(entry).unwrap();
+        assert_eq!(vault.get_entry(&id).unwrap().history.len(), 3);
+
+        // The history is saved inside the encrypted payload
+        manager.save_vault().unwrap();
+        manager.close_vault();
+        manager.load_vault(&vault_path, "test_password").unwrap();
+        let vault = manager.get_vault_mut().unwrap();
+
+        // Restoring swaps the current password into the history
+        vault.restore_history(&id, 1).unwrap();
+        let entry = vault.get_entry(&id).unwrap();
+        assert_eq!(entry.password.expose(), "password-2");
+        let kept: Vec<&str> = entry
+            .history
+            .iter()
+            .map(|item| item.password.expose())
+            .collect();
+        assert_eq!(kept, ["password-4", "password-3", "password-1"]);
+        assert!(vault.restore_history(&id, 3).is_err());
+
+        // Old items expire, and a size of 0 disables the history
+        vault.settings.history_max_age_days = 30;
+        let later = chrono::Utc::now() + chrono::Duration::days(31);
+        assert_eq!(vault.prune_history(later), 3);
+        vault.settings.history_size = 0;
+        let mut entry = vault.get_entry(&id).unwrap().clone();
+        entry.password = "password-5".into();
+        vault.update_entry(entry).unwrap();
+        assert!(vault.get_entry(&id).unwrap().history.is_empty());
+    }
+
+    #[test]
+    fn test_rename_and_move_tags() {
+        let mut vault = Vault::new("vault.enc");
+        let mut ids = Vec::new();
+        for (title, tags) in [
+            ("db01", vec!["Work/Servers", "work"]),
+            ("GitHub", vec!["work", "personal"]),
+            ("Bank", vec!["personal"]),
+        ] {
+            let mut entry = crate::storage::PasswordEntry::new(
+                title.to_string(),
+                "user".to_string(),
+                "secret".to_string(),
+            );
+            entry.tags = tags.into_iter().map(String::from).collect();
+            ids.push(entry.id);
+            vault.add_entry(entry);
+        }
+        vault.trash_entry(&ids[1]).unwrap();
+        vault.is_modified = false;
+        let tags = |vault: &Vault, id: &Uuid| match vault.get_entry(id) {
+            Some(entry) => entry.tags.clone(),
+            None => vault.trash[id].entry.tags.clone(),
+        };
+
+        // Nested tags and trashed entries are renamed too
+        assert_eq!(vault.rename_tag("WORK", "job").unwrap(), 2);
+        assert!(vault.is_modified);
+        assert_eq!(tags(&vault, &ids[0]), ["job/Servers", "job"]);
+        assert_eq!(tags(&vault, &ids[1]), ["job", "personal"]);
+        assert_eq!(tags(&vault, &ids[2]), ["personal"]);
+
+        assert_eq!(vault.move_tag("job/servers", Some("infra")).unwrap(), 1);
+        assert_eq!(tags(&vault, &ids[0]), ["infra/servers", "job"]);
+        assert_eq!(vault.move_tag("infra/Servers", None).unwrap(), 1);
+        assert_eq!(tags(&vault, &ids[0]), ["Servers", "job"]);
+
+        // Renaming onto a tag the entry already has merges them
+        assert_eq!(vault.rename_tag("servers", "job").unwrap(), 1);
+        assert_eq!(tags(&vault, &ids[0]), ["job"]);
+
+        assert!(vault.rename_tag("missing", "job").is_err());
+        assert!(vault.rename_tag("job", " / ").is_err());
+        assert!(vault.move_tag("job", Some("job/sub")).is_err());
+    }
 }